@@ -1,136 +1,241 @@
-use crate::{ArithmeticOperator, Context, Expression, ExpressionToken, Function};
+use crate::{
+    ArithmeticOperator, BinaryOperator, Context, Expression, ExpressionToken, Function,
+    LogicalOperator,
+};
 use num_traits::Float;
 
-fn shunting_yard<V>(tokens: Expression<V>, context: Option<&Context<V>>) -> Vec<ExpressionToken<V>>
+/// An operator in the postfix output, including unary operators inferred from their position
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Arithmetic(ArithmeticOperator),
+    Binary(BinaryOperator),
+    Logical(LogicalOperator),
+    Negate,
+}
+
+impl Operator {
+    /// Operator precedence, following the LinuxCNC
+    /// [binary operators](http://linuxcnc.org/docs/html/gcode/overview.html#gcode:binary-operators)
+    /// table. Higher numbers bind tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Negate | Operator::Logical(LogicalOperator::Not) => 4,
+            Operator::Arithmetic(ArithmeticOperator::Mul)
+            | Operator::Arithmetic(ArithmeticOperator::Div)
+            | Operator::Arithmetic(ArithmeticOperator::Mod) => 3,
+            Operator::Arithmetic(ArithmeticOperator::Add)
+            | Operator::Arithmetic(ArithmeticOperator::Sub) => 2,
+            Operator::Binary(_) => 1,
+            Operator::Logical(_) => 0,
+        }
+    }
+
+    fn is_unary(self) -> bool {
+        matches!(
+            self,
+            Operator::Negate | Operator::Logical(LogicalOperator::Not)
+        )
+    }
+}
+
+/// A single item in the postfix output
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Postfix<V> {
+    Value(V),
+    Operator(Operator),
+}
+
+fn bool_value<V: Float>(value: bool) -> V {
+    if value {
+        V::one()
+    } else {
+        V::zero()
+    }
+}
+
+fn function<V>(func: Function<V>, context: Option<&Context<V>>) -> Result<V, ()>
+where
+    V: Float + From<f32>,
+{
+    let res = match func {
+        Function::Abs(arg) => evaluate(arg, context)?.abs(),
+        Function::Acos(arg) => evaluate(arg, context)?.acos(),
+        Function::Asin(arg) => evaluate(arg, context)?.asin(),
+        Function::Atan((arg1, arg2)) => {
+            let res1 = evaluate(arg1, context)?;
+            let res2 = evaluate(arg2, context)?;
+
+            res1.atan2(res2)
+        }
+        Function::Cos(arg) => evaluate(arg, context)?.cos(),
+        Function::Exp(arg) => evaluate(arg, context)?.exp(),
+        Function::Floor(arg) => evaluate(arg, context)?.floor(),
+        Function::Ceil(arg) => evaluate(arg, context)?.ceil(),
+        Function::Ln(arg) => evaluate(arg, context)?.ln(),
+        Function::Round(arg) => evaluate(arg, context)?.round(),
+        Function::Sin(arg) => evaluate(arg, context)?.sin(),
+        Function::Sqrt(arg) => evaluate(arg, context)?.sqrt(),
+        Function::Tan(arg) => evaluate(arg, context)?.tan(),
+        Function::Exists(param) => match context {
+            Some(ctx) => bool_value(ctx.contains_key(&param)),
+            None => V::zero(),
+        },
+    };
+
+    Ok(res)
+}
+
+fn shunting_yard<V>(
+    tokens: Expression<V>,
+    context: Option<&Context<V>>,
+) -> Result<Vec<Postfix<V>>, ()>
 where
     V: Float + From<f32>,
 {
-    let mut output: Vec<ExpressionToken<V>> = Vec::new();
-    let mut operators: Vec<ExpressionToken<V>> = Vec::new();
+    let mut output: Vec<Postfix<V>> = Vec::new();
+    let mut operators: Vec<Operator> = Vec::new();
+
+    // Whether the next token should be an operand. Operators found in this position are unary.
+    let mut expect_operand = true;
 
     for token in tokens.0 {
-        match token {
-            ExpressionToken::Literal(_) => output.push(token),
-            ExpressionToken::ArithmeticOperator(operator) => {
-                while let Some(top) = operators.last().map(|value| value.clone()) {
-                    match top {
-                        ExpressionToken::ArithmeticOperator(top_op) => {
-                            if top_op > operator
-                            /* || (top_op == operator&& operator.is_left_associative)*/
-                            {
-                                output.push(operators.pop().unwrap());
-                            } else {
-                                break;
-                            }
-                        }
-                        _ => unimplemented!(),
-                    }
-                }
-                operators.push(token);
+        let operator = match token {
+            ExpressionToken::Literal(value) => {
+                output.push(Postfix::Value(value));
+                expect_operand = false;
+
+                continue;
+            }
+            ExpressionToken::Parameter(param) => {
+                let value = context.and_then(|ctx| ctx.get(&param)).ok_or(())?;
+
+                output.push(Postfix::Value(*value));
+                expect_operand = false;
+
+                continue;
             }
             ExpressionToken::Expression(nested_expr) => {
-                let res = evaluate(nested_expr, context);
+                output.push(Postfix::Value(evaluate(nested_expr, context)?));
+                expect_operand = false;
 
-                output.push(ExpressionToken::Literal(res.unwrap().into()));
+                continue;
             }
-            ExpressionToken::Parameter(_) => output.push(token),
             ExpressionToken::Function(func) => {
-                let res = match func {
-                    Function::Abs(arg) => evaluate(arg, context).unwrap().abs(),
-                    Function::Acos(arg) => evaluate(arg, context).unwrap().acos(),
-                    Function::Asin(arg) => evaluate(arg, context).unwrap().asin(),
-                    Function::Atan((arg1, arg2)) => {
-                        let res1 = evaluate(arg1, context).unwrap();
-                        let res2 = evaluate(arg2, context).unwrap();
-
-                        res1.atan2(res2)
-                    }
-                    Function::Cos(arg) => evaluate(arg, context).unwrap().cos(),
-                    Function::Exp(arg) => evaluate(arg, context).unwrap().exp(),
-                    Function::Floor(arg) => evaluate(arg, context).unwrap().floor(),
-                    Function::Ceil(arg) => evaluate(arg, context).unwrap().ceil(),
-                    Function::Ln(arg) => evaluate(arg, context).unwrap().ln(),
-                    Function::Round(arg) => evaluate(arg, context).unwrap().round(),
-                    Function::Sin(arg) => evaluate(arg, context).unwrap().sin(),
-                    Function::Sqrt(arg) => evaluate(arg, context).unwrap().sqrt(),
-                    Function::Tan(arg) => evaluate(arg, context).unwrap().tan(),
-                    Function::Exists(param) => match context {
-                        Some(ctx) => match ctx.contains_key(&param) {
-                            true => 1.0.into(),
-                            false => 0.0.into(),
-                        },
-                        None => 0.0.into(),
-                    },
-                };
-
-                output.push(ExpressionToken::Literal(res.into()))
+                output.push(Postfix::Value(function(func, context)?));
+                expect_operand = false;
+
+                continue;
+            }
+            ExpressionToken::ArithmeticOperator(ArithmeticOperator::Sub) if expect_operand => {
+                Operator::Negate
+            }
+            ExpressionToken::ArithmeticOperator(ArithmeticOperator::Add) if expect_operand => {
+                continue;
+            }
+            ExpressionToken::LogicalOperator(LogicalOperator::Not) if expect_operand => {
+                Operator::Logical(LogicalOperator::Not)
+            }
+            _ if expect_operand => return Err(()),
+            ExpressionToken::LogicalOperator(LogicalOperator::Not) => return Err(()),
+            ExpressionToken::ArithmeticOperator(op) => Operator::Arithmetic(op),
+            ExpressionToken::BinaryOperator(op) => Operator::Binary(op),
+            ExpressionToken::LogicalOperator(op) => Operator::Logical(op),
+        };
+
+        // Binary operators are left associative, unary operators are right associative
+        while let Some(top) = operators.last().cloned() {
+            if !operator.is_unary() && top.precedence() >= operator.precedence() {
+                output.push(Postfix::Operator(top));
+                operators.pop();
+            } else {
+                break;
             }
-            _ => unimplemented!(),
         }
+
+        operators.push(operator);
+        expect_operand = true;
     }
 
-    while let Some(token) = operators.pop() {
-        output.push(token)
+    if expect_operand {
+        return Err(());
     }
 
-    assert!(operators.is_empty());
+    while let Some(operator) = operators.pop() {
+        output.push(Postfix::Operator(operator))
+    }
 
-    output
+    Ok(output)
 }
 
-fn calculate<V>(
-    postfix_tokens: Vec<ExpressionToken<V>>,
-    context: Option<&Context<V>>,
-) -> Result<V, ()>
+fn calculate<V>(postfix_tokens: Vec<Postfix<V>>) -> Result<V, ()>
 where
     V: Float,
 {
     let mut stack = Vec::new();
 
     for token in postfix_tokens {
-        match token {
-            ExpressionToken::Literal(number) => stack.push(number),
-            ExpressionToken::ArithmeticOperator(operator) => {
-                if let Some(y) = stack.pop() {
-                    if let Some(x) = stack.pop() {
-                        let result = match operator {
-                            ArithmeticOperator::Div => x / y,
-                            ArithmeticOperator::Mul => x * y,
-                            ArithmeticOperator::Mod => x % y,
-                            ArithmeticOperator::Add => x + y,
-                            ArithmeticOperator::Sub => x - y,
-                        };
-
-                        stack.push(result);
-
-                        continue;
+        let operator = match token {
+            Postfix::Value(number) => {
+                stack.push(number);
+
+                continue;
+            }
+            Postfix::Operator(operator) => operator,
+        };
+
+        let y = stack.pop().ok_or(())?;
+
+        let result = match operator {
+            Operator::Negate => -y,
+            Operator::Logical(LogicalOperator::Not) => bool_value(y == V::zero()),
+            operator => {
+                let x = stack.pop().ok_or(())?;
+
+                match operator {
+                    Operator::Arithmetic(ArithmeticOperator::Div) => x / y,
+                    Operator::Arithmetic(ArithmeticOperator::Mul) => x * y,
+                    Operator::Arithmetic(ArithmeticOperator::Mod) => x % y,
+                    Operator::Arithmetic(ArithmeticOperator::Add) => x + y,
+                    Operator::Arithmetic(ArithmeticOperator::Sub) => x - y,
+                    Operator::Binary(BinaryOperator::Equal) => bool_value(x == y),
+                    Operator::Binary(BinaryOperator::NotEqual) => bool_value(x != y),
+                    Operator::Binary(BinaryOperator::GreaterThan) => bool_value(x > y),
+                    Operator::Binary(BinaryOperator::GreaterThanOrEqual) => bool_value(x >= y),
+                    Operator::Binary(BinaryOperator::LessThan) => bool_value(x < y),
+                    Operator::Binary(BinaryOperator::LessThanOrEqual) => bool_value(x <= y),
+                    Operator::Logical(LogicalOperator::And) => {
+                        bool_value(x != V::zero() && y != V::zero())
                     }
+                    Operator::Logical(LogicalOperator::Or) => {
+                        bool_value(x != V::zero() || y != V::zero())
+                    }
+                    Operator::Logical(LogicalOperator::Not) | Operator::Negate => unreachable!(),
                 }
             }
-            ExpressionToken::Parameter(param) => {
-                let value = context.unwrap().get(&param).unwrap();
+        };
 
-                stack.push(*value)
-            }
-            _ => unimplemented!(),
-        }
+        stack.push(result);
     }
 
-    assert!(stack.len() == 1);
-
-    Ok(stack.pop().unwrap())
+    match (stack.pop(), stack.is_empty()) {
+        (Some(result), true) => Ok(result),
+        _ => Err(()),
+    }
 }
 
 // TODO: Better error than `()`
 /// Evaluate an expression with an optional context object
+///
+/// Comparison and logical operators evaluate to `1.0` for true and `0.0` for false. Returns an
+/// error if the expression is malformed or references a parameter not present in `context`.
 pub fn evaluate<E, V>(expression: E, context: Option<&Context<V>>) -> Result<V, ()>
 where
     V: Float + From<f32>,
     E: Into<Expression<V>>,
 {
-    let postfix_tokens = shunting_yard(expression.into(), context);
+    let postfix_tokens = shunting_yard(expression.into(), context)?;
 
-    calculate(postfix_tokens, context)
+    calculate(postfix_tokens)
 }
 
 #[cfg(test)]
@@ -328,4 +433,58 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn it_respects_precedence_and_associativity() {
+        let expr = |s: &str| crate::gcode::expression::<(), f32>(s).unwrap().1;
+
+        assert_eq!(evaluate(expr("[1 - 2 + 3]"), None), Ok(2.0));
+        assert_eq!(evaluate(expr("[8 / 2 / 2]"), None), Ok(2.0));
+        assert_eq!(evaluate(expr("[1 + 2 * 3]"), None), Ok(7.0));
+        assert_eq!(evaluate(expr("[2 * 3 - 4 / 2]"), None), Ok(4.0));
+        assert_eq!(evaluate(expr("[7 mod 4 * 2]"), None), Ok(6.0));
+    }
+
+    #[test]
+    fn it_evaluates_unary_minus() {
+        let context: Context<f32> = hashmap! {
+            Parameter::Numbered(1) => 3.0,
+        };
+
+        let expr = |s: &str| crate::gcode::expression::<(), f32>(s).unwrap().1;
+
+        assert_eq!(evaluate(expr("[-#1]"), Some(&context)), Ok(-3.0));
+        assert_eq!(evaluate(expr("[2 * -#1]"), Some(&context)), Ok(-6.0));
+        assert_eq!(evaluate(expr("[-[1 + 1] * 2]"), None), Ok(-4.0));
+    }
+
+    #[test]
+    fn it_evaluates_comparisons_and_logic() {
+        let context: Context<f32> = hashmap! {
+            Parameter::Local("fraction".into()) => 0.5,
+        };
+
+        let expr = |s: &str| crate::gcode::expression::<(), f32>(s).unwrap().1;
+
+        assert_eq!(evaluate(expr("[1 + 1 EQ 2]"), None), Ok(1.0));
+        assert_eq!(evaluate(expr("[1 GT 2]"), None), Ok(0.0));
+        assert_eq!(evaluate(expr("[2 GE 2 AND 1 LT 2]"), None), Ok(1.0));
+        assert_eq!(
+            evaluate(
+                expr("[[#<fraction> GT 0.99] OR [#<fraction> LT 0.01]]"),
+                Some(&context)
+            ),
+            Ok(0.0)
+        );
+        assert_eq!(evaluate(expr("[NOT 0]"), None), Ok(1.0));
+    }
+
+    #[test]
+    fn it_errors_on_bad_input() {
+        let expr = |s: &str| crate::gcode::expression::<(), f32>(s).unwrap().1;
+
+        assert_eq!(evaluate(expr("[#1 + 1]"), None), Err(()));
+        assert_eq!(evaluate(expr("[1 +]"), None), Err(()));
+        assert_eq!(evaluate(expr("[1 NOT 2]"), None), Err(()));
+    }
 }
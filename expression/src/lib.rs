@@ -117,12 +117,12 @@ where
             Function::Abs(expr) => write!(f, "abs{}", expr),
             Function::Acos(expr) => write!(f, "acos{}", expr),
             Function::Asin(expr) => write!(f, "asin{}", expr),
-            Function::Atan((expr1, expr2)) => write!(f, "atan[{},{}]", expr1, expr2),
+            Function::Atan((expr1, expr2)) => write!(f, "atan{}/{}", expr1, expr2),
             Function::Cos(expr) => write!(f, "cos{}", expr),
-            Function::Exists(param) => write!(f, "exists{}", param),
+            Function::Exists(param) => write!(f, "exists[{}]", param),
             Function::Exp(expr) => write!(f, "exp{}", expr),
-            Function::Floor(expr) => write!(f, "floor{}", expr),
-            Function::Ceil(expr) => write!(f, "ceil{}", expr),
+            Function::Floor(expr) => write!(f, "fix{}", expr),
+            Function::Ceil(expr) => write!(f, "fup{}", expr),
             Function::Ln(expr) => write!(f, "ln{}", expr),
             Function::Round(expr) => write!(f, "round{}", expr),
             Function::Sin(expr) => write!(f, "sin{}", expr),
@@ -133,7 +133,7 @@ where
 }
 
 /// Comparison operators
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BinaryOperator {
     /// `==`
     Equal,
@@ -324,5 +324,22 @@ mod tests {
             .to_string(),
             "[#1234 + [#<named> + #<_global>]]"
         );
+
+        assert_eq!(
+            Expression::<f64>::from_tokens(vec![ExpressionToken::Function(Function::Exists(
+                Parameter::Local("named".into())
+            ))])
+            .to_string(),
+            "[exists[#<named>]]"
+        );
+
+        assert_eq!(
+            Expression::<f64>::from_tokens(vec![ExpressionToken::Function(Function::Atan((
+                vec![ExpressionToken::Literal(1.0)].into(),
+                vec![ExpressionToken::Literal(2.0)].into(),
+            )))])
+            .to_string(),
+            "[atan[1]/[2]]"
+        );
     }
 }
@@ -45,23 +45,41 @@ pub fn expression<'a, E: ParseError<&'a str>, V: FromStr>(
     )(i)
 }
 
+/// Parse an expression without surrounding brackets, like the right hand side of a Fanuc macro
+/// assignment `#100 = #101 + 1`
+///
+/// Unlike a bracketed expression, this one ends at the end of the line.
+pub fn bare_expression<'a, E: ParseError<&'a str>, V: FromStr>(
+    i: &'a str,
+) -> IResult<&'a str, Expression<V>, E> {
+    context(
+        "bare expression",
+        map(
+            many1(delimited(space0, expression_token_kind, space0)),
+            Expression::from_tokens,
+        ),
+    )(i)
+}
+
 fn expression_token<'a, E: ParseError<&'a str>, V: FromStr>(
     i: &'a str,
 ) -> IResult<&'a str, ExpressionToken<V>, E> {
-    delimited(
-        multispace0,
-        alt((
-            map(literal, ExpressionToken::Literal),
-            map(operator, ExpressionToken::ArithmeticOperator),
-            map(logical_operator, ExpressionToken::LogicalOperator),
-            map(binary_operator, ExpressionToken::BinaryOperator),
-            map(function, ExpressionToken::Function),
-            map(exists, ExpressionToken::Function),
-            map(expression, ExpressionToken::Expression),
-            map(parameter, ExpressionToken::Parameter),
-        )),
-        multispace0,
-    )(i)
+    delimited(multispace0, expression_token_kind, multispace0)(i)
+}
+
+fn expression_token_kind<'a, E: ParseError<&'a str>, V: FromStr>(
+    i: &'a str,
+) -> IResult<&'a str, ExpressionToken<V>, E> {
+    alt((
+        map(literal, ExpressionToken::Literal),
+        map(operator, ExpressionToken::ArithmeticOperator),
+        map(logical_operator, ExpressionToken::LogicalOperator),
+        map(binary_operator, ExpressionToken::BinaryOperator),
+        map(function, ExpressionToken::Function),
+        map(exists, ExpressionToken::Function),
+        map(expression, ExpressionToken::Expression),
+        map(parameter, ExpressionToken::Parameter),
+    ))(i)
 }
 
 // TODO: Optimise a bit? Just us `float`?
@@ -590,4 +608,18 @@ mod tests {
             ))].into();
         );
     }
+
+    #[test]
+    fn it_parses_bare_expressions_to_the_end_of_the_line() {
+        assert_parse!(
+            parser = bare_expression;
+            input = "#101 + 1\nG0 X1";
+            expected = vec![
+                ExpressionToken::<f64>::Parameter(Parameter::Numbered(101)),
+                ExpressionToken::ArithmeticOperator(ArithmeticOperator::Add),
+                ExpressionToken::Literal(1.0),
+            ].into();
+            remaining = "\nG0 X1";
+        );
+    }
 }
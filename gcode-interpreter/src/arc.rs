@@ -0,0 +1,541 @@
+//! Circular and helical arcs, and converting them into straight line segments
+//!
+//! Everything that needs arcs as polylines, like the planner and simulators, should go through
+//! [`Arc::linearise`](struct.Arc.html#method.linearise) so that the same tolerances mean the same
+//! thing everywhere.
+
+use crate::canonical::{first_moving, group_distances};
+use crate::error::ErrorKind;
+use crate::state::{plane_axes, Units};
+use crate::{Vector9, MM_PER_INCH};
+use gcode_parser::token::PlaneSelect;
+use std::f64::consts::PI;
+
+/// Arcs with a smaller radius than this are degenerate
+const MIN_RADIUS: f64 = 1.0e-9;
+
+/// How far the end of a center format arc may be from the start radius, in inches
+const CENTER_RADIUS_TOLERANCE_INCH: f64 = 0.002;
+
+/// How far the end of a center format arc may be from the start radius, in millimeters
+const CENTER_RADIUS_TOLERANCE_MM: f64 = 0.02;
+
+/// Center format arcs whose radii differ by less than this fraction are always accepted
+const CENTER_RADIUS_RELATIVE_TOLERANCE: f64 = 0.001;
+
+/// How much too short a radius format arc's radius may be to reach its end point, in
+/// millimeters (0.00005 inches)
+const RADIUS_TOLERANCE: f64 = 0.00127;
+
+/// Limits on how far the straight line segments approximating an arc may stray from it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArcTolerance {
+    /// The maximum distance between any segment and the arc
+    pub chord: f64,
+
+    /// The maximum angle around the center swept by any segment, in radians
+    pub angle: f64,
+}
+
+impl ArcTolerance {
+    /// Keep segments within `chord` of the arc, each sweeping at most a quarter turn
+    pub fn new(chord: f64) -> Self {
+        Self {
+            chord,
+            angle: PI / 2.0,
+        }
+    }
+}
+
+/// A circular or helical arc in absolute machine coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arc {
+    /// Start position
+    pub from: Vector9,
+
+    /// End position
+    pub to: Vector9,
+
+    /// Arc center. Only the two components in the arc plane are meaningful.
+    pub center: Vector9,
+
+    /// The plane the arc lies in
+    pub plane: PlaneSelect,
+
+    /// Whether the arc moves clockwise (`G2`) or counterclockwise (`G3`) when looking down the
+    /// plane's normal axis
+    pub clockwise: bool,
+
+    /// Number of turns. `1` is a normal arc, each additional turn adds a full circle.
+    pub turns: u32,
+}
+
+impl Arc {
+    /// An arc around `center` from `from` to `to`, like a center format (`IJK`) arc
+    ///
+    /// Both ends must be the same distance from the center to within LinuxCNC's tolerance of
+    /// 0.002 inches or 0.02mm in the given program units, unless they differ by less than 0.1%.
+    pub fn with_center(
+        from: Vector9,
+        to: Vector9,
+        center: Vector9,
+        plane: PlaneSelect,
+        clockwise: bool,
+        turns: u32,
+        units: Units,
+    ) -> Result<Self, ErrorKind> {
+        let arc = Self::new(from, to, center, plane, clockwise, turns)?;
+
+        let (start, end) = (arc.radius(), arc.end_radius());
+
+        if start < MIN_RADIUS {
+            return Err(ErrorKind::InvalidArc(
+                "the arc center is at its start point",
+            ));
+        }
+
+        let tolerance = match units {
+            Units::Millimeters => CENTER_RADIUS_TOLERANCE_MM,
+            Units::Inches => CENTER_RADIUS_TOLERANCE_INCH * MM_PER_INCH,
+        };
+
+        let error = (start - end).abs();
+
+        if error > tolerance && error > start * CENTER_RADIUS_RELATIVE_TOLERANCE {
+            return Err(ErrorKind::InvalidArc(
+                "the start and end points are different distances from the center",
+            ));
+        }
+
+        Ok(arc)
+    }
+
+    /// An arc of the given radius from `from` to `to`, like a radius format (`R`) arc
+    ///
+    /// There are two arcs of any radius between two points. A positive radius picks the one that
+    /// turns less than half a circle, and a negative radius the one that turns more. The radius
+    /// may be up to LinuxCNC's tolerance of 0.00005 inches too short to reach the end point, in
+    /// which case the arc is a half circle.
+    pub fn with_radius(
+        from: Vector9,
+        to: Vector9,
+        radius: f64,
+        plane: PlaneSelect,
+        clockwise: bool,
+        turns: u32,
+    ) -> Result<Self, ErrorKind> {
+        let (a, b, _) = plane_axes(&plane);
+
+        let (dx, dy) = (to[a] - from[a], to[b] - from[b]);
+        let chord = dx.hypot(dy);
+
+        if chord < MIN_RADIUS {
+            return Err(ErrorKind::InvalidArc(
+                "radius format arcs cannot have identical start and end points",
+            ));
+        }
+
+        let half = chord / 2.0;
+
+        if radius.abs() < half - RADIUS_TOLERANCE {
+            return Err(ErrorKind::InvalidArc(
+                "radius is too small to reach the end point",
+            ));
+        }
+
+        let height = (radius * radius - half * half).max(0.0).sqrt();
+
+        // Counterclockwise arcs of less than 180 degrees have their center to the left of the
+        // chord. A negative radius selects the arc of more than 180 degrees.
+        let side = if clockwise { -1.0 } else { 1.0 } * radius.signum();
+
+        let mut center = from;
+
+        center[a] = from[a] + dx / 2.0 - side * height * dy / chord;
+        center[b] = from[b] + dy / 2.0 + side * height * dx / chord;
+
+        Self::new(from, to, center, plane, clockwise, turns)
+    }
+
+    fn new(
+        from: Vector9,
+        to: Vector9,
+        center: Vector9,
+        plane: PlaneSelect,
+        clockwise: bool,
+        turns: u32,
+    ) -> Result<Self, ErrorKind> {
+        if turns == 0 {
+            return Err(ErrorKind::InvalidArc("P must be at least 1"));
+        }
+
+        Ok(Self {
+            from,
+            to,
+            center,
+            plane,
+            clockwise,
+            turns,
+        })
+    }
+
+    /// Radius measured from the center to the start point
+    pub fn radius(&self) -> f64 {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        (self.from[a] - self.center[a]).hypot(self.from[b] - self.center[b])
+    }
+
+    /// Radius measured from the center to the end point
+    pub fn end_radius(&self) -> f64 {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        (self.to[a] - self.center[a]).hypot(self.to[b] - self.center[b])
+    }
+
+    fn start_angle(&self) -> f64 {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        (self.from[b] - self.center[b]).atan2(self.from[a] - self.center[a])
+    }
+
+    /// The total angle swept by the arc in radians, always positive
+    pub fn sweep(&self) -> f64 {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        let start = self.start_angle();
+        let end = (self.to[b] - self.center[b]).atan2(self.to[a] - self.center[a]);
+
+        let mut sweep = if self.clockwise {
+            start - end
+        } else {
+            end - start
+        };
+
+        // Coincident start and end points describe a full circle
+        while sweep <= 1.0e-9 {
+            sweep += 2.0 * PI;
+        }
+
+        while sweep > 2.0 * PI + 1.0e-9 {
+            sweep -= 2.0 * PI;
+        }
+
+        sweep + f64::from(self.turns.saturating_sub(1)) * 2.0 * PI
+    }
+
+    /// Length of the arc including any helical component
+    pub fn length(&self) -> f64 {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        let mut linear = self.to - self.from;
+        linear[a] = 0.0;
+        linear[b] = 0.0;
+
+        (self.radius() * self.sweep()).hypot(linear.norm())
+    }
+
+    /// The distance the feed rate is measured along
+    ///
+    /// This is the length of the arc in the group of axes it lies in, unless it's a `UVW` arc
+    /// combined with a straight `XYZ` move.
+    pub fn feed_distance(&self) -> f64 {
+        let (a, _, normal) = plane_axes(&self.plane);
+
+        let delta = self.to - self.from;
+        let mut distances = group_distances(&delta);
+
+        distances[a / 3] = (self.radius() * self.sweep()).hypot(delta[normal]);
+
+        first_moving(distances)
+    }
+
+    /// The point a given fraction of the way along the arc, from `0.0` to `1.0`
+    ///
+    /// The radius is interpolated from the start radius to the end radius so that the arc ends
+    /// exactly on its end point.
+    pub fn point(&self, fraction: f64) -> Vector9 {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        let direction = if self.clockwise { -1.0 } else { 1.0 };
+        let angle = self.start_angle() + direction * self.sweep() * fraction;
+        let radius = self.radius() + (self.end_radius() - self.radius()) * fraction;
+
+        let mut point = self.from + (self.to - self.from) * fraction;
+
+        point[a] = self.center[a] + radius * angle.cos();
+        point[b] = self.center[b] + radius * angle.sin();
+
+        point
+    }
+
+    /// The smallest box containing the whole arc, as its minimum and maximum corners
+    pub fn bounds(&self) -> (Vector9, Vector9) {
+        let mut min = self.from.zip_map(&self.to, f64::min);
+        let mut max = self.from.zip_map(&self.to, f64::max);
+
+        let sweep = self.sweep();
+        let start = self.start_angle();
+        let direction = if self.clockwise { -1.0 } else { 1.0 };
+
+        // The axes in the arc plane are at their extremes where the arc crosses the lines
+        // through the center parallel to them, every quarter turn
+        for quarter in 0..4 {
+            let mut angle =
+                (direction * (f64::from(quarter) * PI / 2.0 - start)).rem_euclid(2.0 * PI);
+
+            while angle < sweep {
+                let point = self.point(angle / sweep);
+
+                min = min.zip_map(&point, f64::min);
+                max = max.zip_map(&point, f64::max);
+
+                angle += 2.0 * PI;
+            }
+        }
+
+        (min, max)
+    }
+
+    /// Split the arc into straight line segments that deviate from the true arc by no more than
+    /// `tolerance`, each sweeping at most a quarter turn
+    ///
+    /// The start point is not included. The last point is always exactly the end point.
+    pub fn points(&self, tolerance: f64) -> Vec<Vector9> {
+        self.linearise(&ArcTolerance::new(tolerance))
+    }
+
+    /// Split the arc into straight line segments within the given tolerances
+    ///
+    /// Segments are all the same length, and the number of them is the least that keeps both the
+    /// chord error, measured against the larger of the start and end radii, and the angle swept
+    /// by each segment within tolerance. Helical and multi-turn arcs are split by the angle swept
+    /// in the arc plane. The start point is not included, and the last point is always exactly
+    /// the end point.
+    pub fn linearise(&self, tolerance: &ArcTolerance) -> Vec<Vector9> {
+        let radius = self.radius().max(self.end_radius());
+        let max_angle = tolerance.angle.min(2.0 * PI);
+
+        let step = if tolerance.chord < radius {
+            (2.0 * (1.0 - tolerance.chord / radius).acos()).min(max_angle)
+        } else {
+            max_angle
+        };
+
+        let count = (self.sweep() / step).ceil().max(1.0) as usize;
+
+        (1..count)
+            .map(|n| self.point(n as f64 / count as f64))
+            .chain(std::iter::once(self.to))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xyz(x: f64, y: f64, z: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = x;
+        v[1] = y;
+        v[2] = z;
+        v
+    }
+
+    fn quarter(clockwise: bool) -> Arc {
+        Arc {
+            from: xyz(1.0, 0.0, 0.0),
+            to: if clockwise {
+                xyz(0.0, -1.0, 0.0)
+            } else {
+                xyz(0.0, 1.0, 0.0)
+            },
+            center: Vector9::zeros(),
+            plane: PlaneSelect::XY,
+            clockwise,
+            turns: 1,
+        }
+    }
+
+    #[test]
+    fn quarter_circles() {
+        for clockwise in [true, false].iter() {
+            let arc = quarter(*clockwise);
+
+            assert!((arc.sweep() - PI / 2.0).abs() < 1.0e-9);
+            assert!((arc.length() - PI / 2.0).abs() < 1.0e-9);
+            assert!((arc.point(1.0) - arc.to).norm() < 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn full_circle_with_turns() {
+        let arc = Arc {
+            to: xyz(1.0, 0.0, 2.0),
+            turns: 2,
+            ..quarter(false)
+        };
+
+        assert!((arc.sweep() - 4.0 * PI).abs() < 1.0e-9);
+        assert!((arc.length() - (4.0 * PI).hypot(2.0)).abs() < 1.0e-9);
+        assert!((arc.point(0.5) - xyz(1.0, 0.0, 1.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn bounds() {
+        let (min, max) = quarter(false).bounds();
+
+        assert!((min - xyz(0.0, 0.0, 0.0)).norm() < 1.0e-9);
+        assert!((max - xyz(1.0, 1.0, 0.0)).norm() < 1.0e-9);
+
+        // Clockwise from (1, 0) round to (0, 1) passes through the bottom and left of the circle
+        let arc = Arc {
+            to: xyz(0.0, 1.0, 0.0),
+            ..quarter(true)
+        };
+
+        let (min, max) = arc.bounds();
+
+        assert!((min - xyz(-1.0, -1.0, 0.0)).norm() < 1.0e-9);
+        assert!((max - xyz(1.0, 1.0, 0.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn polyline_tolerance() {
+        let arc = quarter(false);
+
+        let points = arc.points(0.001);
+
+        assert_eq!(points.last(), Some(&arc.to));
+
+        let mut previous = arc.from;
+
+        for point in points {
+            let midpoint = (previous + point) / 2.0;
+
+            assert!(1.0 - midpoint.norm() <= 0.001 + 1.0e-12);
+
+            previous = point;
+        }
+    }
+
+    /// Check every segment is within tolerance of an arc in the XY plane, returning the points
+    fn assert_linearised(arc: &Arc, tolerance: &ArcTolerance) -> Vec<Vector9> {
+        let points = arc.linearise(tolerance);
+
+        assert_eq!(points.last(), Some(&arc.to));
+
+        let step = arc.sweep() / points.len() as f64;
+
+        assert!(step <= tolerance.angle + 1.0e-12);
+
+        let mut previous = arc.from;
+
+        for point in points.iter() {
+            let midpoint = (previous + point) / 2.0;
+            let radius = (midpoint - arc.center).xy().norm();
+
+            assert!(arc.radius() - radius <= tolerance.chord + 1.0e-12);
+
+            previous = *point;
+        }
+
+        points
+    }
+
+    #[test]
+    fn segment_angle_limit() {
+        let arc = quarter(false);
+
+        // A loose chord tolerance alone would allow a single segment
+        let points = assert_linearised(
+            &arc,
+            &ArcTolerance {
+                chord: 1.0,
+                angle: PI / 8.0,
+            },
+        );
+
+        assert_eq!(points.len(), 4);
+    }
+
+    #[test]
+    fn full_circles_and_helices() {
+        let tolerance = ArcTolerance::new(0.001);
+
+        let circle = Arc {
+            to: xyz(1.0, 0.0, 0.0),
+            ..quarter(true)
+        };
+
+        let single = assert_linearised(&circle, &tolerance).len();
+
+        let helix = Arc {
+            to: xyz(1.0, 0.0, -3.0),
+            turns: 3,
+            ..circle
+        };
+
+        let points = assert_linearised(&helix, &tolerance);
+
+        assert!(points.len() > 2 * single);
+
+        // The depth changes evenly through every turn
+        for (n, point) in points.iter().enumerate() {
+            let expected = -3.0 * (n + 1) as f64 / points.len() as f64;
+
+            assert!((point[2] - expected).abs() < 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn radius_format_picks_side_by_sign() {
+        let (from, to) = (xyz(0.0, 0.0, 0.0), xyz(2.0, 0.0, 0.0));
+
+        let short = Arc::with_radius(from, to, 2.0, PlaneSelect::XY, false, 1).unwrap();
+        let long = Arc::with_radius(from, to, -2.0, PlaneSelect::XY, false, 1).unwrap();
+
+        assert!((short.center - xyz(1.0, 3.0f64.sqrt(), 0.0)).norm() < 1.0e-9);
+        assert!((short.sweep() - PI / 3.0).abs() < 1.0e-9);
+        assert!((long.center - xyz(1.0, -(3.0f64.sqrt()), 0.0)).norm() < 1.0e-9);
+        assert!((long.sweep() - 5.0 * PI / 3.0).abs() < 1.0e-9);
+
+        // Just too short to reach is rounded up to a half circle
+        let half = Arc::with_radius(from, to, 0.999, PlaneSelect::XY, true, 1).unwrap();
+
+        assert!((half.center - xyz(1.0, 0.0, 0.0)).norm() < 1.0e-9);
+        assert_eq!(
+            Arc::with_radius(from, to, 0.99, PlaneSelect::XY, true, 1),
+            Err(ErrorKind::InvalidArc(
+                "radius is too small to reach the end point"
+            ))
+        );
+    }
+
+    #[test]
+    fn center_format_radius_agreement() {
+        let arc = |end: f64, units: Units| {
+            Arc::with_center(
+                xyz(10.0, 0.0, 0.0),
+                xyz(0.0, end, 0.0),
+                Vector9::zeros(),
+                PlaneSelect::XY,
+                false,
+                1,
+                units,
+            )
+        };
+
+        let mismatch = Err(ErrorKind::InvalidArc(
+            "the start and end points are different distances from the center",
+        ));
+
+        assert!(arc(10.015, Units::Millimeters).is_ok());
+        assert_eq!(arc(10.03, Units::Millimeters), mismatch);
+        // 0.002 inches is about 0.05mm
+        assert!(arc(10.03, Units::Inches).is_ok());
+        assert_eq!(arc(10.06, Units::Inches), mismatch);
+        assert_eq!(arc(10.0, Units::Millimeters).map(|arc| arc.turns), Ok(1));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::arc::Arc;
+use crate::spline::Spline;
+use crate::state::PathMode;
+use crate::Vector9;
+
+/// Moves shorter than this along every group of axes have no feed distance
+const MIN_FEED_DISTANCE: f64 = 1.0e-9;
+
+/// Pick the distance a feed rate applies to from the distances moved by the `XYZ`, `UVW` and
+/// `ABC` axis groups
+///
+/// Like LinuxCNC, this is the `XYZ` distance if those axes move, otherwise the `UVW` distance,
+/// otherwise the angle turned by the rotary axes in degrees.
+pub(crate) fn first_moving(distances: [f64; 3]) -> f64 {
+    distances
+        .iter()
+        .cloned()
+        .find(|distance| *distance > MIN_FEED_DISTANCE)
+        .unwrap_or(0.0)
+}
+
+/// Distance moved by each of the `XYZ`, `UVW` and `ABC` axis groups
+pub(crate) fn group_distances(delta: &Vector9) -> [f64; 3] {
+    [
+        delta.fixed_rows::<nalgebra::U3>(0).norm(),
+        delta.fixed_rows::<nalgebra::U3>(3).norm(),
+        delta.fixed_rows::<nalgebra::U3>(6).norm(),
+    ]
+}
+
+/// The distance a feed rate is measured along for a straight move
+pub(crate) fn feed_distance(from: &Vector9, to: &Vector9) -> f64 {
+    first_moving(group_distances(&(to - from)))
+}
+
+/// Spindle rotation direction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpindleDirection {
+    /// Clockwise (`M3`)
+    Clockwise,
+
+    /// Counterclockwise (`M4`)
+    Counterclockwise,
+
+    /// Stopped (`M5`)
+    Stopped,
+}
+
+/// The type of a canonical machine command
+#[derive(Debug, Clone, PartialEq)]
+pub enum CanonicalKind {
+    /// Move at maximum speed in a straight line
+    Rapid {
+        /// Start position
+        from: Vector9,
+
+        /// End position
+        to: Vector9,
+    },
+
+    /// Move at the given feed rate in a straight line
+    Linear {
+        /// Start position
+        from: Vector9,
+
+        /// End position
+        to: Vector9,
+
+        /// Feed rate in millimeters per minute, or degrees per minute for a purely rotary move
+        feed: f64,
+    },
+
+    /// Move along a circular or helical arc at the given feed rate
+    Arc {
+        /// Arc geometry
+        arc: Arc,
+
+        /// Feed rate in millimeters per minute along the arc
+        feed: f64,
+    },
+
+    /// Move along a spline in the `XY` plane at the given feed rate
+    Spline {
+        /// Spline geometry
+        spline: Spline,
+
+        /// Feed rate in millimeters per minute along the spline
+        feed: f64,
+    },
+
+    /// Move in a straight line locked to the rotation of the spindle (`G33`), or tap along the
+    /// line then reverse the spindle and back out to the start (`G33.1`)
+    SpindleSynchronized {
+        /// Start position
+        from: Vector9,
+
+        /// End position of the cut
+        to: Vector9,
+
+        /// Distance moved along the line per spindle revolution in millimeters
+        pitch: f64,
+
+        /// Programmed spindle speed in RPM
+        speed: f64,
+
+        /// Whether the spindle reverses at the end of the move to retract back to the start
+        rigid_tap: bool,
+    },
+
+    /// Pause for a given time
+    Dwell {
+        /// Dwell time in seconds
+        seconds: f64,
+    },
+
+    /// Change to a new tool
+    ToolChange {
+        /// The new tool number
+        tool: u32,
+    },
+
+    /// Start, stop or change the speed of the spindle
+    Spindle {
+        /// Rotation direction
+        direction: SpindleDirection,
+
+        /// Speed in RPM
+        speed: f64,
+    },
+
+    /// Turn coolant on or off
+    Coolant {
+        /// Whether mist coolant is on (`M7`)
+        mist: bool,
+
+        /// Whether flood coolant is on (`M8`)
+        flood: bool,
+    },
+
+    /// Stop until the operator resumes the program (`M0`), or only if optional stops are enabled
+    /// (`M1`)
+    Pause {
+        /// Whether the pause can be skipped
+        optional: bool,
+    },
+
+    /// Change how the moves that follow are joined together
+    PathMode {
+        /// The new path control mode
+        mode: PathMode,
+    },
+
+    /// End of program
+    End,
+}
+
+impl CanonicalKind {
+    /// The position the machine is at after this command completes, if it moves the machine
+    pub fn target(&self) -> Option<&Vector9> {
+        match self {
+            CanonicalKind::Rapid { to, .. } | CanonicalKind::Linear { to, .. } => Some(to),
+            CanonicalKind::Arc { arc, .. } => Some(&arc.to),
+            CanonicalKind::Spline { spline, .. } => Some(spline.to()),
+            CanonicalKind::SpindleSynchronized {
+                from, rigid_tap, ..
+            } if *rigid_tap => Some(from),
+            CanonicalKind::SpindleSynchronized { to, .. } => Some(to),
+            _ => None,
+        }
+    }
+
+    /// The programmed speed of a feed move in units per second through all nine axes
+    ///
+    /// Feed rates only apply to the feed distance, so a move that turns a rotary axis as well as
+    /// moving the linear axes covers more ground in the same time.
+    pub fn path_speed(&self) -> Option<f64> {
+        let (length, distance, feed) = match self {
+            CanonicalKind::Linear { from, to, feed } => {
+                ((to - from).norm(), feed_distance(from, to), *feed)
+            }
+            CanonicalKind::Arc { arc, feed } => (arc.length(), arc.feed_distance(), *feed),
+            // Splines only move X and Y, so their whole length is the feed distance
+            CanonicalKind::Spline { feed, .. } => return Some(feed / 60.0),
+            _ => return None,
+        };
+
+        let speed = feed / 60.0;
+
+        Some(if distance > MIN_FEED_DISTANCE {
+            speed * length / distance
+        } else {
+            speed
+        })
+    }
+}
+
+/// A single canonical machine command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canonical {
+    /// The 1-indexed source line that produced this command
+    pub line: usize,
+
+    /// The command
+    pub kind: CanonicalKind,
+}
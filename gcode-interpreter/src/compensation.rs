@@ -0,0 +1,1163 @@
+//! Cutter radius compensation (`G41`, `G42`, `G41.1` and `G42.1`)
+//!
+//! Like LinuxCNC, moves in the active plane are offset by the tool radius to the left or right of
+//! the programmed path. Left and right are seen looking down the plane's normal axis in the `XY`
+//! plane, and with `X` across and `Z` upwards in the `XZ` plane, so a program keeps its sides
+//! when `Y` is swapped for `Z`, even though its arcs swap direction. Each move is held back until the
+//! next one is known so the corner between them can be joined up: the tool swings round outside
+//! corners on an arc centered on the programmed corner, and both moves are cut short where their
+//! offset paths cross at inside corners. A corner that can't be joined without the tool cutting
+//! into the part is an error.
+//!
+//! The first move in the plane after compensation is turned on is the entry move. It must be a
+//! straight line, and ends where the tool just touches the programmed end point, so it can't make
+//! an inside corner with the move after it. Moves out of the plane, like plunges along `Z` in the
+//! `XY` plane, stay at the tool's offset position.
+//!
+//! Lathe tools in the `XZ` plane are controlled by their imaginary tip rather than the center of
+//! their nose: the corner where lines along `X` and `Z` touching the nose on its cutting side
+//! meet. The tool's orientation from 1 to 8 sets which corner that is. The nose center follows
+//! the offset path as it would for a mill cutter, and the tip follows it a fixed distance away,
+//! so moves along `X` and `Z` keep the tip on the programmed line. Orientation 9, or 0 for a tool
+//! without one, controls the nose center.
+
+use crate::arc::Arc;
+use crate::canonical::{Canonical, CanonicalKind};
+use crate::error::ErrorKind;
+use crate::state::{plane_axes, CompensationSide};
+use crate::Vector9;
+use gcode_parser::token::PlaneSelect;
+use nalgebra::Vector2;
+use std::f64::consts::PI;
+
+/// Offset paths that end and start closer together than this in millimeters meet without a
+/// corner
+const CORNER_TOLERANCE: f64 = 1.0e-4;
+
+type Point = Vector2<f64>;
+
+/// The direction from the center of a lathe tool's nose to its imaginary tip for orientations 1 to
+/// 8, as `(X, Z)`
+const TIP_DIRECTIONS: [(f64, f64); 8] = [
+    (-1.0, 1.0),
+    (-1.0, -1.0),
+    (1.0, -1.0),
+    (1.0, 1.0),
+    (0.0, 1.0),
+    (-1.0, 0.0),
+    (0.0, -1.0),
+    (1.0, 0.0),
+];
+
+/// Rotate a vector counterclockwise by an angle in radians
+fn rotate(vector: Point, angle: f64) -> Point {
+    let (sin, cos) = angle.sin_cos();
+
+    Point::new(
+        vector.x * cos - vector.y * sin,
+        vector.x * sin + vector.y * cos,
+    )
+}
+
+/// The direction of travel around an arc at a point the given vector away from its center
+fn tangent(radial: Point, clockwise: bool) -> Point {
+    let left = Point::new(-radial.y, radial.x).normalize();
+
+    if clockwise {
+        -left
+    } else {
+        left
+    }
+}
+
+/// A move projected onto the compensation plane
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Line {
+        from: Point,
+        to: Point,
+    },
+    Arc {
+        from: Point,
+        to: Point,
+        center: Point,
+        clockwise: bool,
+    },
+}
+
+impl Segment {
+    fn from(&self) -> Point {
+        match self {
+            Segment::Line { from, .. } | Segment::Arc { from, .. } => *from,
+        }
+    }
+
+    fn to(&self) -> Point {
+        match self {
+            Segment::Line { to, .. } | Segment::Arc { to, .. } => *to,
+        }
+    }
+
+    fn set_from(&mut self, point: Point) {
+        match self {
+            Segment::Line { from, .. } | Segment::Arc { from, .. } => *from = point,
+        }
+    }
+
+    fn set_to(&mut self, point: Point) {
+        match self {
+            Segment::Line { to, .. } | Segment::Arc { to, .. } => *to = point,
+        }
+    }
+
+    /// The direction of travel at the start of the move
+    fn start_direction(&self) -> Point {
+        match self {
+            Segment::Line { from, to } => (to - from).normalize(),
+            Segment::Arc {
+                from,
+                center,
+                clockwise,
+                ..
+            } => tangent(from - center, *clockwise),
+        }
+    }
+
+    /// The direction of travel at the end of the move
+    fn end_direction(&self) -> Point {
+        match self {
+            Segment::Line { from, to } => (to - from).normalize(),
+            Segment::Arc {
+                to,
+                center,
+                clockwise,
+                ..
+            } => tangent(to - center, *clockwise),
+        }
+    }
+
+    /// The path the tool takes when offset by `distance` to the left of the direction of travel,
+    /// or to the right if negative
+    fn offset(&self, distance: f64) -> Result<Self, ErrorKind> {
+        match self {
+            Segment::Line { from, to } => {
+                let direction = self.start_direction();
+                let normal = Point::new(-direction.y, direction.x) * distance;
+
+                Ok(Segment::Line {
+                    from: from + normal,
+                    to: to + normal,
+                })
+            }
+            Segment::Arc {
+                from,
+                to,
+                center,
+                clockwise,
+            } => {
+                // The left of a counterclockwise arc is its inside
+                let change = if *clockwise { distance } else { -distance };
+
+                let resize = |point: &Point| {
+                    let radius = (point - center).norm();
+
+                    if radius + change > CORNER_TOLERANCE {
+                        Ok(center + (point - center) * (radius + change) / radius)
+                    } else {
+                        Err(ErrorKind::Gouge("the tool radius is larger than an arc"))
+                    }
+                };
+
+                Ok(Segment::Arc {
+                    from: resize(from)?,
+                    to: resize(to)?,
+                    center: *center,
+                    clockwise: *clockwise,
+                })
+            }
+        }
+    }
+
+    /// The angle swept from the start of an arc to a point on its circle
+    fn swept(from: Point, center: Point, clockwise: bool, point: Point) -> f64 {
+        let start = (from - center).y.atan2((from - center).x);
+        let end = (point - center).y.atan2((point - center).x);
+
+        let angle = if clockwise { start - end } else { end - start };
+
+        angle.rem_euclid(2.0 * PI)
+    }
+
+    /// Whether a point on this segment's line or circle lies between its ends
+    fn contains(&self, point: Point) -> bool {
+        match self {
+            Segment::Line { from, to } => {
+                let length = (to - from).norm();
+                let along = (point - from).dot(&(to - from)) / length;
+
+                along > -CORNER_TOLERANCE && along < length + CORNER_TOLERANCE
+            }
+            Segment::Arc {
+                from,
+                to,
+                center,
+                clockwise,
+            } => {
+                let radius = (from - center).norm();
+
+                let mut sweep = Self::swept(*from, *center, *clockwise, *to);
+
+                // Coincident ends describe a full circle
+                if sweep * radius < CORNER_TOLERANCE {
+                    sweep = 2.0 * PI;
+                }
+
+                let angle = Self::swept(*from, *center, *clockwise, point);
+
+                // Points just before the start have swept almost a full turn
+                angle * radius < sweep * radius + CORNER_TOLERANCE
+                    || (2.0 * PI - angle) * radius < CORNER_TOLERANCE
+            }
+        }
+    }
+
+    /// The points where this segment's line or circle crosses another's
+    fn intersections(&self, other: &Self) -> Vec<Point> {
+        match (self, other) {
+            (
+                Segment::Line { from, to },
+                Segment::Line {
+                    from: start,
+                    to: end,
+                },
+            ) => {
+                let (a, b) = (to - from, end - start);
+
+                let denominator = a.perp(&b);
+
+                if denominator.abs() < 1.0e-12 {
+                    return Vec::new();
+                }
+
+                vec![from + a * (start - from).perp(&b) / denominator]
+            }
+            (Segment::Arc { .. }, Segment::Line { .. }) => other.intersections(self),
+            (
+                Segment::Line { from, to },
+                Segment::Arc {
+                    from: start,
+                    center,
+                    ..
+                },
+            ) => {
+                let radius = (start - center).norm();
+
+                let direction = (to - from).normalize();
+                let foot = from + direction * (center - from).dot(&direction);
+                let distance = (center - foot).norm();
+
+                if distance > radius + CORNER_TOLERANCE {
+                    return Vec::new();
+                }
+
+                let half = (radius * radius - distance * distance).max(0.0).sqrt();
+
+                vec![foot - direction * half, foot + direction * half]
+            }
+            (
+                Segment::Arc { from, center, .. },
+                Segment::Arc {
+                    from: start,
+                    center: other_center,
+                    ..
+                },
+            ) => {
+                let (r1, r2) = ((from - center).norm(), (start - other_center).norm());
+
+                let between = other_center - center;
+                let distance = between.norm();
+
+                if distance < CORNER_TOLERANCE
+                    || distance > r1 + r2 + CORNER_TOLERANCE
+                    || distance < (r1 - r2).abs() - CORNER_TOLERANCE
+                {
+                    return Vec::new();
+                }
+
+                let along = (r1 * r1 - r2 * r2 + distance * distance) / (2.0 * distance);
+                let half = (r1 * r1 - along * along).max(0.0).sqrt();
+
+                let direction = between / distance;
+                let middle = center + direction * along;
+                let normal = Point::new(-direction.y, direction.x);
+
+                vec![middle - normal * half, middle + normal * half]
+            }
+        }
+    }
+}
+
+/// A move whose end may still change when the next move is known
+#[derive(Debug)]
+struct Held {
+    canonical: Canonical,
+
+    /// The tool's path in the plane
+    path: Segment,
+
+    /// Whether this is the entry move, which ends tangent to the tool
+    entry: bool,
+}
+
+/// Offsets moves in a plane by the tool radius
+#[derive(Debug)]
+pub(crate) struct Compensation {
+    /// Distance to offset the path by, positive to the left
+    offset: f64,
+
+    /// Where the controlled point is from the center of the tool
+    tip: Point,
+
+    plane: PlaneSelect,
+    held: Option<Held>,
+
+    /// Commands issued after the held move, which have to wait for it
+    queue: Vec<Canonical>,
+}
+
+impl Compensation {
+    /// Offset moves in a plane to one side by the given tool radius in millimeters, controlling
+    /// the imaginary tip of a lathe tool with the given orientation in the `XZ` plane
+    pub(crate) fn new(
+        side: CompensationSide,
+        radius: f64,
+        orientation: u32,
+        plane: PlaneSelect,
+    ) -> Self {
+        let offset = match side {
+            CompensationSide::Left => radius,
+            CompensationSide::Right => -radius,
+        };
+
+        // The plane's axes are the other way round to how sides are seen in the XZ plane
+        let offset = if plane == PlaneSelect::ZX {
+            -offset
+        } else {
+            offset
+        };
+
+        let tip = match orientation {
+            1..=8 if plane == PlaneSelect::ZX => {
+                let (x, z) = TIP_DIRECTIONS[orientation as usize - 1];
+
+                Point::new(z, x) * radius
+            }
+            _ => Point::zeros(),
+        };
+
+        Self {
+            offset,
+            tip,
+            plane,
+            held: None,
+            queue: Vec::new(),
+        }
+    }
+
+    fn point(&self, position: &Vector9) -> Point {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        Point::new(position[a], position[b])
+    }
+
+    fn set_point(&self, position: &mut Vector9, point: Point) {
+        let (a, b, _) = plane_axes(&self.plane);
+
+        position[a] = point.x;
+        position[b] = point.y;
+    }
+
+    /// Move the controlled point of the tool to where its center is at a point in the plane
+    fn set_tool(&self, position: &mut Vector9, center: Point) {
+        self.set_point(position, center + self.tip);
+    }
+
+    /// The controlled point with the tool's center at a point in the plane, with the other axes
+    /// taken from `position`
+    fn position(&self, position: &Vector9, center: Point) -> Vector9 {
+        let mut position = *position;
+
+        self.set_tool(&mut position, center);
+
+        position
+    }
+
+    /// The programmed path of a move in the plane, or `None` if it doesn't move in the plane
+    fn segment(&self, kind: &CanonicalKind) -> Result<Option<Segment>, ErrorKind> {
+        let segment = match kind {
+            CanonicalKind::Rapid { from, to } | CanonicalKind::Linear { from, to, .. } => {
+                let (from, to) = (self.point(from), self.point(to));
+
+                if (to - from).norm() < CORNER_TOLERANCE {
+                    return Ok(None);
+                }
+
+                Segment::Line { from, to }
+            }
+            CanonicalKind::Arc { arc, .. } => {
+                if arc.turns > 1 {
+                    return Err(ErrorKind::InvalidArc(
+                        "arcs of more than one turn can't be used with cutter compensation",
+                    ));
+                }
+
+                Segment::Arc {
+                    from: self.point(&arc.from),
+                    to: self.point(&arc.to),
+                    center: self.point(&arc.center),
+                    clockwise: arc.clockwise,
+                }
+            }
+            CanonicalKind::SpindleSynchronized { .. } => {
+                return Err(ErrorKind::InvalidWords(
+                    "spindle synchronised motion can't be used with cutter compensation",
+                ))
+            }
+            CanonicalKind::Spline { .. } => {
+                return Err(ErrorKind::InvalidWords(
+                    "splines can't be used with cutter compensation",
+                ))
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(segment))
+    }
+
+    /// Move a command onto the tool's path in the plane
+    fn place(&self, kind: &mut CanonicalKind, path: &Segment) {
+        match kind {
+            CanonicalKind::Rapid { from, to } | CanonicalKind::Linear { from, to, .. } => {
+                self.set_tool(from, path.from());
+                self.set_tool(to, path.to());
+            }
+            CanonicalKind::Arc { arc, .. } => {
+                self.set_tool(&mut arc.from, path.from());
+                self.set_tool(&mut arc.to, path.to());
+
+                if let Segment::Arc { center, .. } = path {
+                    self.set_tool(&mut arc.center, *center);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The tool's path for the entry move, which ends where the tool touches the programmed end
+    /// point with the move tangent to the tool
+    fn entry(&self, programmed: &Segment) -> Result<Segment, ErrorKind> {
+        // The tool starts with its controlled point at the programmed start
+        let (from, to) = match programmed {
+            Segment::Line { from, to } => (from - self.tip, *to),
+            Segment::Arc { .. } => {
+                return Err(ErrorKind::InvalidWords(
+                    "the first move with cutter compensation must be a straight line",
+                ))
+            }
+        };
+
+        let radius = self.offset.abs();
+        let distance = (to - from).norm();
+
+        if distance <= radius {
+            return Err(ErrorKind::Gouge(
+                "the entry move is shorter than the tool radius",
+            ));
+        }
+
+        let angle = (radius / distance).acos();
+        let back = (from - to) / distance;
+
+        let end = if self.offset > 0.0 {
+            to + rotate(back, -angle) * radius
+        } else {
+            to + rotate(back, angle) * radius
+        };
+
+        Ok(Segment::Line { from, to: end })
+    }
+
+    /// Pass on a command, or keep it until the held move is finished
+    pub(crate) fn command(&mut self, canonical: Canonical) -> Option<Canonical> {
+        if self.held.is_some() {
+            self.queue.push(canonical);
+
+            None
+        } else {
+            Some(canonical)
+        }
+    }
+
+    /// Issue the held move ending at its final position, followed by everything after it
+    fn release(&mut self, held: Held) -> Vec<Canonical> {
+        let Held {
+            mut canonical,
+            path,
+            ..
+        } = held;
+
+        self.place(&mut canonical.kind, &path);
+
+        let mut ready = vec![canonical];
+
+        for mut canonical in std::mem::take(&mut self.queue) {
+            // Moves out of the plane stay where the tool is
+            if let CanonicalKind::Rapid { from, to } | CanonicalKind::Linear { from, to, .. } =
+                &mut canonical.kind
+            {
+                self.set_tool(from, path.to());
+                self.set_tool(to, path.to());
+            }
+
+            ready.push(canonical);
+        }
+
+        ready
+    }
+
+    /// Compensate a move, returning the commands that are ready to be issued
+    pub(crate) fn motion(&mut self, canonical: Canonical) -> Result<Vec<Canonical>, ErrorKind> {
+        let programmed = match self.segment(&canonical.kind)? {
+            Some(segment) => segment,
+            None => return Ok(self.command(canonical).into_iter().collect()),
+        };
+
+        let mut held = match self.held.take() {
+            Some(held) => held,
+            None => {
+                self.held = Some(Held {
+                    path: self.entry(&programmed)?,
+                    canonical,
+                    entry: true,
+                });
+
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut path = programmed.offset(self.offset)?;
+
+        let corner = programmed.from();
+        let (end, start) = (held.path.to(), path.from());
+
+        let mut joint = None;
+
+        if (start - end).norm() < CORNER_TOLERANCE {
+            path.set_from(end);
+        } else {
+            let turn = held.path.end_direction().perp(&path.start_direction());
+
+            // Turning away from the side the tool is on leaves it outside the corner
+            let outside = if self.offset > 0.0 {
+                turn <= 0.0
+            } else {
+                turn >= 0.0
+            };
+
+            if outside {
+                joint = Some((end, start));
+            } else {
+                if held.entry {
+                    return Err(ErrorKind::Gouge(
+                        "the entry move makes an inside corner with the next move",
+                    ));
+                }
+
+                let meet = held
+                    .path
+                    .intersections(&path)
+                    .into_iter()
+                    .filter(|point| held.path.contains(*point) && path.contains(*point))
+                    .min_by(|a, b| {
+                        let (a, b) = ((a - corner).norm(), (b - corner).norm());
+
+                        a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                    .ok_or(ErrorKind::Gouge("a move is too short for the tool radius"))?;
+
+                held.path.set_to(meet);
+                path.set_from(meet);
+
+                for trimmed in [&held.path, &path].iter() {
+                    if let Segment::Arc { from, to, .. } = trimmed {
+                        if (to - from).norm() < CORNER_TOLERANCE {
+                            return Err(ErrorKind::Gouge(
+                                "an arc is too short for the tool radius",
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        let line = canonical.line;
+        let feed = match (&canonical.kind, &held.canonical.kind) {
+            (CanonicalKind::Linear { feed, .. }, _)
+            | (CanonicalKind::Arc { feed, .. }, _)
+            | (_, CanonicalKind::Linear { feed, .. })
+            | (_, CanonicalKind::Arc { feed, .. }) => Some(*feed),
+            _ => None,
+        };
+
+        let mut ready = self.release(held);
+
+        if let Some((end, start)) = joint {
+            let from = match &canonical.kind {
+                CanonicalKind::Rapid { from, .. } | CanonicalKind::Linear { from, .. } => *from,
+                CanonicalKind::Arc { arc, .. } => arc.from,
+                _ => unreachable!(),
+            };
+
+            let kind = match feed {
+                Some(feed) => CanonicalKind::Arc {
+                    arc: Arc {
+                        from: self.position(&from, end),
+                        to: self.position(&from, start),
+                        center: self.position(&from, corner),
+                        plane: self.plane.clone(),
+                        clockwise: self.offset > 0.0,
+                        turns: 1,
+                    },
+                    feed,
+                },
+                // Rapids cut straight across the corner
+                None => CanonicalKind::Rapid {
+                    from: self.position(&from, end),
+                    to: self.position(&from, start),
+                },
+            };
+
+            ready.push(Canonical { line, kind });
+        }
+
+        self.held = Some(Held {
+            canonical,
+            path,
+            entry: false,
+        });
+
+        Ok(ready)
+    }
+
+    /// Issue everything still held back, moving the in-plane axes of `position` to where the tool
+    /// ends up
+    pub(crate) fn finish(mut self, position: &mut Vector9) -> Vec<Canonical> {
+        match self.held.take() {
+            Some(held) => {
+                self.set_tool(position, held.path.to());
+
+                self.release(held)
+            }
+            None => std::mem::take(&mut self.queue),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InterpretError;
+    use crate::interpreter::Interpreter;
+    use crate::tool::{Tool, ToolTable};
+    use crate::MM_PER_INCH;
+    use gcode_parser::Program;
+    use std::fs;
+    use std::path::Path;
+
+    const EPSILON: f64 = 1.0e-6;
+
+    fn run(program: &str, tools: &[(u32, f64)]) -> Result<Vec<Canonical>, InterpretError> {
+        let tools = tools
+            .iter()
+            .map(|(number, diameter)| {
+                (
+                    *number,
+                    Tool {
+                        diameter: *diameter,
+                        ..Tool::default()
+                    },
+                )
+            })
+            .collect::<ToolTable>();
+
+        run_with_tools(program, tools)
+    }
+
+    fn run_with_tools(program: &str, tools: ToolTable) -> Result<Vec<Canonical>, InterpretError> {
+        let program = Program::from_str(program).unwrap();
+
+        Interpreter::new(&program).with_tools(tools).collect()
+    }
+
+    fn corpus(name: &str) -> String {
+        fs::read_to_string(Path::new("../test_files/linuxcnc/nc_files").join(name)).unwrap()
+    }
+
+    /// The `XY` end points of every move from the given line onwards
+    fn ends(canonicals: &[Canonical], from_line: usize, scale: f64) -> Vec<(f64, f64)> {
+        canonicals
+            .iter()
+            .filter(|canonical| canonical.line >= from_line)
+            .filter_map(|canonical| canonical.kind.target())
+            .map(|to| (to[0] / scale, to[1] / scale))
+            .collect()
+    }
+
+    fn assert_ends(actual: &[(f64, f64)], expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual.0 - expected.0).abs() < 1.0e-4 && (actual.1 - expected.1).abs() < 1.0e-4,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn outside_corners() {
+        // A square around the origin, cut on the outside with a 2mm tool
+        let result = run(
+            "G0 X-5 Y-15\nG41.1 D2\nG1 X-10 Y-10 F100\nY10\nX10\nY-10\nX-10\nG40\nG0 X-20",
+            &[],
+        )
+        .unwrap();
+
+        let arcs = result
+            .iter()
+            .filter_map(|canonical| match &canonical.kind {
+                CanonicalKind::Arc { arc, .. } => Some(arc),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(arcs.len(), 4);
+
+        for arc in arcs.iter() {
+            assert!(arc.clockwise);
+            assert!((arc.radius() - 1.0).abs() < EPSILON);
+        }
+
+        // The entry move doesn't turn a full corner
+        assert!(arcs[0].sweep() < PI / 2.0);
+
+        for arc in arcs[1..].iter() {
+            assert!((arc.sweep() - PI / 2.0).abs() < EPSILON);
+        }
+
+        assert_ends(
+            &ends(&result, 4, 1.0),
+            &[
+                (-11.0, -10.0),
+                (-11.0, 10.0),
+                (-10.0, 11.0),
+                (10.0, 11.0),
+                (11.0, 10.0),
+                (11.0, -10.0),
+                (10.0, -11.0),
+                (-10.0, -11.0),
+                // Axes left out of the exit move stay where the tool is
+                (-20.0, -11.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn inside_corners() {
+        // The same square cut on the inside meets at the corners without arcs
+        let result = run(
+            "G0 X-10 Y-15\nG42.1 D2\nG1 Y0 F100\nY10\nX10\nY-10\nX-10\nY0\nG40\nG0 X0",
+            &[],
+        )
+        .unwrap();
+
+        // Apart from where the entry move meets the first side
+        assert!(result
+            .iter()
+            .all(|canonical| canonical.line == 4
+                || !matches!(canonical.kind, CanonicalKind::Arc { .. })));
+
+        assert_ends(
+            &ends(&result, 4, 1.0),
+            &[
+                (-9.0, 0.0),
+                (-9.0, 9.0),
+                (9.0, 9.0),
+                (9.0, -9.0),
+                (-9.0, -9.0),
+                (-9.0, 0.0),
+                (0.0, 0.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn plunges_stay_offset() {
+        let result = run(
+            "G0 X-20 Y-10 Z5\nG42.1 D2\nG1 X-10 F100\nZ-1\nY10\nZ5\nG40\nM2",
+            &[],
+        )
+        .unwrap();
+
+        let entry = result
+            .iter()
+            .find(|canonical| canonical.line == 3)
+            .and_then(|canonical| canonical.kind.target())
+            .unwrap();
+
+        let plunges = result
+            .iter()
+            .filter_map(|canonical| match &canonical.kind {
+                CanonicalKind::Linear { from, to, .. } if from[2] != to[2] => Some((from, to)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(plunges.len(), 2);
+
+        // The first plunge waits at the end of the entry move, the second at the end of the side
+        assert_eq!((plunges[0].1[0], plunges[0].1[1]), (entry[0], entry[1]));
+        assert_ends(&[(plunges[1].1[0], plunges[1].1[1])], &[(-9.0, 10.0)]);
+    }
+
+    #[test]
+    fn tool_table_diameter() {
+        let program = "T3 M6\nG0 X-20 Y-10\nG42\nG1 X-10 F100\nY10\nG40\nM2";
+
+        let from_table = run(program, &[(3, 4.0)]).unwrap();
+
+        assert_ends(&ends(&from_table, 5, 1.0)[1..], &[(-8.0, 10.0)]);
+
+        assert_eq!(
+            run(program, &[]).unwrap_err().kind,
+            ErrorKind::UnknownTool(3)
+        );
+
+        let dynamic = run("G20\nG0 X-2 Y-1\nG42.1 D0.5\nG1 X-1 F10\nY1\nG40\nM2", &[]).unwrap();
+
+        assert_ends(&ends(&dynamic, 5, MM_PER_INCH)[1..], &[(-0.75, 1.0)]);
+    }
+
+    #[test]
+    fn gouges() {
+        let errors = [
+            // A notch narrower than the tool
+            "G0 X-10 Y0\nG41.1 D4\nG1 X0 F100\nY-1\nX1\nY0\nX10\nM2",
+            // An inside arc smaller than the tool
+            "G0 X0 Y-10\nG41.1 D4\nG1 Y0 F100\nG3 X-2 Y2 R2\nM2",
+            // An entry move shorter than the tool radius
+            "G0 X0 Y0\nG41.1 D4\nG1 X1 F100\nM2",
+            // An entry move making an inside corner
+            "G0 X0 Y0\nG41.1 D2\nG1 X10 F100\nY10\nM2",
+        ];
+
+        for program in errors.iter() {
+            match run(program, &[]) {
+                Err(InterpretError {
+                    kind: ErrorKind::Gouge(_),
+                    ..
+                }) => (),
+                other => panic!("{}: {:?}", program, other),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_words() {
+        let errors = [
+            "G41.1 D1\nG42.1 D1",
+            "G41.1 D1\nG18",
+            "G19\nG41.1 D1",
+            "G41.1 D1\nG28",
+            "G41.1 D1\nG53 G0 X1",
+            "G41.1 D1\nG3 X1 Y1 I1",
+        ];
+
+        for program in errors.iter() {
+            match run(program, &[]) {
+                Err(InterpretError {
+                    kind: ErrorKind::InvalidWords(_),
+                    ..
+                }) => (),
+                other => panic!("{}: {:?}", program, other),
+            }
+        }
+    }
+
+    #[test]
+    fn comp311() {
+        let result = run(&corpus("comp311.ngc"), &[(4, MM_PER_INCH)]).unwrap();
+
+        // From the tool change onwards
+        assert_ends(
+            &ends(&result, 22, MM_PER_INCH),
+            &[
+                (2.0, 3.0),
+                (1.0, 5.0),
+                (1.0, 5.0),
+                (1.0 + 0.75f64.sqrt() / 2.0, 4.25),
+                (1.5, 4.0),
+                (2.0, 3.5),
+                (3.5, 2.0),
+                (3.5, -1.0),
+                (2.0, -2.5),
+                (-2.0, -2.5),
+                (-2.9, 0.2),
+                (1.1, 3.2),
+                (2.0, 3.5),
+            ],
+        );
+    }
+
+    #[test]
+    fn comp_g1() {
+        let program = corpus("comp-g1.ngc");
+
+        let result = run(&program, &[(4, MM_PER_INCH)]).unwrap();
+
+        assert_ends(
+            &ends(&result, 28, MM_PER_INCH),
+            &[
+                // The entry move is tangent to the arc after it
+                (2.0, 3.5),
+                (3.5, 2.0),
+                (3.5, -1.0),
+                (2.0, -2.5),
+                (-3.0, -2.5),
+                (-3.0 - 0.5 * 4.8 / 6.5115, -2.0 + 0.5 * 4.4 / 6.5115),
+                (1.4 - 0.5 * 4.8 / 6.5115, 2.8 + 0.5 * 4.4 / 6.5115),
+                (1.1, 3.2),
+                (2.0, 3.5),
+                // The exit move
+                (3.0, 3.5),
+            ],
+        );
+
+        // As the comments in the file say, entering from Y4 makes an inside corner
+        let concave = program.replace("G0 X0 Y3.5", "G0 X0 Y4");
+
+        assert_eq!(
+            run(&concave, &[(4, MM_PER_INCH)]).unwrap_err(),
+            InterpretError {
+                line: 29,
+                kind: ErrorKind::Gouge("the entry move makes an inside corner with the next move"),
+            }
+        );
+    }
+
+    #[test]
+    fn comp_in_both_planes() {
+        let result = run(&corpus("comp.ngc"), &[(1, 0.125 * MM_PER_INCH)]).unwrap();
+
+        let moves = |lines: std::ops::Range<usize>, swap: bool| {
+            result
+                .iter()
+                .filter(|canonical| lines.contains(&canonical.line))
+                .filter_map(|canonical| canonical.kind.target())
+                .map(|to| {
+                    let y = if swap { to[2] } else { to[1] };
+
+                    ((to[0] * 1.0e6).round(), (y * 1.0e6).round())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The XZ half of the file is the XY half with Y and Z swapped, apart from a last rapid
+        assert_eq!(moves(2..29, false), moves(31..57, true));
+
+        assert_ends(
+            &ends(&result, 13, MM_PER_INCH)[..7],
+            &[
+                (0.0333, -0.0529),
+                (0.0625, 0.0),
+                (0.0625, 1.0),
+                (-0.25, 1.3125),
+                (-0.5, 1.3125),
+                (-0.6875, 1.5),
+                (-0.6875, 1.75),
+            ],
+        );
+    }
+
+    /// The `(X, Z)` position of the controlled point
+    fn xz(position: &Vector9) -> Point {
+        Point::new(position[0], position[2])
+    }
+
+    #[test]
+    fn lathe_profile() {
+        // A turned profile cut along -Z with the tool on the +X side, using a 0.4mm nose radius
+        // tool whose imaginary tip is towards -X and -Z from the center of its nose
+        let program = "G18 G21 F100
+G0 X5 Z5
+G41.1 D0.8 L2
+G1 X10 Z2
+Z-10
+X20
+Z-20
+X30 Z-30
+G2 X40 Z-40 I10 K0
+G1 Z-50
+G40
+G0 X50
+M2";
+
+        let result = run(program, &[]).unwrap();
+
+        let r = 0.4;
+        let tip = Point::new(-r, -r);
+
+        // The programmed profile in (X, Z) by line, with the tool on the left of it seen with X
+        // across and Z up
+        let profile = [
+            (5, Point::new(10.0, 2.0), Point::new(10.0, -10.0)),
+            (6, Point::new(10.0, -10.0), Point::new(20.0, -10.0)),
+            (7, Point::new(20.0, -10.0), Point::new(20.0, -20.0)),
+            (8, Point::new(20.0, -20.0), Point::new(30.0, -30.0)),
+            (10, Point::new(40.0, -40.0), Point::new(40.0, -50.0)),
+        ];
+
+        let arc_center = Point::new(40.0, -30.0);
+
+        let mut corners = 0;
+
+        for canonical in result.iter().filter(|c| (5..=10).contains(&c.line)) {
+            match &canonical.kind {
+                CanonicalKind::Linear { from, to, .. } => {
+                    let (_, start, end) = profile
+                        .iter()
+                        .find(|(line, _, _)| *line == canonical.line)
+                        .unwrap();
+
+                    let along = (end - start).normalize();
+                    let normal = Point::new(-along.y, along.x);
+
+                    // The nose center is offset from the profile by exactly its radius
+                    for point in [xz(from), xz(to)].iter() {
+                        let center = point - tip;
+
+                        assert!(
+                            ((center - start).dot(&normal) - r).abs() < EPSILON,
+                            "line {}: {:?}",
+                            canonical.line,
+                            point
+                        );
+                    }
+                }
+                CanonicalKind::Arc { arc, .. } if arc.radius() > 1.0 => {
+                    // The profile arc turns away from the tool, which is inside it
+                    assert!((xz(&arc.center) - (arc_center + tip)).norm() < EPSILON);
+                    assert!((arc.radius() - (10.0 - r)).abs() < EPSILON);
+                }
+                CanonicalKind::Arc { arc, .. } => {
+                    // Outside corners swing the nose round the programmed corner
+                    let corner = match profile.iter().find(|(line, _, _)| *line == canonical.line) {
+                        Some((_, corner, _)) => *corner,
+                        None => Point::new(30.0, -30.0),
+                    };
+
+                    assert!((xz(&arc.center) - (corner + tip)).norm() < EPSILON);
+                    assert!((arc.radius() - r).abs() < EPSILON);
+
+                    corners += 1;
+                }
+                _ => (),
+            }
+        }
+
+        // After the entry move, at X20 Z-10, and either side of the profile arc
+        assert_eq!(corners, 4);
+
+        let targets = result
+            .iter()
+            .filter(|c| (5..=10).contains(&c.line))
+            .filter_map(|c| c.kind.target())
+            .map(xz)
+            .collect::<Vec<_>>();
+
+        // Moves along X and Z keep the tip on the programmed line, and it reaches the inside
+        // corner at the shoulder exactly
+        assert!((targets[1] - Point::new(10.0, -10.0)).norm() < EPSILON);
+        assert!((targets[2].y + 10.0).abs() < EPSILON);
+        assert!((targets[4].x - 20.0).abs() < EPSILON);
+
+        assert!((targets.last().unwrap().x - 40.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn lathecomp() {
+        let mut tools = ToolTable::new();
+
+        for number in [2, 7].iter() {
+            tools.insert(
+                *number,
+                Tool {
+                    length: 0.0,
+                    diameter: 0.03 * MM_PER_INCH,
+                    orientation: 2,
+                },
+            );
+        }
+
+        tools.insert(9, Tool::default());
+
+        let result = run_with_tools(&corpus("lathecomp.ngc"), tools).unwrap();
+
+        let moves = |line: usize| {
+            result
+                .iter()
+                .filter(|canonical| canonical.line == line)
+                .filter_map(|canonical| match &canonical.kind {
+                    CanonicalKind::Linear { from, to, .. } => Some((xz(from), xz(to))),
+                    _ => None,
+                })
+                .map(|(from, to)| (from / MM_PER_INCH, to / MM_PER_INCH))
+                .collect::<Vec<_>>()
+        };
+
+        // The straight moves along Z keep the tip on the programmed X, like the uncompensated
+        // pass at the top of the file
+        for (line, x) in [(23, 0.0), (26, -0.5), (38, -0.5), (41, 0.0)].iter() {
+            let moves = moves(*line);
+
+            assert_eq!(moves.len(), 1, "line {}", line);
+            assert!((moves[0].0.x - x).abs() < 1.0e-9, "line {}", line);
+            assert!((moves[0].1.x - x).abs() < 1.0e-9, "line {}", line);
+        }
+    }
+
+    #[test]
+    fn lathe_orientations() {
+        // Orientation 9 controls the center of the nose
+        let center = run("G18\nG0 X5 Z5\nG41.1 D2 L9\nG1 Z0 F100\nX-5\nG40\nM2", &[]).unwrap();
+        let none = run("G18\nG0 X5 Z5\nG41.1 D2\nG1 Z0 F100\nX-5\nG40\nM2", &[]).unwrap();
+
+        assert_eq!(center, none);
+
+        assert_eq!(
+            run("G18\nG41.1 D2 L10\nM2", &[]).unwrap_err(),
+            InterpretError {
+                line: 2,
+                kind: ErrorKind::InvalidWords("lathe tool orientations go from 1 to 9"),
+            }
+        );
+
+        // The tip of a mill tool in the XY plane is its center, whatever its orientation
+        let mill = run("G0 X5 Y5\nG41.1 D2 L2\nG1 Y0 F100\nX-5\nG40\nM2", &[]).unwrap();
+        let plain = run("G0 X5 Y5\nG41.1 D2\nG1 Y0 F100\nX-5\nG40\nM2", &[]).unwrap();
+
+        assert_eq!(mill, plain);
+    }
+}
@@ -0,0 +1,709 @@
+//! Convert programs from one dialect to another by running them
+//!
+//! The program is run through the interpreter and its canonical commands are written back out in
+//! the target dialect, so loops and subroutines are unrolled, parameters and expressions are
+//! replaced by the values they had and canned cycles become the moves they make. Arcs the target
+//! can't express and splines are split into straight moves.
+//!
+//! Positions are written in millimeters in absolute mode, with any work offsets the program sets
+//! already applied, so the converted program runs in whichever work offset is active when it's
+//! started.
+
+use crate::arc::{Arc, ArcTolerance};
+use crate::canonical::{Canonical, CanonicalKind, SpindleDirection};
+use crate::error::InterpretError;
+use crate::interpreter::{is_code, Interpreter};
+use crate::state::{plane_axes, PathMode};
+use crate::Vector9;
+use gcode_parser::token::{MarlinCode, PlaneSelect, Token, TokenType, Unknown};
+use gcode_parser::{Dialect, Program};
+use std::fmt::{self, Write};
+
+/// Axis letters in the order of a `Vector9`
+const AXES: [char; 9] = ['X', 'Y', 'Z', 'U', 'V', 'W', 'A', 'B', 'C'];
+
+/// Arc center offset letters for each of the `XYZ` axes
+const OFFSETS: [char; 3] = ['I', 'J', 'K'];
+
+/// Conversion options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConvertOptions {
+    /// The dialect to write the program in
+    pub dialect: Dialect,
+
+    /// The maximum distance the line segments written in place of an arc or spline may deviate
+    /// from it
+    pub tolerance: f64,
+
+    /// Digits written after the decimal point
+    pub precision: usize,
+}
+
+impl ConvertOptions {
+    /// Write in the given dialect, splitting curves to within a micron and writing positions to
+    /// a tenth of a micron
+    pub fn new(dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            tolerance: 0.001,
+            precision: 4,
+        }
+    }
+}
+
+/// An error encountered whilst converting a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvertError {
+    /// The program could not be interpreted
+    Interpret(InterpretError),
+
+    /// The program does something that can't be written in the target dialect
+    Untranslatable {
+        /// The 1-indexed source line that can't be converted
+        line: usize,
+
+        /// The dialect being written
+        dialect: Dialect,
+
+        /// Why the line can't be converted
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertError::Interpret(error) => write!(f, "{}", error),
+            ConvertError::Untranslatable {
+                line,
+                dialect,
+                reason,
+            } => write!(
+                f,
+                "line {}: can't convert to {}: {}",
+                line,
+                dialect_name(*dialect),
+                reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+impl From<InterpretError> for ConvertError {
+    fn from(error: InterpretError) -> Self {
+        ConvertError::Interpret(error)
+    }
+}
+
+fn dialect_name(dialect: Dialect) -> &'static str {
+    match dialect {
+        Dialect::LinuxCnc => "LinuxCNC",
+        Dialect::Marlin => "Marlin",
+        Dialect::Grbl => "GRBL",
+        Dialect::Fanuc => "Fanuc",
+    }
+}
+
+/// Run a program and write it out again in another dialect
+///
+/// Printer codes for the extruder, heaters and fans aren't run by the interpreter. They are
+/// carried over as they were written when converting to Marlin, with any `E` word staying on the
+/// move it was on. Other dialects have no extruder, so the codes are left out and a comment giving
+/// the line and the codes dropped is written in their place.
+///
+/// GRBL and Marlin can't move the `UVW` and `ABC` axes or make spindle synchronised moves, and
+/// have no path control modes, so those are left out. Tool changes become a pause with a comment
+/// for the operator on GRBL and Marlin, which can't change tools themselves.
+pub fn convert(program: &Program, options: &ConvertOptions) -> Result<String, ConvertError> {
+    // Lines are kept as they run so printer codes can be written in the right place
+    let mut interpreter = Interpreter::new(program).with_flattening();
+    let mut writer = Writer::new(options);
+
+    while let Some(canonical) = interpreter.next() {
+        let canonical = canonical?;
+
+        writer.printer_codes(interpreter.drain_flattened(), Some(canonical.line));
+        writer.write(canonical)?;
+    }
+
+    writer.printer_codes(interpreter.drain_flattened(), None);
+
+    Ok(writer.out)
+}
+
+/// Writes canonical commands as lines of GCode in a dialect
+struct Writer<'a> {
+    options: &'a ConvertOptions,
+    out: String,
+
+    /// The position the last move written ended at
+    position: Vector9,
+
+    /// The last feed rate written
+    feed: Option<String>,
+
+    /// The last plane written
+    plane: PlaneSelect,
+
+    /// The last coolant state written
+    coolant: (bool, bool),
+
+    /// An `E` word to write on the next move
+    extrude: Option<String>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(options: &'a ConvertOptions) -> Self {
+        let mut writer = Self {
+            options,
+            out: String::new(),
+            position: Vector9::zeros(),
+            feed: None,
+            plane: PlaneSelect::XY,
+            coolant: (false, false),
+            extrude: None,
+        };
+
+        match options.dialect {
+            // Marlin is always in units per minute and has no plane until an arc needs one
+            Dialect::Marlin => writer.line("G21 G90"),
+            _ => writer.line("G21 G90 G94 G17"),
+        }
+
+        writer
+    }
+
+    fn line(&mut self, line: &str) {
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn comment(&mut self, text: &str) {
+        match self.options.dialect {
+            Dialect::Marlin => self.out.push_str(&format!("; {}\n", text)),
+            _ => self.out.push_str(&format!("({})\n", text)),
+        }
+    }
+
+    fn number(&self, value: f64) -> String {
+        let mut number = format!("{:.*}", self.options.precision, value);
+
+        if number.contains('.') {
+            let trimmed = number.trim_end_matches('0').trim_end_matches('.').len();
+
+            number.truncate(trimmed);
+        }
+
+        if number == "-0" {
+            number.remove(0);
+        }
+
+        number
+    }
+
+    fn untranslatable(&self, line: usize, reason: &'static str) -> ConvertError {
+        ConvertError::Untranslatable {
+            line,
+            dialect: self.options.dialect,
+            reason,
+        }
+    }
+
+    /// The dialect can move all nine axes, not just `XYZ`
+    fn all_axes(&self) -> bool {
+        matches!(self.options.dialect, Dialect::LinuxCnc | Dialect::Fanuc)
+    }
+
+    /// Write the words for the axes that move on the way to a position
+    fn axes(
+        &mut self,
+        line: usize,
+        to: &Vector9,
+        always: &[usize],
+    ) -> Result<String, ConvertError> {
+        let mut words = String::new();
+
+        for (axis, letter) in AXES.iter().enumerate() {
+            let moved = self.number(to[axis]) != self.number(self.position[axis]);
+
+            if !moved && !always.contains(&axis) {
+                continue;
+            }
+
+            if axis >= 3 && !self.all_axes() {
+                return Err(self.untranslatable(line, "only the X, Y and Z axes can move"));
+            }
+
+            write!(words, " {}{}", letter, self.number(to[axis])).unwrap();
+        }
+
+        self.position = *to;
+
+        Ok(words)
+    }
+
+    fn feed_word(&mut self, feed: f64) -> String {
+        let feed = self.number(feed);
+
+        if self.feed.as_ref() == Some(&feed) {
+            return String::new();
+        }
+
+        let word = format!(" F{}", feed);
+
+        self.feed = Some(feed);
+
+        word
+    }
+
+    /// The `E` word waiting for the next move, if any
+    fn extrude_word(&mut self) -> String {
+        self.extrude.take().unwrap_or_default()
+    }
+
+    /// Write an `E` word that wasn't written on a move as a move of its own
+    fn extrude_alone(&mut self, feed: Option<f64>) {
+        if let Some(extrude) = self.extrude.take() {
+            let feed = feed.map(|feed| self.feed_word(feed)).unwrap_or_default();
+
+            self.line(&format!("G1{}{}", feed, extrude));
+        }
+    }
+
+    /// Write the printer codes of lines that have run, keeping any `E` word on `moving`, the
+    /// line whose moves are about to be written, for the first of them
+    fn printer_codes(&mut self, lines: Vec<(usize, Vec<Token>)>, moving: Option<usize>) {
+        let last = lines.len();
+
+        for (idx, (line, tokens)) in lines.into_iter().enumerate() {
+            self.extrude_alone(None);
+
+            // Homing is run by the interpreter like any other move
+            let codes: Vec<&MarlinCode> = tokens
+                .iter()
+                .filter_map(|token| match &token.token {
+                    TokenType::Marlin(MarlinCode::Home(_)) => None,
+                    TokenType::Marlin(code) => Some(code),
+                    _ => None,
+                })
+                .collect();
+
+            if codes.is_empty() {
+                continue;
+            }
+
+            if self.options.dialect != Dialect::Marlin {
+                let dropped: Vec<String> = codes.iter().map(|code| code.to_string()).collect();
+
+                self.comment(&format!("line {}: dropped {}", line, dropped.join(" ")));
+
+                continue;
+            }
+
+            let mut reset = false;
+            let mut feed = None;
+
+            for token in tokens.iter() {
+                match &token.token {
+                    TokenType::Unknown(Unknown {
+                        code_letter: 'G' | 'g',
+                        code_number,
+                    }) => reset |= is_code(code_number.as_f64_unchecked(), 92.0),
+                    TokenType::Feedrate(feedrate) => {
+                        feed = Some(feedrate.feedrate.as_f64_unchecked())
+                    }
+                    _ => (),
+                }
+            }
+
+            for code in codes {
+                match code {
+                    MarlinCode::Extrude(_) if reset => self.line(&format!("G92 {}", code)),
+                    MarlinCode::Extrude(_) => self.extrude = Some(format!(" {}", code)),
+                    code => self.line(&code.to_string()),
+                }
+            }
+
+            if !(idx + 1 == last && moving == Some(line)) {
+                self.extrude_alone(feed);
+            }
+        }
+
+        if moving.is_none() {
+            self.extrude_alone(None);
+        }
+    }
+
+    fn linear(&mut self, line: usize, to: &Vector9, feed: f64) -> Result<(), ConvertError> {
+        let axes = self.axes(line, to, &[])?;
+        let feed = self.feed_word(feed);
+        let extrude = self.extrude_word();
+
+        if !axes.is_empty() || !extrude.is_empty() {
+            self.line(&format!("G1{}{}{}", axes, feed, extrude));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the dialect can write an arc as `G2` or `G3`
+    fn native_arc(&self, arc: &Arc) -> bool {
+        match self.options.dialect {
+            Dialect::LinuxCnc => matches!(
+                arc.plane,
+                PlaneSelect::XY | PlaneSelect::ZX | PlaneSelect::YZ
+            ),
+            Dialect::Grbl | Dialect::Fanuc => {
+                arc.turns == 1
+                    && matches!(
+                        arc.plane,
+                        PlaneSelect::XY | PlaneSelect::ZX | PlaneSelect::YZ
+                    )
+            }
+            Dialect::Marlin => arc.turns == 1 && arc.plane == PlaneSelect::XY,
+        }
+    }
+
+    fn arc(&mut self, line: usize, arc: &Arc, feed: f64) -> Result<(), ConvertError> {
+        if !self.native_arc(arc) {
+            let tolerance = ArcTolerance::new(self.options.tolerance);
+
+            for to in arc.linearise(&tolerance) {
+                self.linear(line, &to, feed)?;
+            }
+
+            return Ok(());
+        }
+
+        if arc.plane != self.plane {
+            self.plane = arc.plane.clone();
+
+            self.line(&arc.plane.to_string());
+        }
+
+        let (first, second, _) = plane_axes(&arc.plane);
+        let code = if arc.clockwise { "G2" } else { "G3" };
+        let axes = self.axes(line, &arc.to, &[first, second])?;
+
+        let mut offsets = String::new();
+
+        for axis in [first, second].iter() {
+            let offset = arc.center[*axis] - arc.from[*axis];
+
+            write!(offsets, " {}{}", OFFSETS[*axis], self.number(offset)).unwrap();
+        }
+
+        if arc.turns > 1 {
+            write!(offsets, " P{}", arc.turns).unwrap();
+        }
+
+        let feed = self.feed_word(feed);
+        let extrude = self.extrude_word();
+
+        self.line(&format!("{}{}{}{}{}", code, axes, offsets, feed, extrude));
+
+        Ok(())
+    }
+
+    fn dwell(&mut self, seconds: f64) {
+        let dwell = match self.options.dialect {
+            Dialect::LinuxCnc | Dialect::Grbl => format!("G4 P{}", self.number(seconds)),
+            Dialect::Marlin => format!("G4 P{}", (seconds * 1000.0).round()),
+            Dialect::Fanuc => format!("G4 X{}", self.number(seconds)),
+        };
+
+        self.line(&dwell);
+    }
+
+    fn coolant(&mut self, mist: bool, flood: bool) {
+        let (mut was_mist, mut was_flood) = self.coolant;
+
+        // M9 is the only way to turn either off, so the other is turned back on after it
+        if (was_mist && !mist) || (was_flood && !flood) {
+            self.line("M9");
+
+            was_mist = false;
+            was_flood = false;
+        }
+
+        if mist && !was_mist {
+            self.line("M7");
+        }
+
+        if flood && !was_flood {
+            self.line("M8");
+        }
+
+        self.coolant = (mist, flood);
+    }
+
+    fn write(&mut self, canonical: Canonical) -> Result<(), ConvertError> {
+        let Canonical { line, kind } = canonical;
+        let dialect = self.options.dialect;
+
+        match kind {
+            CanonicalKind::Rapid { to, .. } => {
+                let axes = self.axes(line, &to, &[])?;
+                let extrude = self.extrude_word();
+
+                if !axes.is_empty() || !extrude.is_empty() {
+                    self.line(&format!("G0{}{}", axes, extrude));
+                }
+            }
+            CanonicalKind::Linear { to, feed, .. } => self.linear(line, &to, feed)?,
+            CanonicalKind::Arc { arc, feed } => self.arc(line, &arc, feed)?,
+            CanonicalKind::Spline { spline, feed } => {
+                for to in spline.linearise(self.options.tolerance) {
+                    self.linear(line, &to, feed)?;
+                }
+            }
+            CanonicalKind::SpindleSynchronized {
+                from,
+                to,
+                pitch,
+                rigid_tap,
+                ..
+            } => {
+                if dialect != Dialect::LinuxCnc {
+                    return Err(
+                        self.untranslatable(line, "there is no spindle synchronised motion")
+                    );
+                }
+
+                let code = if rigid_tap { "G33.1" } else { "G33" };
+                let axes = self.axes(line, &to, &[])?;
+                let pitch = self.number(pitch);
+
+                self.line(&format!("{}{} K{}", code, axes, pitch));
+
+                // Rigid tapping ends back where it started
+                if rigid_tap {
+                    self.position = from;
+                }
+            }
+            CanonicalKind::Dwell { seconds } => self.dwell(seconds),
+            CanonicalKind::ToolChange { tool } => match dialect {
+                Dialect::LinuxCnc | Dialect::Fanuc => self.line(&format!("T{} M6", tool)),
+                Dialect::Grbl | Dialect::Marlin => {
+                    self.comment(&format!("Change to tool {}", tool));
+                    self.line("M0");
+                }
+            },
+            CanonicalKind::Spindle { direction, speed } => {
+                let spindle = match direction {
+                    SpindleDirection::Clockwise => format!("M3 S{}", self.number(speed)),
+                    SpindleDirection::Counterclockwise => format!("M4 S{}", self.number(speed)),
+                    SpindleDirection::Stopped => "M5".to_string(),
+                };
+
+                self.line(&spindle);
+            }
+            CanonicalKind::Coolant { mist, flood } => self.coolant(mist, flood),
+            CanonicalKind::Pause { optional } => self.line(if optional { "M1" } else { "M0" }),
+            CanonicalKind::PathMode { mode } => {
+                // Only LinuxCNC can choose how moves are blended
+                if dialect == Dialect::LinuxCnc {
+                    let code = match mode {
+                        PathMode::ExactPath => "G61".to_string(),
+                        PathMode::ExactStop => "G61.1".to_string(),
+                        PathMode::Blend {
+                            tolerance,
+                            naive_tolerance,
+                        } => {
+                            let mut code = "G64".to_string();
+
+                            if let Some(tolerance) = tolerance {
+                                write!(code, " P{}", self.number(tolerance)).unwrap();
+                            }
+
+                            if let Some(tolerance) = naive_tolerance {
+                                write!(code, " Q{}", self.number(tolerance)).unwrap();
+                            }
+
+                            code
+                        }
+                    };
+
+                    self.line(&code);
+                }
+            }
+            // Marlin has no end of program
+            CanonicalKind::End if dialect == Dialect::Marlin => (),
+            CanonicalKind::End => self.line("M2"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to(program: &str, dialect: Dialect) -> Result<String, ConvertError> {
+        let program = Program::from_str(program).unwrap();
+
+        convert(&program, &ConvertOptions::new(dialect))
+    }
+
+    #[test]
+    fn linuxcnc_to_grbl() {
+        let converted = to(
+            "#<x> = 0\n\
+             o100 repeat [2]\n\
+             #<x> = [#<x> + 10]\n\
+             G1 X#<x> F[50 * 2]\n\
+             o100 endrepeat\n\
+             M8\n\
+             G81 X5 Y5 Z-1 R1\n\
+             G80\n\
+             M2",
+            Dialect::Grbl,
+        );
+
+        assert_eq!(
+            converted,
+            Ok("G21 G90 G94 G17\n\
+                G1 X10 F100\n\
+                G1 X20\n\
+                M8\n\
+                G0 Z1\n\
+                G0 X5 Y5\n\
+                G1 Z-1\n\
+                G0 Z1\n\
+                M2\n"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn linuxcnc_to_marlin() {
+        let converted = to(
+            "T2 M6\nG0 X10\nG3 X20 I5 F600\nG4 P1.5\nG18 G2 X30 Z0 I5\nM2",
+            Dialect::Marlin,
+        )
+        .unwrap();
+
+        assert!(converted.starts_with(
+            "G21 G90\n\
+             ; Change to tool 2\n\
+             M0\n\
+             G0 X10\n\
+             G3 X20 Y0 I5 J0 F600\n\
+             G4 P1500\n\
+             G1 X"
+        ));
+
+        // Marlin only has arcs in the XY plane, so the G18 arc is split into straight moves
+        assert!(!converted.contains("G18"));
+        assert!(converted.ends_with("G1 X30 Z0\n"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let grbl = Program::from_str_dialect(
+            "$H\nG0 X10 Y10\nG1 X20 F500\nM3 S1000\nG2 X30 Y20 J10\nM5\nM2",
+            Dialect::Grbl,
+        )
+        .unwrap();
+
+        let linuxcnc = convert(&grbl, &ConvertOptions::new(Dialect::LinuxCnc)).unwrap();
+        let linuxcnc = Program::from_str(&linuxcnc).unwrap();
+
+        assert_eq!(
+            convert(&linuxcnc, &ConvertOptions::new(Dialect::Grbl)),
+            convert(&grbl, &ConvertOptions::new(Dialect::Grbl))
+        );
+    }
+
+    #[test]
+    fn untranslatable() {
+        assert_eq!(
+            to("S100 M3\nG33 Z-10 K1", Dialect::Grbl),
+            Err(ConvertError::Untranslatable {
+                line: 2,
+                dialect: Dialect::Grbl,
+                reason: "there is no spindle synchronised motion",
+            })
+        );
+        assert_eq!(
+            to("G0 X1\nG0 A90", Dialect::Marlin)
+                .unwrap_err()
+                .to_string(),
+            "line 2: can't convert to Marlin: only the X, Y and Z axes can move"
+        );
+        assert!(to("S100 M3\nG33 Z-10 K1\nG0 A90", Dialect::LinuxCnc).is_ok());
+    }
+
+    const PRINT: &str = "M82 ;absolute extrusion mode\n\
+                         G28\n\
+                         M104 S200\n\
+                         M109 S200\n\
+                         G92 E0\n\
+                         G1 Z0.3 F3000\n\
+                         G1 X20 Y20 E1.5 F1200\n\
+                         G1 X40 E3\n\
+                         G1 E1 F2400\n\
+                         M106 S255\n\
+                         G2 X40 Y0 J-10 E5.5 F1200\n\
+                         M107\n\
+                         M104 S0";
+
+    #[test]
+    fn marlin_round_trip() {
+        let print = Program::from_str_dialect(PRINT, Dialect::Marlin).unwrap();
+        let options = ConvertOptions::new(Dialect::Marlin);
+
+        let converted = convert(&print, &options).unwrap();
+
+        assert_eq!(
+            converted,
+            "G21 G90\n\
+             M82\n\
+             M104 S200\n\
+             M109 S200\n\
+             G92 E0\n\
+             G1 Z0.3 F3000\n\
+             G1 X20 Y20 F1200 E1.5\n\
+             G1 X40 E3\n\
+             G1 F2400 E1\n\
+             M106 S255\n\
+             G2 X40 Y0 I0 J-10 F1200 E5.5\n\
+             M107\n\
+             M104 S0\n"
+        );
+
+        let again = Program::from_str_dialect(&converted, Dialect::Marlin).unwrap();
+
+        assert_eq!(convert(&again, &options), Ok(converted));
+    }
+
+    #[test]
+    fn printer_codes_dropped() {
+        let print = Program::from_str_dialect(PRINT, Dialect::Marlin).unwrap();
+
+        let converted = convert(&print, &ConvertOptions::new(Dialect::Grbl)).unwrap();
+
+        assert_eq!(
+            converted,
+            "G21 G90 G94 G17\n\
+             (line 1: dropped M82)\n\
+             (line 3: dropped M104 S200)\n\
+             (line 4: dropped M109 S200)\n\
+             (line 5: dropped E0)\n\
+             G1 Z0.3 F3000\n\
+             (line 7: dropped E1.5)\n\
+             G1 X20 Y20 F1200\n\
+             (line 8: dropped E3)\n\
+             G1 X40\n\
+             (line 9: dropped E1)\n\
+             (line 10: dropped M106 S255)\n\
+             (line 11: dropped E5.5)\n\
+             G2 X40 Y0 I0 J-10\n\
+             (line 12: dropped M107)\n\
+             (line 13: dropped M104 S0)\n"
+        );
+    }
+}
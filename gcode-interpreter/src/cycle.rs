@@ -0,0 +1,184 @@
+//! Drilling and boring canned cycles, broken down into moves along the Z axis
+
+use crate::state::CannedCycle;
+
+/// How far `G73` backs off after each peck to break the chip, and how far above the bottom of
+/// the hole `G83` stops rapiding back down, in millimeters (0.010 inches, like LinuxCNC)
+const PECK_CLEARANCE: f64 = 0.254;
+
+/// The words of a canned cycle that carry over to the lines after it, in program units
+#[derive(Debug, Default)]
+pub(crate) struct CycleWords {
+    /// The `R` plane
+    pub(crate) r: Option<f64>,
+
+    /// The bottom of the hole
+    pub(crate) z: Option<f64>,
+
+    /// Peck depth
+    pub(crate) q: Option<f64>,
+
+    /// Dwell time in seconds
+    pub(crate) p: Option<f64>,
+
+    /// The Z position in machine coordinates before the current run of cycles started
+    pub(crate) old_z: f64,
+}
+
+/// A move along the Z axis to a position in machine coordinates, or a pause, made while drilling
+/// a hole
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Step {
+    Rapid(f64),
+    Feed(f64),
+    Dwell(f64),
+}
+
+/// Where one hole is drilled along the Z axis, in machine coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Hole {
+    /// The `R` plane, where drilling starts
+    pub(crate) r: f64,
+
+    /// The bottom of the hole
+    pub(crate) bottom: f64,
+
+    /// Where to retract to once the hole is finished
+    pub(crate) clear: f64,
+
+    /// How deep to drill in each peck
+    pub(crate) peck: f64,
+
+    /// How long to dwell at the bottom of the hole in seconds
+    pub(crate) dwell: f64,
+}
+
+impl CannedCycle {
+    /// Whether the cycle drills in pecks of `Q`
+    pub(crate) fn pecks(self) -> bool {
+        matches!(self, CannedCycle::ChipBreak | CannedCycle::PeckDrill)
+    }
+
+    /// The moves that drill a hole, starting from the `R` plane above it
+    pub(crate) fn steps(self, hole: &Hole) -> Vec<Step> {
+        let mut steps = Vec::new();
+
+        match self {
+            CannedCycle::Drill => steps.push(Step::Feed(hole.bottom)),
+            CannedCycle::DrillDwell => {
+                steps.push(Step::Feed(hole.bottom));
+                steps.push(Step::Dwell(hole.dwell));
+            }
+            CannedCycle::ChipBreak => {
+                let mut depth = hole.r;
+
+                while depth > hole.bottom {
+                    if depth < hole.r {
+                        steps.push(Step::Rapid(depth + PECK_CLEARANCE));
+                    }
+
+                    depth = (depth - hole.peck).max(hole.bottom);
+
+                    steps.push(Step::Feed(depth));
+                }
+            }
+            CannedCycle::PeckDrill => {
+                let mut depth = hole.r;
+
+                while depth > hole.bottom {
+                    if depth < hole.r {
+                        steps.push(Step::Rapid(hole.r));
+                        steps.push(Step::Rapid(depth + PECK_CLEARANCE));
+                    }
+
+                    depth = (depth - hole.peck).max(hole.bottom);
+
+                    steps.push(Step::Feed(depth));
+                }
+            }
+            CannedCycle::Bore => {
+                steps.push(Step::Feed(hole.bottom));
+                steps.push(Step::Feed(hole.r));
+            }
+            CannedCycle::BoreDwell => {
+                steps.push(Step::Feed(hole.bottom));
+                steps.push(Step::Dwell(hole.dwell));
+                steps.push(Step::Feed(hole.r));
+            }
+        }
+
+        // Boring cycles are already back at the R plane
+        if steps.last() != Some(&Step::Feed(hole.clear)) {
+            steps.push(Step::Rapid(hole.clear));
+        }
+
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLE: Hole = Hole {
+        r: 2.0,
+        bottom: -1.0,
+        clear: 5.0,
+        peck: 1.5,
+        dwell: 0.5,
+    };
+
+    #[test]
+    fn drill() {
+        assert_eq!(
+            CannedCycle::DrillDwell.steps(&HOLE),
+            vec![Step::Feed(-1.0), Step::Dwell(0.5), Step::Rapid(5.0)]
+        );
+    }
+
+    #[test]
+    fn peck_drill() {
+        assert_eq!(
+            CannedCycle::PeckDrill.steps(&HOLE),
+            vec![
+                Step::Feed(0.5),
+                Step::Rapid(2.0),
+                Step::Rapid(0.5 + PECK_CLEARANCE),
+                Step::Feed(-1.0),
+                Step::Rapid(5.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn chip_break() {
+        assert_eq!(
+            CannedCycle::ChipBreak.steps(&HOLE),
+            vec![
+                Step::Feed(0.5),
+                Step::Rapid(0.5 + PECK_CLEARANCE),
+                Step::Feed(-1.0),
+                Step::Rapid(5.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn bore_to_r_plane() {
+        let hole = Hole { clear: 2.0, ..HOLE };
+
+        assert_eq!(
+            CannedCycle::Bore.steps(&hole),
+            vec![Step::Feed(-1.0), Step::Feed(2.0)]
+        );
+        assert_eq!(
+            CannedCycle::BoreDwell.steps(&HOLE),
+            vec![
+                Step::Feed(-1.0),
+                Step::Dwell(0.5),
+                Step::Feed(2.0),
+                Step::Rapid(5.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use expression::Parameter;
+use std::fmt;
+
+/// The reason interpretation failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// A named parameter was read before being assigned a value
+    UndefinedParameter(Parameter),
+
+    /// An expression could not be evaluated
+    InvalidExpression,
+
+    /// A value that must be a non-negative integer was negative or not finite
+    InvalidUnsignedValue(f64),
+
+    /// A subroutine was called that is not defined in this program
+    UnknownSubroutine(String),
+
+    /// A return statement was found outside a subroutine
+    ReturnOutsideSubroutine,
+
+    /// A `GOTO` jumped to a sequence number that isn't in the program or subroutine being run
+    UnknownSequenceNumber(u32),
+
+    /// An arc could not be constructed from the given words
+    InvalidArc(&'static str),
+
+    /// A tool was referenced that is not present in the tool table
+    UnknownTool(u32),
+
+    /// A combination of words that cannot appear on the same line
+    InvalidWords(&'static str),
+
+    /// Cutter compensation can't follow the path without cutting into the part
+    Gouge(&'static str),
+
+    /// The message handler couldn't act on an active comment
+    Message(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UndefinedParameter(param) => write!(f, "parameter {} is not defined", param),
+            ErrorKind::InvalidExpression => write!(f, "expression could not be evaluated"),
+            ErrorKind::InvalidUnsignedValue(value) => {
+                write!(f, "expected a positive integer, got {}", value)
+            }
+            ErrorKind::UnknownSubroutine(ident) => write!(f, "unknown subroutine o{}", ident),
+            ErrorKind::ReturnOutsideSubroutine => write!(f, "return outside of a subroutine"),
+            ErrorKind::UnknownSequenceNumber(number) => {
+                write!(f, "no line numbered N{} to jump to", number)
+            }
+            ErrorKind::InvalidArc(reason) => write!(f, "invalid arc: {}", reason),
+            ErrorKind::UnknownTool(tool) => write!(f, "tool {} is not in the tool table", tool),
+            ErrorKind::InvalidWords(reason) => write!(f, "{}", reason),
+            ErrorKind::Gouge(reason) => write!(f, "cutter compensation would gouge: {}", reason),
+            ErrorKind::Message(reason) => write!(f, "message failed: {}", reason),
+        }
+    }
+}
+
+/// An error encountered whilst interpreting a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpretError {
+    /// The 1-indexed source line the error occurred on
+    pub line: usize,
+
+    /// What went wrong
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for InterpretError {}
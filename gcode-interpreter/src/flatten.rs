@@ -0,0 +1,332 @@
+//! Flatten programs into straight-line GCode by running them
+//!
+//! Unlike [`convert`](fn.convert.html), which writes out the moves a program makes, flattening
+//! keeps each line as it was written but with every parameter and expression replaced by the
+//! value it had when the line ran. Loops and subroutines are unrolled into the lines they run, so
+//! the result can be sent to controllers that only understand plain GCode. Work offsets, canned
+//! cycles and modal state are left for the controller to deal with as before.
+
+use crate::error::{ErrorKind, InterpretError};
+use crate::interpreter::Interpreter;
+use crate::parameters::Parameters;
+use gcode_parser::token::{
+    CenterFormatArc, Comment, CommentKind, Coord, CutterCompensation, Dwell, Fan, Feedrate, GCode,
+    MarlinCode, MessagePart, PathControl, PolarCoord, RadiusFormatArc, SpindleSpeed, Spline,
+    Temperature, Token, TokenType, ToolNumber, Unknown, UnsignedValue, Value,
+};
+use gcode_parser::{Line, Program};
+
+/// Flattening options
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FlattenOptions {
+    /// End each line with a comment like `(line 12)` giving the source line it came from
+    pub source_lines: bool,
+}
+
+/// Run a program and write out every line it runs, in the order it runs them
+///
+/// Parameter assignments, blocks, calls, returns and `GOTO`s are left out once they've been run,
+/// along with line numbers, which no longer name a single line once loops are unrolled. `DEBUG`,
+/// `PRINT` and `LOG` comments have their parameters substituted. Lines skipped by block delete
+/// and lines left empty aren't written.
+pub fn flatten(program: &Program, options: &FlattenOptions) -> Result<Program, InterpretError> {
+    let mut interpreter = Interpreter::new(program).with_flattening();
+
+    for canonical in &mut interpreter {
+        canonical?;
+    }
+
+    let lines = interpreter
+        .take_flattened()
+        .into_iter()
+        .filter(|(_, tokens)| !tokens.is_empty())
+        .map(|(number, mut tokens)| {
+            if options.source_lines {
+                tokens.push(Token {
+                    token: TokenType::Comment(Comment {
+                        text: format!("line {}", number),
+                        kind: CommentKind::Plain,
+                    }),
+                });
+            }
+
+            Line::new(tokens)
+        })
+        .collect();
+
+    Ok(Program::from_lines(lines))
+}
+
+/// The tokens of a line with every value worked out using the parameters at the start of the
+/// line, leaving out control flow
+pub(crate) fn flatten_line(
+    line: &Line,
+    parameters: &mut Parameters,
+) -> Result<Vec<Token>, ErrorKind> {
+    let mut tokens = Vec::new();
+
+    for token in line.iter() {
+        let token = match &token.token {
+            TokenType::Assignment(_)
+            | TokenType::Block(_)
+            | TokenType::Call(_)
+            | TokenType::Return(_)
+            | TokenType::Goto(_)
+            | TokenType::LineNumber(_) => continue,
+            TokenType::GCode(code) => TokenType::GCode(gcode(code, parameters)?),
+            TokenType::Coord(coord) => TokenType::Coord(Coord {
+                x: optional(&coord.x, parameters)?,
+                y: optional(&coord.y, parameters)?,
+                z: optional(&coord.z, parameters)?,
+                a: optional(&coord.a, parameters)?,
+                b: optional(&coord.b, parameters)?,
+                c: optional(&coord.c, parameters)?,
+                u: optional(&coord.u, parameters)?,
+                v: optional(&coord.v, parameters)?,
+                w: optional(&coord.w, parameters)?,
+            }),
+            TokenType::PolarCoord(polar) => TokenType::PolarCoord(PolarCoord {
+                distance: optional(&polar.distance, parameters)?,
+                angle: optional(&polar.angle, parameters)?,
+            }),
+            TokenType::CenterFormatArc(arc) => TokenType::CenterFormatArc(CenterFormatArc {
+                x: optional(&arc.x, parameters)?,
+                y: optional(&arc.y, parameters)?,
+                z: optional(&arc.z, parameters)?,
+                i: optional(&arc.i, parameters)?,
+                j: optional(&arc.j, parameters)?,
+                k: optional(&arc.k, parameters)?,
+                turns: unsigned(&arc.turns, parameters)?,
+            }),
+            TokenType::RadiusFormatArc(arc) => TokenType::RadiusFormatArc(RadiusFormatArc {
+                x: optional(&arc.x, parameters)?,
+                y: optional(&arc.y, parameters)?,
+                z: optional(&arc.z, parameters)?,
+                radius: value(&arc.radius, parameters)?,
+                turns: unsigned(&arc.turns, parameters)?,
+            }),
+            TokenType::Spline(spline) => TokenType::Spline(Spline {
+                x: optional(&spline.x, parameters)?,
+                y: optional(&spline.y, parameters)?,
+                i: optional(&spline.i, parameters)?,
+                j: optional(&spline.j, parameters)?,
+                p: optional(&spline.p, parameters)?,
+                q: optional(&spline.q, parameters)?,
+                order: match &spline.order {
+                    Some(order) => Some(unsigned(order, parameters)?),
+                    None => None,
+                },
+            }),
+            TokenType::Feedrate(feed) => TokenType::Feedrate(Feedrate {
+                feedrate: value(&feed.feedrate, parameters)?,
+            }),
+            TokenType::SpindleSpeed(speed) => TokenType::SpindleSpeed(SpindleSpeed {
+                rpm: value(&speed.rpm, parameters)?,
+            }),
+            TokenType::ToolNumber(tool) => TokenType::ToolNumber(ToolNumber {
+                tool_number: unsigned(&tool.tool_number, parameters)?,
+            }),
+            TokenType::Unknown(unknown) => TokenType::Unknown(Unknown {
+                code_letter: unknown.code_letter,
+                code_number: value(&unknown.code_number, parameters)?,
+            }),
+            TokenType::Comment(comment) => TokenType::Comment(self::comment(comment, parameters)?),
+            TokenType::Marlin(code) => TokenType::Marlin(marlin(code, parameters)?),
+            // GRBL has no parameters, and the remaining tokens have no values
+            other => other.clone(),
+        };
+
+        tokens.push(Token { token });
+    }
+
+    Ok(tokens)
+}
+
+fn value(value: &Value, parameters: &mut Parameters) -> Result<Value, ErrorKind> {
+    parameters
+        .value(value)
+        .map(|value| Value::Literal(value as f32))
+}
+
+fn optional(
+    value: &Option<Value>,
+    parameters: &mut Parameters,
+) -> Result<Option<Value>, ErrorKind> {
+    match value {
+        Some(value) => self::value(value, parameters).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn unsigned(
+    value: &UnsignedValue,
+    parameters: &mut Parameters,
+) -> Result<UnsignedValue, ErrorKind> {
+    parameters.unsigned(value).map(UnsignedValue::Literal)
+}
+
+fn gcode(code: &GCode, parameters: &mut Parameters) -> Result<GCode, ErrorKind> {
+    Ok(match code {
+        GCode::Dwell(dwell) => GCode::Dwell(Dwell {
+            time: value(&dwell.time, parameters)?,
+        }),
+        GCode::CutterCompensation(compensation) => GCode::CutterCompensation(match compensation {
+            CutterCompensation::Off => CutterCompensation::Off,
+            CutterCompensation::Left(tool) => CutterCompensation::Left(optional(tool, parameters)?),
+            CutterCompensation::Right(tool) => {
+                CutterCompensation::Right(optional(tool, parameters)?)
+            }
+            CutterCompensation::DynamicLeft(diameter) => {
+                CutterCompensation::DynamicLeft(value(diameter, parameters)?)
+            }
+            CutterCompensation::DynamicRight(diameter) => {
+                CutterCompensation::DynamicRight(value(diameter, parameters)?)
+            }
+        }),
+        GCode::PathControl(PathControl::Blend {
+            tolerance,
+            naive_tolerance,
+        }) => GCode::PathControl(PathControl::Blend {
+            tolerance: optional(tolerance, parameters)?,
+            naive_tolerance: optional(naive_tolerance, parameters)?,
+        }),
+        other => other.clone(),
+    })
+}
+
+/// Substitute the parameters in a message, leaving a comment that reads the same when parsed again
+fn comment(comment: &Comment, parameters: &mut Parameters) -> Result<Comment, ErrorKind> {
+    let (keyword, parts) = match &comment.kind {
+        CommentKind::Debug(parts) => ("DEBUG", parts),
+        CommentKind::Print(parts) => ("PRINT", parts),
+        CommentKind::Log(parts) => ("LOG", parts),
+        _ => return Ok(comment.clone()),
+    };
+
+    let message = parameters.substitute(parts)?;
+    let text = format!("{}, {}", keyword, message);
+    let parts = vec![MessagePart::Text(message)];
+
+    Ok(Comment {
+        text,
+        kind: match comment.kind {
+            CommentKind::Debug(_) => CommentKind::Debug(parts),
+            CommentKind::Print(_) => CommentKind::Print(parts),
+            _ => CommentKind::Log(parts),
+        },
+    })
+}
+
+fn marlin(code: &MarlinCode, parameters: &mut Parameters) -> Result<MarlinCode, ErrorKind> {
+    let index = |index: &Option<UnsignedValue>, parameters: &mut Parameters| match index {
+        Some(index) => unsigned(index, parameters).map(Some),
+        None => Ok(None),
+    };
+
+    Ok(match code {
+        MarlinCode::Extrude(length) => MarlinCode::Extrude(value(length, parameters)?),
+        MarlinCode::Temperature(temperature) => MarlinCode::Temperature(Temperature {
+            heater: temperature.heater,
+            wait: temperature.wait,
+            temperature: optional(&temperature.temperature, parameters)?,
+            exact_temperature: optional(&temperature.exact_temperature, parameters)?,
+            tool: index(&temperature.tool, parameters)?,
+        }),
+        MarlinCode::FanOn(fan) => MarlinCode::FanOn(Fan {
+            index: index(&fan.index, parameters)?,
+            speed: optional(&fan.speed, parameters)?,
+        }),
+        MarlinCode::FanOff(fan) => MarlinCode::FanOff(Fan {
+            index: index(&fan.index, parameters)?,
+            speed: optional(&fan.speed, parameters)?,
+        }),
+        other => other.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcode_parser::Dialect;
+
+    fn flat(program: &str, dialect: Dialect, source_lines: bool) -> String {
+        let program = Program::from_str_dialect(program, dialect).unwrap();
+
+        flatten(&program, &FlattenOptions { source_lines })
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn loops_and_subroutines() {
+        let program = "#<_depth> = -1\n\
+                       o100 sub\n\
+                       G1 X[#1 * 2] Z#<_depth> F100\n\
+                       o100 endsub\n\
+                       #2 = 0\n\
+                       o101 while [#2 LT 2]\n\
+                       o100 call [#2 + 1]\n\
+                       #2 = [#2 + 1]\n\
+                       o101 endwhile\n\
+                       M2";
+
+        assert_eq!(
+            flat(program, Dialect::LinuxCnc, false),
+            "G1 X2 Z-1 F100\nG1 X4 Z-1 F100\nM2"
+        );
+        assert_eq!(
+            flat(program, Dialect::LinuxCnc, true),
+            "G1 X2 Z-1 F100 (line 3)\nG1 X4 Z-1 F100 (line 3)\nM2 (line 10)"
+        );
+    }
+
+    #[test]
+    fn values_read_at_start_of_line() {
+        assert_eq!(
+            flat(
+                "#1 = 5\nG0 X#1 #1 = 6 (DEBUG, x is #1)\nG1 X#1 F[#1 * 100]",
+                Dialect::LinuxCnc,
+                false
+            ),
+            "G0 X5 (DEBUG, x is 5.000000)\nG1 X6 F600"
+        );
+    }
+
+    #[test]
+    fn fanuc() {
+        let program = "O1000\n\
+                       G0 Z#1\n\
+                       M99\n\
+                       #1 = 0\n\
+                       N10 G1 X#1 F100\n\
+                       #1 = #1 + 1\n\
+                       IF [#1 LT 3] GOTO 10\n\
+                       M98 P1000 L2\n\
+                       M30";
+
+        let flattened = flat(program, Dialect::Fanuc, false);
+
+        assert_eq!(
+            flattened,
+            "G1 X0 F100\nG1 X1 F100\nG1 X2 F100\nG0 Z3\nG0 Z3\nM30"
+        );
+        assert_eq!(
+            Program::from_str_dialect(&flattened, Dialect::Fanuc)
+                .unwrap()
+                .to_string(),
+            flattened
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let program = Program::from_str("G1 X#<undefined>").unwrap();
+
+        assert_eq!(
+            flatten(&program, &FlattenOptions::default())
+                .unwrap_err()
+                .line,
+            1
+        );
+    }
+}
@@ -0,0 +1,3112 @@
+use crate::arc::Arc;
+use crate::canonical::{feed_distance, Canonical, CanonicalKind, SpindleDirection};
+use crate::compensation::Compensation;
+use crate::cycle::{CycleWords, Hole, Step};
+use crate::error::{ErrorKind, InterpretError};
+use crate::flatten::flatten_line;
+use crate::message::{Handler, Message, MessageHandler};
+use crate::parameters::{
+    to_unsigned, Parameters, AXIS_PARAMETERS, G28_HOME, G92_OFFSET, WORK_OFFSETS,
+};
+use crate::spline::Spline;
+use crate::state::{
+    is_rotary, plane_axes, CannedCycle, CompensationSide, DistanceMode, FeedMode, MotionMode,
+    PathMode, RetractMode, State, Units,
+};
+use crate::tool::{Tool, ToolTable};
+use crate::Vector9;
+use expression::Parameter;
+use gcode_parser::token::{
+    Assignment, Block, BlockIdent, Call, CallKind, CenterFormatArc, Comment, CommentKind, Coord,
+    CutterCompensation, DoWhile, GCode, Goto, GrblCommand, HomeAxes, MCode, MarlinCode,
+    PathControl, PlaneSelect, PolarCoord, RadiusFormatArc, Return, Spline as SplineWords,
+    Subroutine, Token, TokenType, UnsignedValue, Value, While,
+};
+use gcode_parser::{Line, Program};
+use std::collections::{HashMap, VecDeque};
+
+/// Tolerance when comparing code numbers like `92.1`
+const CODE_TOLERANCE: f64 = 0.0001;
+
+/// Whether a parsed code number matches the given code
+pub(crate) fn is_code(number: f64, code: f64) -> bool {
+    (number - code).abs() < CODE_TOLERANCE
+}
+
+/// The canned cycles started by each `G` code
+const CANNED_CYCLES: [(f64, CannedCycle); 6] = [
+    (73.0, CannedCycle::ChipBreak),
+    (81.0, CannedCycle::Drill),
+    (82.0, CannedCycle::DrillDwell),
+    (83.0, CannedCycle::PeckDrill),
+    (85.0, CannedCycle::Bore),
+    (89.0, CannedCycle::BoreDwell),
+];
+
+/// Key used to look up a subroutine by its identifier. Named subroutines are case insensitive.
+fn subroutine_key(ident: &BlockIdent) -> String {
+    ident.to_string().to_lowercase()
+}
+
+/// What happens when the end of a frame is reached
+#[derive(Debug)]
+enum FrameKind<'a> {
+    /// The top level program
+    Program,
+
+    /// A called subroutine, holding the caller's local parameters to restore on return, if it
+    /// has its own, and how many more times it is to be run after this one
+    Subroutine {
+        subroutine: &'a Subroutine,
+        saved: Option<Vec<(Parameter, f32)>>,
+        remaining: u32,
+    },
+
+    While(&'a While),
+
+    DoWhile(&'a DoWhile),
+
+    Repeat {
+        remaining: u32,
+    },
+
+    /// A taken `if`/`elseif`/`else` branch
+    Branch,
+}
+
+/// A list of lines being executed
+#[derive(Debug)]
+struct Frame<'a> {
+    lines: &'a [Line],
+
+    /// Source line number of each line in `lines`
+    numbers: Vec<usize>,
+
+    /// Source line number of the line that closes this frame
+    end: usize,
+
+    /// Index of the next line to execute
+    position: usize,
+
+    kind: FrameKind<'a>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(lines: &'a [Line], first: usize, kind: FrameKind<'a>) -> Self {
+        let mut end = first;
+
+        let numbers = lines
+            .iter()
+            .map(|line| {
+                let number = end;
+
+                end += line.span();
+
+                number
+            })
+            .collect();
+
+        Self {
+            lines,
+            numbers,
+            end,
+            position: 0,
+            kind,
+        }
+    }
+}
+
+/// The words on a single line, gathered so they can be executed in a fixed order regardless of
+/// the order they were written in
+#[derive(Debug, Default)]
+struct Words<'a> {
+    gcodes: Vec<&'a GCode>,
+    mcodes: Vec<&'a MCode>,
+
+    /// Numbers of any `G` codes the parser doesn't have a dedicated token for
+    g: Vec<f64>,
+
+    /// Numbers of any `M` codes the parser doesn't have a dedicated token for
+    m: Vec<f64>,
+
+    /// Any other letter words, with the letter in lower case
+    letters: Vec<(char, f64)>,
+
+    coord: Option<&'a Coord>,
+    center_arc: Option<&'a CenterFormatArc>,
+    radius_arc: Option<&'a RadiusFormatArc>,
+    spline: Option<&'a SplineWords>,
+    feed: Option<&'a Value>,
+    spindle: Option<&'a Value>,
+    tool: Option<&'a UnsignedValue>,
+    assignments: Vec<&'a Assignment>,
+    blocks: Vec<&'a Block>,
+    call: Option<&'a Call<f32>>,
+    ret: Option<&'a Return>,
+    goto: Option<&'a Goto>,
+    polar: Option<&'a PolarCoord>,
+    comments: Vec<&'a Comment>,
+    marlin: Vec<&'a MarlinCode>,
+    home: bool,
+}
+
+impl<'a> Words<'a> {
+    fn has_g(&self, code: f64) -> bool {
+        self.g.iter().any(|n| is_code(*n, code))
+    }
+
+    fn has_m(&self, code: f64) -> bool {
+        self.m.iter().any(|n| is_code(*n, code))
+    }
+
+    fn letter(&self, letter: char) -> Option<f64> {
+        self.letters
+            .iter()
+            .find(|(l, _)| *l == letter)
+            .map(|(_, value)| *value)
+    }
+
+    fn has_axes(&self) -> bool {
+        self.coord.is_some()
+            || self.center_arc.is_some()
+            || self.radius_arc.is_some()
+            || self.spline.is_some()
+            || self.polar.is_some()
+    }
+}
+
+/// NURBS control points collected between `G5.2` and `G5.3`
+#[derive(Debug)]
+struct NurbsBlock {
+    /// The line the block started on
+    line: usize,
+    order: usize,
+
+    /// Control points, starting at the position the block started at
+    points: Vec<Vector9>,
+    weights: Vec<f64>,
+}
+
+/// A GCode program interpreter
+///
+/// The interpreter is an iterator over the canonical commands produced by a program. Iteration
+/// stops after the end of the program, an `M2` or `M30`, or the first error.
+#[derive(Debug)]
+pub struct Interpreter<'a> {
+    frames: Vec<Frame<'a>>,
+    subroutines: HashMap<String, (&'a Subroutine, usize)>,
+    state: State,
+    parameters: Parameters,
+    tools: ToolTable,
+    pending: VecDeque<Canonical>,
+    finished: bool,
+
+    /// Cutter compensation, holding back moves until the corners after them are known
+    compensation: Option<Compensation>,
+
+    /// The open NURBS block, if any
+    nurbs: Option<NurbsBlock>,
+
+    /// The end and second control point of the last move if it was a cubic spline, which the
+    /// next cubic spline can carry on smoothly from
+    cubic: Option<(Vector9, Vector9)>,
+
+    /// Which of the `ABC` axes wrap around every 360 degrees
+    wrapped: [bool; 3],
+
+    /// Where active comments go, if anywhere
+    messages: Option<Handler<'a>>,
+
+    /// The canned cycle words carried over from earlier lines
+    cycle: CycleWords,
+
+    /// Every line run so far and its source line number, with values worked out, if the program
+    /// is being flattened
+    flattened: Option<Vec<(usize, Vec<Token>)>>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Create an interpreter for a program, starting at the machine origin
+    pub fn new(program: &'a Program) -> Self {
+        let root = Frame::new(program.lines(), 1, FrameKind::Program);
+
+        let mut subroutines = HashMap::new();
+
+        for (line, number) in root.lines.iter().zip(root.numbers.iter()) {
+            for token in line.iter() {
+                if let TokenType::Block(Block::Subroutine(subroutine)) = &token.token {
+                    subroutines.insert(
+                        subroutine_key(subroutine.identifier()),
+                        (subroutine, number + 1),
+                    );
+                }
+            }
+        }
+
+        Self {
+            frames: vec![root],
+            subroutines,
+            state: State::default(),
+            parameters: Parameters::new(),
+            tools: ToolTable::new(),
+            pending: VecDeque::new(),
+            finished: false,
+            compensation: None,
+            nurbs: None,
+            cubic: None,
+            wrapped: [false; 3],
+            messages: None,
+            cycle: CycleWords::default(),
+            flattened: None,
+        }
+    }
+
+    /// Use the given tool table for tool length offsets and cutter compensation
+    pub fn with_tools(self, tools: ToolTable) -> Self {
+        Self { tools, ..self }
+    }
+
+    /// Start with the given parameter values, for example work offsets in `#5221` onwards
+    pub fn with_parameters(self, parameters: Parameters) -> Self {
+        Self { parameters, ..self }
+    }
+
+    /// Start at the given position in machine coordinates
+    pub fn with_position(mut self, position: Vector9) -> Self {
+        self.state.position = position;
+
+        self
+    }
+
+    /// Treat the given `ABC` axes as wrapping around every 360 degrees
+    ///
+    /// Absolute moves on a wrapped axis take the shortest way round to the programmed angle, so
+    /// `B350` followed by `B10` turns 20 degrees forwards instead of 340 backwards. Positions are
+    /// not reduced modulo 360, so consecutive moves always join up.
+    pub fn with_wrapped_axes(self, wrapped: [bool; 3]) -> Self {
+        Self { wrapped, ..self }
+    }
+
+    /// Pass active comments like `(MSG, ...)` and `(LOG, ...)` to the given handler
+    ///
+    /// Without a handler, active comments are ignored like any other comment.
+    pub fn with_messages(self, handler: impl MessageHandler + 'a) -> Self {
+        Self {
+            messages: Some(Handler(Box::new(handler))),
+            ..self
+        }
+    }
+
+    /// Keep a copy of every line that is run, with parameters and expressions replaced by their
+    /// values and control flow left out
+    pub(crate) fn with_flattening(self) -> Self {
+        Self {
+            flattened: Some(Vec::new()),
+            ..self
+        }
+    }
+
+    /// Take the lines kept since flattening started
+    pub(crate) fn take_flattened(&mut self) -> Vec<(usize, Vec<Token>)> {
+        self.flattened.take().unwrap_or_default()
+    }
+
+    /// Take the lines kept so far, carrying on keeping the lines run after them
+    pub(crate) fn drain_flattened(&mut self) -> Vec<(usize, Vec<Token>)> {
+        self.flattened
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// The current modal state
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The current parameter values
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    fn emit(&mut self, line: usize, kind: CanonicalKind) {
+        let canonical = Canonical { line, kind };
+
+        // Commands can't overtake moves held back by cutter compensation
+        let canonical = match &mut self.compensation {
+            Some(compensation) => compensation.command(canonical),
+            None => Some(canonical),
+        };
+
+        self.pending.extend(canonical);
+    }
+
+    /// Execute the next line of the program
+    fn step(&mut self) -> Result<(), InterpretError> {
+        let frame = match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => {
+                self.finished = true;
+
+                return Ok(());
+            }
+        };
+
+        if frame.position >= frame.lines.len() {
+            let end = frame.end;
+
+            return self
+                .end_frame()
+                .map_err(|kind| InterpretError { line: end, kind });
+        }
+
+        let lines = frame.lines;
+        let line = &lines[frame.position];
+        let number = frame.numbers[frame.position];
+
+        frame.position += 1;
+
+        self.execute(line, number)
+            .map_err(|kind| InterpretError { line: number, kind })
+    }
+
+    /// Handle reaching the end of the innermost frame
+    fn end_frame(&mut self) -> Result<(), ErrorKind> {
+        let mut frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let repeat = match &mut frame.kind {
+            FrameKind::Program => {
+                self.check_nurbs_ended()?;
+                self.finish_compensation();
+
+                self.finished = true;
+
+                false
+            }
+            FrameKind::Subroutine { remaining, .. } if *remaining > 0 => {
+                *remaining -= 1;
+
+                true
+            }
+            FrameKind::Subroutine {
+                subroutine, saved, ..
+            } => {
+                let value = match subroutine.returns() {
+                    Some(expression) => Some(self.parameters.evaluate(expression)?),
+                    None => None,
+                };
+
+                let saved = saved.take();
+
+                self.finish_subroutine(saved, value);
+
+                false
+            }
+            FrameKind::While(block) => self.parameters.evaluate(block.condition())? != 0.0,
+            FrameKind::DoWhile(block) => self.parameters.evaluate(block.condition())? != 0.0,
+            FrameKind::Repeat { remaining } => {
+                *remaining = remaining.saturating_sub(1);
+
+                *remaining > 0
+            }
+            FrameKind::Branch => false,
+        };
+
+        if repeat {
+            frame.position = 0;
+
+            self.frames.push(frame);
+        }
+
+        Ok(())
+    }
+
+    /// Restore the caller's parameters, if the subroutine had its own, and set the return value
+    fn finish_subroutine(&mut self, saved: Option<Vec<(Parameter, f32)>>, value: Option<f64>) {
+        if let Some(saved) = saved {
+            self.parameters.restore_locals(saved);
+        }
+
+        let returned = Parameter::Global("value_returned".into());
+
+        match value {
+            Some(value) => {
+                self.parameters
+                    .set(Parameter::Global("value".into()), value as f32);
+                self.parameters.set(returned, 1.0);
+            }
+            None => self.parameters.set(returned, 0.0),
+        }
+    }
+
+    fn execute(&mut self, line: &'a Line, number: usize) -> Result<(), ErrorKind> {
+        let mut words = Words::default();
+
+        for token in line.iter() {
+            match &token.token {
+                // Block delete switch is on, so skip the line entirely
+                TokenType::BlockDelete => return Ok(()),
+                TokenType::GCode(code) => words.gcodes.push(code),
+                TokenType::MCode(code) => words.mcodes.push(code),
+                TokenType::Coord(coord) => words.coord = Some(coord),
+                TokenType::CenterFormatArc(arc) => words.center_arc = Some(arc),
+                TokenType::RadiusFormatArc(arc) => words.radius_arc = Some(arc),
+                TokenType::Spline(spline) => words.spline = Some(spline),
+                TokenType::PolarCoord(polar) => words.polar = Some(polar),
+                TokenType::Feedrate(feed) => words.feed = Some(&feed.feedrate),
+                TokenType::SpindleSpeed(speed) => words.spindle = Some(&speed.rpm),
+                TokenType::ToolNumber(tool) => words.tool = Some(&tool.tool_number),
+                TokenType::Assignment(assignment) => words.assignments.push(assignment),
+                TokenType::Block(block) => words.blocks.push(block),
+                TokenType::Call(call) => words.call = Some(call),
+                TokenType::Return(ret) => words.ret = Some(ret),
+                TokenType::Goto(goto) => words.goto = Some(goto),
+                TokenType::Unknown(unknown) => {
+                    let value = self.parameters.value(&unknown.code_number)?;
+
+                    match unknown.code_letter.to_ascii_lowercase() {
+                        'g' => words.g.push(value),
+                        'm' => words.m.push(value),
+                        letter => words.letters.push((letter, value)),
+                    }
+                }
+                TokenType::Comment(comment) => words.comments.push(comment),
+                TokenType::Marlin(code) => words.marlin.push(code),
+                TokenType::Grbl(GrblCommand::Home) => words.home = true,
+                // Jogs, settings and reports don't belong to the program, and realtime bytes
+                // act on the controller rather than the program
+                TokenType::Grbl(_) | TokenType::Realtime(_) => {}
+                TokenType::LineNumber(_) | TokenType::ProgramDelimiter => {}
+            }
+        }
+
+        if let Some(flattened) = &mut self.flattened {
+            flattened.push((number, flatten_line(line, &mut self.parameters)?));
+        }
+
+        // Parameters are read with the values they had at the start of the line, so assignments
+        // are evaluated first but applied last
+        let assignments = words
+            .assignments
+            .iter()
+            .map(|assignment| {
+                self.parameters
+                    .value(assignment.rhs())
+                    .map(|value| (assignment.lhs().clone(), value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.execute_words(&words, number)?;
+
+        for (param, value) in assignments {
+            self.parameters.set(param, value as f32);
+        }
+
+        for block in words.blocks {
+            self.enter_block(block, number)?;
+        }
+
+        if let Some(call) = words.call {
+            self.call(call)?;
+        }
+
+        if let Some(ret) = words.ret {
+            self.ret(ret)?;
+        }
+
+        if let Some(goto) = words.goto {
+            self.goto(goto)?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute everything but control flow on a line, in the order given by the LinuxCNC
+    /// [order of execution](http://linuxcnc.org/docs/html/gcode/overview.html#_g_code_order_of_execution)
+    fn execute_words(&mut self, words: &Words, number: usize) -> Result<(), ErrorKind> {
+        for comment in words.comments.iter() {
+            self.comment(comment, number)?;
+        }
+
+        // Units are changed before reading the feed rate so `G20 F10` means 10 inches per minute
+        for code in words.gcodes.iter() {
+            match code {
+                GCode::UnitsMM => self.state.units = Units::Millimeters,
+                GCode::UnitsInch => self.state.units = Units::Inches,
+                _ => (),
+            }
+        }
+
+        // Likewise the feed mode, so `G93 F2` means two moves per minute
+        let feed_mode = if words.has_g(93.0) {
+            Some(FeedMode::InverseTime)
+        } else if words.has_g(94.0) {
+            Some(FeedMode::UnitsPerMinute)
+        } else {
+            None
+        };
+
+        if let Some(mode) = feed_mode {
+            // Feed rates don't carry over between modes
+            if mode != self.state.feed_mode {
+                self.state.feed = 0.0;
+            }
+
+            self.state.feed_mode = mode;
+        }
+
+        if let Some(feed) = words.feed {
+            let feed = self.parameters.value(feed)?;
+
+            self.state.feed = match self.state.feed_mode {
+                FeedMode::UnitsPerMinute => feed * self.state.unit_scale(),
+                FeedMode::InverseTime => feed,
+            };
+        }
+
+        if let Some(speed) = words.spindle {
+            self.state.spindle_speed = self.parameters.value(speed)?;
+        }
+
+        if let Some(tool) = words.tool {
+            self.state.selected_tool = self.parameters.unsigned(tool)?;
+        }
+
+        if words.mcodes.contains(&&MCode::ToolChange) {
+            self.state.tool = self.state.selected_tool;
+
+            self.emit(
+                number,
+                CanonicalKind::ToolChange {
+                    tool: self.state.tool,
+                },
+            );
+        }
+
+        for code in words.mcodes.iter() {
+            let direction = match code {
+                MCode::SpindleForward => SpindleDirection::Clockwise,
+                MCode::SpindleReverse => SpindleDirection::Counterclockwise,
+                MCode::SpindleStop => SpindleDirection::Stopped,
+                _ => continue,
+            };
+
+            self.state.spindle_direction = direction;
+        }
+
+        if words.spindle.is_some()
+            || words.mcodes.iter().any(|code| {
+                matches!(
+                    code,
+                    MCode::SpindleForward | MCode::SpindleReverse | MCode::SpindleStop
+                )
+            })
+        {
+            self.emit(
+                number,
+                CanonicalKind::Spindle {
+                    direction: self.state.spindle_direction,
+                    speed: self.state.spindle_speed,
+                },
+            );
+        }
+
+        self.coolant(words, number);
+
+        for code in words.gcodes.iter() {
+            match code {
+                GCode::Dwell(dwell) => {
+                    let seconds = self.parameters.value(&dwell.time)?;
+
+                    self.emit(number, CanonicalKind::Dwell { seconds });
+                }
+                GCode::PlaneSelect(plane) => {
+                    if self.compensation.is_some() && *plane != self.state.plane {
+                        return Err(ErrorKind::InvalidWords(
+                            "the plane can't be changed with cutter compensation on",
+                        ));
+                    }
+
+                    self.state.plane = plane.clone();
+                }
+                _ => (),
+            }
+        }
+
+        self.cutter_compensation(words)?;
+
+        self.tool_length_offset(words)?;
+
+        for code in words.gcodes.iter() {
+            if let GCode::WorkOffset(offset) = code {
+                self.state.work_offset = offset.clone() as usize;
+            }
+        }
+
+        for code in words.gcodes.iter() {
+            if let GCode::PathControl(control) = code {
+                self.state.path = self.path_mode(control)?;
+
+                self.emit(
+                    number,
+                    CanonicalKind::PathMode {
+                        mode: self.state.path,
+                    },
+                );
+            }
+        }
+
+        for code in words.g.iter() {
+            if is_code(*code, 90.0) {
+                self.state.distance = DistanceMode::Absolute;
+            } else if is_code(*code, 91.0) {
+                self.state.distance = DistanceMode::Incremental;
+            } else if is_code(*code, 90.1) {
+                self.state.arc_distance = DistanceMode::Absolute;
+            } else if is_code(*code, 91.1) {
+                self.state.arc_distance = DistanceMode::Incremental;
+            } else if is_code(*code, 33.0) {
+                self.state.motion = Some(MotionMode::SpindleSynchronized);
+            } else if is_code(*code, 33.1) {
+                self.state.motion = Some(MotionMode::RigidTap);
+            } else if is_code(*code, 80.0) {
+                self.state.motion = None;
+            } else if is_code(*code, 98.0) {
+                self.state.retract = RetractMode::OldZ;
+            } else if is_code(*code, 99.0) {
+                self.state.retract = RetractMode::RPlane;
+            } else if let Some((_, cycle)) = CANNED_CYCLES
+                .iter()
+                .find(|(number, _)| is_code(*code, *number))
+            {
+                // G98 retracts to where Z was before a run of cycles started
+                if !matches!(self.state.motion, Some(MotionMode::CannedCycle(_))) {
+                    self.cycle.old_z = self.state.position[2];
+                }
+
+                self.state.motion = Some(MotionMode::CannedCycle(*cycle));
+            } else if *code >= 73.0 && *code < 90.0 {
+                return Err(ErrorKind::InvalidWords(
+                    "only the G73, G81, G82, G83, G85 and G89 canned cycles are supported",
+                ));
+            }
+        }
+
+        let consumed = self.non_modal(words, number)?;
+
+        // Printers home to the machine origin. Their other codes don't affect motion.
+        for code in words.marlin.iter() {
+            if let MarlinCode::Home(axes) = code {
+                self.home(axes, number)?;
+            }
+        }
+
+        // GRBL's `$H` homes every axis
+        if words.home {
+            self.home(&HomeAxes::default(), number)?;
+        }
+
+        for code in words.gcodes.iter() {
+            let mode = match code {
+                GCode::Rapid => MotionMode::Rapid,
+                GCode::Feed => MotionMode::Linear,
+                GCode::ClockwiseArc => MotionMode::ClockwiseArc,
+                GCode::CounterclockwiseArc => MotionMode::CounterclockwiseArc,
+                GCode::CubicSpline => MotionMode::CubicSpline,
+                GCode::QuadraticSpline => MotionMode::QuadraticSpline,
+                GCode::Nurbs => {
+                    self.start_nurbs(words, number)?;
+
+                    MotionMode::Nurbs
+                }
+                _ => continue,
+            };
+
+            if self.nurbs.is_some() && mode != MotionMode::Nurbs {
+                return Err(ErrorKind::InvalidWords(
+                    "NURBS blocks must be ended with G5.3 before another motion mode",
+                ));
+            }
+
+            self.state.motion = Some(mode);
+        }
+
+        if !consumed && words.has_axes() {
+            self.motion(words, number)?;
+        }
+
+        if words.gcodes.contains(&&GCode::EndNurbs) {
+            self.end_nurbs()?;
+        }
+
+        if words.has_m(0.0) || words.mcodes.contains(&&MCode::OptionalPause) {
+            self.emit(
+                number,
+                CanonicalKind::Pause {
+                    optional: !words.has_m(0.0),
+                },
+            );
+        }
+
+        if words
+            .mcodes
+            .iter()
+            .any(|code| matches!(code, MCode::EndProgram | MCode::EndProgramSwapPallets))
+        {
+            self.check_nurbs_ended()?;
+            self.finish_compensation();
+
+            self.emit(number, CanonicalKind::End);
+
+            self.finished = true;
+        }
+
+        Ok(())
+    }
+
+    /// Pass an active comment to the message handler
+    fn comment(&mut self, comment: &Comment, number: usize) -> Result<(), ErrorKind> {
+        if self.messages.is_none() {
+            return Ok(());
+        }
+
+        let message = match &comment.kind {
+            CommentKind::Plain | CommentKind::Metadata { .. } => return Ok(()),
+            CommentKind::Message(text) => Message::Message(text.clone()),
+            CommentKind::Debug(parts) => Message::Debug(self.parameters.substitute(parts)?),
+            CommentKind::Print(parts) => Message::Print(self.parameters.substitute(parts)?),
+            CommentKind::LogOpen(file) => Message::LogOpen {
+                file: file.clone(),
+                append: false,
+            },
+            CommentKind::LogAppend(file) => Message::LogOpen {
+                file: file.clone(),
+                append: true,
+            },
+            CommentKind::Log(parts) => Message::Log(self.parameters.substitute(parts)?),
+            CommentKind::LogClose => Message::LogClose,
+            CommentKind::ProbeOpen(file) => Message::ProbeOpen(file.clone()),
+            CommentKind::ProbeClose => Message::ProbeClose,
+        };
+
+        match &mut self.messages {
+            Some(Handler(handler)) => handler
+                .message(number, &message)
+                .map_err(ErrorKind::Message),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluate every axis word on a line in `XYZUVWABC` order
+    fn axis_words(&mut self, words: &Words) -> Result<[Option<f64>; 9], ErrorKind> {
+        let mut axes = [None; 9];
+
+        if let Some(coord) = words.coord {
+            let values = [
+                &coord.x, &coord.y, &coord.z, &coord.u, &coord.v, &coord.w, &coord.a, &coord.b,
+                &coord.c,
+            ];
+
+            for (axis, value) in values.iter().enumerate() {
+                if let Some(value) = value {
+                    axes[axis] = Some(self.parameters.value(value)?);
+                }
+            }
+        }
+
+        if let Some(spline) = words.spline {
+            for (axis, value) in [&spline.x, &spline.y].iter().enumerate() {
+                if let Some(value) = value {
+                    axes[axis] = Some(self.parameters.value(value)?);
+                }
+            }
+        }
+
+        let arc_axes = match (words.center_arc, words.radius_arc) {
+            (Some(arc), _) => Some([&arc.x, &arc.y, &arc.z]),
+            (None, Some(arc)) => Some([&arc.x, &arc.y, &arc.z]),
+            (None, None) => None,
+        };
+
+        if let Some(values) = arc_axes {
+            for (axis, value) in values.iter().enumerate() {
+                if let Some(value) = value {
+                    axes[axis] = Some(self.parameters.value(value)?);
+                }
+            }
+        }
+
+        if let Some(polar) = words.polar {
+            self.polar_axes(polar, &mut axes)?;
+        }
+
+        Ok(axes)
+    }
+
+    /// Resolve polar coordinate words into the axis words of the active plane
+    ///
+    /// Like LinuxCNC, the distance (`@`) and angle (`^`) are measured from the program origin,
+    /// with the angle counterclockwise from the first axis of the plane. In absolute mode they
+    /// replace the distance and angle of the current position, and in incremental mode they are
+    /// added to them. Either may be left out to keep the current value.
+    fn polar_axes(
+        &mut self,
+        polar: &PolarCoord,
+        axes: &mut [Option<f64>; 9],
+    ) -> Result<(), ErrorKind> {
+        let (first, second, _) = plane_axes(&self.state.plane);
+
+        if axes[first].is_some() || axes[second].is_some() {
+            return Err(ErrorKind::InvalidWords(
+                "polar coordinates can't be used with axis words in the active plane",
+            ));
+        }
+
+        let distance = polar
+            .distance
+            .as_ref()
+            .map(|value| self.parameters.value(value))
+            .transpose()?;
+        let angle = polar
+            .angle
+            .as_ref()
+            .map(|value| self.parameters.value(value).map(f64::to_radians))
+            .transpose()?;
+
+        // The current position in program units, relative to the program origin
+        let origin = self.origin();
+        let scale = self.state.unit_scale();
+        let x = (self.state.position[first] - origin[first]) / scale;
+        let y = (self.state.position[second] - origin[second]) / scale;
+
+        let current = (x.hypot(y), y.atan2(x));
+
+        let (distance, angle) = match self.state.distance {
+            DistanceMode::Absolute => (distance.unwrap_or(current.0), angle.unwrap_or(current.1)),
+            DistanceMode::Incremental => {
+                if x == 0.0 && y == 0.0 {
+                    return Err(ErrorKind::InvalidWords(
+                        "incremental polar coordinates can't start at the origin",
+                    ));
+                }
+
+                (
+                    current.0 + distance.unwrap_or(0.0),
+                    current.1 + angle.unwrap_or(0.0),
+                )
+            }
+        };
+
+        let (sin, cos) = angle.sin_cos();
+
+        axes[first] = Some(distance * cos);
+        axes[second] = Some(distance * sin);
+
+        // Incremental axis words are relative to the current position
+        if self.state.distance == DistanceMode::Incremental {
+            axes[first] = axes[first].map(|value| value - x);
+            axes[second] = axes[second].map(|value| value - y);
+        }
+
+        Ok(())
+    }
+
+    /// A work offset in machine units, read from parameters `#5221` onwards
+    fn work_offset(&self, index: usize) -> Vector9 {
+        let base = WORK_OFFSETS + 20 * index as u32;
+
+        Vector9::from_iterator(
+            AXIS_PARAMETERS
+                .iter()
+                .map(|axis| self.parameters.numbered(base + axis)),
+        )
+    }
+
+    /// Machine position of the program origin
+    fn origin(&self) -> Vector9 {
+        self.work_offset(self.state.work_offset)
+            + self.state.axis_offset
+            + self.state.tool_length_offset
+    }
+
+    /// Convert a program word value to machine units
+    fn scale(&self, axis: usize, value: f64) -> f64 {
+        if is_rotary(axis) {
+            value
+        } else {
+            value * self.state.unit_scale()
+        }
+    }
+
+    /// Resolve axis words into a target position in machine coordinates
+    fn target(&self, axes: &[Option<f64>; 9], machine: bool) -> Vector9 {
+        let origin = self.origin();
+        let mut target = self.state.position;
+
+        for (axis, value) in axes.iter().enumerate() {
+            if let Some(value) = value {
+                let value = self.scale(axis, *value);
+
+                let current = self.state.position[axis];
+
+                let absolute = if machine {
+                    value
+                } else {
+                    match self.state.distance {
+                        DistanceMode::Absolute => value + origin[axis],
+                        DistanceMode::Incremental => {
+                            target[axis] = current + value;
+
+                            continue;
+                        }
+                    }
+                };
+
+                target[axis] = if is_rotary(axis) && self.wrapped[axis - 6] {
+                    // Turn at most half way round in either direction
+                    let turn = (absolute - current).rem_euclid(360.0);
+
+                    if turn > 180.0 {
+                        current + turn - 360.0
+                    } else {
+                        current + turn
+                    }
+                } else {
+                    absolute
+                };
+            }
+        }
+
+        target
+    }
+
+    fn tool_length_offset(&mut self, words: &Words) -> Result<(), ErrorKind> {
+        if words.has_g(43.0) {
+            let tool = match words.letter('h') {
+                Some(h) => to_unsigned(h)?,
+                None => self.state.tool,
+            };
+
+            let length = match self.tools.get(&tool) {
+                Some(tool) => tool.length,
+                None if tool == 0 => 0.0,
+                None => return Err(ErrorKind::UnknownTool(tool)),
+            };
+
+            self.state.tool_length_offset = Vector9::zeros();
+            self.state.tool_length_offset[2] = length;
+        } else if words.has_g(49.0) {
+            self.state.tool_length_offset = Vector9::zeros();
+        }
+
+        Ok(())
+    }
+
+    /// Turn cutter compensation on or off
+    fn cutter_compensation(&mut self, words: &Words) -> Result<(), ErrorKind> {
+        for code in words.gcodes.iter() {
+            let (side, tool) = match code {
+                GCode::DisableCutterCompensation
+                | GCode::CutterCompensation(CutterCompensation::Off) => {
+                    self.finish_compensation();
+
+                    continue;
+                }
+                GCode::CutterCompensation(CutterCompensation::Left(tool)) => (
+                    CompensationSide::Left,
+                    self.compensation_tool(tool.as_ref())?,
+                ),
+                GCode::CutterCompensation(CutterCompensation::Right(tool)) => (
+                    CompensationSide::Right,
+                    self.compensation_tool(tool.as_ref())?,
+                ),
+                GCode::CutterCompensation(CutterCompensation::DynamicLeft(diameter)) => {
+                    (CompensationSide::Left, self.dynamic_tool(diameter, words)?)
+                }
+                GCode::CutterCompensation(CutterCompensation::DynamicRight(diameter)) => {
+                    (CompensationSide::Right, self.dynamic_tool(diameter, words)?)
+                }
+                _ => continue,
+            };
+
+            if self.compensation.is_some() {
+                return Err(ErrorKind::InvalidWords("cutter compensation is already on"));
+            }
+
+            if self.state.plane != PlaneSelect::XY && self.state.plane != PlaneSelect::ZX {
+                return Err(ErrorKind::InvalidWords(
+                    "cutter compensation only works in the XY and XZ planes",
+                ));
+            }
+
+            if tool.diameter < 0.0 {
+                return Err(ErrorKind::InvalidWords(
+                    "cutter compensation needs a positive tool diameter",
+                ));
+            }
+
+            if tool.orientation > 9 {
+                return Err(ErrorKind::InvalidWords(
+                    "lathe tool orientations go from 1 to 9",
+                ));
+            }
+
+            self.state.compensation = Some(side);
+            self.compensation = Some(Compensation::new(
+                side,
+                tool.diameter / 2.0,
+                tool.orientation,
+                self.state.plane.clone(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// A tool table entry for cutter compensation, or the tool in the spindle if not given
+    fn compensation_tool(&mut self, tool: Option<&Value>) -> Result<Tool, ErrorKind> {
+        let tool = match tool {
+            Some(tool) => to_unsigned(self.parameters.value(tool)?)?,
+            None => self.state.tool,
+        };
+
+        match self.tools.get(&tool) {
+            Some(tool) => Ok(*tool),
+            None if tool == 0 => Ok(Tool::default()),
+            None => Err(ErrorKind::UnknownTool(tool)),
+        }
+    }
+
+    /// The tool given by `G41.1` or `G42.1`, with a diameter and an optional lathe orientation
+    /// from the `L` word
+    fn dynamic_tool(&mut self, diameter: &Value, words: &Words) -> Result<Tool, ErrorKind> {
+        Ok(Tool {
+            diameter: self.parameters.value(diameter)? * self.state.unit_scale(),
+            orientation: words.letter('l').map(to_unsigned).transpose()?.unwrap_or(0),
+            ..Tool::default()
+        })
+    }
+
+    /// Turn cutter compensation off, issuing any moves it was holding back
+    ///
+    /// The tool stays where it is, so the next move starts from the end of the offset path.
+    fn finish_compensation(&mut self) {
+        if let Some(compensation) = self.compensation.take() {
+            let ready = compensation.finish(&mut self.state.position);
+
+            self.pending.extend(ready);
+        }
+
+        self.state.compensation = None;
+    }
+
+    /// Turn `G61`, `G61.1` or `G64 P Q` into a `PathMode`, converting the tolerances to millimeters
+    fn path_mode(&mut self, control: &PathControl) -> Result<PathMode, ErrorKind> {
+        Ok(match control {
+            PathControl::ExactPath => PathMode::ExactPath,
+            PathControl::ExactStop => PathMode::ExactStop,
+            PathControl::Blend {
+                tolerance,
+                naive_tolerance,
+            } => PathMode::Blend {
+                tolerance: self.length(tolerance.as_ref())?,
+                naive_tolerance: self.length(naive_tolerance.as_ref())?,
+            },
+        })
+    }
+
+    /// Evaluate an optional length in program units, converting it into millimeters
+    fn length(&mut self, value: Option<&Value>) -> Result<Option<f64>, ErrorKind> {
+        value
+            .map(|value| Ok(self.parameters.value(value)? * self.state.unit_scale()))
+            .transpose()
+    }
+
+    /// Execute non-modal codes that consume axis words. Returns whether the axis words on the line
+    /// were used.
+    fn non_modal(&mut self, words: &Words, number: usize) -> Result<bool, ErrorKind> {
+        if words.polar.is_some() && (words.has_g(10.0) || words.has_g(43.1) || words.has_g(92.0)) {
+            return Err(ErrorKind::InvalidWords(
+                "polar coordinates can only be used for moves",
+            ));
+        }
+
+        if words.has_g(43.1) {
+            let axes = self.axis_words(words)?;
+
+            for (axis, value) in axes.iter().enumerate() {
+                if let Some(value) = value {
+                    self.state.tool_length_offset[axis] = self.scale(axis, *value);
+                }
+            }
+
+            return Ok(true);
+        }
+
+        if words.has_g(10.0) {
+            let axes = self.axis_words(words)?;
+
+            let index = match words.letter('p').map(to_unsigned).transpose()? {
+                Some(0) | None => self.state.work_offset,
+                Some(p) if p <= 9 => p as usize - 1,
+                Some(_) => return Err(ErrorKind::InvalidWords("G10 P must be between 0 and 9")),
+            };
+
+            let l = words.letter('l').map(to_unsigned).transpose()?;
+            let base = WORK_OFFSETS + 20 * index as u32;
+
+            for (axis, value) in axes.iter().enumerate() {
+                if let Some(value) = value {
+                    let value = self.scale(axis, *value);
+
+                    let offset = match l {
+                        Some(2) => value,
+                        Some(20) => {
+                            self.state.position[axis]
+                                - value
+                                - self.state.axis_offset[axis]
+                                - self.state.tool_length_offset[axis]
+                        }
+                        _ => {
+                            return Err(ErrorKind::InvalidWords(
+                                "only G10 L2 and G10 L20 are supported",
+                            ))
+                        }
+                    };
+
+                    self.parameters
+                        .set_numbered(base + AXIS_PARAMETERS[axis], offset);
+                }
+            }
+
+            return Ok(true);
+        }
+
+        if words.has_g(92.0) {
+            let axes = self.axis_words(words)?;
+
+            if axes.iter().all(Option::is_none) {
+                // Printers reset the extruder's position with `G92 E0`, which moves no axes
+                if words
+                    .marlin
+                    .iter()
+                    .any(|code| matches!(code, MarlinCode::Extrude(_)))
+                {
+                    return Ok(true);
+                }
+
+                return Err(ErrorKind::InvalidWords(
+                    "G92 requires at least one axis word",
+                ));
+            }
+
+            let origin = self.work_offset(self.state.work_offset) + self.state.tool_length_offset;
+
+            for (axis, value) in axes.iter().enumerate() {
+                if let Some(value) = value {
+                    let offset =
+                        self.state.position[axis] - self.scale(axis, *value) - origin[axis];
+
+                    self.state.axis_offset[axis] = offset;
+                    self.parameters
+                        .set_numbered(G92_OFFSET + AXIS_PARAMETERS[axis], offset);
+                }
+            }
+
+            return Ok(true);
+        }
+
+        if words.has_g(92.1) || words.has_g(92.2) {
+            self.state.axis_offset = Vector9::zeros();
+
+            if words.has_g(92.1) {
+                for axis in AXIS_PARAMETERS.iter() {
+                    self.parameters.set_numbered(G92_OFFSET + axis, 0.0);
+                }
+            }
+        }
+
+        if words.has_g(92.3) {
+            for (axis, param) in AXIS_PARAMETERS.iter().enumerate() {
+                self.state.axis_offset[axis] = self.parameters.numbered(G92_OFFSET + param);
+            }
+        }
+
+        if words.gcodes.contains(&&GCode::SetPredefinedPosition) {
+            for (axis, param) in AXIS_PARAMETERS.iter().enumerate() {
+                self.parameters
+                    .set_numbered(G28_HOME + param, self.state.position[axis]);
+            }
+        }
+
+        if words.gcodes.contains(&&GCode::GotoPredefinedPosition) {
+            if self.compensation.is_some() {
+                return Err(ErrorKind::InvalidWords(
+                    "G28 can't be used with cutter compensation on",
+                ));
+            }
+
+            let axes = self.axis_words(words)?;
+
+            // Move through the intermediate point, if given
+            if axes.iter().any(Option::is_some) {
+                let to = self.target(&axes, false);
+
+                self.rapid(number, to);
+            }
+
+            let mut to = self.state.position;
+
+            for (axis, param) in AXIS_PARAMETERS.iter().enumerate() {
+                // Only axes with words are homed, unless none are given
+                if axes[axis].is_some() || axes.iter().all(Option::is_none) {
+                    to[axis] = self.parameters.numbered(G28_HOME + param);
+                }
+            }
+
+            self.rapid(number, to);
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Home the given axes, or all of them if none are given
+    fn home(&mut self, axes: &HomeAxes, number: usize) -> Result<(), ErrorKind> {
+        if self.compensation.is_some() {
+            return Err(ErrorKind::InvalidWords(
+                "homing can't be used with cutter compensation on",
+            ));
+        }
+
+        let all = !axes.x && !axes.y && !axes.z;
+        let mut to = self.state.position;
+
+        for (axis, home) in [axes.x, axes.y, axes.z].iter().enumerate() {
+            if *home || all {
+                to[axis] = 0.0;
+            }
+        }
+
+        self.rapid(number, to);
+
+        Ok(())
+    }
+
+    fn rapid(&mut self, number: usize, to: Vector9) {
+        let from = self.state.position;
+
+        self.state.position = to;
+
+        self.emit(number, CanonicalKind::Rapid { from, to });
+    }
+
+    fn motion(&mut self, words: &Words, number: usize) -> Result<(), ErrorKind> {
+        let mode = self.state.motion.ok_or(ErrorKind::InvalidWords(
+            "axis words given with no active motion mode",
+        ))?;
+
+        let axes = self.axis_words(words)?;
+        let machine = words.has_g(53.0);
+
+        if machine && self.compensation.is_some() {
+            return Err(ErrorKind::InvalidWords(
+                "G53 can't be used with cutter compensation on",
+            ));
+        }
+
+        if machine && words.polar.is_some() {
+            return Err(ErrorKind::InvalidWords(
+                "polar coordinates can't be used with G53",
+            ));
+        }
+
+        if machine && mode != MotionMode::Rapid && mode != MotionMode::Linear {
+            return Err(ErrorKind::InvalidWords(
+                "G53 can only be used with G0 or G1",
+            ));
+        }
+
+        if let MotionMode::CannedCycle(cycle) = mode {
+            return self.canned_cycle(words, number, cycle);
+        }
+
+        let fed = matches!(
+            mode,
+            MotionMode::Linear
+                | MotionMode::ClockwiseArc
+                | MotionMode::CounterclockwiseArc
+                | MotionMode::CubicSpline
+                | MotionMode::QuadraticSpline
+        );
+
+        if fed && self.state.feed_mode == FeedMode::InverseTime && words.feed.is_none() {
+            return Err(ErrorKind::InvalidWords(
+                "inverse time feed moves need an F word",
+            ));
+        }
+
+        let spline = matches!(
+            mode,
+            MotionMode::CubicSpline | MotionMode::QuadraticSpline | MotionMode::Nurbs
+        );
+
+        if spline && self.state.plane != PlaneSelect::XY {
+            return Err(ErrorKind::InvalidWords("splines only work in the XY plane"));
+        }
+
+        if spline && axes[2..].iter().any(Option::is_some) {
+            return Err(ErrorKind::InvalidWords("splines can only move X and Y"));
+        }
+
+        let from = self.state.position;
+        let to = self.target(&axes, machine);
+
+        if mode == MotionMode::Nurbs {
+            let weight = self.spline_word(words, 'p')?.unwrap_or(1.0);
+
+            if let Some(block) = &mut self.nurbs {
+                block.points.push(to);
+                block.weights.push(weight);
+            }
+
+            self.state.position = to;
+
+            return Ok(());
+        }
+
+        let kind = match mode {
+            MotionMode::Rapid => CanonicalKind::Rapid { from, to },
+            MotionMode::Linear => CanonicalKind::Linear {
+                from,
+                to,
+                feed: self.feed(feed_distance(&from, &to))?,
+            },
+            MotionMode::ClockwiseArc | MotionMode::CounterclockwiseArc => {
+                let arc = self.arc(words, to, mode == MotionMode::ClockwiseArc)?;
+                let feed = self.feed(arc.feed_distance())?;
+
+                CanonicalKind::Arc { arc, feed }
+            }
+            MotionMode::SpindleSynchronized | MotionMode::RigidTap => {
+                CanonicalKind::SpindleSynchronized {
+                    from,
+                    to,
+                    pitch: self.pitch(words)?,
+                    speed: self.spindle_speed()?,
+                    rigid_tap: mode == MotionMode::RigidTap,
+                }
+            }
+            MotionMode::CubicSpline => {
+                let spline = self.cubic_spline(words, to)?;
+                let feed = self.feed(spline.length())?;
+
+                CanonicalKind::Spline { spline, feed }
+            }
+            MotionMode::QuadraticSpline => {
+                let spline = self.quadratic_spline(words, to)?;
+                let feed = self.feed(spline.length())?;
+
+                CanonicalKind::Spline { spline, feed }
+            }
+            MotionMode::Nurbs | MotionMode::CannedCycle(_) => unreachable!(),
+        };
+
+        self.cubic = match &kind {
+            CanonicalKind::Spline { spline, .. } if mode == MotionMode::CubicSpline => {
+                Some((to, spline.points[2]))
+            }
+            _ => None,
+        };
+
+        // Rigid tapping retracts back to where it started
+        if mode != MotionMode::RigidTap {
+            self.state.position = to;
+        }
+
+        self.issue_move(number, kind)
+    }
+
+    /// Drill a hole with a canned cycle at the position given on a line, and again `L` times in
+    /// incremental mode
+    ///
+    /// Like LinuxCNC, the tool first rises to the `R` plane if it is below it, rapids across to
+    /// the hole and down to the `R` plane, drills the hole, then retracts as set by `G98` or
+    /// `G99`. In incremental mode `R` is measured from the starting Z position and `Z` from the
+    /// `R` plane. `R`, `Z`, `Q` and `P` carry over to later lines.
+    fn canned_cycle(
+        &mut self,
+        words: &Words,
+        number: usize,
+        cycle: CannedCycle,
+    ) -> Result<(), ErrorKind> {
+        if self.compensation.is_some() {
+            return Err(ErrorKind::InvalidWords(
+                "canned cycles can't be used with cutter compensation on",
+            ));
+        }
+
+        if self.state.plane != PlaneSelect::XY {
+            return Err(ErrorKind::InvalidWords(
+                "canned cycles only work in the XY plane",
+            ));
+        }
+
+        if self.state.feed_mode == FeedMode::InverseTime {
+            return Err(ErrorKind::InvalidWords(
+                "canned cycles can't be used with inverse time feeds",
+            ));
+        }
+
+        let axes = self.axis_words(words)?;
+
+        if axes[3..].iter().any(Option::is_some) {
+            return Err(ErrorKind::InvalidWords(
+                "canned cycles can only move X, Y and Z",
+            ));
+        }
+
+        // R is read as part of the arc words when it comes after an axis word
+        if let Some(arc) = words.radius_arc {
+            self.cycle.r = Some(self.parameters.value(&arc.radius)?);
+        } else if let Some(r) = words.letter('r') {
+            self.cycle.r = Some(r);
+        }
+
+        if let Some(z) = axes[2] {
+            self.cycle.z = Some(z);
+        }
+
+        if let Some(q) = words.letter('q') {
+            self.cycle.q = Some(q);
+        }
+
+        if let Some(p) = self.cycle_dwell(words)? {
+            self.cycle.p = Some(p);
+        }
+
+        let repeats = match words.letter('l') {
+            Some(repeats) => to_unsigned(repeats)?,
+            None => 1,
+        };
+
+        let (r, z) = match (self.cycle.r, self.cycle.z) {
+            (Some(r), Some(z)) => (self.scale(2, r), self.scale(2, z)),
+            (None, _) => return Err(ErrorKind::InvalidWords("canned cycles need an R word")),
+            (_, None) => return Err(ErrorKind::InvalidWords("canned cycles need a Z word")),
+        };
+
+        let (r, bottom) = match self.state.distance {
+            DistanceMode::Absolute => {
+                let origin = self.origin()[2];
+
+                (origin + r, origin + z)
+            }
+            DistanceMode::Incremental => {
+                let start = self.state.position[2];
+
+                (start + r, start + r + z)
+            }
+        };
+
+        if bottom > r {
+            return Err(ErrorKind::InvalidWords(
+                "the bottom of a canned cycle's hole can't be above the R plane",
+            ));
+        }
+
+        let peck = self.scale(2, self.cycle.q.unwrap_or(0.0));
+
+        if cycle.pecks() && peck <= 0.0 {
+            return Err(ErrorKind::InvalidWords(
+                "G73 and G83 need a positive Q word",
+            ));
+        }
+
+        let hole = Hole {
+            r,
+            bottom,
+            clear: match self.state.retract {
+                RetractMode::OldZ => r.max(self.cycle.old_z),
+                RetractMode::RPlane => r,
+            },
+            peck,
+            dwell: self.cycle.p.unwrap_or(0.0),
+        };
+
+        for repeat in 0..repeats {
+            // Incremental polar coordinates turn further round from each hole to the next
+            let axes = if repeat == 0 {
+                axes
+            } else {
+                self.axis_words(words)?
+            };
+
+            let mut xy = [None; 9];
+            xy[..2].copy_from_slice(&axes[..2]);
+
+            let mut above = self.target(&xy, false);
+
+            if self.state.position[2] < r {
+                let mut to = self.state.position;
+                to[2] = r;
+
+                self.cycle_rapid(number, to);
+            }
+
+            above[2] = self.state.position[2];
+            self.cycle_rapid(number, above);
+
+            above[2] = r;
+            self.cycle_rapid(number, above);
+
+            for step in cycle.steps(&hole) {
+                let mut to = self.state.position;
+
+                match step {
+                    Step::Rapid(z) => {
+                        to[2] = z;
+
+                        self.cycle_rapid(number, to);
+                    }
+                    Step::Feed(z) => {
+                        to[2] = z;
+
+                        let from = self.state.position;
+                        let feed = self.feed(feed_distance(&from, &to))?;
+
+                        self.state.position = to;
+
+                        self.emit(number, CanonicalKind::Linear { from, to, feed });
+                    }
+                    Step::Dwell(seconds) => self.emit(number, CanonicalKind::Dwell { seconds }),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A rapid move made by a canned cycle, left out if the tool is already there
+    fn cycle_rapid(&mut self, number: usize, to: Vector9) {
+        if to != self.state.position {
+            self.rapid(number, to);
+        }
+    }
+
+    /// The dwell time of a canned cycle from `P`
+    ///
+    /// A whole number `P` after an `R` word is read as the number of turns of an arc, where `P1`
+    /// can't be told apart from leaving it out, so a one second dwell there must be written as
+    /// `P1.0`.
+    fn cycle_dwell(&mut self, words: &Words) -> Result<Option<f64>, ErrorKind> {
+        if let Some(p) = words.letter('p') {
+            return Ok(Some(p));
+        }
+
+        match words.radius_arc.map(|arc| &arc.turns) {
+            Some(turns) if *turns != UnsignedValue::Literal(1) => {
+                Ok(Some(f64::from(self.parameters.unsigned(turns)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Turn mist and flood coolant on and off (`M7`, `M8` and `M9`)
+    fn coolant(&mut self, words: &Words, number: usize) {
+        let (mut mist, mut flood) = (self.state.mist, self.state.flood);
+
+        if words.has_m(9.0) {
+            mist = false;
+            flood = false;
+        }
+
+        if words.has_m(7.0) {
+            mist = true;
+        }
+
+        if words.has_m(8.0) {
+            flood = true;
+        }
+
+        if (mist, flood) != (self.state.mist, self.state.flood) {
+            self.state.mist = mist;
+            self.state.flood = flood;
+
+            self.emit(number, CanonicalKind::Coolant { mist, flood });
+        }
+    }
+
+    /// Issue a move, through cutter compensation if it's on
+    fn issue_move(&mut self, number: usize, kind: CanonicalKind) -> Result<(), ErrorKind> {
+        match &mut self.compensation {
+            Some(compensation) => {
+                let ready = compensation.motion(Canonical { line: number, kind })?;
+
+                self.pending.extend(ready);
+            }
+            None => self.emit(number, kind),
+        }
+
+        Ok(())
+    }
+
+    /// The feed rate in units per minute for a move with the given feed distance
+    fn feed(&self, distance: f64) -> Result<f64, ErrorKind> {
+        if self.state.feed <= 0.0 {
+            return Err(ErrorKind::InvalidWords("feed move with a zero feed rate"));
+        }
+
+        match self.state.feed_mode {
+            FeedMode::UnitsPerMinute => Ok(self.state.feed),
+            // A move with nothing to measure takes no time whatever its feed rate
+            FeedMode::InverseTime if distance > 0.0 => Ok(self.state.feed * distance),
+            FeedMode::InverseTime => Ok(self.state.feed),
+        }
+    }
+
+    /// Distance per spindle revolution in millimeters for spindle synchronised motion, from `K`
+    fn pitch(&mut self, words: &Words) -> Result<f64, ErrorKind> {
+        let k = match words.center_arc.and_then(|arc| arc.k.as_ref()) {
+            Some(k) => self.parameters.value(k)?,
+            None => {
+                return Err(ErrorKind::InvalidWords(
+                    "spindle synchronised motion needs a K word",
+                ))
+            }
+        };
+
+        if k > 0.0 {
+            Ok(k * self.state.unit_scale())
+        } else {
+            Err(ErrorKind::InvalidWords(
+                "spindle synchronised motion needs a positive K word",
+            ))
+        }
+    }
+
+    /// The speed of the running spindle in RPM
+    fn spindle_speed(&self) -> Result<f64, ErrorKind> {
+        if self.state.spindle_direction != SpindleDirection::Stopped
+            && self.state.spindle_speed > 0.0
+        {
+            Ok(self.state.spindle_speed)
+        } else {
+            Err(ErrorKind::InvalidWords(
+                "spindle synchronised motion with the spindle stopped",
+            ))
+        }
+    }
+
+    fn arc(&mut self, words: &Words, to: Vector9, clockwise: bool) -> Result<Arc, ErrorKind> {
+        let from = self.state.position;
+        let plane = self.state.plane.clone();
+        let (a, b, _) = plane_axes(&plane);
+
+        match plane {
+            PlaneSelect::XY | PlaneSelect::ZX | PlaneSelect::YZ => (),
+            _ => {
+                return Err(ErrorKind::InvalidArc(
+                    "arcs in the UVW planes are not supported",
+                ))
+            }
+        }
+
+        match (words.center_arc, words.radius_arc) {
+            (Some(arc), _) => {
+                // I, J and K are the offsets along X, Y and Z respectively
+                let offsets = [&arc.i, &arc.j, &arc.k];
+
+                if offsets[a].is_none() && offsets[b].is_none() {
+                    return Err(ErrorKind::InvalidArc(
+                        "no center offsets given in the active plane",
+                    ));
+                }
+
+                let origin = self.origin();
+                let mut center = from;
+
+                for axis in [a, b].iter() {
+                    let offset = match offsets[*axis] {
+                        Some(value) => {
+                            let value = self.parameters.value(value)?;
+
+                            self.scale(*axis, value)
+                        }
+                        None => 0.0,
+                    };
+
+                    center[*axis] = match self.state.arc_distance {
+                        DistanceMode::Incremental => from[*axis] + offset,
+                        DistanceMode::Absolute => origin[*axis] + offset,
+                    };
+                }
+
+                let turns = self.parameters.unsigned(&arc.turns)?;
+
+                Arc::with_center(from, to, center, plane, clockwise, turns, self.state.units)
+            }
+            (None, Some(arc)) => {
+                let radius = self.parameters.value(&arc.radius)? * self.state.unit_scale();
+                let turns = self.parameters.unsigned(&arc.turns)?;
+
+                Arc::with_radius(from, to, radius, plane, clockwise, turns)
+            }
+            // The parser leaves an `R` written before the axis words as a word of its own
+            (None, None) => match words.letter('r') {
+                Some(radius) => {
+                    let turns = words.letter('p').map(to_unsigned).transpose()?;
+
+                    Arc::with_radius(
+                        from,
+                        to,
+                        radius * self.state.unit_scale(),
+                        plane,
+                        clockwise,
+                        turns.unwrap_or(1),
+                    )
+                }
+                None => Err(ErrorKind::InvalidArc(
+                    "arc moves require center offsets or a radius",
+                )),
+            },
+        }
+    }
+
+    /// A spline word, wherever the parser put it
+    fn spline_word(&mut self, words: &Words, letter: char) -> Result<Option<f64>, ErrorKind> {
+        let spline = words.spline.and_then(|spline| match letter {
+            'i' => spline.i.as_ref(),
+            'j' => spline.j.as_ref(),
+            'p' => spline.p.as_ref(),
+            'q' => spline.q.as_ref(),
+            _ => None,
+        });
+
+        // Quadratic spline words look like a center format arc
+        let arc = words.center_arc.and_then(|arc| match letter {
+            'i' => arc.i.as_ref(),
+            'j' => arc.j.as_ref(),
+            _ => None,
+        });
+
+        match spline.or(arc) {
+            Some(value) => Ok(Some(self.parameters.value(value)?)),
+            None => Ok(words.letter(letter)),
+        }
+    }
+
+    /// A point offset in `X` and `Y` by values in program units
+    fn offset_point(&self, point: Vector9, x: f64, y: f64) -> Vector9 {
+        let mut point = point;
+
+        point[0] += self.scale(0, x);
+        point[1] += self.scale(1, y);
+
+        point
+    }
+
+    /// A cubic spline from the current position, with its first control point offset from the
+    /// start by `I` and `J` and its second offset from the end by `P` and `Q`
+    ///
+    /// Without `I` and `J`, a cubic spline straight after another one carries on in the
+    /// direction the last one ended in.
+    fn cubic_spline(&mut self, words: &Words, to: Vector9) -> Result<Spline, ErrorKind> {
+        let from = self.state.position;
+
+        let i = self.spline_word(words, 'i')?;
+        let j = self.spline_word(words, 'j')?;
+
+        let first = match (i, j) {
+            (Some(i), Some(j)) => self.offset_point(from, i, j),
+            (None, None) => match self.cubic {
+                Some((end, second)) if end == from => from * 2.0 - second,
+                _ => {
+                    return Err(ErrorKind::InvalidWords(
+                        "cubic splines need I and J unless they follow another cubic spline",
+                    ))
+                }
+            },
+            _ => return Err(ErrorKind::InvalidWords("cubic splines need both I and J")),
+        };
+
+        let p = self.spline_word(words, 'p')?;
+        let q = self.spline_word(words, 'q')?;
+
+        let second = match (p, q) {
+            (Some(p), Some(q)) => self.offset_point(to, p, q),
+            _ => return Err(ErrorKind::InvalidWords("cubic splines need both P and Q")),
+        };
+
+        Ok(Spline::cubic(from, first, second, to))
+    }
+
+    /// A quadratic spline from the current position, with its control point offset from the
+    /// start by `I` and `J`
+    fn quadratic_spline(&mut self, words: &Words, to: Vector9) -> Result<Spline, ErrorKind> {
+        let from = self.state.position;
+
+        let i = self.spline_word(words, 'i')?.unwrap_or(0.0);
+        let j = self.spline_word(words, 'j')?.unwrap_or(0.0);
+
+        if i == 0.0 && j == 0.0 {
+            return Err(ErrorKind::InvalidWords(
+                "quadratic splines need a nonzero I or J",
+            ));
+        }
+
+        Ok(Spline::quadratic(from, self.offset_point(from, i, j), to))
+    }
+
+    /// Start collecting NURBS control points at the current position, with the order from `L`,
+    /// or 3 for a quadratic curve if not given
+    fn start_nurbs(&mut self, words: &Words, number: usize) -> Result<(), ErrorKind> {
+        // G5.2 is modal, so it may be repeated on every line of the block
+        if self.nurbs.is_some() {
+            return Ok(());
+        }
+
+        if self.compensation.is_some() {
+            return Err(ErrorKind::InvalidWords(
+                "splines can't be used with cutter compensation",
+            ));
+        }
+
+        let order = match words.spline.and_then(|spline| spline.order.as_ref()) {
+            Some(order) => self.parameters.unsigned(order)?,
+            None => words.letter('l').map(to_unsigned).transpose()?.unwrap_or(3),
+        };
+
+        self.nurbs = Some(NurbsBlock {
+            line: number,
+            order: order as usize,
+            points: vec![self.state.position],
+            weights: vec![1.0],
+        });
+
+        Ok(())
+    }
+
+    /// Issue the NURBS curve through the control points collected since `G5.2`
+    fn end_nurbs(&mut self) -> Result<(), ErrorKind> {
+        let block = self.nurbs.take().ok_or(ErrorKind::InvalidWords(
+            "G5.3 without a NURBS block started by G5.2",
+        ))?;
+
+        let spline = Spline::nurbs(block.points, block.weights, block.order)?;
+        let feed = self.feed(spline.length())?;
+
+        self.state.motion = None;
+        self.cubic = None;
+
+        self.issue_move(block.line, CanonicalKind::Spline { spline, feed })
+    }
+
+    /// Programs can't end part way through a NURBS block
+    fn check_nurbs_ended(&self) -> Result<(), ErrorKind> {
+        match self.nurbs {
+            Some(_) => Err(ErrorKind::InvalidWords(
+                "NURBS blocks must be ended with G5.3",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn enter_block(&mut self, block: &'a Block, number: usize) -> Result<(), ErrorKind> {
+        match block {
+            // Subroutines are collected when the interpreter is created and only run when called
+            Block::Subroutine(_) => (),
+            Block::While(block) => {
+                if self.parameters.evaluate(block.condition())? != 0.0 {
+                    self.frames.push(Frame::new(
+                        block.lines(),
+                        number + 1,
+                        FrameKind::While(block),
+                    ));
+                }
+            }
+            Block::DoWhile(block) => {
+                self.frames.push(Frame::new(
+                    block.lines(),
+                    number + 1,
+                    FrameKind::DoWhile(block),
+                ));
+            }
+            Block::Repeat(block) => {
+                let remaining = to_unsigned(self.parameters.evaluate(block.condition())?)?;
+
+                if remaining > 0 {
+                    self.frames.push(Frame::new(
+                        block.lines(),
+                        number + 1,
+                        FrameKind::Repeat { remaining },
+                    ));
+                }
+            }
+            Block::Conditional(block) => {
+                let mut first = number + 1;
+
+                for branch in block.branches() {
+                    let taken = match branch.condition() {
+                        Some(condition) => self.parameters.evaluate(condition)? != 0.0,
+                        None => true,
+                    };
+
+                    if taken {
+                        self.frames
+                            .push(Frame::new(branch.lines(), first, FrameKind::Branch));
+
+                        break;
+                    }
+
+                    // Skip over this branch's lines and the next branch's opening line
+                    first += branch.lines().iter().map(Line::span).sum::<usize>() + 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, call: &'a Call<f32>) -> Result<(), ErrorKind> {
+        let key = subroutine_key(call.subroutine_ident());
+
+        let (subroutine, first) = *self
+            .subroutines
+            .get(&key)
+            .ok_or_else(|| ErrorKind::UnknownSubroutine(key.clone()))?;
+
+        let arguments = call
+            .arguments()
+            .iter()
+            .map(|argument| self.parameters.evaluate(argument))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let macro_arguments = call
+            .macro_arguments()
+            .into_iter()
+            .map(|(number, value)| Ok((number, self.parameters.value(value)?)))
+            .collect::<Result<Vec<_>, ErrorKind>>()?;
+
+        // Fanuc subprograms share the caller's local parameters
+        let saved = match call.kind() {
+            CallKind::Subprogram => None,
+            CallKind::OWord | CallKind::Macro(_) => Some(self.parameters.take_locals()),
+        };
+
+        for (idx, value) in arguments.into_iter().enumerate() {
+            self.parameters.set_numbered(idx as u32 + 1, value);
+        }
+
+        for (number, value) in macro_arguments {
+            self.parameters.set_numbered(number, value);
+        }
+
+        if call.repeats() > 0 {
+            self.frames.push(Frame::new(
+                subroutine.lines(),
+                first,
+                FrameKind::Subroutine {
+                    subroutine,
+                    saved,
+                    remaining: call.repeats() - 1,
+                },
+            ));
+        } else if let Some(saved) = saved {
+            self.parameters.restore_locals(saved);
+        }
+
+        Ok(())
+    }
+
+    /// Jump to the line with a sequence number in the program or subroutine being run, leaving
+    /// any loops or branches in the way
+    fn goto(&mut self, goto: &Goto) -> Result<(), ErrorKind> {
+        if let Some(condition) = &goto.condition {
+            if self.parameters.evaluate(condition)? == 0.0 {
+                return Ok(());
+            }
+        }
+
+        let sequence = self.parameters.unsigned(&goto.sequence)?;
+
+        while let Some(frame) = self.frames.last_mut() {
+            let target = frame.lines.iter().position(|line| {
+                line.iter().any(|token| {
+                    matches!(&token.token, TokenType::LineNumber(number) if number.line_number == sequence)
+                })
+            });
+
+            if let Some(position) = target {
+                frame.position = position;
+
+                return Ok(());
+            }
+
+            match frame.kind {
+                FrameKind::Program | FrameKind::Subroutine { .. } => break,
+                _ => {
+                    self.frames.pop();
+                }
+            }
+        }
+
+        Err(ErrorKind::UnknownSequenceNumber(sequence))
+    }
+
+    fn ret(&mut self, ret: &Return) -> Result<(), ErrorKind> {
+        let value = match ret.value() {
+            Some(expression) => Some(self.parameters.evaluate(expression)?),
+            None => None,
+        };
+
+        while let Some(frame) = self.frames.pop() {
+            if let FrameKind::Subroutine { saved, .. } = frame.kind {
+                self.finish_subroutine(saved, value);
+
+                return Ok(());
+            }
+        }
+
+        Err(ErrorKind::ReturnOutsideSubroutine)
+    }
+}
+
+impl<'a> Iterator for Interpreter<'a> {
+    type Item = Result<Canonical, InterpretError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(canonical) = self.pending.pop_front() {
+                return Some(Ok(canonical));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            if let Err(e) = self.step() {
+                self.finished = true;
+
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcode_parser::Dialect;
+
+    fn xyz(x: f64, y: f64, z: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = x;
+        v[1] = y;
+        v[2] = z;
+        v
+    }
+
+    fn run(program: &str) -> Vec<Canonical> {
+        let program = Program::from_str(program).unwrap();
+
+        Interpreter::new(&program)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    fn targets(program: &str) -> Vec<(usize, Vector9)> {
+        run(program)
+            .into_iter()
+            .filter_map(|c| c.kind.target().map(|target| (c.line, *target)))
+            .collect()
+    }
+
+    #[test]
+    fn modal_motion_and_units() {
+        let result = run("G21 G90\nG0 X1 Y2\nG1 Z-1 F100\nG20 X1\nM2");
+
+        assert_eq!(
+            result,
+            vec![
+                Canonical {
+                    line: 2,
+                    kind: CanonicalKind::Rapid {
+                        from: Vector9::zeros(),
+                        to: xyz(1.0, 2.0, 0.0),
+                    },
+                },
+                Canonical {
+                    line: 3,
+                    kind: CanonicalKind::Linear {
+                        from: xyz(1.0, 2.0, 0.0),
+                        to: xyz(1.0, 2.0, -1.0),
+                        feed: 100.0,
+                    },
+                },
+                Canonical {
+                    line: 4,
+                    kind: CanonicalKind::Linear {
+                        from: xyz(1.0, 2.0, -1.0),
+                        to: xyz(25.4, 2.0, -1.0),
+                        feed: 100.0,
+                    },
+                },
+                Canonical {
+                    line: 5,
+                    kind: CanonicalKind::End,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn incremental() {
+        assert_eq!(
+            targets("G91 G0 X1\nX1 Y1\nG90 X0"),
+            vec![
+                (1, xyz(1.0, 0.0, 0.0)),
+                (2, xyz(2.0, 1.0, 0.0)),
+                (3, xyz(0.0, 1.0, 0.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn work_offsets() {
+        assert_eq!(
+            targets("#5241 = 10\nG10 L2 P3 X5\nG55 G0 X1 Y1\nG56 X1\nG54 X1\nG53 X0"),
+            vec![
+                (3, xyz(11.0, 1.0, 0.0)),
+                (4, xyz(6.0, 1.0, 0.0)),
+                (5, xyz(1.0, 1.0, 0.0)),
+                (6, xyz(0.0, 1.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn g92_offset() {
+        assert_eq!(
+            targets("G0 X5\nG92 X0\nX1\nG92.1\nX1"),
+            vec![
+                (1, xyz(5.0, 0.0, 0.0)),
+                (3, xyz(6.0, 0.0, 0.0)),
+                (5, xyz(1.0, 0.0, 0.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn polar_coordinates() {
+        let cases: &[(&str, Vec<Vector9>)] = &[
+            (
+                "G0 @10 ^90\n^180\nG91 ^-90\n@5\nG90 G1 @1 ^0 Z-1 F100",
+                vec![
+                    xyz(0.0, 10.0, 0.0),
+                    xyz(-10.0, 0.0, 0.0),
+                    xyz(0.0, 10.0, 0.0),
+                    xyz(0.0, 15.0, 0.0),
+                    xyz(1.0, 0.0, -1.0),
+                ],
+            ),
+            // Measured from the program origin, in program units
+            (
+                "G0 X5 Y5\nG92 X0 Y0\n@1 ^90\nG20 @1 ^0",
+                vec![xyz(5.0, 5.0, 0.0), xyz(5.0, 6.0, 0.0), xyz(30.4, 5.0, 0.0)],
+            ),
+            // The angle starts on the first axis of the active plane
+            ("G18 G0 @10 ^90 Y1", vec![xyz(10.0, 1.0, 0.0)]),
+            ("G19 G0 @10 ^90", vec![xyz(0.0, 0.0, 10.0)]),
+        ];
+
+        for (program, expected) in cases.iter() {
+            let result = targets(program);
+
+            assert_eq!(result.len(), expected.len(), "{}", program);
+
+            for ((_, target), expected) in result.iter().zip(expected.iter()) {
+                assert!(
+                    (target - expected).norm() < 1.0e-9,
+                    "{}: {} != {}",
+                    program,
+                    target,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn polar_errors() {
+        let cases = [
+            (
+                "G0 X1 @1",
+                "polar coordinates can't be used with axis words in the active plane",
+            ),
+            (
+                "G18 G0 Z1 ^90",
+                "polar coordinates can't be used with axis words in the active plane",
+            ),
+            (
+                "G91 G0 @1",
+                "incremental polar coordinates can't start at the origin",
+            ),
+            ("G53 G0 @1 ^0", "polar coordinates can't be used with G53"),
+            (
+                "G10 L2 P1 @1",
+                "polar coordinates can only be used for moves",
+            ),
+        ];
+
+        for (program, message) in cases.iter() {
+            let parsed = Program::from_str(program).unwrap();
+            let error = Interpreter::new(&parsed)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_err();
+
+            assert_eq!(error.kind, ErrorKind::InvalidWords(message), "{}", program);
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let program = Program::from_str(
+            "#<x> = 1.5\n(MSG, Hello #<x>)\nG0 X1 (DEBUG, x is #<x>)\n#<x> = 2 (PRINT, #<x>)\n\
+             ; MSG, not a message\n(LOGAPPEND,run.log)\n(LOG,#5221)\n(LOGCLOSE)",
+        )
+        .unwrap();
+
+        let mut received = Vec::new();
+
+        let commands = Interpreter::new(&program)
+            .with_messages(|line, message: &Message| {
+                received.push((line, message.clone()));
+
+                Ok(())
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            received,
+            vec![
+                (2, Message::Message("Hello #<x>".into())),
+                (3, Message::Debug("x is 1.500000".into())),
+                (4, Message::Print("1.500000".into())),
+                (
+                    6,
+                    Message::LogOpen {
+                        file: "run.log".into(),
+                        append: true
+                    }
+                ),
+                (7, Message::Log("0.000000".into())),
+                (8, Message::LogClose),
+            ]
+        );
+    }
+
+    #[test]
+    fn message_errors() {
+        let error = |program: &str| {
+            let program = Program::from_str(program).unwrap();
+
+            Interpreter::new(&program)
+                .with_messages(|_, message: &Message| match message {
+                    Message::LogOpen { file, .. } => Err(format!("can't open {}", file)),
+                    _ => Ok(()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_err()
+        };
+
+        assert_eq!(
+            error("(LOGOPEN,run.log)"),
+            InterpretError {
+                line: 1,
+                kind: ErrorKind::Message("can't open run.log".into())
+            }
+        );
+        assert_eq!(
+            error("G0 X1\n(DEBUG,#<missing>)").kind,
+            ErrorKind::UndefinedParameter(Parameter::Local("missing".into()))
+        );
+    }
+
+    #[test]
+    fn marlin_homing() {
+        let program = Program::from_str_dialect(
+            "M104 S200\nG0 X10 Y10 Z5\nG1 X20 E1.5 F1200\nG28 X\nG28",
+            Dialect::Marlin,
+        )
+        .unwrap();
+
+        let targets = Interpreter::new(&program)
+            .map(|c| c.unwrap())
+            .filter_map(|c| c.kind.target().map(|target| (c.line, *target)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            targets,
+            vec![
+                (2, xyz(10.0, 10.0, 5.0)),
+                (3, xyz(20.0, 10.0, 5.0)),
+                (4, xyz(0.0, 10.0, 5.0)),
+                (5, xyz(0.0, 0.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn grbl_homing() {
+        let program = Program::from_str_dialect(
+            "$X\nG0 X10 Y10 Z5\n$J=G91 X5 F100\n?\n$H\nG1 X1 F100",
+            Dialect::Grbl,
+        )
+        .unwrap();
+
+        let targets = Interpreter::new(&program)
+            .map(|c| c.unwrap())
+            .filter_map(|c| c.kind.target().map(|target| (c.line, *target)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            targets,
+            vec![
+                (2, xyz(10.0, 10.0, 5.0)),
+                (5, xyz(0.0, 0.0, 0.0)),
+                (6, xyz(1.0, 0.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn drill_cycle() {
+        let result = run("G0 Z10\nG98 G81 X1 Y2 Z-1 R2 F100\nX3\nG99 G82 X4 Z-2 P0.5\nG80");
+
+        assert_eq!(
+            result.iter().map(|c| c.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                CanonicalKind::Rapid {
+                    from: xyz(0.0, 0.0, 0.0),
+                    to: xyz(0.0, 0.0, 10.0),
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(0.0, 0.0, 10.0),
+                    to: xyz(1.0, 2.0, 10.0),
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(1.0, 2.0, 10.0),
+                    to: xyz(1.0, 2.0, 2.0),
+                },
+                CanonicalKind::Linear {
+                    from: xyz(1.0, 2.0, 2.0),
+                    to: xyz(1.0, 2.0, -1.0),
+                    feed: 100.0,
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(1.0, 2.0, -1.0),
+                    to: xyz(1.0, 2.0, 10.0),
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(1.0, 2.0, 10.0),
+                    to: xyz(3.0, 2.0, 10.0),
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(3.0, 2.0, 10.0),
+                    to: xyz(3.0, 2.0, 2.0),
+                },
+                CanonicalKind::Linear {
+                    from: xyz(3.0, 2.0, 2.0),
+                    to: xyz(3.0, 2.0, -1.0),
+                    feed: 100.0,
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(3.0, 2.0, -1.0),
+                    to: xyz(3.0, 2.0, 10.0),
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(3.0, 2.0, 10.0),
+                    to: xyz(4.0, 2.0, 10.0),
+                },
+                CanonicalKind::Rapid {
+                    from: xyz(4.0, 2.0, 10.0),
+                    to: xyz(4.0, 2.0, 2.0),
+                },
+                CanonicalKind::Linear {
+                    from: xyz(4.0, 2.0, 2.0),
+                    to: xyz(4.0, 2.0, -2.0),
+                    feed: 100.0,
+                },
+                CanonicalKind::Dwell { seconds: 0.5 },
+                CanonicalKind::Rapid {
+                    from: xyz(4.0, 2.0, -2.0),
+                    to: xyz(4.0, 2.0, 2.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn incremental_cycle_repeats() {
+        // R is measured from Z5 and Z from the R plane, so each hole is drilled down to Z0
+        let holes = run("G0 Z5\nG91 G99 G81 X10 Z-3 R-2 L3 F100")
+            .into_iter()
+            .filter_map(|c| match c.kind {
+                CanonicalKind::Linear { to, .. } => Some(to),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            holes,
+            vec![
+                xyz(10.0, 0.0, 0.0),
+                xyz(20.0, 0.0, 0.0),
+                xyz(30.0, 0.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn peck_drill_cycle() {
+        let bottoms = targets("G0 Z5\nG83 X0 Y0 Z-3 R1 Q2 F100")
+            .into_iter()
+            .map(|(_, target)| target[2])
+            .collect::<Vec<_>>();
+
+        assert_eq!(bottoms, vec![5.0, 1.0, -1.0, 1.0, -0.746, -3.0, 5.0]);
+    }
+
+    #[test]
+    fn unsupported_cycles() {
+        let program = Program::from_str("G84 X0 Y0 Z-1 R1 F100").unwrap();
+
+        let result = Interpreter::new(&program).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(
+            result,
+            Err(InterpretError {
+                line: 1,
+                kind: ErrorKind::InvalidWords(
+                    "only the G73, G81, G82, G83, G85 and G89 canned cycles are supported"
+                ),
+            })
+        );
+    }
+
+    #[test]
+    fn coolant_and_pauses() {
+        let kinds = run("M8\nM7 M8\nM0\nM9\nM1")
+            .into_iter()
+            .map(|c| c.kind)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds,
+            vec![
+                CanonicalKind::Coolant {
+                    mist: false,
+                    flood: true,
+                },
+                CanonicalKind::Coolant {
+                    mist: true,
+                    flood: true,
+                },
+                CanonicalKind::Pause { optional: false },
+                CanonicalKind::Coolant {
+                    mist: false,
+                    flood: false,
+                },
+                CanonicalKind::Pause { optional: true },
+            ]
+        );
+    }
+
+    fn fanuc_targets(program: &str) -> Vec<Vector9> {
+        let program = Program::from_str_dialect(program, Dialect::Fanuc).unwrap();
+
+        Interpreter::new(&program)
+            .map(|c| c.unwrap())
+            .filter_map(|c| c.kind.target().copied())
+            .collect()
+    }
+
+    #[test]
+    fn fanuc_goto() {
+        let targets = fanuc_targets(
+            "#1 = 0\nN10 #1 = #1 + 1\nG1 X#1 F100\nIF [#1 LT 3] GOTO 10\nGOTO 20\nG0 X100\nN20 G0 Y1",
+        );
+
+        assert_eq!(
+            targets,
+            vec![
+                xyz(1.0, 0.0, 0.0),
+                xyz(2.0, 0.0, 0.0),
+                xyz(3.0, 0.0, 0.0),
+                xyz(3.0, 1.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fanuc_while() {
+        let targets =
+            fanuc_targets("#1 = 1\nWHILE [#1 LE 3] DO 1\nG1 X#1 F100\n#1 = #1 + 1\nEND 1\nG0 Y1");
+
+        assert_eq!(
+            targets,
+            vec![
+                xyz(1.0, 0.0, 0.0),
+                xyz(2.0, 0.0, 0.0),
+                xyz(3.0, 0.0, 0.0),
+                xyz(3.0, 1.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fanuc_goto_leaves_loops() {
+        let targets = fanuc_targets(
+            "#1 = 1\nDO 1\nG1 X#1 F100\nIF [#1 GE 2] GOTO 5\n#1 = #1 + 1\nEND 1\nN5 G0 Y1",
+        );
+
+        assert_eq!(
+            targets,
+            vec![xyz(1.0, 0.0, 0.0), xyz(2.0, 0.0, 0.0), xyz(2.0, 1.0, 0.0),]
+        );
+    }
+
+    #[test]
+    fn fanuc_subprogram_repeats() {
+        // Subprograms share the caller's locals, so #1 keeps counting up between runs
+        let targets =
+            fanuc_targets("O1000\n#1 = #1 + 1\nG1 X#1 F100\nM99\n#1 = 0\nM98 P1000 L3\nG0 Y#1");
+
+        assert_eq!(
+            targets,
+            vec![
+                xyz(1.0, 0.0, 0.0),
+                xyz(2.0, 0.0, 0.0),
+                xyz(3.0, 0.0, 0.0),
+                xyz(3.0, 3.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fanuc_macro_arguments() {
+        // Macros get their own locals, with arguments set from the letters they're called with
+        let targets =
+            fanuc_targets("O9010\nG1 X#1 Y#2 Z#26 F100\nM99\n#1 = 7\nG65 P9010 A1 B2 Z3\nG0 X#1");
+
+        assert_eq!(targets, vec![xyz(1.0, 2.0, 3.0), xyz(7.0, 2.0, 3.0)]);
+    }
+
+    #[test]
+    fn fanuc_unknown_sequence_number() {
+        let program = Program::from_str_dialect("G0 X1\nGOTO 99", Dialect::Fanuc).unwrap();
+
+        let result = Interpreter::new(&program).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(
+            result,
+            Err(InterpretError {
+                line: 2,
+                kind: ErrorKind::UnknownSequenceNumber(99),
+            })
+        );
+    }
+
+    #[test]
+    fn tool_length_offset() {
+        let program = Program::from_str("T1 M6\nG43\nG0 Z0\nG49\nZ0").unwrap();
+
+        let mut tools = ToolTable::new();
+
+        tools.insert(
+            1,
+            Tool {
+                length: 5.0,
+                diameter: 3.0,
+                orientation: 0,
+            },
+        );
+
+        let result = Interpreter::new(&program)
+            .with_tools(tools)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(result[0].kind, CanonicalKind::ToolChange { tool: 1 },);
+        assert_eq!(result[1].kind.target(), Some(&xyz(0.0, 0.0, 5.0)));
+        assert_eq!(result[2].kind.target(), Some(&xyz(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn loops_and_conditionals() {
+        let program = "#1 = 0
+o100 while [#1 LT 3]
+  #1 = [#1 + 1]
+  o101 if [#1 EQ 2]
+    G0 X#1
+  o101 else
+    G0 Y#1
+  o101 endif
+o100 endwhile
+o102 repeat [2]
+  G0 Z1
+o102 endrepeat
+o103 do
+  G0 Z2
+o103 while [0]";
+
+        assert_eq!(
+            targets(program),
+            vec![
+                (7, xyz(0.0, 1.0, 0.0)),
+                (5, xyz(2.0, 1.0, 0.0)),
+                (7, xyz(2.0, 3.0, 0.0)),
+                (11, xyz(2.0, 3.0, 1.0)),
+                (11, xyz(2.0, 3.0, 1.0)),
+                (14, xyz(2.0, 3.0, 2.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn subroutines() {
+        let program = "o<square> sub
+  G91 G1 X#1 F100
+  Y#1
+  G90
+o<square> endsub [#1 * 4]
+o200 sub
+  o200 return [7]
+  G0 X100
+o200 endsub
+o<square> call [2]
+#10 = #<_value>
+o200 call
+G0 X#10 Y#<_value>";
+
+        assert_eq!(
+            targets(program),
+            vec![
+                (2, xyz(2.0, 0.0, 0.0)),
+                (3, xyz(2.0, 2.0, 0.0)),
+                (13, xyz(8.0, 7.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_subroutine() {
+        let program = Program::from_str("G0 X1\no123 call").unwrap();
+
+        let result = Interpreter::new(&program).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(
+            result,
+            Err(InterpretError {
+                line: 2,
+                kind: ErrorKind::UnknownSubroutine("123".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn undefined_named_parameter() {
+        let program = Program::from_str("G0 X#<nope>").unwrap();
+
+        let result = Interpreter::new(&program).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(
+            result,
+            Err(InterpretError {
+                line: 1,
+                kind: ErrorKind::UndefinedParameter(Parameter::Local("nope".into())),
+            })
+        );
+    }
+
+    #[test]
+    fn center_format_arc() {
+        let result = run("G0 X1\nG3 X0 Y1 I-1 J0 F60");
+
+        match &result[1].kind {
+            CanonicalKind::Arc { arc, feed } => {
+                assert_eq!(*feed, 60.0);
+                assert_eq!(arc.center, Vector9::zeros());
+                assert!(!arc.clockwise);
+                assert!((arc.sweep() - std::f64::consts::PI / 2.0).abs() < 1.0e-9);
+            }
+            other => panic!("expected an arc, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn radius_format_arc() {
+        for (program, center, sweep) in [
+            ("G0 X1\nG3 X0 Y1 R1 F60", xyz(0.0, 0.0, 0.0), 0.5),
+            ("G0 X1\nG3 X0 Y1 R-1 F60", xyz(1.0, 1.0, 0.0), 1.5),
+            ("G0 X1\nG2 X0 Y1 R1 F60", xyz(1.0, 1.0, 0.0), 0.5),
+        ]
+        .iter()
+        {
+            match &run(program)[1].kind {
+                CanonicalKind::Arc { arc, .. } => {
+                    assert!((arc.center - center).norm() < 1.0e-6, "{}", program);
+                    assert!(
+                        (arc.sweep() - sweep * std::f64::consts::PI).abs() < 1.0e-6,
+                        "{}",
+                        program
+                    );
+                }
+                other => panic!("expected an arc, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn dwell_and_spindle() {
+        let kinds: Vec<CanonicalKind> = run("S1000 M3\nG4 P1.5\nM5")
+            .into_iter()
+            .map(|c| c.kind)
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                CanonicalKind::Spindle {
+                    direction: SpindleDirection::Clockwise,
+                    speed: 1000.0
+                },
+                CanonicalKind::Dwell { seconds: 1.5 },
+                CanonicalKind::Spindle {
+                    direction: SpindleDirection::Stopped,
+                    speed: 1000.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn path_control() {
+        let kinds: Vec<CanonicalKind> = run("G61\nG61.1\nG20 G64 P0.5 Q0.25\nG64")
+            .into_iter()
+            .map(|c| c.kind)
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                CanonicalKind::PathMode {
+                    mode: PathMode::ExactPath
+                },
+                CanonicalKind::PathMode {
+                    mode: PathMode::ExactStop
+                },
+                CanonicalKind::PathMode {
+                    mode: PathMode::Blend {
+                        tolerance: Some(0.5 * 25.4),
+                        naive_tolerance: Some(0.25 * 25.4)
+                    }
+                },
+                CanonicalKind::PathMode {
+                    mode: PathMode::Blend {
+                        tolerance: None,
+                        naive_tolerance: None
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn g28_home() {
+        assert_eq!(
+            targets("G0 X3 Y4\nG28.1\nG0 X0 Y0\nG28 Z1\nG28"),
+            vec![
+                (1, xyz(3.0, 4.0, 0.0)),
+                (3, xyz(0.0, 0.0, 0.0)),
+                (4, xyz(0.0, 0.0, 1.0)),
+                (4, xyz(0.0, 0.0, 0.0)),
+                (5, xyz(3.0, 4.0, 0.0)),
+            ]
+        );
+    }
+
+    fn feeds(program: &str) -> Vec<f64> {
+        run(program)
+            .into_iter()
+            .filter_map(|c| match c.kind {
+                CanonicalKind::Linear { feed, .. } | CanonicalKind::Arc { feed, .. } => Some(feed),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inverse_time_feed() {
+        // Half a minute for a 10mm move, then a quarter of a minute for a 90 degree turn
+        assert_eq!(
+            feeds(
+                "G93 G1 X10 F2
+B90 F4
+G94 G1 X0 F100"
+            ),
+            vec![20.0, 360.0, 100.0]
+        );
+
+        let program = Program::from_str(
+            "G93 G1 X10 F2
+X20",
+        )
+        .unwrap();
+
+        let result = Interpreter::new(&program).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(
+            result,
+            Err(InterpretError {
+                line: 2,
+                kind: ErrorKind::InvalidWords("inverse time feed moves need an F word"),
+            })
+        );
+    }
+
+    #[test]
+    fn combined_rotary_feed() {
+        let commands = run("G1 X30 B40 F600
+G1 B0");
+
+        // The feed applies to the linear axes alone, which move 30mm in 3 seconds
+        assert!((commands[0].kind.path_speed().unwrap() - 50.0 / 3.0).abs() < 1.0e-9);
+        // A purely rotary move is fed in degrees per minute
+        assert_eq!(commands[1].kind.path_speed(), Some(10.0));
+    }
+
+    #[test]
+    fn wrapped_rotary_axes() {
+        let b = |program: &str, wrapped: bool| -> Vec<f64> {
+            let program = Program::from_str(program).unwrap();
+
+            Interpreter::new(&program)
+                .with_wrapped_axes([false, wrapped, false])
+                .map(|c| c.unwrap().kind.target().unwrap()[7])
+                .collect()
+        };
+
+        let program = "G0 B350\nB10\nB-90\nG91 B-400";
+
+        assert_eq!(b(program, false), vec![350.0, 10.0, -90.0, -490.0]);
+        assert_eq!(b(program, true), vec![-10.0, 10.0, -90.0, -490.0]);
+    }
+
+    #[test]
+    fn spindle_synchronized() {
+        let kinds: Vec<CanonicalKind> =
+            run("G20\nS500 M3\nG33 Z-1 K0.125\nG0 X1\nG33.1 Z-2 K0.0625\nX2 Z-2 K0.0625")
+                .into_iter()
+                .map(|c| c.kind)
+                .filter(|kind| matches!(kind, CanonicalKind::SpindleSynchronized { .. }))
+                .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                CanonicalKind::SpindleSynchronized {
+                    from: Vector9::zeros(),
+                    to: xyz(0.0, 0.0, -25.4),
+                    pitch: 3.175,
+                    speed: 500.0,
+                    rigid_tap: false,
+                },
+                CanonicalKind::SpindleSynchronized {
+                    from: xyz(25.4, 0.0, -25.4),
+                    to: xyz(25.4, 0.0, -50.8),
+                    pitch: 1.5875,
+                    speed: 500.0,
+                    rigid_tap: true,
+                },
+                // Still tapping from the retracted position
+                CanonicalKind::SpindleSynchronized {
+                    from: xyz(25.4, 0.0, -25.4),
+                    to: xyz(50.8, 0.0, -50.8),
+                    pitch: 1.5875,
+                    speed: 500.0,
+                    rigid_tap: true,
+                },
+            ]
+        );
+
+        let program = Program::from_str("G33 Z-1 K1").unwrap();
+
+        let result = Interpreter::new(&program).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(
+            result,
+            Err(InterpretError {
+                line: 1,
+                kind: ErrorKind::InvalidWords(
+                    "spindle synchronised motion with the spindle stopped"
+                ),
+            })
+        );
+    }
+
+    fn splines(program: &str) -> Vec<(usize, Spline)> {
+        run(program)
+            .into_iter()
+            .filter_map(|c| match c.kind {
+                CanonicalKind::Spline { spline, .. } => Some((c.line, spline)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cubic_splines() {
+        let result = splines("F100\nG5 X4 Y0 I1 J2 P-1 Q2\nG5 X8 P-1 Q-2\nM2");
+
+        assert_eq!(
+            result,
+            vec![
+                (
+                    2,
+                    Spline::cubic(
+                        xyz(0.0, 0.0, 0.0),
+                        xyz(1.0, 2.0, 0.0),
+                        xyz(3.0, 2.0, 0.0),
+                        xyz(4.0, 0.0, 0.0)
+                    )
+                ),
+                // Without I and J the second carries on in the direction the first ended in
+                (
+                    3,
+                    Spline::cubic(
+                        xyz(4.0, 0.0, 0.0),
+                        xyz(5.0, -2.0, 0.0),
+                        xyz(7.0, -2.0, 0.0),
+                        xyz(8.0, 0.0, 0.0)
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn quadratic_spline() {
+        assert_eq!(
+            splines("G20 F10\nG5.1 X1 I.5 J.5\nM2"),
+            vec![(
+                2,
+                Spline::quadratic(
+                    xyz(0.0, 0.0, 0.0),
+                    xyz(12.7, 12.7, 0.0),
+                    xyz(25.4, 0.0, 0.0)
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn nurbs_block() {
+        // The example from the LinuxCNC documentation
+        let result = splines(
+            "G0 X0 Y0\nF10\nG5.2 X0 Y1 P1 L3\nX2 Y2 P1\nX2 Y0 P1\nX0 Y0 P2\nG5.3\nG0 X1\nM2",
+        );
+
+        assert_eq!(
+            result,
+            vec![(
+                3,
+                Spline::nurbs(
+                    vec![
+                        xyz(0.0, 0.0, 0.0),
+                        xyz(0.0, 1.0, 0.0),
+                        xyz(2.0, 2.0, 0.0),
+                        xyz(2.0, 0.0, 0.0),
+                        xyz(0.0, 0.0, 0.0),
+                    ],
+                    vec![1.0, 1.0, 1.0, 1.0, 2.0],
+                    3
+                )
+                .unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn butterfly() {
+        let program =
+            std::fs::read_to_string("../test_files/linuxcnc/nc_files/butterfly.ngc").unwrap();
+
+        let result = splines(&program);
+
+        assert_eq!(result.len(), 6);
+
+        // The first pair of blocks makes a closed wing
+        assert_eq!(result[0].1.to(), result[1].1.from());
+        assert_eq!(result[1].1.to(), &xyz(0.0, 0.0, -5.0));
+
+        for (_, spline) in result.iter() {
+            assert_eq!(spline.weights.len(), spline.points.len());
+            assert!(!spline.linearise(0.01).is_empty());
+        }
+    }
+
+    #[test]
+    fn spline_errors() {
+        let error = |program: &str| {
+            let program = Program::from_str(program).unwrap();
+
+            Interpreter::new(&program)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_err()
+        };
+
+        let cases = [
+            (
+                "F10\nG5 X1 P0 Q1",
+                "cubic splines need I and J unless they follow another cubic spline",
+            ),
+            ("F10\nG5 X1 I1 J0 P0", "cubic splines need both P and Q"),
+            ("F10\nG5.1 X1 Y1", "quadratic splines need a nonzero I or J"),
+            (
+                "F10\nG18\nG5 X1 I1 J0 P0 Q1",
+                "splines only work in the XY plane",
+            ),
+            ("F10\nG5 X1 Z1 I1 J0 P0 Q1", "splines can only move X and Y"),
+            ("F10\nG5.3", "G5.3 without a NURBS block started by G5.2"),
+            (
+                "F10\nG5.2 X1 Y1\nX2 Y0\nG1 X0",
+                "NURBS blocks must be ended with G5.3 before another motion mode",
+            ),
+            (
+                "F10\nG5.2 X1 Y1\nX2 Y0\nM2",
+                "NURBS blocks must be ended with G5.3",
+            ),
+            (
+                "F10\nG41.1 D1\nG5 X1 Y1 I1 J0 P0 Q1",
+                "splines can't be used with cutter compensation",
+            ),
+        ];
+
+        for (program, message) in cases.iter() {
+            assert_eq!(
+                error(program).kind,
+                ErrorKind::InvalidWords(message),
+                "{}",
+                program
+            );
+        }
+    }
+}
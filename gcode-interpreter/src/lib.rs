@@ -0,0 +1,54 @@
+//! Execute parsed GCode programs
+//!
+//! The [`Interpreter`](struct.Interpreter.html) walks a [`Program`](../gcode_parser/struct.Program.html),
+//! tracking modal state, parameters, work offsets and control flow (loops, conditionals and
+//! subroutines), and produces a stream of [`Canonical`](struct.Canonical.html) machine commands in
+//! absolute machine coordinates. All linear positions are in millimeters and all feed rates in
+//! millimeters per minute, regardless of the units the program was written in.
+
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+mod arc;
+mod canonical;
+mod compensation;
+mod convert;
+mod cycle;
+mod error;
+mod flatten;
+mod interpreter;
+mod message;
+mod parameters;
+mod spline;
+mod state;
+mod tool;
+
+pub use crate::arc::{Arc, ArcTolerance};
+pub use crate::canonical::{Canonical, CanonicalKind, SpindleDirection};
+pub use crate::convert::{convert, ConvertError, ConvertOptions};
+pub use crate::error::{ErrorKind, InterpretError};
+pub use crate::flatten::{flatten, FlattenOptions};
+pub use crate::interpreter::Interpreter;
+pub use crate::message::{LogFiles, Message, MessageHandler};
+pub use crate::parameters::Parameters;
+pub use crate::spline::Spline;
+pub use crate::state::{
+    CannedCycle, CompensationSide, DistanceMode, FeedMode, MotionMode, PathMode, RetractMode,
+    State, Units,
+};
+pub use crate::tool::{Tool, ToolTable};
+use nalgebra::{VectorN, U9};
+
+/// A position or vector in all 9 `XYZUVWABC` axes
+pub type Vector9 = VectorN<f64, U9>;
+
+/// Millimeters per inch
+pub(crate) const MM_PER_INCH: f64 = 25.4;
@@ -0,0 +1,203 @@
+//! Active comments like `(MSG, ...)` and `(LOG, ...)`, and somewhere for them to go
+//!
+//! The interpreter turns each active comment into a [`Message`](enum.Message.html) with any
+//! parameters substituted, and passes it to the [`MessageHandler`](trait.MessageHandler.html)
+//! given to [`Interpreter::with_messages`](struct.Interpreter.html#method.with_messages) as soon
+//! as the line is executed. Messages are handled in the order they appear, before anything else
+//! on the same line, like LinuxCNC.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// An active comment, ready to be acted on
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// `(MSG, text)`: show a message to the operator
+    Message(String),
+
+    /// `(DEBUG, text)`: show a message to the operator
+    Debug(String),
+
+    /// `(PRINT, text)`: print a message to the controller's standard error
+    Print(String),
+
+    /// `(LOGOPEN, file)` or `(LOGAPPEND, file)`: open a log file, closing any open one
+    LogOpen {
+        /// File name as written in the program
+        file: String,
+
+        /// Whether to add to the file instead of replacing its contents
+        append: bool,
+    },
+
+    /// `(LOG, text)`: write a line to the open log file
+    Log(String),
+
+    /// `(LOGCLOSE)`: close the open log file
+    LogClose,
+
+    /// `(PROBEOPEN file)`: open a file to record probe results in
+    ProbeOpen(String),
+
+    /// `(PROBECLOSE)`: close the probe results file
+    ProbeClose,
+}
+
+/// Something that acts on the messages in a program as it is interpreted
+///
+/// Closures taking the source line and the message are handlers too. An error stops the
+/// interpreter.
+pub trait MessageHandler {
+    /// Act on a message from the given 1-indexed source line
+    fn message(&mut self, line: usize, message: &Message) -> Result<(), String>;
+}
+
+impl<F> MessageHandler for F
+where
+    F: FnMut(usize, &Message) -> Result<(), String>,
+{
+    fn message(&mut self, line: usize, message: &Message) -> Result<(), String> {
+        self(line, message)
+    }
+}
+
+/// A boxed message handler, which can't be printed
+pub(crate) struct Handler<'a>(pub(crate) Box<dyn MessageHandler + 'a>);
+
+impl fmt::Debug for Handler<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MessageHandler")
+    }
+}
+
+/// Writes log and probe result files, keeping them all inside one directory
+///
+/// File names in the program must be relative paths that stay inside the directory, so a program
+/// can't overwrite anything else. Operator messages are ignored; to show them as well, call
+/// [`message`](trait.MessageHandler.html#tymethod.message) on this from a closure that handles
+/// the rest.
+#[derive(Debug)]
+pub struct LogFiles {
+    directory: PathBuf,
+    log: Option<File>,
+    probe: Option<File>,
+}
+
+impl LogFiles {
+    /// Write files into the given directory, which must already exist
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            log: None,
+            probe: None,
+        }
+    }
+
+    /// The open probe results file, for whatever runs the probing moves to write results to
+    pub fn probe_file(&mut self) -> Option<&mut File> {
+        self.probe.as_mut()
+    }
+
+    /// The path to a file inside the directory
+    fn path(&self, file: &str) -> Result<PathBuf, String> {
+        let path = Path::new(file);
+
+        let inside = path.components().count() > 0
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+
+        if inside {
+            Ok(self.directory.join(path))
+        } else {
+            Err(format!("{} is outside the log directory", file))
+        }
+    }
+
+    fn open(&self, file: &str, append: bool) -> Result<File, String> {
+        let path = self.path(file)?;
+
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(&path)
+            .map_err(|error| format!("couldn't open {}: {}", path.display(), error))
+    }
+}
+
+impl MessageHandler for LogFiles {
+    fn message(&mut self, _line: usize, message: &Message) -> Result<(), String> {
+        match message {
+            Message::LogOpen { file, append } => self.log = Some(self.open(file, *append)?),
+            Message::Log(text) => {
+                // LinuxCNC ignores log messages when no log file is open
+                if let Some(log) = &mut self.log {
+                    writeln!(log, "{}", text).map_err(|error| error.to_string())?;
+                }
+            }
+            Message::LogClose => self.log = None,
+            Message::ProbeOpen(file) => self.probe = Some(self.open(file, false)?),
+            Message::ProbeClose => self.probe = None,
+            Message::Message(_) | Message::Debug(_) | Message::Print(_) => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sandboxed_paths() {
+        let files = LogFiles::new("logs");
+
+        assert_eq!(files.path("probe.txt"), Ok(PathBuf::from("logs/probe.txt")));
+        assert_eq!(files.path("a/b.txt"), Ok(PathBuf::from("logs/a/b.txt")));
+
+        for file in ["", "/etc/passwd", "../escape.txt", "a/../../b", "./a"].iter() {
+            assert!(files.path(file).is_err(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn log_file() {
+        let directory = std::env::temp_dir().join(format!("log_file_{}", std::process::id()));
+
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut files = LogFiles::new(&directory);
+
+        let messages = [
+            Message::Log("dropped".into()),
+            Message::LogOpen {
+                file: "run.log".into(),
+                append: false,
+            },
+            Message::Log("first".into()),
+            Message::LogClose,
+            Message::LogOpen {
+                file: "run.log".into(),
+                append: true,
+            },
+            Message::Log("second".into()),
+            Message::LogClose,
+        ];
+
+        for message in messages.iter() {
+            files.message(1, message).unwrap();
+        }
+
+        let contents = fs::read_to_string(directory.join("run.log")).unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(contents, "first\nsecond\n");
+    }
+}
@@ -0,0 +1,297 @@
+use crate::error::ErrorKind;
+use expression::{evaluate, Context, Expression, ExpressionToken, Function, Parameter};
+use gcode_parser::token::{MessagePart, UnsignedValue, Value};
+
+/// First parameter of the `G28` home position
+pub(crate) const G28_HOME: u32 = 5161;
+
+/// First parameter of the `G92` offset
+pub(crate) const G92_OFFSET: u32 = 5211;
+
+/// First parameter of the `G54` work offset. Each further offset is 20 parameters on.
+pub(crate) const WORK_OFFSETS: u32 = 5221;
+
+/// The parameter number of each `XYZUVWABC` axis relative to the start of a parameter group
+///
+/// LinuxCNC stores axis values in `XYZABCUVW` order.
+pub(crate) const AXIS_PARAMETERS: [u32; 9] = [0, 1, 2, 6, 7, 8, 3, 4, 5];
+
+/// Parameter (variable) storage
+///
+/// Numbered parameters that have never been assigned read as zero. Reading an unassigned named
+/// parameter is an error.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameters {
+    values: Context<f32>,
+}
+
+impl Parameters {
+    /// Create an empty parameter store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the value of a parameter
+    pub fn get(&self, param: &Parameter) -> Option<f32> {
+        match (self.values.get(param), param) {
+            (Some(value), _) => Some(*value),
+            (None, Parameter::Numbered(_)) => Some(0.0),
+            (None, _) => None,
+        }
+    }
+
+    /// Set the value of a parameter
+    pub fn set(&mut self, param: Parameter, value: f32) {
+        self.values.insert(param, value);
+    }
+
+    /// Remove a parameter, returning its previous value
+    pub fn remove(&mut self, param: &Parameter) -> Option<f32> {
+        self.values.remove(param)
+    }
+
+    /// Value of a numbered parameter
+    pub(crate) fn numbered(&self, number: u32) -> f64 {
+        f64::from(self.get(&Parameter::Numbered(number)).unwrap_or(0.0))
+    }
+
+    /// Set a numbered parameter
+    pub(crate) fn set_numbered(&mut self, number: u32, value: f64) {
+        self.set(Parameter::Numbered(number), value as f32);
+    }
+
+    /// Remove and return all parameters local to a subroutine call: `#1` to `#30` and named locals
+    pub(crate) fn take_locals(&mut self) -> Vec<(Parameter, f32)> {
+        let locals: Vec<Parameter> = self
+            .values
+            .keys()
+            .filter(|param| match param {
+                Parameter::Numbered(n) => *n >= 1 && *n <= 30,
+                Parameter::Local(_) => true,
+                Parameter::Global(_) => false,
+            })
+            .cloned()
+            .collect();
+
+        locals
+            .into_iter()
+            .filter_map(|param| {
+                let value = self.values.remove(&param)?;
+
+                Some((param, value))
+            })
+            .collect()
+    }
+
+    /// Replace all subroutine locals with a previously saved set
+    pub(crate) fn restore_locals(&mut self, saved: Vec<(Parameter, f32)>) {
+        self.take_locals();
+
+        self.values.extend(saved);
+    }
+
+    /// Evaluate an expression, defaulting any unassigned numbered parameters to zero
+    pub(crate) fn evaluate(&mut self, expression: &Expression<f32>) -> Result<f64, ErrorKind> {
+        self.define_numbered(expression)?;
+
+        evaluate(expression.clone(), Some(&self.values))
+            .map(f64::from)
+            .map_err(|_| ErrorKind::InvalidExpression)
+    }
+
+    /// Get the numeric value of a word
+    pub(crate) fn value(&mut self, value: &Value) -> Result<f64, ErrorKind> {
+        match value {
+            Value::Literal(n) => Ok(f64::from(*n)),
+            Value::Parameter(param) => self
+                .get(param)
+                .map(f64::from)
+                .ok_or_else(|| ErrorKind::UndefinedParameter(param.clone())),
+            Value::Expression(expression) => self.evaluate(expression),
+        }
+    }
+
+    /// Get the numeric value of a word that must be a non-negative integer
+    pub(crate) fn unsigned(&mut self, value: &UnsignedValue) -> Result<u32, ErrorKind> {
+        let n = match value {
+            UnsignedValue::Literal(n) => return Ok(*n),
+            UnsignedValue::Parameter(param) => self
+                .get(param)
+                .map(f64::from)
+                .ok_or_else(|| ErrorKind::UndefinedParameter(param.clone()))?,
+            UnsignedValue::Expression(expression) => self.evaluate(&to_float(expression))?,
+        };
+
+        to_unsigned(n)
+    }
+
+    /// Put parameter values into a message, with six decimal places like LinuxCNC
+    pub(crate) fn substitute(&self, parts: &[MessagePart]) -> Result<String, ErrorKind> {
+        parts
+            .iter()
+            .map(|part| match part {
+                MessagePart::Text(text) => Ok(text.clone()),
+                MessagePart::Parameter(param) => self
+                    .get(param)
+                    .map(|value| format!("{:.6}", value))
+                    .ok_or_else(|| ErrorKind::UndefinedParameter(param.clone())),
+            })
+            .collect()
+    }
+
+    /// Give any numbered parameters in `expression` that have never been assigned a value of zero,
+    /// and return an error for any unassigned named parameters
+    fn define_numbered(&mut self, expression: &Expression<f32>) -> Result<(), ErrorKind> {
+        for token in expression.0.iter() {
+            match token {
+                ExpressionToken::Parameter(param) if !self.values.contains_key(param) => {
+                    match param {
+                        Parameter::Numbered(_) => self.set(param.clone(), 0.0),
+                        _ => return Err(ErrorKind::UndefinedParameter(param.clone())),
+                    }
+                }
+                ExpressionToken::Expression(nested) => self.define_numbered(nested)?,
+                ExpressionToken::Function(func) => match func {
+                    Function::Atan((a, b)) => {
+                        self.define_numbered(a)?;
+                        self.define_numbered(b)?;
+                    }
+                    Function::Exists(_) => (),
+                    Function::Abs(arg)
+                    | Function::Acos(arg)
+                    | Function::Asin(arg)
+                    | Function::Cos(arg)
+                    | Function::Exp(arg)
+                    | Function::Floor(arg)
+                    | Function::Ceil(arg)
+                    | Function::Ln(arg)
+                    | Function::Round(arg)
+                    | Function::Sin(arg)
+                    | Function::Sqrt(arg)
+                    | Function::Tan(arg) => self.define_numbered(arg)?,
+                },
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Round a value to a non-negative integer
+pub(crate) fn to_unsigned(n: f64) -> Result<u32, ErrorKind> {
+    if n.is_finite() && n > -0.5 && n < f64::from(u32::MAX) {
+        Ok(n.round() as u32)
+    } else {
+        Err(ErrorKind::InvalidUnsignedValue(n))
+    }
+}
+
+/// Convert an integer expression to a floating point one so it can be evaluated
+fn to_float(expression: &Expression<u32>) -> Expression<f32> {
+    let arg = |e: &Expression<u32>| to_float(e);
+
+    expression
+        .0
+        .iter()
+        .map(|token| match token {
+            ExpressionToken::BinaryOperator(op) => ExpressionToken::BinaryOperator(*op),
+            ExpressionToken::ArithmeticOperator(op) => ExpressionToken::ArithmeticOperator(*op),
+            ExpressionToken::LogicalOperator(op) => ExpressionToken::LogicalOperator(*op),
+            ExpressionToken::Expression(nested) => ExpressionToken::Expression(arg(nested)),
+            ExpressionToken::Literal(n) => ExpressionToken::Literal(*n as f32),
+            ExpressionToken::Parameter(param) => ExpressionToken::Parameter(param.clone()),
+            ExpressionToken::Function(func) => ExpressionToken::Function(match func {
+                Function::Abs(e) => Function::Abs(arg(e)),
+                Function::Acos(e) => Function::Acos(arg(e)),
+                Function::Asin(e) => Function::Asin(arg(e)),
+                Function::Atan((a, b)) => Function::Atan((arg(a), arg(b))),
+                Function::Cos(e) => Function::Cos(arg(e)),
+                Function::Exists(param) => Function::Exists(param.clone()),
+                Function::Exp(e) => Function::Exp(arg(e)),
+                Function::Floor(e) => Function::Floor(arg(e)),
+                Function::Ceil(e) => Function::Ceil(arg(e)),
+                Function::Ln(e) => Function::Ln(arg(e)),
+                Function::Round(e) => Function::Round(arg(e)),
+                Function::Sin(e) => Function::Sin(arg(e)),
+                Function::Sqrt(e) => Function::Sqrt(arg(e)),
+                Function::Tan(e) => Function::Tan(arg(e)),
+            }),
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::{gcode::expression, ArithmeticOperator};
+
+    #[test]
+    fn numbered_default_to_zero() {
+        let mut params = Parameters::new();
+
+        let (_, expr) = expression::<(), f32>("[#1 + 2]").unwrap();
+
+        assert_eq!(params.evaluate(&expr), Ok(2.0));
+    }
+
+    #[test]
+    fn undefined_named() {
+        let mut params = Parameters::new();
+
+        let (_, expr) = expression::<(), f32>("[#<width> * 2]").unwrap();
+
+        assert_eq!(
+            params.evaluate(&expr),
+            Err(ErrorKind::UndefinedParameter(Parameter::Local(
+                "width".into()
+            )))
+        );
+
+        params.set(Parameter::Local("width".into()), 3.0);
+
+        assert_eq!(params.evaluate(&expr), Ok(6.0));
+    }
+
+    #[test]
+    fn unsigned_expression() {
+        let mut params = Parameters::new();
+
+        params.set(Parameter::Numbered(5), 2.0);
+
+        let expr = UnsignedValue::Expression(
+            vec![
+                ExpressionToken::Parameter(Parameter::Numbered(5)),
+                ExpressionToken::ArithmeticOperator(ArithmeticOperator::Add),
+                ExpressionToken::Literal(1),
+            ]
+            .into(),
+        );
+
+        assert_eq!(params.unsigned(&expr), Ok(3));
+    }
+
+    #[test]
+    fn locals() {
+        let mut params = Parameters::new();
+
+        params.set(Parameter::Numbered(1), 1.0);
+        params.set(Parameter::Numbered(100), 100.0);
+        params.set(Parameter::Local("local".into()), 2.0);
+        params.set(Parameter::Global("global".into()), 3.0);
+
+        let saved = params.take_locals();
+
+        assert_eq!(params.get(&Parameter::Numbered(1)), Some(0.0));
+        assert_eq!(params.get(&Parameter::Local("local".into())), None);
+        assert_eq!(params.get(&Parameter::Numbered(100)), Some(100.0));
+
+        params.set(Parameter::Numbered(2), 5.0);
+        params.restore_locals(saved);
+
+        assert_eq!(params.get(&Parameter::Numbered(1)), Some(1.0));
+        assert_eq!(params.get(&Parameter::Numbered(2)), Some(0.0));
+        assert_eq!(params.get(&Parameter::Local("local".into())), Some(2.0));
+    }
+}
@@ -0,0 +1,373 @@
+//! Spline moves (`G5`, `G5.1` and `G5.2`), and converting them into straight line segments
+//!
+//! Cubic and quadratic splines are Bézier curves, which are the simplest NURBS curves, so every
+//! spline is kept as a non-uniform rational B-spline with a clamped, uniform knot vector like
+//! LinuxCNC's. Everything that needs splines as polylines should go through
+//! [`Spline::linearise`](struct.Spline.html#method.linearise), like arcs.
+
+use crate::error::ErrorKind;
+use crate::Vector9;
+
+/// How many times a span of the curve may be halved to get within tolerance
+const MAX_DEPTH: u32 = 16;
+
+/// How far the polyline used to find the bounds of a spline may stray from it, in millimeters
+const BOUNDS_TOLERANCE: f64 = 1.0e-4;
+
+/// A non-uniform rational B-spline in absolute machine coordinates
+///
+/// The curve starts at the first control point and ends at the last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spline {
+    /// Control points, from the start position to the end position
+    pub points: Vec<Vector9>,
+
+    /// The weight of each control point
+    pub weights: Vec<f64>,
+
+    /// Order of the curve, one more than its degree
+    pub order: usize,
+}
+
+impl Spline {
+    /// A cubic Bézier curve (`G5`)
+    pub fn cubic(from: Vector9, first: Vector9, second: Vector9, to: Vector9) -> Self {
+        Self {
+            points: vec![from, first, second, to],
+            weights: vec![1.0; 4],
+            order: 4,
+        }
+    }
+
+    /// A quadratic Bézier curve (`G5.1`)
+    pub fn quadratic(from: Vector9, control: Vector9, to: Vector9) -> Self {
+        Self {
+            points: vec![from, control, to],
+            weights: vec![1.0; 3],
+            order: 3,
+        }
+    }
+
+    /// A NURBS curve through weighted control points, of the given order (`G5.2`)
+    ///
+    /// There must be at least two control points, at least as many as the order, and every
+    /// weight must be positive.
+    pub fn nurbs(points: Vec<Vector9>, weights: Vec<f64>, order: usize) -> Result<Self, ErrorKind> {
+        if order < 2 {
+            return Err(ErrorKind::InvalidWords(
+                "NURBS curves must be at least order 2",
+            ));
+        }
+
+        if points.len() < order {
+            return Err(ErrorKind::InvalidWords(
+                "NURBS curves need at least as many control points as their order",
+            ));
+        }
+
+        if weights.len() != points.len() || weights.iter().any(|weight| *weight <= 0.0) {
+            return Err(ErrorKind::InvalidWords(
+                "NURBS control point weights must be positive",
+            ));
+        }
+
+        Ok(Self {
+            points,
+            weights,
+            order,
+        })
+    }
+
+    /// Start position
+    pub fn from(&self) -> &Vector9 {
+        &self.points[0]
+    }
+
+    /// End position
+    pub fn to(&self) -> &Vector9 {
+        &self.points[self.points.len() - 1]
+    }
+
+    /// The number of spans between distinct knots
+    fn spans(&self) -> usize {
+        self.points.len() + 1 - self.order
+    }
+
+    /// Knot `index` of the clamped, uniform knot vector, which runs from `0` to `spans()`
+    fn knot(&self, index: usize) -> f64 {
+        let knot = index.saturating_sub(self.order - 1).min(self.spans());
+
+        knot as f64
+    }
+
+    /// The point at a given fraction of the way through the curve's parameter, from `0.0` to
+    /// `1.0`
+    ///
+    /// This is not the same fraction of the distance along the curve.
+    pub fn point(&self, fraction: f64) -> Vector9 {
+        let degree = self.order - 1;
+        let u = fraction.clamp(0.0, 1.0) * self.spans() as f64;
+
+        // The control points that affect this span
+        let span = (u.floor() as usize).min(self.spans() - 1) + degree;
+
+        let mut points = (0..=degree)
+            .map(|j| {
+                let index = span - degree + j;
+
+                (
+                    self.points[index] * self.weights[index],
+                    self.weights[index],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // De Boor's algorithm on the weighted points
+        for r in 1..=degree {
+            for j in (r..=degree).rev() {
+                let index = span - degree + j;
+                let start = self.knot(index);
+                let alpha = (u - start) / (self.knot(index + self.order - r) - start);
+
+                let (previous, previous_weight) = points[j - 1];
+                let (point, weight) = &mut points[j];
+
+                *point = previous * (1.0 - alpha) + *point * alpha;
+                *weight = previous_weight * (1.0 - alpha) + *weight * alpha;
+            }
+        }
+
+        let (point, weight) = points[degree];
+
+        point / weight
+    }
+
+    /// Split the spline into straight line segments that stray from the curve by no more than
+    /// `tolerance`
+    ///
+    /// Each span between knots is halved until the curve at a quarter, half and three quarters
+    /// of the way through each piece is within tolerance of the straight line across it. The
+    /// start point is not included, and the last point is always exactly the end point.
+    pub fn linearise(&self, tolerance: f64) -> Vec<Vector9> {
+        let spans = self.spans();
+        let mut points = Vec::new();
+
+        let mut start = (0.0, *self.from());
+
+        for span in 1..=spans {
+            let fraction = span as f64 / spans as f64;
+            let end = (fraction, self.point(fraction));
+
+            self.subdivide(start, end, tolerance, 0, &mut points);
+
+            start = end;
+        }
+
+        if let Some(last) = points.last_mut() {
+            *last = *self.to();
+        }
+
+        points
+    }
+
+    fn subdivide(
+        &self,
+        start: (f64, Vector9),
+        end: (f64, Vector9),
+        tolerance: f64,
+        depth: u32,
+        points: &mut Vec<Vector9>,
+    ) {
+        let fraction = |part: f64| start.0 + (end.0 - start.0) * part;
+        let middle = (fraction(0.5), self.point(fraction(0.5)));
+
+        let within = depth >= MAX_DEPTH
+            || [0.25, 0.5, 0.75].iter().all(|part| {
+                let point = if *part == 0.5 {
+                    middle.1
+                } else {
+                    self.point(fraction(*part))
+                };
+
+                distance_to_segment(&point, &start.1, &end.1) <= tolerance
+            });
+
+        if within {
+            points.push(end.1);
+        } else {
+            self.subdivide(start, middle, tolerance, depth + 1, points);
+            self.subdivide(middle, end, tolerance, depth + 1, points);
+        }
+    }
+
+    /// Length of the curve, to within a ten thousandth of a millimeter or so
+    pub fn length(&self) -> f64 {
+        let mut from = *self.from();
+
+        self.linearise(BOUNDS_TOLERANCE)
+            .into_iter()
+            .map(|to| {
+                let length = (to - from).norm();
+
+                from = to;
+
+                length
+            })
+            .sum()
+    }
+
+    /// The smallest box containing the whole curve, to within a ten thousandth of a millimeter,
+    /// as its minimum and maximum corners
+    pub fn bounds(&self) -> (Vector9, Vector9) {
+        let from = *self.from();
+
+        self.linearise(BOUNDS_TOLERANCE)
+            .into_iter()
+            .fold((from, from), |(min, max), point| {
+                (min.zip_map(&point, f64::min), max.zip_map(&point, f64::max))
+            })
+    }
+}
+
+/// The distance from a point to the closest point on a line segment
+fn distance_to_segment(point: &Vector9, from: &Vector9, to: &Vector9) -> f64 {
+    let line = to - from;
+    let length = line.norm_squared();
+
+    if length == 0.0 {
+        return (point - from).norm();
+    }
+
+    let along = ((point - from).dot(&line) / length).clamp(0.0, 1.0);
+
+    (point - (from + line * along)).norm()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1.0e-9;
+
+    fn xy(x: f64, y: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = x;
+        v[1] = y;
+        v
+    }
+
+    #[test]
+    fn cubic_bezier() {
+        let spline = Spline::cubic(xy(0.0, 0.0), xy(1.0, 2.0), xy(3.0, 2.0), xy(4.0, 0.0));
+
+        // Bernstein polynomials
+        for n in 0..=10 {
+            let t = f64::from(n) / 10.0;
+            let s = 1.0 - t;
+
+            let expected = xy(0.0, 0.0) * s.powi(3)
+                + xy(1.0, 2.0) * 3.0 * s * s * t
+                + xy(3.0, 2.0) * 3.0 * s * t * t
+                + xy(4.0, 0.0) * t.powi(3);
+
+            assert!((spline.point(t) - expected).norm() < EPSILON, "{}", t);
+        }
+    }
+
+    #[test]
+    fn quadratic_bezier() {
+        let spline = Spline::quadratic(xy(0.0, 0.0), xy(1.0, 1.0), xy(2.0, 0.0));
+
+        assert!((spline.point(0.5) - xy(1.0, 0.5)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn rational_circle() {
+        // A quarter circle is a rational quadratic with a middle weight of cos(45°)
+        let spline = Spline::nurbs(
+            vec![xy(1.0, 0.0), xy(1.0, 1.0), xy(0.0, 1.0)],
+            vec![1.0, 0.5f64.sqrt(), 1.0],
+            3,
+        )
+        .unwrap();
+
+        for n in 0..=20 {
+            let point = spline.point(f64::from(n) / 20.0);
+
+            assert!((point.norm() - 1.0).abs() < EPSILON);
+        }
+
+        assert!((spline.length() - std::f64::consts::PI / 2.0).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn clamped_ends() {
+        let points = vec![
+            xy(0.0, 0.0),
+            xy(0.0, 1.0),
+            xy(2.0, 2.0),
+            xy(2.0, 0.0),
+            xy(0.0, 0.0),
+        ];
+
+        let spline = Spline::nurbs(points, vec![1.0, 1.0, 1.0, 1.0, 2.0], 3).unwrap();
+
+        assert_eq!(spline.point(0.0), xy(0.0, 0.0));
+        assert!(spline.point(1.0).norm() < EPSILON);
+
+        // The curve stays inside the control polygon
+        for n in 0..=20 {
+            let point = spline.point(f64::from(n) / 20.0);
+
+            assert!(point[0] >= -EPSILON && point[0] <= 2.0 + EPSILON);
+            assert!(point[1] >= -EPSILON && point[1] <= 2.0 + EPSILON);
+        }
+    }
+
+    #[test]
+    fn linearise_within_tolerance() {
+        let spline = Spline::cubic(xy(0.0, 0.0), xy(0.0, 10.0), xy(10.0, -10.0), xy(10.0, 0.0));
+
+        for tolerance in [0.1, 0.01, 0.001].iter() {
+            let points = spline.linearise(*tolerance);
+
+            assert_eq!(points.last(), Some(&xy(10.0, 0.0)));
+
+            // Check the curve against the polyline at many points
+            let mut polyline = vec![xy(0.0, 0.0)];
+            polyline.extend(points.iter().cloned());
+
+            for n in 0..=1000 {
+                let point = spline.point(f64::from(n) / 1000.0);
+
+                let distance = polyline
+                    .windows(2)
+                    .map(|pair| distance_to_segment(&point, &pair[0], &pair[1]))
+                    .fold(f64::INFINITY, f64::min);
+
+                assert!(distance <= tolerance * 1.01, "{} > {}", distance, tolerance);
+            }
+        }
+
+        // Tighter tolerances take more segments
+        assert!(spline.linearise(0.001).len() > spline.linearise(0.1).len());
+    }
+
+    #[test]
+    fn bounds() {
+        let spline = Spline::quadratic(xy(0.0, 0.0), xy(1.0, 2.0), xy(2.0, 0.0));
+
+        let (min, max) = spline.bounds();
+
+        assert!((max[1] - 1.0).abs() < BOUNDS_TOLERANCE);
+        assert!((max[0] - 2.0).abs() < EPSILON);
+        assert!(min.norm() < EPSILON);
+    }
+
+    #[test]
+    fn invalid_nurbs() {
+        assert!(Spline::nurbs(vec![xy(0.0, 0.0), xy(1.0, 0.0)], vec![1.0, 1.0], 3).is_err());
+        assert!(Spline::nurbs(vec![xy(0.0, 0.0), xy(1.0, 0.0)], vec![1.0, 0.0], 2).is_err());
+        assert!(Spline::nurbs(vec![xy(0.0, 0.0), xy(1.0, 0.0)], vec![1.0, 1.0], 2).is_ok());
+    }
+}
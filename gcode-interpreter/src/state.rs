@@ -0,0 +1,255 @@
+use crate::canonical::SpindleDirection;
+use crate::Vector9;
+use gcode_parser::token::PlaneSelect;
+
+/// Program units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    /// Millimeters (`G21`)
+    Millimeters,
+
+    /// Inches (`G20`)
+    Inches,
+}
+
+/// How coordinates are interpreted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMode {
+    /// Coordinates are relative to the current coordinate system origin (`G90`, `G90.1`)
+    Absolute,
+
+    /// Coordinates are relative to the current position (`G91`, `G91.1`)
+    Incremental,
+}
+
+/// How feed rates are interpreted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedMode {
+    /// Units per minute along the move (`G94`)
+    UnitsPerMinute,
+
+    /// Each feed move takes one over the feed rate minutes, so `F` must be given on every feed
+    /// move (`G93`)
+    InverseTime,
+}
+
+/// The active motion mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionMode {
+    /// `G0`
+    Rapid,
+
+    /// `G1`
+    Linear,
+
+    /// `G2`
+    ClockwiseArc,
+
+    /// `G3`
+    CounterclockwiseArc,
+
+    /// Spindle synchronised motion (`G33`)
+    SpindleSynchronized,
+
+    /// Rigid tapping (`G33.1`)
+    RigidTap,
+
+    /// `G5`
+    CubicSpline,
+
+    /// `G5.1`
+    QuadraticSpline,
+
+    /// Collecting NURBS control points (`G5.2`)
+    Nurbs,
+
+    /// A drilling or boring canned cycle, run at every position given until `G80` or another
+    /// motion mode
+    CannedCycle(CannedCycle),
+}
+
+/// A drilling or boring canned cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CannedCycle {
+    /// Drill in pecks of `Q`, backing off a little after each to break the chip (`G73`)
+    ChipBreak,
+
+    /// Drill to the bottom and rapid out (`G81`)
+    Drill,
+
+    /// Drill to the bottom, dwell for `P` seconds and rapid out (`G82`)
+    DrillDwell,
+
+    /// Drill in pecks of `Q`, rapiding out of the hole after each to clear the chips (`G83`)
+    PeckDrill,
+
+    /// Bore to the bottom and feed back out (`G85`)
+    Bore,
+
+    /// Bore to the bottom, dwell for `P` seconds and feed back out (`G89`)
+    BoreDwell,
+}
+
+/// Where canned cycles retract to after each hole
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetractMode {
+    /// The higher of the `R` plane and the `Z` position before the cycles started (`G98`)
+    OldZ,
+
+    /// The `R` plane (`G99`)
+    RPlane,
+}
+
+/// How consecutive moves are joined together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathMode {
+    /// Follow the programmed path exactly, stopping wherever it changes direction (`G61`)
+    ExactPath,
+
+    /// Stop at the end of every move (`G61.1`)
+    ExactStop,
+
+    /// Blend corners together (`G64`)
+    Blend {
+        /// The maximum distance in millimeters the machine may deviate from the programmed path,
+        /// or as far as needed to keep up speed if not given (`P`)
+        tolerance: Option<f64>,
+
+        /// Runs of moves that all lie within this many millimeters of a straight line may be
+        /// merged into one move (`Q`)
+        naive_tolerance: Option<f64>,
+    },
+}
+
+/// The side of the programmed path cutter compensation offsets the tool to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompensationSide {
+    /// `G41` or `G41.1`
+    Left,
+
+    /// `G42` or `G42.1`
+    Right,
+}
+
+/// Modal machine state
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    /// Current position in machine coordinates
+    ///
+    /// While cutter compensation is on this is the programmed position, not where the tool is.
+    pub position: Vector9,
+
+    /// Active motion mode, if one has been set
+    pub motion: Option<MotionMode>,
+
+    /// Program units
+    pub units: Units,
+
+    /// Distance mode for axis words
+    pub distance: DistanceMode,
+
+    /// Distance mode for arc center `IJK` words
+    pub arc_distance: DistanceMode,
+
+    /// Active plane
+    pub plane: PlaneSelect,
+
+    /// Active work offset, `0` for `G54` to `8` for `G59.3`
+    pub work_offset: usize,
+
+    /// Offset applied by `G92`, in machine units
+    pub axis_offset: Vector9,
+
+    /// Active tool length offset, in machine units
+    pub tool_length_offset: Vector9,
+
+    /// Feed rate in millimeters per minute, or moves per minute in inverse time mode
+    pub feed: f64,
+
+    /// How the feed rate is interpreted
+    pub feed_mode: FeedMode,
+
+    /// Spindle speed in RPM
+    pub spindle_speed: f64,
+
+    /// Spindle direction
+    pub spindle_direction: SpindleDirection,
+
+    /// Tool number selected by the last `T` word
+    pub selected_tool: u32,
+
+    /// Tool number in the spindle
+    pub tool: u32,
+
+    /// Whether mist coolant is on
+    pub mist: bool,
+
+    /// Whether flood coolant is on
+    pub flood: bool,
+
+    /// Path control mode
+    pub path: PathMode,
+
+    /// Which side of the path the tool is offset to, if cutter compensation is on
+    pub compensation: Option<CompensationSide>,
+
+    /// Where canned cycles retract to
+    pub retract: RetractMode,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            position: Vector9::zeros(),
+            motion: None,
+            units: Units::Millimeters,
+            distance: DistanceMode::Absolute,
+            arc_distance: DistanceMode::Incremental,
+            plane: PlaneSelect::XY,
+            work_offset: 0,
+            axis_offset: Vector9::zeros(),
+            tool_length_offset: Vector9::zeros(),
+            feed: 0.0,
+            feed_mode: FeedMode::UnitsPerMinute,
+            spindle_speed: 0.0,
+            spindle_direction: SpindleDirection::Stopped,
+            selected_tool: 0,
+            tool: 0,
+            mist: false,
+            flood: false,
+            path: PathMode::Blend {
+                tolerance: None,
+                naive_tolerance: None,
+            },
+            compensation: None,
+            retract: RetractMode::OldZ,
+        }
+    }
+}
+
+impl State {
+    /// Factor to convert program units into millimeters
+    pub fn unit_scale(&self) -> f64 {
+        match self.units {
+            Units::Millimeters => 1.0,
+            Units::Inches => crate::MM_PER_INCH,
+        }
+    }
+}
+
+/// Indices of the first and second axes in a plane, and of the axis normal to it
+pub(crate) fn plane_axes(plane: &PlaneSelect) -> (usize, usize, usize) {
+    match plane {
+        PlaneSelect::XY => (0, 1, 2),
+        PlaneSelect::ZX => (2, 0, 1),
+        PlaneSelect::YZ => (1, 2, 0),
+        PlaneSelect::UV => (3, 4, 5),
+        PlaneSelect::WU => (5, 3, 4),
+        PlaneSelect::VW => (4, 5, 3),
+    }
+}
+
+/// Whether an axis index is a rotary axis (`ABC`) which is not scaled by program units
+pub(crate) fn is_rotary(axis: usize) -> bool {
+    axis >= 6
+}
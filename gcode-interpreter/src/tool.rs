@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+/// A single tool table entry
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Tool {
+    /// Tool length offset in millimeters, applied to the Z axis by `G43`
+    pub length: f64,
+
+    /// Tool diameter in millimeters
+    pub diameter: f64,
+
+    /// Lathe tool orientation from 1 to 9, which sets where the imaginary tip is compared to the
+    /// center of the nose for cutter compensation in the `XZ` plane, or 0 for a mill tool
+    pub orientation: u32,
+}
+
+/// Tools available to a program, keyed by tool number
+pub type ToolTable = HashMap<u32, Tool>;
@@ -0,0 +1,518 @@
+//! Replace runs of short straight moves with the arcs they approximate
+//!
+//! CAM output often describes curves as thousands of tiny `G1` moves. Where a run of consecutive
+//! moves in the `XY` plane all lie on one circle to within a tolerance, the run is replaced with a
+//! single center format `G2` or `G3` arc that ends exactly where the run ended.
+//!
+//! Only top level lines whose effect is fully known are considered: moves with literal absolute
+//! coordinates in `G1`, `G17` and `G90` mode, carrying nothing but a line number, `G1` and the
+//! coordinates themselves. Any other line ends the current run.
+
+use crate::line::Line;
+use crate::modal::{has_motion_code, insert_feed_code, is_move, uses_motion_mode, Modal};
+use crate::program::Program;
+use crate::token::{CenterFormatArc, GCode, Token, TokenType, Value};
+use std::f64::consts::PI;
+
+/// The most moves that are merged into a single arc
+///
+/// Each candidate arc is checked against every point it replaces, so this bounds the time spent
+/// fitting very long runs.
+const MAX_ARC_MOVES: usize = 256;
+
+/// Runs that turn further than this are left alone, as an arc that ends close to where it started
+/// could be mistaken for a full circle
+const MAX_SWEEP: f64 = 2.0 * PI - 0.1;
+
+/// Arc fitting options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArcFitOptions {
+    /// The furthest any replaced move's end point or midpoint may be from the fitted arc, in
+    /// program units
+    pub tolerance: f64,
+
+    /// The fewest consecutive moves worth replacing with an arc
+    pub min_moves: usize,
+
+    /// Runs that only fit a circle larger than this radius, in program units, are left as straight
+    /// moves
+    pub max_radius: f64,
+}
+
+impl ArcFitOptions {
+    /// Fit arcs within the given tolerance to runs of at least 3 moves, with radii up to 1000
+    pub fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            min_moves: 3,
+            max_radius: 1000.0,
+        }
+    }
+}
+
+/// What arc fitting did to a program
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ArcFitReport {
+    /// The number of top level lines that moved the machine before fitting
+    pub moves_before: usize,
+
+    /// The number of top level lines that move the machine after fitting
+    pub moves_after: usize,
+
+    /// The number of arcs that replaced runs of straight moves
+    pub arcs: usize,
+}
+
+impl ArcFitReport {
+    /// How many times fewer moves the fitted program has
+    pub fn reduction_ratio(&self) -> f64 {
+        if self.moves_after == 0 {
+            1.0
+        } else {
+            self.moves_before as f64 / self.moves_after as f64
+        }
+    }
+}
+
+/// A circle through the points of a run, and which way the run goes round it
+#[derive(Debug, Clone, Copy)]
+struct Circle {
+    center: (f64, f64),
+    clockwise: bool,
+}
+
+/// The end point of a line that could be part of an arc, if it is one
+fn candidate(modal: &Modal, line: &Line) -> Option<(f64, f64)> {
+    // Arcs can only be written once the plane and arc center mode are known
+    if modal.xy_plane != Some(true) || modal.absolute_arc_centers.is_none() {
+        return None;
+    }
+
+    let (from_x, from_y) = (modal.position[0]?, modal.position[1]?);
+    let [x, y, z] = modal.linear_move(line)?;
+
+    // Helical moves aren't fitted
+    if let Some(z) = z {
+        if modal.position[2] != Some(z) {
+            return None;
+        }
+    }
+
+    Some((x.unwrap_or(from_x), y.unwrap_or(from_y)))
+}
+
+/// Find the circle through a run of points, if every point and every move between them is within
+/// tolerance of it
+fn fit(points: &[(f64, f64)], options: &ArcFitOptions) -> Option<Circle> {
+    let (a, b, c) = (
+        points[0],
+        points[points.len() / 2],
+        points[points.len() - 1],
+    );
+
+    let d = 2.0 * (a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+
+    if d.abs() < 1.0e-12 {
+        return None;
+    }
+
+    let square = |p: (f64, f64)| p.0 * p.0 + p.1 * p.1;
+
+    let center = (
+        (square(a) * (b.1 - c.1) + square(b) * (c.1 - a.1) + square(c) * (a.1 - b.1)) / d,
+        (square(a) * (c.0 - b.0) + square(b) * (a.0 - c.0) + square(c) * (b.0 - a.0)) / d,
+    );
+
+    let distance = |p: (f64, f64)| (p.0 - center.0).hypot(p.1 - center.1);
+    let radius = distance(a);
+
+    if radius > options.max_radius {
+        return None;
+    }
+
+    let mut sweep = 0.0;
+
+    for pair in points.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let midpoint = ((from.0 + to.0) / 2.0, (from.1 + to.1) / 2.0);
+
+        if (distance(to) - radius).abs() > options.tolerance
+            || (distance(midpoint) - radius).abs() > options.tolerance
+        {
+            return None;
+        }
+
+        let (u, v) = (
+            (from.0 - center.0, from.1 - center.1),
+            (to.0 - center.0, to.1 - center.1),
+        );
+
+        sweep += (u.0 * v.1 - u.1 * v.0).atan2(u.0 * v.0 + u.1 * v.1);
+    }
+
+    // Every move must turn the same way as the whole run, by less than a quarter turn
+    let counterclockwise = sweep > 0.0;
+
+    let consistent = points.windows(2).all(|pair| {
+        let (u, v) = (
+            (pair[0].0 - center.0, pair[0].1 - center.1),
+            (pair[1].0 - center.0, pair[1].1 - center.1),
+        );
+
+        let angle = (u.0 * v.1 - u.1 * v.0).atan2(u.0 * v.0 + u.1 * v.1);
+
+        (angle > 0.0) == counterclockwise && angle.abs() < PI / 2.0
+    });
+
+    if !consistent || sweep.abs() > MAX_SWEEP {
+        return None;
+    }
+
+    Some(Circle {
+        center,
+        clockwise: !counterclockwise,
+    })
+}
+
+/// Output of the fitting pass
+#[derive(Debug, Default)]
+struct Fitter {
+    lines: Vec<Line>,
+    report: ArcFitReport,
+    /// The last move written was an arc, so following moves that rely on `G1` being active need
+    /// to say so again
+    needs_linear: bool,
+}
+
+impl Fitter {
+    /// Write a line from the original program
+    fn push(&mut self, line: &Line) {
+        let mut line = line.clone();
+
+        if has_motion_code(&line) {
+            self.needs_linear = false;
+        } else if self.needs_linear && uses_motion_mode(&line) {
+            insert_feed_code(&mut line);
+
+            self.needs_linear = false;
+        }
+
+        if is_move(&line) {
+            self.report.moves_after += 1;
+        }
+
+        self.lines.push(line);
+    }
+
+    /// Replace a run of moves with arcs where they fit, starting from `start`
+    fn flush(
+        &mut self,
+        start: (f64, f64),
+        run: &mut Vec<(&Line, (f64, f64))>,
+        modal: &Modal,
+        options: &ArcFitOptions,
+    ) {
+        let points: Vec<(f64, f64)> = std::iter::once(start)
+            .chain(run.iter().map(|(_, point)| *point))
+            .collect();
+
+        let mut first = 0;
+
+        while first < run.len() {
+            let mut best = None;
+            let mut last = first + options.min_moves.max(2);
+
+            while last <= run.len() && last - first <= MAX_ARC_MOVES {
+                match fit(&points[first..=last], options) {
+                    Some(circle) => best = Some((last, circle)),
+                    None => break,
+                }
+
+                last += 1;
+            }
+
+            match best {
+                Some((last, circle)) => {
+                    self.push_arc(run[first].0, points[first], points[last], circle, modal);
+
+                    first = last;
+                }
+                None => {
+                    self.push(run[first].0);
+
+                    first += 1;
+                }
+            }
+        }
+
+        run.clear();
+    }
+
+    fn push_arc(
+        &mut self,
+        first: &Line,
+        from: (f64, f64),
+        to: (f64, f64),
+        circle: Circle,
+        modal: &Modal,
+    ) {
+        let offset = if modal.absolute_arc_centers == Some(true) {
+            circle.center
+        } else {
+            (circle.center.0 - from.0, circle.center.1 - from.1)
+        };
+
+        let mut tokens: Vec<Token> = first
+            .iter()
+            .filter(|token| matches!(token.token, TokenType::LineNumber(_)))
+            .cloned()
+            .collect();
+
+        tokens.push(Token {
+            token: TokenType::GCode(if circle.clockwise {
+                GCode::ClockwiseArc
+            } else {
+                GCode::CounterclockwiseArc
+            }),
+        });
+
+        tokens.push(Token {
+            token: TokenType::CenterFormatArc(CenterFormatArc {
+                x: Some(Value::Literal(to.0 as f32)),
+                y: Some(Value::Literal(to.1 as f32)),
+                i: Some(Value::Literal(offset.0 as f32)),
+                j: Some(Value::Literal(offset.1 as f32)),
+                ..CenterFormatArc::default()
+            }),
+        });
+
+        self.lines.push(Line { tokens });
+        self.report.moves_after += 1;
+        self.report.arcs += 1;
+        self.needs_linear = true;
+    }
+}
+
+/// Replace runs of straight moves that lie on a circle with arcs
+///
+/// Returns the fitted program along with a report of how much smaller it is.
+pub fn fit_arcs(program: &Program, options: &ArcFitOptions) -> (Program, ArcFitReport) {
+    let mut fitter = Fitter::default();
+    let mut modal = Modal::default();
+
+    let mut run: Vec<(&Line, (f64, f64))> = Vec::new();
+    let mut start = (0.0, 0.0);
+
+    for line in program.lines() {
+        if is_move(line) {
+            fitter.report.moves_before += 1;
+        }
+
+        if let Some(point) = candidate(&modal, line) {
+            if run.is_empty() {
+                start = (modal.position[0].unwrap(), modal.position[1].unwrap());
+            }
+
+            run.push((line, point));
+
+            modal.follow([Some(point.0), Some(point.1), None]);
+        } else {
+            fitter.flush(start, &mut run, &modal, options);
+            fitter.push(line);
+
+            modal.apply(line);
+        }
+    }
+
+    fitter.flush(start, &mut run, &modal, options);
+
+    (Program::from_lines(fitter.lines), fitter.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// A program cutting round a circle of radius 10 centered on the origin in `count` moves,
+    /// starting from the positive X axis
+    fn polygon(count: usize, turn: f64) -> String {
+        let mut program = String::from("G0 X10 Y0\n");
+
+        for n in 1..=count {
+            let angle = turn * n as f64 / count as f64;
+
+            program.push_str(&format!(
+                "N{} G1 X{:.4} Y{:.4}\n",
+                n,
+                10.0 * angle.cos(),
+                10.0 * angle.sin()
+            ));
+        }
+
+        program
+    }
+
+    fn arcs(program: &Program) -> Vec<(GCode, CenterFormatArc)> {
+        let mut arcs = Vec::new();
+        let mut code = None;
+
+        for token in program.iter_flat() {
+            match &token.token {
+                TokenType::GCode(c) => code = Some(c.clone()),
+                TokenType::CenterFormatArc(arc) => arcs.push((code.clone().unwrap(), arc.clone())),
+                _ => (),
+            }
+        }
+
+        arcs
+    }
+
+    #[test]
+    fn half_circle() {
+        let program = Program::from_str(&(polygon(50, PI) + "X-20\nM2")).unwrap();
+
+        let (fitted, report) = fit_arcs(&program, &ArcFitOptions::new(0.01));
+
+        assert_eq!(report.moves_before, 52);
+        assert_eq!(report.arcs, 1);
+        assert_eq!(report.moves_after, 3);
+
+        let arcs = arcs(&fitted);
+
+        assert_eq!(arcs.len(), 1);
+
+        let (code, arc) = &arcs[0];
+
+        assert_eq!(*code, GCode::CounterclockwiseArc);
+        assert!((arc.x.as_ref().unwrap().as_f64_unchecked() + 10.0).abs() < 1.0e-4);
+        assert!(arc.y.as_ref().unwrap().as_f64_unchecked().abs() < 1.0e-4);
+        assert!((arc.i.as_ref().unwrap().as_f64_unchecked() + 10.0).abs() < 1.0e-3);
+        assert!(arc.j.as_ref().unwrap().as_f64_unchecked().abs() < 1.0e-3);
+
+        // The straight move after the arc has to switch back to G1
+        let last = &fitted.lines()[fitted.lines().len() - 2];
+
+        assert_eq!(
+            last.iter().next().map(|token| &token.token),
+            Some(&TokenType::GCode(GCode::Feed))
+        );
+    }
+
+    #[test]
+    fn clockwise_and_corners() {
+        // A clockwise half circle, then a sharp corner into a separate counterclockwise one
+        let mut program = polygon(30, -PI);
+
+        program.push_str("G1 X-10 Y10\n");
+
+        for n in 1..=30 {
+            let angle = PI / 2.0 - PI * n as f64 / 30.0;
+
+            program.push_str(&format!(
+                "X{:.4} Y{:.4}\n",
+                -10.0 + 10.0 * angle.cos(),
+                10.0 * angle.sin()
+            ));
+        }
+
+        let program = Program::from_str(&program).unwrap();
+
+        let (fitted, report) = fit_arcs(&program, &ArcFitOptions::new(0.02));
+
+        let codes: Vec<GCode> = arcs(&fitted).into_iter().map(|(code, _)| code).collect();
+
+        assert_eq!(codes, vec![GCode::ClockwiseArc, GCode::ClockwiseArc]);
+        assert_eq!(report.arcs, 2);
+        assert_eq!(report.moves_after, 4);
+    }
+
+    #[test]
+    fn leaves_unknowns_alone() {
+        let unchanged = [
+            // Straight lines
+            "G0 X0 Y0\nG1 X1\nX2\nX3\nX4",
+            // Incremental moves
+            &format!("G91\n{}", polygon(20, PI)),
+            // Zig-zags
+            "G0 X0 Y0\nG1 X1 Y1\nX2 Y0\nX3 Y1\nX4 Y0",
+            // Another plane
+            &format!("G18\n{}", polygon(20, PI)),
+            // Incremental moves after an assignment
+            &format!("G91\nG1 X0 Y0 F100\n#1=2\n{}", polygon(50, PI)),
+            // A subroutine could switch to incremental moves or another plane
+            &format!("o100 call\n{}", polygon(50, PI)),
+        ];
+
+        for program in unchanged.iter() {
+            let program = Program::from_str(program).unwrap();
+
+            let (fitted, report) = fit_arcs(&program, &ArcFitOptions::new(0.01));
+
+            assert_eq!(report.arcs, 0, "{:?}", program);
+            assert_eq!(fitted, program);
+        }
+    }
+
+    #[test]
+    fn modes_set_again() {
+        // Assignments only forget the position, and a subroutine's modes can be set again
+        for prefix in ["#1=2\n", "o100 call\nG90 G17 G91.1\n"].iter() {
+            let program = Program::from_str(&format!("{}{}", prefix, polygon(50, PI))).unwrap();
+
+            let (_, report) = fit_arcs(&program, &ArcFitOptions::new(0.01));
+
+            assert_eq!(report.arcs, 1, "{:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn tinyg_files() {
+        for (file, ratio) in [("spiro.gcode", 10.0), ("girl.gcode", 1.5)].iter() {
+            let program = fs::read_to_string(Path::new("../test_files/tinyg").join(file)).unwrap();
+            let program = Program::from_str(&program).unwrap();
+
+            let (fitted, report) = fit_arcs(&program, &ArcFitOptions::new(0.002));
+
+            assert!(
+                report.reduction_ratio() > *ratio,
+                "{} reduced by {}",
+                file,
+                report.reduction_ratio()
+            );
+            assert_eq!(
+                report.moves_after,
+                fitted.lines().iter().filter(|line| is_move(line)).count()
+            );
+
+            // Every arc starts and ends the same distance from its center
+            let mut position = (0.0, 0.0);
+
+            for token in fitted.iter_flat() {
+                match &token.token {
+                    TokenType::Coord(coord) => {
+                        position = (
+                            coord.x.as_ref().map_or(position.0, Value::as_f64_unchecked),
+                            coord.y.as_ref().map_or(position.1, Value::as_f64_unchecked),
+                        );
+                    }
+                    TokenType::CenterFormatArc(arc) => {
+                        let value = |v: &Option<Value>| v.as_ref().unwrap().as_f64_unchecked();
+
+                        let center = (position.0 + value(&arc.i), position.1 + value(&arc.j));
+                        let end = (value(&arc.x), value(&arc.y));
+
+                        let start_radius = (position.0 - center.0).hypot(position.1 - center.1);
+                        let end_radius = (end.0 - center.0).hypot(end.1 - center.1);
+
+                        assert!((start_radius - end_radius).abs() < 0.002, "{}", file);
+
+                        position = end;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! The flavours of GCode a program can be parsed as
+
+use crate::line::{lines, lines_with, Line};
+use crate::program::Program;
+use crate::token::block::lines_span;
+use crate::token::fanuc::fanuc_token;
+use crate::token::grbl::grbl_token;
+use crate::token::marlin::marlin_token;
+use crate::token::{
+    Block, CallKind, CutterCompensation, GCode, GrblCommand, MarlinCode, PathControl, TokenType,
+    UnsignedValue, Value,
+};
+use expression::Parameter;
+use nom::{error::ParseError, IResult};
+use std::fmt;
+
+/// The flavour of GCode a program is written in
+///
+/// The LinuxCNC dialect is the default, and the grammar the rest of the parser is based on. Other
+/// dialects parse their own codes first, falling back to LinuxCNC tokens for everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dialect {
+    /// [LinuxCNC](http://linuxcnc.org/docs/html/gcode/g-code.html)
+    #[default]
+    LinuxCnc,
+
+    /// [Marlin](https://marlinfw.org/meta/gcode/) and other RepRap 3D printer firmware
+    ///
+    /// Adds the extruder axis `E`, temperatures, fans, extrusion modes and bed levelling, reads
+    /// `G28` as homing and reads slicer metadata from `;` comments.
+    Marlin,
+
+    /// [GRBL](https://github.com/gnea/grbl/wiki) and the controllers derived from it
+    ///
+    /// Adds `$` system commands, like homing, jogging and settings, and the realtime command
+    /// bytes `?`, `!`, `~` and `0x18`.
+    Grbl,
+
+    /// [Fanuc](https://www.fanucamerica.com/) Custom Macro B
+    ///
+    /// Adds assignments without brackets, `IF [...] GOTO` jumps to sequence numbers,
+    /// `WHILE [...] DO 1` ... `END 1` loops, subprograms from `O1000` to `M99`, and `M98` and
+    /// `G65` calls.
+    Fanuc,
+}
+
+/// A construct that not every dialect can run, like control flow, parameters or printer codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Construct {
+    /// An O-word block, subroutine call or return
+    OWord,
+
+    /// A numbered parameter like `#3`
+    NumberedParameter,
+
+    /// A named parameter like `#<depth>`
+    NamedParameter,
+
+    /// An expression like `[#1 + 2]`
+    Expression,
+
+    /// A Fanuc jump to a sequence number, like `IF [#1 GT 0] GOTO 100`
+    Goto,
+
+    /// A 3D printer code for the extruder, heaters, fans or bed levelling, like `M104 S200`
+    PrinterCode,
+}
+
+/// Somewhere a program uses a construct its dialect doesn't support
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unsupported {
+    /// The 1-indexed source line the construct is on
+    pub line: usize,
+
+    /// The construct used
+    pub construct: Construct,
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Construct::OWord => "O-words",
+            Construct::NumberedParameter => "numbered parameters",
+            Construct::NamedParameter => "named parameters",
+            Construct::Expression => "expressions",
+            Construct::Goto => "GOTO jumps",
+            Construct::PrinterCode => "3D printer codes",
+        })
+    }
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {} are not supported",
+            self.line, self.construct
+        )
+    }
+}
+
+impl Dialect {
+    /// Parse the lines of a program in this dialect
+    pub(crate) fn lines<'a, E: ParseError<&'a str>>(
+        self,
+        i: &'a str,
+    ) -> IResult<&'a str, Vec<Line>, E> {
+        match self {
+            Dialect::LinuxCnc => lines(i),
+            Dialect::Marlin => lines_with(marlin_token)(i),
+            Dialect::Grbl => lines_with(grbl_token)(i),
+            Dialect::Fanuc => lines_with(fanuc_token)(i),
+        }
+    }
+
+    /// Whether programs in this dialect can use a construct
+    ///
+    /// GRBL and Marlin have no parameters or control flow at all, Fanuc has no named parameters
+    /// and LinuxCNC has no `GOTO`. Only Marlin has printer codes.
+    pub fn supports(self, construct: Construct) -> bool {
+        match self {
+            Dialect::LinuxCnc => !matches!(construct, Construct::Goto | Construct::PrinterCode),
+            Dialect::Fanuc => !matches!(
+                construct,
+                Construct::NamedParameter | Construct::PrinterCode
+            ),
+            Dialect::Marlin => construct == Construct::PrinterCode,
+            Dialect::Grbl => false,
+        }
+    }
+
+    /// Find every use of a construct in a program that this dialect can't run
+    ///
+    /// Each construct is reported once per line, in the order the lines appear, including the
+    /// lines inside blocks.
+    pub fn unsupported(self, program: &Program) -> Vec<Unsupported> {
+        let mut unsupported = Vec::new();
+
+        self.check_lines(program.lines(), 1, &mut unsupported);
+
+        unsupported
+    }
+
+    fn check_lines(self, lines: &[Line], first: usize, unsupported: &mut Vec<Unsupported>) {
+        let mut line = first;
+
+        for tokens in lines {
+            for token in tokens.iter() {
+                let mut constructs = Vec::new();
+
+                token_constructs(&token.token, &mut constructs);
+
+                for construct in constructs {
+                    let found = Unsupported { line, construct };
+
+                    if !self.supports(construct) && !unsupported.contains(&found) {
+                        unsupported.push(found);
+                    }
+                }
+
+                if let TokenType::Block(block) = &token.token {
+                    for (lines, first) in block_bodies(block, line) {
+                        self.check_lines(lines, first, unsupported);
+                    }
+                }
+            }
+
+            line += tokens.span();
+        }
+    }
+}
+
+/// The lines inside a block starting on the given line, with the line each list starts on
+fn block_bodies(block: &Block, line: usize) -> Vec<(&[Line], usize)> {
+    match block {
+        Block::Conditional(conditional) => {
+            let mut first = line + 1;
+
+            conditional
+                .branches()
+                .iter()
+                .map(|branch| {
+                    let body = (branch.lines(), first);
+
+                    // The next branch starts after this one's lines and opening line
+                    first += lines_span(branch.lines()) + 1;
+
+                    body
+                })
+                .collect()
+        }
+        Block::DoWhile(block) => vec![(block.lines(), line + 1)],
+        Block::While(block) => vec![(block.lines(), line + 1)],
+        Block::Repeat(block) => vec![(block.lines(), line + 1)],
+        Block::Subroutine(block) => vec![(block.lines(), line + 1)],
+    }
+}
+
+fn parameter_construct(parameter: &Parameter) -> Construct {
+    match parameter {
+        Parameter::Numbered(_) => Construct::NumberedParameter,
+        Parameter::Local(_) | Parameter::Global(_) => Construct::NamedParameter,
+    }
+}
+
+fn value_construct(value: &Value) -> Option<Construct> {
+    match value {
+        Value::Literal(_) => None,
+        Value::Expression(_) => Some(Construct::Expression),
+        Value::Parameter(parameter) => Some(parameter_construct(parameter)),
+    }
+}
+
+fn unsigned_construct(value: &UnsignedValue) -> Option<Construct> {
+    match value {
+        UnsignedValue::Literal(_) => None,
+        UnsignedValue::Expression(_) => Some(Construct::Expression),
+        UnsignedValue::Parameter(parameter) => Some(parameter_construct(parameter)),
+    }
+}
+
+/// The constructs a token uses that some dialects don't have, including those in any of its
+/// values
+fn token_constructs(token: &TokenType, constructs: &mut Vec<Construct>) {
+    let mut values: Vec<Option<&Value>> = Vec::new();
+    let mut unsigned: Vec<Option<&UnsignedValue>> = Vec::new();
+
+    // Homing is the one printer code every dialect has
+    match token {
+        TokenType::Marlin(MarlinCode::Home(_)) => (),
+        TokenType::Marlin(_) => constructs.push(Construct::PrinterCode),
+        _ => (),
+    }
+
+    match token {
+        TokenType::Block(_) | TokenType::Return(_) => constructs.push(Construct::OWord),
+        TokenType::Call(call) => {
+            constructs.push(Construct::OWord);
+
+            if let CallKind::Macro(arguments) = call.kind() {
+                values.extend(arguments.iter().map(|(_, value)| Some(value)));
+            }
+        }
+        TokenType::Goto(goto) => {
+            constructs.push(Construct::Goto);
+
+            if goto.condition.is_some() {
+                constructs.push(Construct::Expression);
+            }
+
+            unsigned.push(Some(&goto.sequence));
+        }
+        TokenType::Assignment(assignment) => {
+            constructs.push(parameter_construct(assignment.lhs()));
+            values.push(Some(assignment.rhs()));
+        }
+        TokenType::GCode(GCode::Dwell(dwell)) => values.push(Some(&dwell.time)),
+        TokenType::GCode(GCode::CutterCompensation(compensation)) => match compensation {
+            CutterCompensation::Left(diameter) | CutterCompensation::Right(diameter) => {
+                values.push(diameter.as_ref())
+            }
+            CutterCompensation::DynamicLeft(diameter)
+            | CutterCompensation::DynamicRight(diameter) => values.push(Some(diameter)),
+            CutterCompensation::Off => (),
+        },
+        TokenType::GCode(GCode::PathControl(PathControl::Blend {
+            tolerance,
+            naive_tolerance,
+        })) => values.extend(vec![tolerance.as_ref(), naive_tolerance.as_ref()]),
+        TokenType::Coord(coord) => values.extend(vec![
+            coord.x.as_ref(),
+            coord.y.as_ref(),
+            coord.z.as_ref(),
+            coord.a.as_ref(),
+            coord.b.as_ref(),
+            coord.c.as_ref(),
+            coord.u.as_ref(),
+            coord.v.as_ref(),
+            coord.w.as_ref(),
+        ]),
+        TokenType::PolarCoord(polar) => {
+            values.extend(vec![polar.distance.as_ref(), polar.angle.as_ref()])
+        }
+        TokenType::CenterFormatArc(arc) => {
+            values.extend(vec![
+                arc.x.as_ref(),
+                arc.y.as_ref(),
+                arc.z.as_ref(),
+                arc.i.as_ref(),
+                arc.j.as_ref(),
+                arc.k.as_ref(),
+            ]);
+            unsigned.push(Some(&arc.turns));
+        }
+        TokenType::RadiusFormatArc(arc) => {
+            values.extend(vec![
+                arc.x.as_ref(),
+                arc.y.as_ref(),
+                arc.z.as_ref(),
+                Some(&arc.radius),
+            ]);
+            unsigned.push(Some(&arc.turns));
+        }
+        TokenType::Spline(spline) => {
+            values.extend(vec![
+                spline.x.as_ref(),
+                spline.y.as_ref(),
+                spline.i.as_ref(),
+                spline.j.as_ref(),
+                spline.p.as_ref(),
+                spline.q.as_ref(),
+            ]);
+            unsigned.push(spline.order.as_ref());
+        }
+        TokenType::Feedrate(feed) => values.push(Some(&feed.feedrate)),
+        TokenType::SpindleSpeed(speed) => values.push(Some(&speed.rpm)),
+        TokenType::ToolNumber(tool) => unsigned.push(Some(&tool.tool_number)),
+        TokenType::Unknown(unknown) => values.push(Some(&unknown.code_number)),
+        TokenType::Marlin(MarlinCode::Extrude(e)) => values.push(Some(e)),
+        TokenType::Marlin(MarlinCode::Temperature(temperature)) => {
+            values.extend(vec![
+                temperature.temperature.as_ref(),
+                temperature.exact_temperature.as_ref(),
+            ]);
+            unsigned.push(temperature.tool.as_ref());
+        }
+        TokenType::Marlin(MarlinCode::FanOn(fan)) | TokenType::Marlin(MarlinCode::FanOff(fan)) => {
+            values.push(fan.speed.as_ref());
+            unsigned.push(fan.index.as_ref());
+        }
+        TokenType::Grbl(GrblCommand::Jog(tokens)) => {
+            for token in tokens {
+                token_constructs(&token.token, constructs);
+            }
+        }
+        _ => (),
+    }
+
+    constructs.extend(values.into_iter().flatten().filter_map(value_construct));
+    constructs.extend(
+        unsigned
+            .into_iter()
+            .flatten()
+            .filter_map(unsigned_construct),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{BlockIdent, CommentKind, HomeAxes, Realtime};
+
+    const PRINT: &str = ";FLAVOR:Marlin\n\
+                         ;LAYER_COUNT:2\n\
+                         M140 S60\n\
+                         M104 S200\n\
+                         M190 S60\n\
+                         M109 S200\n\
+                         M82 ;absolute extrusion mode\n\
+                         G28 ;Home\n\
+                         G29\n\
+                         G92 E0\n\
+                         G1 F1500 E-6.5\n\
+                         ;LAYER:0\n\
+                         M107\n\
+                         G0 F3600 X10.5 Y20 Z0.3\n\
+                         G1 F1200 X20 Y20 E0.5\n\
+                         M106 S255\n\
+                         M83\n\
+                         G28 X Y\n";
+
+    #[test]
+    fn marlin() {
+        let program = Program::from_str_dialect(PRINT, Dialect::Marlin).unwrap();
+
+        let tokens = program
+            .lines()
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|token| token.token.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens[0],
+            vec![TokenType::Comment(crate::token::Comment {
+                text: "FLAVOR:Marlin".into(),
+                kind: CommentKind::Metadata {
+                    key: "FLAVOR".into(),
+                    value: "Marlin".into()
+                }
+            })]
+        );
+        assert_eq!(
+            tokens[7][0],
+            TokenType::Marlin(MarlinCode::Home(HomeAxes::default()))
+        );
+        assert_eq!(
+            tokens[9][1],
+            TokenType::Marlin(MarlinCode::Extrude(0.0f32.into()))
+        );
+        assert_eq!(tokens[14][0], TokenType::GCode(GCode::Feed));
+        assert_eq!(
+            tokens[14][3],
+            TokenType::Marlin(MarlinCode::Extrude(0.5f32.into()))
+        );
+        assert_eq!(
+            tokens[17],
+            vec![TokenType::Marlin(MarlinCode::Home(HomeAxes {
+                x: true,
+                y: true,
+                z: false
+            }))]
+        );
+
+        // Printer codes are written back out the way they were read
+        let written = program.to_string();
+
+        assert_eq!(
+            Program::from_str_dialect(&written, Dialect::Marlin).unwrap(),
+            program
+        );
+        assert!(written.starts_with("; FLAVOR:Marlin\n; LAYER_COUNT:2\nM140 S60\n"));
+        assert!(written.ends_with("G1 F1200 X20 Y20 E0.5\nM106 S255\nM83\nG28 X Y\n"));
+
+        // Only Marlin has printer codes, but every dialect can home
+        assert_eq!(Dialect::Marlin.unsupported(&program), vec![]);
+        assert_eq!(
+            Dialect::LinuxCnc.unsupported(&program)[..2],
+            [
+                Unsupported {
+                    line: 3,
+                    construct: Construct::PrinterCode
+                },
+                Unsupported {
+                    line: 4,
+                    construct: Construct::PrinterCode
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn grbl() {
+        let program = Program::from_str_dialect(
+            "$H\n$X\n$J=G91 X-5 F500\n$110=500.000\n$#\n$G\n?\nG0 X10\n!\n~\n\x18",
+            Dialect::Grbl,
+        )
+        .unwrap();
+
+        let first = |line: usize| program.lines()[line].iter().next().unwrap().token.clone();
+
+        assert_eq!(first(0), TokenType::Grbl(GrblCommand::Home));
+        assert_eq!(first(1), TokenType::Grbl(GrblCommand::Unlock));
+        assert_eq!(
+            first(3),
+            TokenType::Grbl(GrblCommand::Setting {
+                number: 110,
+                value: 500.0
+            })
+        );
+        assert_eq!(first(4), TokenType::Grbl(GrblCommand::Parameters));
+        assert_eq!(first(5), TokenType::Grbl(GrblCommand::ParserState));
+        assert_eq!(first(6), TokenType::Realtime(Realtime::StatusReport));
+        assert_eq!(first(7), TokenType::GCode(GCode::Rapid));
+        assert_eq!(first(8), TokenType::Realtime(Realtime::FeedHold));
+        assert_eq!(first(9), TokenType::Realtime(Realtime::CycleStart));
+        assert_eq!(first(10), TokenType::Realtime(Realtime::SoftReset));
+
+        match first(2) {
+            TokenType::Grbl(GrblCommand::Jog(tokens)) => assert_eq!(tokens.len(), 3),
+            token => panic!("not a jog: {:?}", token),
+        }
+
+        let written = program.to_string();
+
+        assert!(written.starts_with("$H\n$X\n$J=G91 X-5 F500\n$110=500\n"));
+        assert_eq!(
+            Program::from_str_dialect(&written, Dialect::Grbl).unwrap(),
+            program
+        );
+    }
+
+    #[test]
+    fn grbl_sender_files() {
+        for file in ["circle.gcode", "comments.gcode", "no_spaces.gcode"].iter() {
+            let content =
+                std::fs::read_to_string(format!("../test_files/universal_gcode_sender/{}", file))
+                    .unwrap();
+
+            let program = Program::from_str_dialect(&content, Dialect::Grbl).unwrap();
+
+            assert_eq!(Dialect::Grbl.unsupported(&program), vec![], "{}", file);
+        }
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        let program = Program::from_str(
+            "#<depth> = 2\n\
+             G1 X#1 Y[#<depth> * 2] F100\n\
+             o100 sub\n\
+             G0 X#2\n\
+             o100 endsub\n\
+             o100 call\n\
+             G0 X1 Y1\n",
+        )
+        .unwrap();
+
+        let unsupported = |line, construct| Unsupported { line, construct };
+
+        assert_eq!(
+            Dialect::Grbl.unsupported(&program),
+            vec![
+                unsupported(1, Construct::NamedParameter),
+                unsupported(2, Construct::NumberedParameter),
+                unsupported(2, Construct::Expression),
+                unsupported(3, Construct::OWord),
+                unsupported(4, Construct::NumberedParameter),
+                unsupported(6, Construct::OWord),
+            ]
+        );
+        assert_eq!(
+            unsupported(2, Construct::Expression).to_string(),
+            "line 2: expressions are not supported"
+        );
+        assert_eq!(Dialect::LinuxCnc.unsupported(&program), vec![]);
+    }
+
+    const MACRO: &str = "%\n\
+                         O0001 (MAIN)\n\
+                         #1 = 0\n\
+                         N10 #1 = #1 + 1\n\
+                         IF [#1 LT 3] GOTO 10\n\
+                         WHILE [#1 GT 0] DO 1\n\
+                         G0 X#1\n\
+                         #1 = #1 - 1\n\
+                         END 1\n\
+                         M98 P1000 L2\n\
+                         G65 P9010 A1 B2\n\
+                         M30\n\
+                         %\n\
+                         O1000 (SUB)\n\
+                         G91 G0 X1\n\
+                         M99\n\
+                         %\n";
+
+    #[test]
+    fn fanuc() {
+        let program = Program::from_str_dialect(MACRO, Dialect::Fanuc).unwrap();
+
+        let tokens = program
+            .lines()
+            .iter()
+            .map(|line| line.iter().map(|token| &token.token).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        match tokens[3][1] {
+            TokenType::Assignment(assignment) => {
+                assert_eq!(assignment.to_string(), "#1 = [#1 + 1]")
+            }
+            token => panic!("not an assignment: {:?}", token),
+        }
+
+        assert_eq!(tokens[4][0].to_string(), "IF [#1 LT 3] GOTO 10");
+
+        match tokens[5][0] {
+            TokenType::Block(Block::While(block)) => {
+                assert_eq!(block.identifier(), &BlockIdent::Numbered(1));
+                assert_eq!(block.lines().len(), 2);
+            }
+            token => panic!("not a loop: {:?}", token),
+        }
+
+        assert_eq!(tokens[6][0].to_string(), "M98 P1000 L2");
+        assert_eq!(tokens[7][0].to_string(), "G65 P9010 A1 B2");
+
+        match tokens[10][0] {
+            TokenType::Block(Block::Subroutine(sub)) => {
+                assert_eq!(sub.identifier(), &BlockIdent::Numbered(1000));
+                assert_eq!(sub.lines().len(), 1);
+            }
+            token => panic!("not a subprogram: {:?}", token),
+        }
+
+        // Each line is still where it was in the source
+        let numbered = program
+            .iter_flat_numbered()
+            .map(|(line, token)| (line, token.to_string()))
+            .collect::<Vec<_>>();
+
+        assert!(numbered.contains(&(10, "M98 P1000 L2".to_string())));
+        assert!(numbered.contains(&(12, "M30".to_string())));
+        assert_eq!(numbered.last(), Some(&(17, "%".to_string())));
+
+        assert_eq!(Dialect::Fanuc.unsupported(&program), vec![]);
+        assert_eq!(
+            Dialect::LinuxCnc.unsupported(&program),
+            vec![Unsupported {
+                line: 5,
+                construct: Construct::Goto
+            }]
+        );
+    }
+
+    #[test]
+    fn linuxcnc_g28() {
+        let program = Program::from_str("G28 X1").unwrap();
+
+        assert_eq!(
+            program.lines()[0].iter().next().unwrap().token,
+            TokenType::GCode(GCode::GotoPredefinedPosition)
+        );
+    }
+}
@@ -19,14 +19,22 @@
 
 #[macro_use]
 mod macros;
+mod arc_fit;
+mod dialect;
 mod line;
+mod modal;
 mod parsers;
 mod program;
+mod simplify;
 pub mod token;
 mod value;
 mod word;
 
+pub use crate::arc_fit::{fit_arcs, ArcFitOptions, ArcFitReport};
+pub use crate::dialect::{Construct, Dialect, Unsupported};
+pub use crate::line::Line;
 pub use crate::program::Program;
+pub use crate::simplify::{simplify, SimplifyReport};
 
 #[doc(hidden)]
 pub mod dev {
@@ -1,4 +1,4 @@
-use crate::token::{block_delete, line_number, token, Token};
+use crate::token::{block_delete, line_number, token, Token, TokenType};
 use nom::{
     character::complete::{line_ending, space0},
     combinator::{complete, map, opt},
@@ -7,7 +7,11 @@ use nom::{
     sequence::{delimited, pair, terminated, tuple},
     IResult,
 };
+use std::fmt;
 
+/// A single line of a program
+///
+/// A line containing a block also holds every line inside that block.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Line {
     // pub(crate) span: Span,
@@ -15,9 +19,46 @@ pub struct Line {
 }
 
 impl Line {
+    /// Create a line holding the given tokens
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens }
+    }
+
+    /// Iterate over the tokens on this line
     pub fn iter(&self) -> impl Iterator<Item = &Token> {
         self.tokens.iter()
     }
+
+    /// The number of source lines this line covers, including the contents of any blocks
+    pub fn span(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter_map(|t| match &t.token {
+                TokenType::Block(block) => Some(block.span() - 1),
+                _ => None,
+            })
+            .sum::<usize>()
+            + 1
+    }
+}
+
+/// Write the tokens of this line separated by spaces
+///
+/// A line containing a block spans several lines of text, but the text never ends in a newline.
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tokens = self.tokens.iter();
+
+        if let Some(token) = tokens.next() {
+            write!(f, "{}", token)?;
+        }
+
+        for token in tokens {
+            write!(f, " {}", token)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for Line {
@@ -27,35 +68,61 @@ impl Default for Line {
 }
 
 pub fn line<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Line, E> {
-    let (i, (block_delete, line_number, line_tokens)) = context(
-        "line",
-        complete(delimited(
-            space0,
-            tuple((
-                opt(terminated(block_delete, space0)),
-                opt(terminated(line_number, space0)),
-                many0(terminated(token, space0)),
+    line_with(token)(i)
+}
+
+/// Like `line`, but parsing tokens with the given parser
+pub(crate) fn line_with<'a, E, T>(token: T) -> impl Fn(&'a str) -> IResult<&'a str, Line, E>
+where
+    E: ParseError<&'a str>,
+    T: Fn(&'a str) -> IResult<&'a str, Token, E> + Copy,
+{
+    move |i| {
+        let (i, (block_delete, line_number, line_tokens)) = context(
+            "line",
+            complete(delimited(
+                space0,
+                tuple((
+                    opt(terminated(block_delete, space0)),
+                    opt(terminated(line_number, space0)),
+                    many0(terminated(token, space0)),
+                )),
+                space0,
             )),
-            space0,
-        )),
-    )(i)?;
+        )(i)?;
 
-    let tokens: Vec<Token> = vec![block_delete, line_number]
-        .into_iter()
-        .filter_map(|t| t)
-        .chain(line_tokens.into_iter())
-        .collect();
+        let tokens: Vec<Token> = vec![block_delete, line_number]
+            .into_iter()
+            .flatten()
+            .chain(line_tokens)
+            .collect();
 
-    Ok((i, Line { tokens }))
+        Ok((i, Line { tokens }))
+    }
 }
 
 /// A list of newline-separated token lists without trailing newline
 pub fn lines<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Vec<Line>, E> {
-    map(pair(lines_with_newline, line), |(mut lines, last)| {
-        lines.push(last);
+    lines_with(token)(i)
+}
+
+/// Like `lines`, but parsing tokens with the given parser
+pub(crate) fn lines_with<'a, E, T>(token: T) -> impl Fn(&'a str) -> IResult<&'a str, Vec<Line>, E>
+where
+    E: ParseError<&'a str>,
+    T: Fn(&'a str) -> IResult<&'a str, Token, E> + Copy,
+{
+    map(
+        pair(
+            many0(terminated(line_with(token), line_ending)),
+            line_with(token),
+        ),
+        |(mut lines, last)| {
+            lines.push(last);
 
-        lines
-    })(i)
+            lines
+        },
+    )
 }
 
 /// Like `line`, but requires a trailing newline
@@ -70,7 +137,8 @@ mod tests {
     use super::*;
     use crate::assert_parse;
     use crate::token::{
-        CenterFormatArc, Comment, CutterCompensation, GCode, MCode, TokenType, WorkOffset,
+        CenterFormatArc, Comment, CommentKind, CutterCompensation, GCode, MCode, TokenType,
+        WorkOffset,
     };
 
     #[test]
@@ -224,7 +292,8 @@ mod tests {
             expected = Line {
                 tokens: vec![Token {
                     token: TokenType::Comment(Comment {
-                        text: "Line comment".to_string()
+                        text: "Line comment".to_string(),
+                        kind: CommentKind::Plain,
                     })
                 }],
                 ..Line::default()
@@ -257,7 +326,8 @@ mod tests {
                     token: TokenType::GCode(GCode::CutterCompensation(CutterCompensation::Off))
                 }, Token {
                     token: TokenType::Comment(Comment {
-                        text: "disable tool radius compensation".into()
+                        text: "disable tool radius compensation".into(),
+                        kind: CommentKind::Plain,
                     })
                 }],
                 ..Line::default()
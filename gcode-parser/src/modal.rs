@@ -0,0 +1,286 @@
+//! Modal state followed through a program by the passes that rewrite its moves
+//!
+//! Only top level lines whose effect is fully known are followed. Anything that could move the
+//! machine or change coordinates in a way that can't be followed without running the program,
+//! like parameter assignments, `G92` or expressions, forgets the machine's position until it's
+//! set again. Blocks and subroutine calls can also change modes, so the distance mode, plane and
+//! arc center mode are forgotten too until the program sets them again.
+
+use crate::line::Line;
+use crate::token::{GCode, GrblCommand, MarlinCode, PlaneSelect, Token, TokenType, Value};
+
+/// Motion mode, as far as the passes care
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Motion {
+    Linear,
+    Other,
+}
+
+/// Modal state followed through the program, with `None` for anything that isn't known
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Modal {
+    /// `XYZ` position in program coordinates
+    pub(crate) position: [Option<f64>; 3],
+    pub(crate) motion: Option<Motion>,
+    pub(crate) xy_plane: Option<bool>,
+    pub(crate) absolute: Option<bool>,
+    pub(crate) absolute_arc_centers: Option<bool>,
+}
+
+impl Default for Modal {
+    fn default() -> Self {
+        Self {
+            position: [None; 3],
+            motion: None,
+            xy_plane: Some(true),
+            absolute: Some(true),
+            absolute_arc_centers: Some(false),
+        }
+    }
+}
+
+/// A literal value, `Ok(None)` if not given, or `Err` if it can't be known without running the
+/// program
+pub(crate) fn literal(value: &Option<Value>) -> Result<Option<f64>, ()> {
+    match value {
+        None => Ok(None),
+        Some(Value::Literal(value)) => Ok(Some(f64::from(*value))),
+        Some(_) => Err(()),
+    }
+}
+
+fn is_code(value: &Value, code: f32) -> bool {
+    match value {
+        Value::Literal(value) => (value - code).abs() < 0.001,
+        _ => false,
+    }
+}
+
+pub(crate) fn has_motion_code(line: &Line) -> bool {
+    line.iter().any(|token| match &token.token {
+        TokenType::GCode(code) => matches!(
+            code,
+            GCode::Rapid
+                | GCode::Feed
+                | GCode::ClockwiseArc
+                | GCode::CounterclockwiseArc
+                | GCode::CubicSpline
+                | GCode::QuadraticSpline
+                | GCode::Nurbs
+        ),
+        _ => false,
+    })
+}
+
+/// Whether a line moves the machine using the active motion mode, rather than an `Unknown` code
+/// or `G28` that uses its axis words for something else
+pub(crate) fn uses_motion_mode(line: &Line) -> bool {
+    is_move(line)
+        && !line.iter().any(|token| match &token.token {
+            TokenType::GCode(code) => matches!(
+                code,
+                GCode::GotoPredefinedPosition | GCode::SetPredefinedPosition
+            ),
+            TokenType::Unknown(_) => true,
+            _ => false,
+        })
+}
+
+pub(crate) fn is_move(line: &Line) -> bool {
+    line.iter().any(|token| {
+        matches!(
+            token.token,
+            TokenType::Coord(_)
+                | TokenType::CenterFormatArc(_)
+                | TokenType::RadiusFormatArc(_)
+                | TokenType::Spline(_)
+                | TokenType::PolarCoord(_)
+        )
+    })
+}
+
+/// Add a `G1` to a line, after its block delete and line number
+pub(crate) fn insert_feed_code(line: &mut Line) {
+    let position = line
+        .tokens
+        .iter()
+        .position(|token| {
+            !matches!(
+                token.token,
+                TokenType::LineNumber(_) | TokenType::BlockDelete
+            )
+        })
+        .unwrap_or(0);
+
+    line.tokens.insert(
+        position,
+        Token {
+            token: TokenType::GCode(GCode::Feed),
+        },
+    );
+}
+
+impl Modal {
+    /// The literal `XYZ` coordinates of a line that is nothing but a straight feed move in
+    /// absolute mode, with an optional line number
+    pub(crate) fn linear_move(&self, line: &Line) -> Option<[Option<f64>; 3]> {
+        if self.absolute != Some(true) {
+            return None;
+        }
+
+        let mut coord = None;
+        let mut linear = self.motion == Some(Motion::Linear);
+
+        for token in line.iter() {
+            match &token.token {
+                TokenType::LineNumber(_) => (),
+                TokenType::GCode(GCode::Feed) => linear = true,
+                TokenType::Coord(c) if coord.is_none() => coord = Some(c),
+                _ => return None,
+            }
+        }
+
+        let coord = coord?;
+
+        if !linear
+            || [&coord.a, &coord.b, &coord.c, &coord.u, &coord.v, &coord.w]
+                .iter()
+                .any(|value| value.is_some())
+        {
+            return None;
+        }
+
+        Some([
+            literal(&coord.x).ok()?,
+            literal(&coord.y).ok()?,
+            literal(&coord.z).ok()?,
+        ])
+    }
+
+    /// Follow a straight feed move to the given end point
+    pub(crate) fn follow(&mut self, to: [Option<f64>; 3]) {
+        self.motion = Some(Motion::Linear);
+
+        for (axis, value) in to.iter().enumerate() {
+            if value.is_some() {
+                self.position[axis] = *value;
+            }
+        }
+    }
+
+    /// Forget everything a subroutine or block could change
+    fn forget(&mut self) {
+        *self = Self {
+            position: [None; 3],
+            motion: None,
+            xy_plane: None,
+            absolute: None,
+            absolute_arc_centers: None,
+        };
+    }
+
+    /// Keep only what is the same in both states
+    fn merge(&mut self, other: &Self) {
+        fn keep<T: PartialEq>(value: &mut Option<T>, other: &Option<T>) {
+            if value != other {
+                *value = None;
+            }
+        }
+
+        for (position, other) in self.position.iter_mut().zip(other.position.iter()) {
+            keep(position, other);
+        }
+
+        keep(&mut self.motion, &other.motion);
+        keep(&mut self.xy_plane, &other.xy_plane);
+        keep(&mut self.absolute, &other.absolute);
+        keep(&mut self.absolute_arc_centers, &other.absolute_arc_centers);
+    }
+
+    /// Follow the effect of any other line
+    pub(crate) fn apply(&mut self, line: &Line) {
+        // A line that may be skipped by block delete leaves only what it doesn't change known
+        if line
+            .iter()
+            .any(|token| matches!(token.token, TokenType::BlockDelete))
+        {
+            let skipped = self.clone();
+
+            self.apply_tokens(line);
+            self.merge(&skipped);
+        } else {
+            self.apply_tokens(line);
+        }
+    }
+
+    fn apply_tokens(&mut self, line: &Line) {
+        for token in line.iter() {
+            match &token.token {
+                TokenType::GCode(code) => match code {
+                    GCode::Feed => self.motion = Some(Motion::Linear),
+                    GCode::Rapid
+                    | GCode::ClockwiseArc
+                    | GCode::CounterclockwiseArc
+                    | GCode::CubicSpline
+                    | GCode::QuadraticSpline
+                    | GCode::Nurbs
+                    | GCode::EndNurbs => self.motion = Some(Motion::Other),
+                    GCode::PlaneSelect(plane) => self.xy_plane = Some(*plane == PlaneSelect::XY),
+                    // Coordinates mean something different afterwards
+                    GCode::UnitsMM
+                    | GCode::UnitsInch
+                    | GCode::WorkOffset(_)
+                    | GCode::GotoPredefinedPosition => self.position = [None; 3],
+                    _ => (),
+                },
+                TokenType::Marlin(MarlinCode::Home(_)) | TokenType::Grbl(GrblCommand::Home) => {
+                    self.position = [None; 3]
+                }
+                TokenType::Unknown(unknown) if unknown.code_letter.eq_ignore_ascii_case(&'g') => {
+                    let code = &unknown.code_number;
+
+                    if is_code(code, 90.0) {
+                        self.absolute = Some(true);
+                    } else if is_code(code, 91.0) {
+                        self.absolute = Some(false);
+                    } else if is_code(code, 90.1) {
+                        self.absolute_arc_centers = Some(true);
+                    } else if is_code(code, 91.1) {
+                        self.absolute_arc_centers = Some(false);
+                    } else {
+                        // Offsets, probing, canned cycles and other motion
+                        self.motion = Some(Motion::Other);
+                        self.position = [None; 3];
+                    }
+                }
+                TokenType::Coord(coord) => {
+                    self.set_position([&coord.x, &coord.y, &coord.z]);
+
+                    if [&coord.a, &coord.b, &coord.c, &coord.u, &coord.v, &coord.w]
+                        .iter()
+                        .any(|value| literal(value).is_err())
+                    {
+                        self.position = [None; 3];
+                    }
+                }
+                TokenType::CenterFormatArc(arc) => self.set_position([&arc.x, &arc.y, &arc.z]),
+                TokenType::RadiusFormatArc(arc) => self.set_position([&arc.x, &arc.y, &arc.z]),
+                TokenType::Spline(spline) => self.set_position([&spline.x, &spline.y, &None]),
+                // Assignments can change work offsets
+                TokenType::PolarCoord(_) | TokenType::Assignment(_) => self.position = [None; 3],
+                TokenType::Block(_) | TokenType::Call(_) | TokenType::Return(_) => self.forget(),
+                _ => (),
+            }
+        }
+    }
+
+    fn set_position(&mut self, values: [&Option<Value>; 3]) {
+        for (axis, value) in values.iter().enumerate() {
+            match literal(value) {
+                Ok(None) => (),
+                Ok(Some(value)) if self.absolute == Some(true) => self.position[axis] = Some(value),
+                _ => self.position[axis] = None,
+            }
+        }
+    }
+}
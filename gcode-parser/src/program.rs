@@ -1,9 +1,11 @@
-use crate::line::{lines, Line};
+use crate::dialect::Dialect;
+use crate::line::Line;
 use crate::token::Token;
 use nom::{
     error::{context, convert_error, ParseError, VerboseError},
     IResult,
 };
+use std::fmt;
 use std::io;
 
 /// A complete GCode program
@@ -20,8 +22,20 @@ impl Program {
     // TODO: Return a custom parse error type
     /// Parse a GCode program from a given string
     pub fn from_str(content: &str) -> Result<Self, io::Error> {
+        Self::parse(content, program)
+    }
+
+    /// Parse a GCode program written in the given dialect
+    pub fn from_str_dialect(content: &str, dialect: Dialect) -> Result<Self, io::Error> {
+        Self::parse(content, |i| program_in(dialect, i))
+    }
+
+    fn parse<'a, P>(content: &'a str, parser: P) -> Result<Self, io::Error>
+    where
+        P: Fn(&'a str) -> IResult<&'a str, Program, VerboseError<&'a str>>,
+    {
         // TODO: Format error helper function to move into common crate
-        program::<VerboseError<&str>>(content)
+        parser(content)
             .map_err(|e| {
                 io::Error::new(
                     io::ErrorKind::Other,
@@ -54,14 +68,69 @@ impl Program {
             })
     }
 
+    /// Build a program out of lines, like those made by a transform that rewrites a program
+    pub fn from_lines(lines: Vec<Line>) -> Self {
+        Self { lines }
+    }
+
+    /// The top level lines of this program
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
     /// Get a flat iterator over every token in this program
     pub fn iter_flat(&self) -> impl Iterator<Item = &Token> {
         self.lines.iter().flat_map(|line| line.iter())
     }
+
+    /// Like [`iter_flat`](#method.iter_flat), but also yield the 1-indexed source line each token
+    /// starts on
+    pub fn iter_flat_numbered(&self) -> impl Iterator<Item = (usize, &Token)> {
+        self.lines
+            .iter()
+            .scan(1, |number, line| {
+                let start = *number;
+
+                *number += line.span();
+
+                Some((start, line))
+            })
+            .flat_map(|(number, line)| line.iter().map(move |token| (number, token)))
+    }
+}
+
+/// Write the program out as GCode text
+///
+/// Lines are separated by newlines, so a program parsed from text ending in a newline is written
+/// with a trailing newline too. Words are written in upper case with coordinates in `XYZABCUVW`
+/// order, and comments lose any surrounding whitespace. Parsing the written text and writing it
+/// again gives back the same text.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = self.lines.iter();
+
+        if let Some(line) = lines.next() {
+            write!(f, "{}", line)?;
+        }
+
+        for line in lines {
+            write!(f, "\n{}", line)?;
+        }
+
+        Ok(())
+    }
 }
 
 pub fn program<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Program, E> {
-    let (i, lines) = context("program", lines)(i)?;
+    program_in(Dialect::LinuxCnc, i)
+}
+
+/// Parse a program written in the given dialect
+pub(crate) fn program_in<'a, E: ParseError<&'a str>>(
+    dialect: Dialect,
+    i: &'a str,
+) -> IResult<&'a str, Program, E> {
+    let (i, lines) = context("program", |i| dialect.lines(i))(i)?;
 
     Ok((i, Program { lines }))
 }
@@ -272,6 +341,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn numbered_tokens_skip_block_contents() {
+        let program = Program::from_str(
+            "G0 X0\no100 sub\nG1 X1\n\no100 endsub\no101 if [1 GT 0]\nG1 X2\no101 else\nG1 X3\no101 endif\nG1 X4\nM2",
+        )
+        .unwrap();
+
+        let numbers: Vec<usize> = program
+            .iter_flat_numbered()
+            .map(|(number, _)| number)
+            .collect();
+
+        assert_eq!(numbers, vec![1, 1, 2, 6, 11, 11, 12]);
+    }
+
     #[test]
     fn blank_lines() {
         assert_parse!(
@@ -308,4 +392,49 @@ mod tests {
             };
         );
     }
+
+    #[test]
+    fn write_program() {
+        let text = "%
+(header) ; note with (parens)
+/ N10 G21 G17 G90 G54 G61.1
+N20 G64 P0.01 Q0.02
+G0 X0 Y-1.5 Z2 A90 B[#1 + 1] C#<angle> U1 V2 W3
+G1 @10 ^45 F500.5
+G2 X1 Y1 I0.5 J0.5 P2
+G3 X-1 Z1 R1.5
+G41 D2
+G42.1 D0.25
+G4 P0.5
+G28.1
+T3 M6
+S12000 M3
+#<depth> = [#1 * 2]
+#2 = [sqrt[#<depth>]]
+O100 sub (helper)
+O101 if [exists[#<x>] AND [#1 GT 0]]
+G1 X#<x>
+O101 elseif [#1 EQ 0]
+O101 else
+M5
+O101 endif
+O102 while [#1 LT 3] (loop)
+#1 = [#1 + 1]
+O102 endwhile
+O103 do
+O103 while [atan[1]/[2] GT fix[1.5]]
+O104 repeat [2]
+O104 endrepeat
+O100 endsub [fup[1.5]]
+O100 call [1] [2]
+O100 return [1]
+M30
+%
+";
+
+        let parsed = Program::from_str(text).unwrap();
+
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(Program::from_str(&parsed.to_string()).unwrap(), parsed);
+    }
 }
@@ -0,0 +1,337 @@
+//! Remove points from runs of straight moves that barely change the path
+//!
+//! Runs of consecutive `G1` moves are simplified with the Douglas–Peucker algorithm: a move's end
+//! point is dropped if it lies within a tolerance of the path through the points that are kept.
+//! The first and last points of a run are always kept.
+//!
+//! The same moves are considered as for arc fitting: moves with literal absolute `XYZ`
+//! coordinates in `G1` and `G90` mode, carrying nothing but a line number, `G1` and the
+//! coordinates themselves. Lines with anything else on them, like feed rate changes, comments or
+//! M-codes, end the current run and are kept as they are, so they stay at the same point in the
+//! path. The line numbers of kept moves are left alone.
+
+use crate::line::Line;
+use crate::modal::{has_motion_code, insert_feed_code, is_move, Modal, Motion};
+use crate::program::Program;
+use crate::token::{Coord, TokenType, Value};
+
+/// What simplification did to a program
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SimplifyReport {
+    /// The number of top level lines that moved the machine before simplifying
+    pub moves_before: usize,
+
+    /// The number of top level lines that move the machine after simplifying
+    pub moves_after: usize,
+}
+
+impl SimplifyReport {
+    /// How many times fewer moves the simplified program has
+    pub fn reduction_ratio(&self) -> f64 {
+        if self.moves_after == 0 {
+            1.0
+        } else {
+            self.moves_before as f64 / self.moves_after as f64
+        }
+    }
+}
+
+type Point = [f64; 3];
+
+/// The end point of a line that could be simplified away, if it is one
+///
+/// Axes that aren't known yet are taken to be zero, which is only safe because no move in a run
+/// may change them.
+fn candidate(modal: &Modal, line: &Line) -> Option<Point> {
+    let to = modal.linear_move(line)?;
+    let mut point = [0.0; 3];
+
+    for axis in 0..3 {
+        point[axis] = match (to[axis], modal.position[axis]) {
+            (Some(_), None) => return None,
+            (Some(value), Some(_)) => value,
+            (None, position) => position.unwrap_or(0.0),
+        };
+    }
+
+    Some(point)
+}
+
+/// The shortest distance from `point` to the line segment from `from` to `to`
+fn segment_distance(point: Point, from: Point, to: Point) -> f64 {
+    let along = [to[0] - from[0], to[1] - from[1], to[2] - from[2]];
+    let offset = [point[0] - from[0], point[1] - from[1], point[2] - from[2]];
+
+    let length_squared: f64 = along.iter().map(|v| v * v).sum();
+
+    let t = if length_squared > 0.0 {
+        let dot: f64 = along.iter().zip(offset.iter()).map(|(a, o)| a * o).sum();
+
+        (dot / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (0..3)
+        .map(|axis| (offset[axis] - t * along[axis]).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Mark the points to keep with the Douglas–Peucker algorithm
+fn douglas_peucker(points: &[Point], tolerance: f64) -> Vec<bool> {
+    let mut keep = vec![false; points.len()];
+
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut spans = vec![(0, points.len() - 1)];
+
+    while let Some((first, last)) = spans.pop() {
+        let mut furthest = (first, 0.0);
+
+        for index in first + 1..last {
+            let distance = segment_distance(points[index], points[first], points[last]);
+
+            if distance > furthest.1 {
+                furthest = (index, distance);
+            }
+        }
+
+        if furthest.1 > tolerance {
+            let index = furthest.0;
+
+            keep[index] = true;
+
+            spans.push((first, index));
+            spans.push((index, last));
+        }
+    }
+
+    keep
+}
+
+/// Output of the simplifying pass
+#[derive(Debug, Default)]
+struct Simplifier {
+    lines: Vec<Line>,
+    report: SimplifyReport,
+}
+
+impl Simplifier {
+    fn push(&mut self, line: Line) {
+        if is_move(&line) {
+            self.report.moves_after += 1;
+        }
+
+        self.lines.push(line);
+    }
+
+    /// Write the moves of a run that are kept, starting from `start`
+    ///
+    /// A kept move gains any axis words it needs to end at the same point now that the moves
+    /// before it may be gone, and a `G1` if the run started in another motion mode and the move
+    /// that switched to `G1` was removed.
+    fn flush(
+        &mut self,
+        start: Point,
+        run: &mut Vec<(&Line, Point)>,
+        starts_linear: bool,
+        tolerance: f64,
+    ) {
+        if run.is_empty() {
+            return;
+        }
+
+        let points: Vec<Point> = std::iter::once(start)
+            .chain(run.iter().map(|(_, point)| *point))
+            .collect();
+
+        let keep = douglas_peucker(&points, tolerance);
+
+        let mut previous = start;
+        let mut needs_linear = !starts_linear;
+
+        for ((line, point), _) in run.iter().zip(&keep[1..]).filter(|(_, keep)| **keep) {
+            let mut line = (*line).clone();
+
+            for token in line.tokens.iter_mut() {
+                if let TokenType::Coord(coord) = &mut token.token {
+                    fill_axes(coord, *point, previous);
+                }
+            }
+
+            if has_motion_code(&line) {
+                needs_linear = false;
+            } else if needs_linear {
+                insert_feed_code(&mut line);
+
+                needs_linear = false;
+            }
+
+            self.push(line);
+
+            previous = *point;
+        }
+
+        run.clear();
+    }
+}
+
+/// Add words for the axes that end somewhere other than `previous` but aren't given
+fn fill_axes(coord: &mut Coord, point: Point, previous: Point) {
+    for (axis, value) in [&mut coord.x, &mut coord.y, &mut coord.z]
+        .iter_mut()
+        .enumerate()
+    {
+        if value.is_none() && point[axis] != previous[axis] {
+            **value = Some(Value::Literal(point[axis] as f32));
+        }
+    }
+}
+
+/// Remove straight moves that are within `tolerance` of the path without them, in program units
+///
+/// Returns the simplified program along with a report of how much smaller it is.
+pub fn simplify(program: &Program, tolerance: f64) -> (Program, SimplifyReport) {
+    let mut simplifier = Simplifier::default();
+    let mut modal = Modal::default();
+
+    let mut run: Vec<(&Line, Point)> = Vec::new();
+    let mut start = [0.0; 3];
+    let mut starts_linear = false;
+
+    for line in program.lines() {
+        if is_move(line) {
+            simplifier.report.moves_before += 1;
+        }
+
+        if let Some(point) = candidate(&modal, line) {
+            if run.is_empty() {
+                start = [
+                    modal.position[0].unwrap_or(0.0),
+                    modal.position[1].unwrap_or(0.0),
+                    modal.position[2].unwrap_or(0.0),
+                ];
+                starts_linear = modal.motion == Some(Motion::Linear);
+            }
+
+            run.push((line, point));
+
+            // Axes that still aren't known stay that way
+            let known = |axis: usize| modal.position[axis].map(|_| point[axis]);
+
+            modal.follow([known(0), known(1), known(2)]);
+        } else {
+            simplifier.flush(start, &mut run, starts_linear, tolerance);
+            simplifier.push(line.clone());
+
+            modal.apply(line);
+        }
+    }
+
+    simplifier.flush(start, &mut run, starts_linear, tolerance);
+
+    (Program::from_lines(simplifier.lines), simplifier.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn simplified(program: &str, tolerance: f64) -> (String, SimplifyReport) {
+        let program = Program::from_str(program).unwrap();
+
+        let (simplified, report) = simplify(&program, tolerance);
+
+        (simplified.to_string(), report)
+    }
+
+    #[test]
+    fn collinear_moves() {
+        let (program, report) = simplified(
+            "G0 X0 Y0 Z1\nN10 G1 Z0\nN20 X1 Y1\nN30 X2 Y2\nN40 X3 Y3\nN50 X3 Y0\nM2",
+            0.001,
+        );
+
+        assert_eq!(program, "G0 X0 Y0 Z1\nN10 G1 Z0\nN40 X3 Y3\nN50 X3 Y0\nM2");
+        assert_eq!(
+            report,
+            SimplifyReport {
+                moves_before: 6,
+                moves_after: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn tolerance() {
+        let zig_zag = "G0 X0 Y0\nG1 X1 Y0.005\nX2 Y-0.005\nX3 Y0.005\nX4 Y0";
+
+        assert_eq!(simplified(zig_zag, 0.01).0, "G0 X0 Y0\nG1 X4 Y0");
+        assert_eq!(simplified(zig_zag, 0.001).0, zig_zag);
+    }
+
+    #[test]
+    fn keeps_other_lines() {
+        let program =
+            "G0 X0 Y0\nG1 X1\nX2 F200\nX3\nX4\n(note)\nX5\nX6\nS1000 M3\nX7\nX8\nG0 X9\nX10";
+
+        assert_eq!(
+            simplified(program, 0.01).0,
+            "G0 X0 Y0\nG1 X1\nX2 F200\nX4\n(note)\nX6\nS1000 M3\nX8\nG0 X9\nX10"
+        );
+    }
+
+    #[test]
+    fn restores_removed_words() {
+        // The move that switched to G1 and set Z is dropped, so the move after it has to carry both
+        let (program, _) = simplified("G0 X0 Y0 Z0\nG1 X1 Z-0.5\nX2 Z-1\nX3", 0.5);
+
+        assert_eq!(program, "G0 X0 Y0 Z0\nG1 X3 Z-1");
+    }
+
+    #[test]
+    fn leaves_unknowns_alone() {
+        let unchanged = [
+            // Incremental moves
+            "G91\nG0 X0 Y0\nG1 X1\nX1\nX1",
+            // Incremental moves after an assignment
+            "G91\nG1 X1 Y0 F100\n#1 = 2\nG1 X1 Y0\nX1 Y0\nX1 Y0\nX1 Y0",
+            // Expressions
+            "G0 X0 Y0\nG1 X[1]\nX2\nX3",
+            // Rapids
+            "G0 X0 Y0\nX1\nX2\nX3",
+        ];
+
+        for program in unchanged.iter() {
+            assert_eq!(simplified(program, 0.01).0, *program);
+        }
+    }
+
+    #[test]
+    fn tinyg_files() {
+        for file in ["girl.gcode", "tiger.gcode"].iter() {
+            let program = fs::read_to_string(Path::new("../test_files/tinyg").join(file)).unwrap();
+            let program = Program::from_str(&program).unwrap();
+
+            let (simplified, report) = simplify(&program, 0.002);
+
+            assert!(report.moves_after < report.moves_before, "{}", file);
+            assert_eq!(
+                report.moves_after,
+                simplified
+                    .lines()
+                    .iter()
+                    .filter(|line| is_move(line))
+                    .count()
+            );
+            assert_eq!(
+                Program::from_str(&simplified.to_string()).unwrap(),
+                simplified
+            );
+        }
+    }
+}
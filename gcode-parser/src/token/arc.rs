@@ -1,13 +1,16 @@
 use crate::parsers::char_no_case;
-use crate::value::{preceded_decimal_value, preceded_unsigned_value, UnsignedValue, Value};
+use crate::value::{
+    preceded_decimal_value, preceded_unsigned_value, write_words, UnsignedValue, Value,
+};
 use nom::{
     branch::permutation,
-    character::complete::space0,
-    combinator::{map_res, opt},
+    character::complete::{char, space0},
+    combinator::{map_res, not, opt},
     error::{context, ParseError},
     sequence::terminated,
     IResult,
 };
+use std::fmt;
 
 /// Center format arc offsets
 ///
@@ -65,6 +68,51 @@ impl Default for CenterFormatArc {
     }
 }
 
+impl fmt::Display for CenterFormatArc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_words(
+            f,
+            &[
+                ('X', &self.x),
+                ('Y', &self.y),
+                ('Z', &self.z),
+                ('I', &self.i),
+                ('J', &self.j),
+                ('K', &self.k),
+            ],
+        )?;
+
+        write_turns(f, &self.turns)
+    }
+}
+
+impl fmt::Display for RadiusFormatArc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_words(f, &[('X', &self.x), ('Y', &self.y), ('Z', &self.z)])?;
+
+        write!(f, " R{}", self.radius)?;
+
+        write_turns(f, &self.turns)
+    }
+}
+
+/// Write the `P` word for the number of turns, leaving out the default of one turn
+fn write_turns(f: &mut fmt::Formatter, turns: &UnsignedValue) -> fmt::Result {
+    if *turns == UnsignedValue::Literal(1) {
+        Ok(())
+    } else {
+        write!(f, " P{}", turns)
+    }
+}
+
+/// The `P` word for the number of turns
+///
+/// A `P` with a fractional part, like the dwell time of a canned cycle, is left as a word of its
+/// own.
+fn turns<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, UnsignedValue, E> {
+    terminated(preceded_unsigned_value(char_no_case('P')), not(char('.')))(i)
+}
+
 /// Parse a center format arc
 pub fn center_format_arc<'a, E: ParseError<&'a str>>(
     i: &'a str,
@@ -97,10 +145,7 @@ pub fn center_format_arc<'a, E: ParseError<&'a str>>(
                     preceded_decimal_value(char_no_case('K')),
                     space0,
                 )),
-                opt(terminated(
-                    preceded_unsigned_value(char_no_case('P')),
-                    space0,
-                )),
+                opt(terminated(turns, space0)),
             )),
             |(x, y, z, i, j, k, turns): (
                 Option<Value>,
@@ -155,7 +200,7 @@ pub fn radius_format_arc<'a, E: ParseError<&'a str>>(
                     space0,
                 )),
                 terminated(preceded_decimal_value(char_no_case('R')), space0),
-                opt(preceded_unsigned_value(char_no_case('P'))),
+                opt(turns),
             )),
             |(x, y, z, radius, turns): (
                 Option<Value>,
@@ -264,6 +309,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fractional_p_is_not_turns() {
+        assert_parse!(
+            parser = radius_format_arc;
+            input = "X1 Y2 Z-1 R2 P0.5";
+            expected = RadiusFormatArc {
+                x: Some(1.0f32.into()),
+                y: Some(2.0f32.into()),
+                z: Some((-1.0f32).into()),
+                radius: 2.0f32.into(),
+                turns: 1.into(),
+            };
+            remaining = "P0.5"
+        );
+    }
+
     #[test]
     fn full_circle() {
         assert_parse!(
@@ -8,6 +8,7 @@ use nom::{
     sequence::{delimited, separated_pair},
     IResult,
 };
+use std::fmt;
 
 /// Assign a value to a variable
 ///
@@ -15,10 +16,28 @@ use nom::{
 #[derive(Debug, PartialEq, Clone)]
 pub struct Assignment {
     /// The parameter to assign a value to
-    lhs: Parameter,
+    pub(crate) lhs: Parameter,
 
     /// The value or result of an expression to assign
-    rhs: Value,
+    pub(crate) rhs: Value,
+}
+
+impl Assignment {
+    /// The parameter to assign a value to
+    pub fn lhs(&self) -> &Parameter {
+        &self.lhs
+    }
+
+    /// The value to assign
+    pub fn rhs(&self) -> &Value {
+        &self.rhs
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.lhs, self.rhs)
+    }
 }
 
 pub fn assignment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Assignment, E> {
@@ -1,4 +1,7 @@
-use super::{block_close, block_close_expr, block_open_expr, BlockIdent};
+use super::{
+    block_close, block_close_expr, block_open_expr, lines_span, write_lines, write_opening_comment,
+    BlockIdent,
+};
 use crate::line::{lines_with_newline, Line};
 use crate::token::Comment;
 use expression::{gcode::expression, Expression};
@@ -11,6 +14,7 @@ use nom::{
     sequence::separated_pair,
     IResult,
 };
+use std::fmt;
 
 /// What type of branch this is
 #[derive(Debug, PartialEq, Clone)]
@@ -43,6 +47,67 @@ pub struct Conditional {
     branches: Vec<Branch>,
 }
 
+impl Branch {
+    /// What type of branch this is
+    pub fn branch_type(&self) -> &BranchType {
+        &self.branch_type
+    }
+
+    /// Lines executed if this branch is taken
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// The condition for this branch to be taken. `None` for `else` branches.
+    pub fn condition(&self) -> Option<&Expression<f32>> {
+        self.condition.as_ref()
+    }
+}
+
+impl Conditional {
+    /// Block identifier
+    pub fn identifier(&self) -> &BlockIdent {
+        &self.identifier
+    }
+
+    /// The `if`, `elseif` and `else` branches of this block in order
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    /// Each branch has an opening line, followed by a single `endif` line
+    pub(crate) fn span(&self) -> usize {
+        self.branches
+            .iter()
+            .map(|branch| 1 + lines_span(&branch.lines))
+            .sum::<usize>()
+            + 1
+    }
+}
+
+impl fmt::Display for Conditional {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for branch in self.branches.iter() {
+            let keyword = match branch.branch_type {
+                BranchType::If => "if",
+                BranchType::ElseIf => "elseif",
+                BranchType::Else => "else",
+            };
+
+            write!(f, "O{} {}", self.identifier, keyword)?;
+
+            if let Some(condition) = &branch.condition {
+                write!(f, " {}", condition)?;
+            }
+
+            write_opening_comment(f, &branch.trailing_comment)?;
+            write_lines(f, &branch.lines)?;
+        }
+
+        write!(f, "O{} endif", self.identifier)
+    }
+}
+
 // TODO: Use conditional_block_open
 pub fn elseif_block<'a, IP, IOP, E: ParseError<&'a str>>(
     ident_parser: IP,
@@ -135,7 +200,7 @@ pub fn conditional<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, C
 mod tests {
     use super::*;
     use crate::assert_parse;
-    use crate::token::{Comment, Feedrate, Token, TokenType};
+    use crate::token::{Comment, CommentKind, Feedrate, Token, TokenType};
     use expression::{BinaryOperator, ExpressionToken};
 
     #[test]
@@ -147,7 +212,7 @@ mod tests {
                 identifier: 1.into(),
                 branches: vec![
                     Branch {
-                        trailing_comment: Some(Comment { text: "comment here".into() }),
+                        trailing_comment: Some(Comment { text: "comment here".into(), kind: CommentKind::Plain }),
                         branch_type: BranchType::If,
                         condition: Some(Expression::from_tokens(vec![
                             ExpressionToken::Literal(1.0),
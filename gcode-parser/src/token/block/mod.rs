@@ -37,9 +37,43 @@ pub enum Block {
     Subroutine(Subroutine),
 }
 
+impl Block {
+    /// The number of source lines this block covers, including its opening and closing lines
+    pub(crate) fn span(&self) -> usize {
+        match self {
+            Block::Conditional(conditional) => conditional.span(),
+            Block::DoWhile(DoWhile { lines, .. })
+            | Block::While(While { lines, .. })
+            | Block::Repeat(Repeat { lines, .. })
+            | Block::Subroutine(Subroutine { lines, .. }) => 2 + lines_span(lines),
+        }
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Block::Conditional(conditional) => write!(f, "{}", conditional),
+            Block::DoWhile(do_while) => write!(f, "{}", do_while),
+            Block::While(while_block) => write!(f, "{}", while_block),
+            Block::Repeat(repeat) => write!(f, "{}", repeat),
+            Block::Subroutine(subroutine) => write!(f, "{}", subroutine),
+        }
+    }
+}
+
+/// The total number of source lines covered by a list of lines
+pub(crate) fn lines_span(lines: &[Line]) -> usize {
+    lines.iter().map(|line| line.span()).sum()
+}
+
+/// An O-word block identifier
 #[derive(Debug, PartialEq, Clone)]
 pub enum BlockIdent {
+    /// A named identifier like `o<my_sub>`
     Named(String),
+
+    /// A numbered identifier like `o100`
     Numbered(u16),
 }
 
@@ -75,10 +109,10 @@ pub struct DoWhile {
 /// A while loop
 #[derive(Debug, PartialEq, Clone)]
 pub struct While {
-    identifier: BlockIdent,
-    condition: Expression<f32>,
-    lines: Vec<Line>,
-    trailing_comment: Option<Comment>,
+    pub(crate) identifier: BlockIdent,
+    pub(crate) condition: Expression<f32>,
+    pub(crate) lines: Vec<Line>,
+    pub(crate) trailing_comment: Option<Comment>,
 }
 
 /// A block that is repeated _n_ times
@@ -93,10 +127,140 @@ pub struct Repeat {
 /// A subroutine definition
 #[derive(Debug, PartialEq, Clone)]
 pub struct Subroutine {
-    identifier: BlockIdent,
-    lines: Vec<Line>,
-    trailing_comment: Option<Comment>,
-    returns: Option<Expression<f32>>,
+    pub(crate) identifier: BlockIdent,
+    pub(crate) lines: Vec<Line>,
+    pub(crate) trailing_comment: Option<Comment>,
+    pub(crate) returns: Option<Expression<f32>>,
+}
+
+impl DoWhile {
+    /// Block identifier
+    pub fn identifier(&self) -> &BlockIdent {
+        &self.identifier
+    }
+
+    /// Condition checked after each iteration
+    pub fn condition(&self) -> &Expression<f32> {
+        &self.condition
+    }
+
+    /// Loop body
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+}
+
+impl While {
+    /// Block identifier
+    pub fn identifier(&self) -> &BlockIdent {
+        &self.identifier
+    }
+
+    /// Condition checked before each iteration
+    pub fn condition(&self) -> &Expression<f32> {
+        &self.condition
+    }
+
+    /// Loop body
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+}
+
+impl Repeat {
+    /// Block identifier
+    pub fn identifier(&self) -> &BlockIdent {
+        &self.identifier
+    }
+
+    /// Number of times to repeat the body
+    pub fn condition(&self) -> &Expression<f32> {
+        &self.condition
+    }
+
+    /// Loop body
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+}
+
+impl Subroutine {
+    /// Block identifier
+    pub fn identifier(&self) -> &BlockIdent {
+        &self.identifier
+    }
+
+    /// Subroutine body
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Value returned by the closing `endsub`, if any
+    pub fn returns(&self) -> Option<&Expression<f32>> {
+        self.returns.as_ref()
+    }
+}
+
+impl fmt::Display for DoWhile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "O{} do", self.identifier)?;
+        write_lines(f, &self.lines)?;
+        write!(f, "O{} while {}", self.identifier, self.condition)
+    }
+}
+
+impl fmt::Display for While {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "O{} while {}", self.identifier, self.condition)?;
+        write_opening_comment(f, &self.trailing_comment)?;
+        write_lines(f, &self.lines)?;
+        write!(f, "O{} endwhile", self.identifier)
+    }
+}
+
+impl fmt::Display for Repeat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "O{} repeat {}", self.identifier, self.condition)?;
+        write_opening_comment(f, &self.trailing_comment)?;
+        write_lines(f, &self.lines)?;
+        write!(f, "O{} endrepeat", self.identifier)
+    }
+}
+
+impl fmt::Display for Subroutine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "O{} sub", self.identifier)?;
+        write_opening_comment(f, &self.trailing_comment)?;
+        write_lines(f, &self.lines)?;
+        write!(f, "O{} endsub", self.identifier)?;
+
+        if let Some(returns) = &self.returns {
+            write!(f, " {}", returns)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Finish the opening line of a block with its comment, if any
+pub(crate) fn write_opening_comment(
+    f: &mut fmt::Formatter,
+    comment: &Option<Comment>,
+) -> fmt::Result {
+    if let Some(comment) = comment {
+        write!(f, " {}", comment)?;
+    }
+
+    writeln!(f)
+}
+
+/// Write the lines inside a block, each followed by a newline
+pub(crate) fn write_lines(f: &mut fmt::Formatter, lines: &[Line]) -> fmt::Result {
+    for line in lines {
+        writeln!(f, "{}", line)?;
+    }
+
+    Ok(())
 }
 
 pub fn parse_block_ident<'a, E: ParseError<&'a str>>(
@@ -1,4 +1,5 @@
 use crate::token::block::{parse_block_ident, BlockIdent};
+use crate::value::Value;
 use expression::{parser::gcode, Expression};
 use nom::{
     bytes::complete::tag_no_case,
@@ -9,13 +10,128 @@ use nom::{
     sequence::{preceded, separated_pair},
     IResult,
 };
+use std::fmt;
 use std::str::FromStr;
 
 /// Which type of block this is
 #[derive(Debug, PartialEq, Clone)]
 pub struct Call<T> {
-    subroutine_ident: BlockIdent,
-    arguments: Vec<Expression<T>>,
+    pub(crate) subroutine_ident: BlockIdent,
+    pub(crate) arguments: Vec<Expression<T>>,
+    pub(crate) kind: CallKind,
+    pub(crate) repeats: u32,
+}
+
+/// How a subroutine is called, which decides how it gets its arguments
+#[derive(Debug, PartialEq, Clone)]
+pub enum CallKind {
+    /// A LinuxCNC O-word call, passing its arguments in order as `#1`, `#2`, etc
+    OWord,
+
+    /// A Fanuc subprogram call (`M98 P1000 L2`), which shares the caller's local parameters
+    Subprogram,
+
+    /// A Fanuc macro call (`G65 P9010 A1 B2`), passing each argument in the parameter its letter
+    /// stands for
+    Macro(Vec<(char, Value)>),
+}
+
+impl<T> Call<T> {
+    /// The subroutine to call
+    pub fn subroutine_ident(&self) -> &BlockIdent {
+        &self.subroutine_ident
+    }
+
+    /// Arguments passed to the subroutine as parameters `#1`, `#2`, etc
+    pub fn arguments(&self) -> &[Expression<T>] {
+        &self.arguments
+    }
+
+    /// How the subroutine is called
+    pub fn kind(&self) -> &CallKind {
+        &self.kind
+    }
+
+    /// How many times in a row the subroutine is run (`L`), which is only ever more than once
+    /// for Fanuc calls
+    pub fn repeats(&self) -> u32 {
+        self.repeats
+    }
+
+    /// The parameter each Fanuc macro call argument is passed in, with its value
+    pub fn macro_arguments(&self) -> Vec<(u32, &Value)> {
+        match &self.kind {
+            CallKind::Macro(arguments) => arguments
+                .iter()
+                .filter_map(|(letter, value)| {
+                    argument_parameter(*letter).map(|parameter| (parameter, value))
+                })
+                .collect(),
+            CallKind::OWord | CallKind::Subprogram => Vec::new(),
+        }
+    }
+}
+
+/// The parameter a Fanuc macro call argument letter is passed in, following argument
+/// specification I
+pub(crate) fn argument_parameter(letter: char) -> Option<u32> {
+    let parameter = match letter.to_ascii_uppercase() {
+        'A' => 1,
+        'B' => 2,
+        'C' => 3,
+        'I' => 4,
+        'J' => 5,
+        'K' => 6,
+        'D' => 7,
+        'E' => 8,
+        'F' => 9,
+        'H' => 11,
+        'M' => 13,
+        'Q' => 17,
+        'R' => 18,
+        'S' => 19,
+        'T' => 20,
+        'U' => 21,
+        'V' => 22,
+        'W' => 23,
+        'X' => 24,
+        'Y' => 25,
+        'Z' => 26,
+        _ => return None,
+    };
+
+    Some(parameter)
+}
+
+impl<T> fmt::Display for Call<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            CallKind::OWord => {
+                write!(f, "O{} call", self.subroutine_ident)?;
+
+                for argument in self.arguments.iter() {
+                    write!(f, " {}", argument)?;
+                }
+            }
+            CallKind::Subprogram => write!(f, "M98 P{}", self.subroutine_ident)?,
+            CallKind::Macro(_) => write!(f, "G65 P{}", self.subroutine_ident)?,
+        }
+
+        if self.repeats != 1 {
+            write!(f, " L{}", self.repeats)?;
+        }
+
+        if let CallKind::Macro(arguments) = &self.kind {
+            for (letter, value) in arguments.iter() {
+                write!(f, " {}{}", letter.to_ascii_uppercase(), value)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 pub fn call<'a, E: ParseError<&'a str>, T>(i: &'a str) -> IResult<&'a str, Call<T>, E>
@@ -33,6 +149,8 @@ where
             |(subroutine_ident, arguments)| Call {
                 subroutine_ident,
                 arguments,
+                kind: CallKind::OWord,
+                repeats: 1,
             },
         ),
     )(i)
@@ -49,6 +167,8 @@ mod tests {
         let expd: Call<f32> = Call {
             subroutine_ident: 100.into(),
             arguments: Vec::new(),
+            kind: CallKind::OWord,
+            repeats: 1,
         };
 
         assert_parse!(
@@ -68,6 +188,8 @@ mod tests {
                 Expression::from_tokens(vec![ExpressionToken::Literal(0.0)]),
                 Expression::from_tokens(vec![ExpressionToken::Literal(0.08)]),
             ],
+            kind: CallKind::OWord,
+            repeats: 1,
         };
 
         assert_parse!(
@@ -95,7 +217,9 @@ mod tests {
                         ExpressionToken::ArithmeticOperator(ArithmeticOperator::Add),
                         ExpressionToken::Literal(2.0),
                     ])
-                ]
+                ],
+                kind: CallKind::OWord,
+                repeats: 1,
             };
         );
     }
@@ -116,7 +240,9 @@ mod tests {
                         ExpressionToken::ArithmeticOperator(ArithmeticOperator::Add),
                         ExpressionToken::Literal(2.0),
                     ])
-                ]
+                ],
+                kind: CallKind::OWord,
+                repeats: 1,
             };
         );
     }
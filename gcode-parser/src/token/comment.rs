@@ -1,12 +1,14 @@
+use expression::{gcode, Parameter};
 use nom::{
     branch::alt,
-    bytes::complete::take_until,
+    bytes::complete::{take_until, take_while},
     character::complete::{char, space0},
     combinator::map,
     error::{context, ParseError},
     sequence::{delimited, preceded},
     IResult,
 };
+use std::fmt;
 
 /// A comment
 ///
@@ -15,29 +17,195 @@ use nom::{
 pub struct Comment {
     /// The comment text
     pub text: String,
+
+    /// What the comment asks the machine to do, if anything
+    pub kind: CommentKind,
 }
 
-pub fn comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Comment, E> {
+/// The meaning LinuxCNC gives to parenthesised comments starting with certain keywords
+///
+/// Keywords are case insensitive. Comments starting with `;` are always plain.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CommentKind {
+    /// An ordinary comment
+    Plain,
+
+    /// `(MSG, text)`: show a message to the operator
+    Message(String),
+
+    /// `(DEBUG, text)`: show a message to the operator with parameters substituted
+    Debug(Vec<MessagePart>),
+
+    /// `(PRINT, text)`: print a message to the controller's standard error with parameters
+    /// substituted
+    Print(Vec<MessagePart>),
+
+    /// `(LOGOPEN, file)`: open a log file, replacing its contents
+    LogOpen(String),
+
+    /// `(LOGAPPEND, file)`: open a log file to add to
+    LogAppend(String),
+
+    /// `(LOG, text)`: write a line to the open log file with parameters substituted
+    Log(Vec<MessagePart>),
+
+    /// `(LOGCLOSE)`: close the open log file
+    LogClose,
+
+    /// `(PROBEOPEN file)`: open a file to record probe results in
+    ProbeOpen(String),
+
+    /// `(PROBECLOSE)`: close the probe results file
+    ProbeClose,
+
+    /// Slicer metadata like `;LAYER:3` in the Marlin dialect
+    Metadata {
+        /// What the metadata is about
+        key: String,
+
+        /// The metadata itself
+        value: String,
+    },
+}
+
+/// A piece of a message that may have parameters substituted into it
+#[derive(Debug, PartialEq, Clone)]
+pub enum MessagePart {
+    /// Text to show as it is
+    Text(String),
+
+    /// A parameter like `#<_x>` or `#5221`, to be replaced with its value
+    Parameter(Parameter),
+}
+
+impl CommentKind {
+    /// Work out the kind of a parenthesised comment from its trimmed text
+    fn from_text(text: &str) -> Self {
+        // Keywords followed by a comma, then their argument
+        let argument = |keyword: &str| {
+            let start = text.get(..keyword.len())?;
+
+            if !start.eq_ignore_ascii_case(keyword) {
+                return None;
+            }
+
+            text[keyword.len()..]
+                .trim_start()
+                .strip_prefix(',')
+                .map(|argument| argument.trim().to_string())
+        };
+
+        let upper = text.to_ascii_uppercase();
+
+        if let Some(message) = argument("MSG") {
+            CommentKind::Message(message)
+        } else if let Some(message) = argument("DEBUG") {
+            CommentKind::Debug(message_parts(&message))
+        } else if let Some(message) = argument("PRINT") {
+            CommentKind::Print(message_parts(&message))
+        } else if let Some(file) = argument("LOGOPEN") {
+            CommentKind::LogOpen(file)
+        } else if let Some(file) = argument("LOGAPPEND") {
+            CommentKind::LogAppend(file)
+        } else if let Some(message) = argument("LOG") {
+            CommentKind::Log(message_parts(&message))
+        } else if upper == "LOGCLOSE" {
+            CommentKind::LogClose
+        } else if upper == "PROBECLOSE" {
+            CommentKind::ProbeClose
+        } else if upper.starts_with("PROBEOPEN ") {
+            CommentKind::ProbeOpen(text["PROBEOPEN ".len()..].trim().to_string())
+        } else {
+            CommentKind::Plain
+        }
+    }
+}
+
+/// Split a message into text and the parameters to substitute into it
+///
+/// A `#` that doesn't start a valid parameter is left in the text.
+fn message_parts(message: &str) -> Vec<MessagePart> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut rest = message;
+
+    while let Some(index) = rest.find('#') {
+        text.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        match gcode::parameter::<()>(rest) {
+            Ok((remaining, parameter)) => {
+                if !text.is_empty() {
+                    parts.push(MessagePart::Text(std::mem::take(&mut text)));
+                }
+
+                parts.push(MessagePart::Parameter(parameter));
+
+                rest = remaining;
+            }
+            Err(_) => {
+                text.push('#');
+
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    text.push_str(rest);
+
+    if !text.is_empty() {
+        parts.push(MessagePart::Text(text));
+    }
+
+    parts
+}
+
+impl fmt::Display for Comment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // A closing parenthesis would end a parenthesised comment early, and slicer metadata is
+        // only read from comments after a `;`
+        if self.text.contains(')') || matches!(self.kind, CommentKind::Metadata { .. }) {
+            write!(f, "; {}", self.text)
+        } else {
+            write!(f, "({})", self.text)
+        }
+    }
+}
+
+/// Parse the trimmed text of a comment, and whether it was in parentheses rather than after a `;`
+pub(crate) fn raw_comment<'a, E: ParseError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, (String, bool), E> {
     context(
         "comment",
         map(
-            map(
-                preceded(
-                    space0,
-                    alt((
-                        delimited(char('('), take_until(")"), char(')')),
-                        preceded(char(';'), take_until("\n")),
-                    )),
-                ),
-                String::from,
+            preceded(
+                space0,
+                alt((
+                    map(delimited(char('('), take_until(")"), char(')')), |text| {
+                        (text, true)
+                    }),
+                    map(preceded(char(';'), take_while(|c| c != '\n')), |text| {
+                        (text, false)
+                    }),
+                )),
             ),
-            |text| Comment {
-                text: text.trim().to_string(),
-            },
+            |(text, parenthesised): (&str, bool)| (text.trim().to_string(), parenthesised),
         ),
     )(i)
 }
 
+pub fn comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Comment, E> {
+    map(raw_comment, |(text, parenthesised)| Comment {
+        kind: if parenthesised {
+            CommentKind::from_text(&text)
+        } else {
+            CommentKind::Plain
+        },
+        text,
+    })(i)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -49,7 +217,8 @@ mod tests {
             parser = comment;
             input = "( some comment text )";
             expected = Comment {
-                text: "some comment text".into()
+                text: "some comment text".into(),
+                kind: CommentKind::Plain,
             }
         );
 
@@ -57,7 +226,8 @@ mod tests {
             parser = comment;
             input = "(some comment text)";
             expected = Comment {
-                text: "some comment text".into()
+                text: "some comment text".into(),
+                kind: CommentKind::Plain,
             }
         );
     }
@@ -68,7 +238,8 @@ mod tests {
             parser = comment;
             input = "; Some comment text\n";
             expected = Comment {
-                text: "Some comment text".into()
+                text: "Some comment text".into(),
+                kind: CommentKind::Plain,
             };
             remaining = "\n"
         );
@@ -78,10 +249,22 @@ mod tests {
             parser = comment;
             input = ";Some comment text\n";
             expected = Comment {
-                text: "Some comment text".into()
+                text: "Some comment text".into(),
+                kind: CommentKind::Plain,
             };
             remaining = "\n"
         );
+
+        // The last line of a file may not end in a newline
+        assert_parse!(
+            parser = comment;
+            input = "; Some comment text";
+            expected = Comment {
+                text: "Some comment text".into(),
+                kind: CommentKind::Plain,
+            };
+            remaining = ""
+        );
     }
 
     #[test]
@@ -90,9 +273,72 @@ mod tests {
             parser = comment;
             input = "; Some comment text\r\n";
             expected = Comment {
-                text: "Some comment text".into()
+                text: "Some comment text".into(),
+                kind: CommentKind::Plain,
             };
             remaining = "\n"
         );
     }
+
+    #[test]
+    fn parse_active_comments() {
+        let kind = |input: &str| comment::<()>(input).unwrap().1.kind;
+
+        assert_eq!(
+            kind("(MSG, Change to a 1/4\" end mill #1)"),
+            CommentKind::Message("Change to a 1/4\" end mill #1".into())
+        );
+        assert_eq!(
+            kind("(debug,X is #<_x> and #5221!)"),
+            CommentKind::Debug(vec![
+                MessagePart::Text("X is ".into()),
+                MessagePart::Parameter(Parameter::Global("x".into())),
+                MessagePart::Text(" and ".into()),
+                MessagePart::Parameter(Parameter::Numbered(5221)),
+                MessagePart::Text("!".into()),
+            ])
+        );
+        assert_eq!(
+            kind("(PRINT, #<depth> # done)"),
+            CommentKind::Print(vec![
+                MessagePart::Parameter(Parameter::Local("depth".into())),
+                MessagePart::Text(" # done".into()),
+            ])
+        );
+        assert_eq!(
+            kind("(LOGOPEN,probe.txt)"),
+            CommentKind::LogOpen("probe.txt".into())
+        );
+        assert_eq!(
+            kind("(LOGAPPEND , probe.txt)"),
+            CommentKind::LogAppend("probe.txt".into())
+        );
+        assert_eq!(
+            kind("(LOG,#1)"),
+            CommentKind::Log(vec![MessagePart::Parameter(Parameter::Numbered(1))])
+        );
+        assert_eq!(kind("( LOGCLOSE )"), CommentKind::LogClose);
+        assert_eq!(
+            kind("(PROBEOPEN points.txt)"),
+            CommentKind::ProbeOpen("points.txt".into())
+        );
+        assert_eq!(kind("(probeclose)"), CommentKind::ProbeClose);
+    }
+
+    #[test]
+    fn plain_comments() {
+        let kind = |input: &str| comment::<()>(input).unwrap().1.kind;
+
+        for input in [
+            "; MSG, not a message\n",
+            "(MSG without a comma)",
+            "(message, not a keyword)",
+            "(LOGCLOSE now)",
+            "(µs)",
+        ]
+        .iter()
+        {
+            assert_eq!(kind(input), CommentKind::Plain, "{}", input);
+        }
+    }
 }
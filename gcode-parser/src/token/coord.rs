@@ -1,13 +1,14 @@
 //! Parse coordinates into a vector
 
 use crate::value::decimal_value;
-use crate::value::Value;
+use crate::value::{write_words, Value};
 use nom::character::complete::anychar;
 use nom::error::ErrorKind;
 use nom::sequence::preceded;
 use nom::sequence::separated_pair;
 use nom::Err;
 use nom::{character::complete::space0, error::ParseError, IResult};
+use std::fmt;
 
 /// A 9 dimensional `XYZABCUVW` coordinate
 ///
@@ -51,6 +52,25 @@ impl Default for Coord {
     }
 }
 
+impl fmt::Display for Coord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_words(
+            f,
+            &[
+                ('X', &self.x),
+                ('Y', &self.y),
+                ('Z', &self.z),
+                ('A', &self.a),
+                ('B', &self.b),
+                ('C', &self.c),
+                ('U', &self.u),
+                ('V', &self.v),
+                ('W', &self.w),
+            ],
+        )
+    }
+}
+
 /// Parse a coordinate
 pub fn coord<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Coord, E> {
     let mut c = Coord::default();
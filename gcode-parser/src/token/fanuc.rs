@@ -0,0 +1,357 @@
+//! Parse Fanuc Custom Macro B: unbracketed assignments, `IF`/`GOTO` jumps, `WHILE`/`DO` loops
+//! and subprograms
+//!
+//! Where LinuxCNC has an equivalent, Fanuc code is parsed into the same types, so loops are
+//! [`While`](struct.While.html) blocks, subprograms are
+//! [`Subroutine`](struct.Subroutine.html) blocks and `M98` and `G65` are
+//! [`Call`](struct.Call.html)s. Loops and subprograms are written back out in LinuxCNC syntax.
+
+use crate::line::{line_with, Line};
+use crate::parsers::char_no_case;
+use crate::token::assignment::Assignment;
+use crate::token::block::{BlockIdent, Subroutine, While};
+use crate::token::call::{argument_parameter, Call, CallKind};
+use crate::token::comment::comment;
+use crate::token::othercode::raw_line_number;
+use crate::token::{token_type, Block, Token, TokenType};
+use crate::value::{decimal_value, unsigned_value, UnsignedValue, Value};
+use crate::word::word;
+use expression::gcode::{bare_expression, expression, parameter};
+use expression::{Expression, ExpressionToken};
+use nom::{
+    branch::{alt, permutation},
+    bytes::complete::tag_no_case,
+    character::complete::{anychar, char, digit1, line_ending, space0},
+    combinator::{map, map_res, not, opt, verify},
+    error::{context, ParseError},
+    multi::many0,
+    sequence::{delimited, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+use std::fmt;
+
+/// A jump to the line with a given sequence number (`GOTO 100`), which may only be taken when a
+/// condition is true (`IF [#1 GT 0] GOTO 100`)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Goto {
+    /// Only jump if this is true
+    pub condition: Option<Expression<f32>>,
+
+    /// The sequence number (`N`) of the line to jump to
+    pub sequence: UnsignedValue,
+}
+
+impl fmt::Display for Goto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(condition) = &self.condition {
+            write!(f, "IF {} ", condition)?;
+        }
+
+        write!(f, "GOTO {}", self.sequence)
+    }
+}
+
+/// Parse a jump, with or without a condition
+pub fn goto<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Goto, E> {
+    context(
+        "GOTO",
+        map(
+            tuple((
+                opt(terminated(
+                    preceded(terminated(tag_no_case("IF"), space0), expression),
+                    space0,
+                )),
+                terminated(tag_no_case("GOTO"), space0),
+                unsigned_value,
+            )),
+            |(condition, _, sequence)| Goto {
+                condition,
+                sequence,
+            },
+        ),
+    )(i)
+}
+
+fn number<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u16, E> {
+    map_res(digit1, |number: &'a str| number.parse::<u16>())(i)
+}
+
+fn repeats<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
+    preceded(
+        char_no_case('L'),
+        map_res(digit1, |number: &'a str| number.parse::<u32>()),
+    )(i)
+}
+
+/// A `WHILE [...] DO 1` loop, or `DO 1` on its own for a loop that never ends, up to `END 1`
+fn fanuc_while<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, While, E> {
+    let (i, (condition, identifier, trailing_comment)) = tuple((
+        opt(terminated(
+            preceded(terminated(tag_no_case("WHILE"), space0), expression),
+            space0,
+        )),
+        preceded(terminated(tag_no_case("DO"), space0), number),
+        terminated(preceded(space0, opt(comment)), line_ending),
+    ))(i)?;
+
+    let (i, lines) = many0(terminated(line_with(fanuc_token), line_ending))(i)?;
+
+    let (i, _) = tuple((
+        space0,
+        opt(terminated(raw_line_number, space0)),
+        terminated(tag_no_case("END"), space0),
+        verify(number, |end| *end == identifier),
+        space0,
+        opt(comment),
+    ))(i)?;
+
+    Ok((
+        i,
+        While {
+            identifier: BlockIdent::Numbered(identifier),
+            condition: condition
+                .unwrap_or_else(|| Expression::from_tokens(vec![ExpressionToken::Literal(1.0)])),
+            lines,
+            trailing_comment,
+        },
+    ))
+}
+
+/// The `M99` line that ends a subprogram
+fn subprogram_end<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
+    map(
+        tuple((
+            space0,
+            opt(terminated(raw_line_number, space0)),
+            word("M99"),
+            space0,
+            opt(comment),
+        )),
+        |_| (),
+    )(i)
+}
+
+/// A line that can't be part of the subprogram before it: its end, the start of another program
+/// or a `%`
+fn program_boundary<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
+    alt((
+        subprogram_end,
+        map(tuple((space0, char_no_case('O'), digit1)), |_| ()),
+        map(preceded(space0, char('%')), |_| ()),
+    ))(i)
+}
+
+/// A numbered program (`O1000`) that ends with `M99`
+///
+/// Programs that don't end in `M99`, like the main program, aren't subprograms, and their
+/// program number is left as an unknown `O` word.
+fn subprogram<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Subroutine, E> {
+    let (i, (identifier, trailing_comment)) = tuple((
+        preceded(char_no_case('O'), number),
+        terminated(preceded(space0, opt(comment)), line_ending),
+    ))(i)?;
+
+    let (i, lines): (_, Vec<Line>) = many0(terminated(
+        preceded(not(program_boundary), line_with(fanuc_token)),
+        line_ending,
+    ))(i)?;
+
+    let (i, _) = subprogram_end(i)?;
+
+    Ok((
+        i,
+        Subroutine {
+            identifier: BlockIdent::Numbered(identifier),
+            lines,
+            trailing_comment,
+            returns: None,
+        },
+    ))
+}
+
+/// A subprogram call (`M98 P1000 L2`)
+fn subprogram_call<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Call<f32>, E> {
+    map(
+        preceded(
+            word("M98"),
+            permutation((
+                preceded(space0, preceded(char_no_case('P'), number)),
+                opt(preceded(space0, repeats)),
+            )),
+        ),
+        |(program, repeats)| Call {
+            subroutine_ident: BlockIdent::Numbered(program),
+            arguments: Vec::new(),
+            kind: CallKind::Subprogram,
+            repeats: repeats.unwrap_or(1),
+        },
+    )(i)
+}
+
+fn macro_argument<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (char, Value), E> {
+    map(
+        separated_pair(
+            verify(anychar, |letter: &char| {
+                argument_parameter(*letter).is_some()
+            }),
+            space0,
+            decimal_value,
+        ),
+        |(letter, value)| (letter.to_ascii_uppercase(), value),
+    )(i)
+}
+
+/// A macro call (`G65 P9010 L2 A1 B2`)
+fn macro_call<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Call<f32>, E> {
+    map(
+        tuple((
+            preceded(
+                word("G65"),
+                permutation((
+                    preceded(space0, preceded(char_no_case('P'), number)),
+                    opt(preceded(space0, repeats)),
+                )),
+            ),
+            many0(preceded(space0, macro_argument)),
+        )),
+        |((program, repeats), arguments)| Call {
+            subroutine_ident: BlockIdent::Numbered(program),
+            arguments: Vec::new(),
+            kind: CallKind::Macro(arguments),
+            repeats: repeats.unwrap_or(1),
+        },
+    )(i)
+}
+
+/// An assignment with or without brackets around the value (`#100 = #101 + 1`)
+fn fanuc_assignment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Assignment, E> {
+    map(
+        separated_pair(
+            parameter,
+            delimited(space0, char('='), space0),
+            bare_expression,
+        ),
+        |(lhs, rhs): (_, Expression<f32>)| {
+            let rhs = match rhs.0.as_slice() {
+                [ExpressionToken::Literal(value)] => Value::Literal(*value),
+                [ExpressionToken::Parameter(parameter)] => Value::Parameter(parameter.clone()),
+                _ => Value::Expression(rhs),
+            };
+
+            Assignment { lhs, rhs }
+        },
+    )(i)
+}
+
+/// Parse a token in the Fanuc dialect
+///
+/// Macro statements, loops, subprograms and calls are tried before the LinuxCNC tokens.
+pub fn fanuc_token<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Token, E> {
+    map(
+        alt((
+            map(fanuc_while, |block| TokenType::Block(Block::While(block))),
+            map(subprogram, |block| {
+                TokenType::Block(Block::Subroutine(block))
+            }),
+            map(goto, TokenType::Goto),
+            map(subprogram_call, TokenType::Call),
+            map(macro_call, TokenType::Call),
+            map(fanuc_assignment, TokenType::Assignment),
+            token_type,
+        )),
+        |token| Token { token },
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expression::{BinaryOperator, Parameter};
+
+    #[test]
+    fn parse_goto() {
+        assert_parse!(
+            parser = goto;
+            input = "GOTO 100", "IF [#1 GT 0] GOTO #2", "if[#1 gt 0]goto5";
+            expected = Goto {
+                condition: None,
+                sequence: 100.into(),
+            },
+            Goto {
+                condition: Some(Expression::from_tokens(vec![
+                    ExpressionToken::Parameter(Parameter::Numbered(1)),
+                    ExpressionToken::BinaryOperator(BinaryOperator::GreaterThan),
+                    ExpressionToken::Literal(0.0),
+                ])),
+                sequence: UnsignedValue::Parameter(Parameter::Numbered(2)),
+            },
+            Goto {
+                condition: Some(Expression::from_tokens(vec![
+                    ExpressionToken::Parameter(Parameter::Numbered(1)),
+                    ExpressionToken::BinaryOperator(BinaryOperator::GreaterThan),
+                    ExpressionToken::Literal(0.0),
+                ])),
+                sequence: 5.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_calls() {
+        assert_parse!(
+            parser = subprogram_call;
+            input = "M98 P1000 L2", "M98P12";
+            expected = Call {
+                subroutine_ident: 1000.into(),
+                arguments: Vec::new(),
+                kind: CallKind::Subprogram,
+                repeats: 2,
+            },
+            Call {
+                subroutine_ident: 12.into(),
+                arguments: Vec::new(),
+                kind: CallKind::Subprogram,
+                repeats: 1,
+            }
+        );
+
+        assert_parse!(
+            parser = macro_call;
+            input = "G65 P9010 A1 B2 X#3";
+            expected = Call {
+                subroutine_ident: 9010.into(),
+                arguments: Vec::new(),
+                kind: CallKind::Macro(vec![
+                    ('A', 1.0f32.into()),
+                    ('B', 2.0f32.into()),
+                    ('X', Parameter::Numbered(3).into()),
+                ]),
+                repeats: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_assignments() {
+        assert_parse!(
+            parser = fanuc_assignment;
+            input = "#100 = #101 + 1", "#1=5", "#2 = #<_x>";
+            expected = Assignment {
+                lhs: Parameter::Numbered(100),
+                rhs: Value::Expression(Expression::from_tokens(vec![
+                    ExpressionToken::Parameter(Parameter::Numbered(101)),
+                    ExpressionToken::ArithmeticOperator(expression::ArithmeticOperator::Add),
+                    ExpressionToken::Literal(1.0),
+                ])),
+            },
+            Assignment {
+                lhs: Parameter::Numbered(1),
+                rhs: 5.0f32.into(),
+            },
+            Assignment {
+                lhs: Parameter::Numbered(2),
+                rhs: Parameter::Global("x".into()).into(),
+            }
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::parsers::char_no_case;
 use crate::value::{preceded_decimal_value, Value};
-use crate::word::word;
+use crate::word::{decimal_word, word};
 use nom::{
     branch::alt,
     character::complete::space0,
@@ -9,6 +9,7 @@ use nom::{
     sequence::separated_pair,
     IResult,
 };
+use std::fmt;
 
 /// Cutter compensation type
 #[derive(Debug, PartialEq, Clone)]
@@ -21,6 +22,32 @@ pub enum CutterCompensation {
 
     /// Offset the tool to the right of the path (G42)
     Right(Option<Value>),
+
+    /// Offset the tool to the left of the path by a given tool diameter (G41.1)
+    DynamicLeft(Value),
+
+    /// Offset the tool to the right of the path by a given tool diameter (G42.1)
+    DynamicRight(Value),
+}
+
+impl fmt::Display for CutterCompensation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (code, diameter) = match self {
+            CutterCompensation::Off => return write!(f, "G40"),
+            CutterCompensation::Left(diameter) => ("G41", diameter.as_ref()),
+            CutterCompensation::Right(diameter) => ("G42", diameter.as_ref()),
+            CutterCompensation::DynamicLeft(diameter) => ("G41.1", Some(diameter)),
+            CutterCompensation::DynamicRight(diameter) => ("G42.1", Some(diameter)),
+        };
+
+        write!(f, "{}", code)?;
+
+        if let Some(diameter) = diameter {
+            write!(f, " D{}", diameter)?;
+        }
+
+        Ok(())
+    }
 }
 
 pub fn cutter_compensation<'a, E: ParseError<&'a str>>(
@@ -30,6 +57,22 @@ pub fn cutter_compensation<'a, E: ParseError<&'a str>>(
         "cutter comp",
         alt((
             map(word("g40"), |_| CutterCompensation::Off),
+            map(
+                separated_pair(
+                    decimal_word("g41.1"),
+                    space0,
+                    preceded_decimal_value(char_no_case('d')),
+                ),
+                |(_, d)| CutterCompensation::DynamicLeft(d),
+            ),
+            map(
+                separated_pair(
+                    decimal_word("g42.1"),
+                    space0,
+                    preceded_decimal_value(char_no_case('d')),
+                ),
+                |(_, d)| CutterCompensation::DynamicRight(d),
+            ),
             map(
                 separated_pair(
                     word("g41"),
@@ -78,11 +121,15 @@ mod tests {
             parser = cutter_compensation;
             input =
                 "G41 D5.0",
-                "G42d10.1"
+                "G42d10.1",
+                "G41.1 D0.5",
+                "g42.1d2"
             ;
             expected =
                 CutterCompensation::Left(Some(5.0.into())),
-                CutterCompensation::Right(Some(10.1.into()))
+                CutterCompensation::Right(Some(10.1.into())),
+                CutterCompensation::DynamicLeft(0.5.into()),
+                CutterCompensation::DynamicRight(2.0.into())
             ;
         );
     }
@@ -8,6 +8,7 @@ use nom::{
     sequence::separated_pair,
     IResult,
 };
+use std::fmt;
 
 /// Dwell
 #[derive(Debug, PartialEq, Clone)]
@@ -16,6 +17,12 @@ pub struct Dwell {
     pub time: Value,
 }
 
+impl fmt::Display for Dwell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "G4 P{}", self.time)
+    }
+}
+
 pub fn dwell<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Dwell, E> {
     context(
         "dwell",
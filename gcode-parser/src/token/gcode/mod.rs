@@ -1,5 +1,6 @@
 mod cutter_compensation;
 mod dwell;
+mod path_control;
 mod plane_select;
 mod work_offset;
 
@@ -7,6 +8,8 @@ use self::cutter_compensation::cutter_compensation;
 pub use self::cutter_compensation::CutterCompensation;
 use self::dwell::dwell;
 pub use self::dwell::Dwell;
+use self::path_control::path_control;
+pub use self::path_control::PathControl;
 use self::plane_select::plane_select;
 pub use self::plane_select::PlaneSelect;
 use self::work_offset::work_offset;
@@ -19,6 +22,7 @@ use nom::{
     error::{context, ParseError},
     IResult,
 };
+use std::fmt;
 
 /// A G-code
 #[derive(Debug, PartialEq, Clone)]
@@ -50,6 +54,18 @@ pub enum GCode {
     /// A counterclockwise arc
     CounterclockwiseArc,
 
+    /// A cubic spline (`G5`)
+    CubicSpline,
+
+    /// A quadratic spline (`G5.1`)
+    QuadraticSpline,
+
+    /// Start a block of NURBS control points (`G5.2`)
+    Nurbs,
+
+    /// End a block of NURBS control points (`G5.3`)
+    EndNurbs,
+
     /// Disable cutter compensation (G40)
     DisableCutterCompensation,
 
@@ -63,6 +79,34 @@ pub enum GCode {
 
     /// Go to predefined position
     GotoPredefinedPosition,
+
+    /// Path control mode (`G61`, `G61.1`, `G64`)
+    PathControl(PathControl),
+}
+
+impl fmt::Display for GCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GCode::Rapid => write!(f, "G0"),
+            GCode::Feed => write!(f, "G1"),
+            GCode::ClockwiseArc => write!(f, "G2"),
+            GCode::CounterclockwiseArc => write!(f, "G3"),
+            GCode::CubicSpline => write!(f, "G5"),
+            GCode::QuadraticSpline => write!(f, "G5.1"),
+            GCode::Nurbs => write!(f, "G5.2"),
+            GCode::EndNurbs => write!(f, "G5.3"),
+            GCode::WorkOffset(offset) => write!(f, "{}", offset),
+            GCode::Dwell(dwell) => write!(f, "{}", dwell),
+            GCode::UnitsMM => write!(f, "G21"),
+            GCode::UnitsInch => write!(f, "G20"),
+            GCode::PlaneSelect(plane) => write!(f, "{}", plane),
+            GCode::DisableCutterCompensation => write!(f, "G40"),
+            GCode::CutterCompensation(compensation) => write!(f, "{}", compensation),
+            GCode::SetPredefinedPosition => write!(f, "G28.1"),
+            GCode::GotoPredefinedPosition => write!(f, "G28"),
+            GCode::PathControl(mode) => write!(f, "{}", mode),
+        }
+    }
 }
 
 pub fn gcode<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, GCode, E> {
@@ -73,6 +117,10 @@ pub fn gcode<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, GCode,
             map(word("G2"), |_| GCode::ClockwiseArc),
             map(word("G3"), |_| GCode::CounterclockwiseArc),
             map(word("G0"), |_| GCode::Rapid),
+            map(decimal_word("G5.1"), |_| GCode::QuadraticSpline),
+            map(decimal_word("G5.2"), |_| GCode::Nurbs),
+            map(decimal_word("G5.3"), |_| GCode::EndNurbs),
+            map(word("G5"), |_| GCode::CubicSpline),
             map(word("G21"), |_| GCode::UnitsMM),
             map(word("G20"), |_| GCode::UnitsInch),
             map(decimal_word("G28.1"), |_| GCode::SetPredefinedPosition),
@@ -81,6 +129,7 @@ pub fn gcode<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, GCode,
             map(cutter_compensation, GCode::CutterCompensation),
             map(dwell, GCode::Dwell),
             map(plane_select, GCode::PlaneSelect),
+            map(path_control, GCode::PathControl),
         )),
     )(i)
 }
@@ -126,4 +175,16 @@ mod tests {
             expected = GCode::CounterclockwiseArc
         );
     }
+
+    #[test]
+    fn parse_spline() {
+        assert_parse!(
+            parser = gcode;
+            input = "G5", "g5.1", "G5.2", "G5.3";
+            expected = GCode::CubicSpline,
+            GCode::QuadraticSpline,
+            GCode::Nurbs,
+            GCode::EndNurbs
+        );
+    }
 }
@@ -0,0 +1,125 @@
+use crate::parsers::char_no_case;
+use crate::value::{preceded_positive_decimal_value, Value};
+use crate::word::decimal_word;
+use crate::word::word;
+use nom::{
+    branch::alt,
+    character::complete::space0,
+    combinator::{map, opt},
+    error::{context, ParseError},
+    sequence::{preceded, tuple},
+    IResult,
+};
+use std::fmt;
+
+/// Path control mode
+#[derive(Debug, PartialEq, Clone)]
+pub enum PathControl {
+    /// Exact path, `G61`. The machine stops at every corner.
+    ExactPath,
+
+    /// Exact stop, `G61.1`. The machine stops at the end of every move.
+    ExactStop,
+
+    /// Blend corners, `G64 P Q`
+    Blend {
+        /// The maximum distance the path may deviate from a corner, or as much as the machine's
+        /// limits allow if not given
+        tolerance: Option<Value>,
+
+        /// Tolerance for merging runs of almost collinear moves into one ("naive CAM detector")
+        naive_tolerance: Option<Value>,
+    },
+}
+
+impl fmt::Display for PathControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathControl::ExactPath => write!(f, "G61"),
+            PathControl::ExactStop => write!(f, "G61.1"),
+            PathControl::Blend {
+                tolerance,
+                naive_tolerance,
+            } => {
+                write!(f, "G64")?;
+
+                if let Some(tolerance) = tolerance {
+                    write!(f, " P{}", tolerance)?;
+                }
+
+                if let Some(naive_tolerance) = naive_tolerance {
+                    write!(f, " Q{}", naive_tolerance)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn path_control<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, PathControl, E> {
+    context(
+        "path control",
+        alt((
+            map(decimal_word("G61.1"), |_| PathControl::ExactStop),
+            map(word("G61"), |_| PathControl::ExactPath),
+            map(
+                tuple((
+                    word("G64"),
+                    opt(preceded(
+                        space0,
+                        preceded_positive_decimal_value(char_no_case('P')),
+                    )),
+                    opt(preceded(
+                        space0,
+                        preceded_positive_decimal_value(char_no_case('Q')),
+                    )),
+                )),
+                |(_, tolerance, naive_tolerance)| PathControl::Blend {
+                    tolerance,
+                    naive_tolerance,
+                },
+            ),
+        )),
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_exact() {
+        assert_parse!(
+            parser = path_control;
+            input =
+                "G61",
+                "G61.1"
+            ;
+            expected =
+                PathControl::ExactPath,
+                PathControl::ExactStop
+            ;
+        );
+    }
+
+    #[test]
+    fn parse_blend() {
+        assert_parse!(
+            parser = path_control;
+            input =
+                "G64",
+                "G64 P0.01",
+                "G64P0.01Q0.005"
+            ;
+            expected =
+                PathControl::Blend { tolerance: None, naive_tolerance: None },
+                PathControl::Blend { tolerance: Some(0.01.into()), naive_tolerance: None },
+                PathControl::Blend {
+                    tolerance: Some(0.01.into()),
+                    naive_tolerance: Some(0.005.into())
+                }
+            ;
+        );
+    }
+}
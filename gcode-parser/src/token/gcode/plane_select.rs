@@ -6,6 +6,7 @@ use nom::{
     error::{context, ParseError},
     IResult,
 };
+use std::fmt;
 
 /// Which plane to use
 #[derive(Debug, PartialEq, Clone)]
@@ -24,6 +25,19 @@ pub enum PlaneSelect {
     VW = 5,
 }
 
+impl fmt::Display for PlaneSelect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaneSelect::XY => write!(f, "G17"),
+            PlaneSelect::ZX => write!(f, "G18"),
+            PlaneSelect::YZ => write!(f, "G19"),
+            PlaneSelect::UV => write!(f, "G17.1"),
+            PlaneSelect::WU => write!(f, "G18.1"),
+            PlaneSelect::VW => write!(f, "G19.1"),
+        }
+    }
+}
+
 pub fn plane_select<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, PlaneSelect, E> {
     context(
         "plane select",
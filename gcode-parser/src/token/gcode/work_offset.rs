@@ -6,6 +6,7 @@ use nom::{
     error::{context, ParseError},
     IResult,
 };
+use std::fmt;
 
 /// Work offset
 #[derive(Debug, PartialEq, Clone)]
@@ -30,6 +31,22 @@ pub enum WorkOffset {
     G59_3 = 8,
 }
 
+impl fmt::Display for WorkOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkOffset::G54 => write!(f, "G54"),
+            WorkOffset::G55 => write!(f, "G55"),
+            WorkOffset::G56 => write!(f, "G56"),
+            WorkOffset::G57 => write!(f, "G57"),
+            WorkOffset::G58 => write!(f, "G58"),
+            WorkOffset::G59 => write!(f, "G59"),
+            WorkOffset::G59_1 => write!(f, "G59.1"),
+            WorkOffset::G59_2 => write!(f, "G59.2"),
+            WorkOffset::G59_3 => write!(f, "G59.3"),
+        }
+    }
+}
+
 pub fn work_offset<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, WorkOffset, E> {
     context(
         "work offset",
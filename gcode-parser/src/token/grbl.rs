@@ -0,0 +1,231 @@
+//! Parse GRBL's `$` system commands and realtime command bytes
+
+use crate::parsers::char_no_case;
+use crate::token::{token, token_type, Token, TokenType};
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit1, space0},
+    combinator::{map, map_res},
+    error::{context, ParseError},
+    multi::many1,
+    number::complete::float,
+    sequence::{preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+use std::fmt;
+
+/// A GRBL system command, starting with `$`
+///
+/// GRBL reads system commands on a line of their own.
+#[derive(Debug, PartialEq, Clone)]
+pub enum GrblCommand {
+    /// Run the homing cycle (`$H`)
+    Home,
+
+    /// Clear the alarm lock without homing (`$X`)
+    Unlock,
+
+    /// Jog with the given words (`$J=`), which don't change the modal state
+    Jog(Vec<Token>),
+
+    /// Print every setting (`$$`)
+    Settings,
+
+    /// Print the work offsets, tool length offset and last probe position (`$#`)
+    Parameters,
+
+    /// Print the active modal state (`$G`)
+    ParserState,
+
+    /// Change a setting (`$nnn=value`)
+    Setting {
+        /// The setting number
+        number: u32,
+
+        /// The new value
+        value: f32,
+    },
+}
+
+/// A realtime command byte, acted on by GRBL as soon as it is received
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Realtime {
+    /// Report the machine's status (`?`)
+    StatusReport,
+
+    /// Pause motion (`!`)
+    FeedHold,
+
+    /// Start or resume motion (`~`)
+    CycleStart,
+
+    /// Stop immediately and reset (`0x18`, or Ctrl-X)
+    SoftReset,
+}
+
+impl fmt::Display for GrblCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrblCommand::Home => write!(f, "$H"),
+            GrblCommand::Unlock => write!(f, "$X"),
+            GrblCommand::Jog(tokens) => {
+                write!(f, "$J=")?;
+
+                let mut tokens = tokens.iter();
+
+                if let Some(token) = tokens.next() {
+                    write!(f, "{}", token)?;
+                }
+
+                for token in tokens {
+                    write!(f, " {}", token)?;
+                }
+
+                Ok(())
+            }
+            GrblCommand::Settings => write!(f, "$$"),
+            GrblCommand::Parameters => write!(f, "$#"),
+            GrblCommand::ParserState => write!(f, "$G"),
+            GrblCommand::Setting { number, value } => write!(f, "${}={}", number, value),
+        }
+    }
+}
+
+impl fmt::Display for Realtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let byte = match self {
+            Realtime::StatusReport => '?',
+            Realtime::FeedHold => '!',
+            Realtime::CycleStart => '~',
+            Realtime::SoftReset => '\x18',
+        };
+
+        write!(f, "{}", byte)
+    }
+}
+
+fn setting<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, GrblCommand, E> {
+    map(
+        separated_pair(
+            map_res(digit1, |number: &str| number.parse::<u32>()),
+            tuple((space0, char('='), space0)),
+            float,
+        ),
+        |(number, value)| GrblCommand::Setting { number, value },
+    )(i)
+}
+
+fn jog<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, GrblCommand, E> {
+    map(
+        preceded(
+            tuple((char_no_case('J'), char('='), space0)),
+            many1(terminated(token, space0)),
+        ),
+        GrblCommand::Jog,
+    )(i)
+}
+
+/// Parse a GRBL system command
+pub fn grbl_command<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, GrblCommand, E> {
+    context(
+        "GRBL command",
+        preceded(
+            char('$'),
+            alt((
+                setting,
+                jog,
+                map(char('$'), |_| GrblCommand::Settings),
+                map(char('#'), |_| GrblCommand::Parameters),
+                map(char_no_case('G'), |_| GrblCommand::ParserState),
+                map(char_no_case('H'), |_| GrblCommand::Home),
+                map(char_no_case('X'), |_| GrblCommand::Unlock),
+            )),
+        ),
+    )(i)
+}
+
+/// Parse a realtime command byte
+pub fn realtime<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Realtime, E> {
+    context(
+        "realtime command",
+        alt((
+            map(char('?'), |_| Realtime::StatusReport),
+            map(char('!'), |_| Realtime::FeedHold),
+            map(char('~'), |_| Realtime::CycleStart),
+            map(tag("\x18"), |_| Realtime::SoftReset),
+        )),
+    )(i)
+}
+
+/// Parse a token in the GRBL dialect
+///
+/// System commands and realtime bytes are tried before the LinuxCNC tokens.
+pub fn grbl_token<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Token, E> {
+    map(
+        alt((
+            map(grbl_command, TokenType::Grbl),
+            map(realtime, TokenType::Realtime),
+            token_type,
+        )),
+        |token| Token { token },
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{Coord, Feedrate, Unknown};
+
+    #[test]
+    fn parse_system_commands() {
+        assert_parse!(
+            parser = grbl_command;
+            input = "$H", "$x", "$$", "$#", "$G", "$110=500.000", "$32 = 1";
+            expected = GrblCommand::Home,
+            GrblCommand::Unlock,
+            GrblCommand::Settings,
+            GrblCommand::Parameters,
+            GrblCommand::ParserState,
+            GrblCommand::Setting { number: 110, value: 500.0 },
+            GrblCommand::Setting { number: 32, value: 1.0 }
+        );
+    }
+
+    #[test]
+    fn parse_jog() {
+        assert_parse!(
+            parser = grbl_command;
+            input = "$J=G91 X10 F100";
+            expected = GrblCommand::Jog(vec![
+                Token {
+                    token: TokenType::Unknown(Unknown {
+                        code_letter: 'G',
+                        code_number: 91.0f32.into()
+                    })
+                },
+                Token {
+                    token: TokenType::Coord(Coord {
+                        x: Some(10.0f32.into()),
+                        ..Coord::default()
+                    })
+                },
+                Token {
+                    token: TokenType::Feedrate(Feedrate { feedrate: 100.0f32.into() })
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_realtime() {
+        assert_parse!(
+            parser = realtime;
+            input = "?", "!", "~", "\x18";
+            expected = Realtime::StatusReport,
+            Realtime::FeedHold,
+            Realtime::CycleStart,
+            Realtime::SoftReset
+        );
+    }
+}
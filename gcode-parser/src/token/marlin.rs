@@ -0,0 +1,370 @@
+//! Parse the codes and words of Marlin and other RepRap 3D printer firmware
+
+use crate::parsers::char_no_case;
+use crate::token::comment::{raw_comment, Comment, CommentKind};
+use crate::token::{token_type, Token, TokenType};
+use crate::value::{
+    decimal_value, preceded_decimal_value, preceded_unsigned_value, UnsignedValue, Value,
+};
+use crate::word::word;
+use nom::{
+    branch::{alt, permutation},
+    character::complete::{one_of, space0},
+    combinator::{map, not, opt},
+    error::{context, ParseError},
+    multi::many0,
+    sequence::{preceded, terminated, tuple},
+    IResult,
+};
+use std::fmt;
+
+/// A printer code, or a word only printers use
+#[derive(Debug, PartialEq, Clone)]
+pub enum MarlinCode {
+    /// Extruder position (`E`), or distance to extrude in relative extrusion mode
+    Extrude(Value),
+
+    /// Home the given axes, or all of them if none are given (`G28`)
+    Home(HomeAxes),
+
+    /// Probe the bed to level it (`G29`)
+    LevelBed,
+
+    /// Extruder positions are absolute (`M82`)
+    AbsoluteExtrusion,
+
+    /// Extruder positions are relative to the last one (`M83`)
+    RelativeExtrusion,
+
+    /// Set a heater's target temperature (`M104`, `M109`, `M140` or `M190`)
+    Temperature(Temperature),
+
+    /// Turn a fan on (`M106`)
+    FanOn(Fan),
+
+    /// Turn a fan off (`M107`)
+    FanOff(Fan),
+}
+
+/// The axes homed by `G28`
+///
+/// Marlin ignores any values given with the axis letters, so they are dropped.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct HomeAxes {
+    /// Home the X axis
+    pub x: bool,
+    /// Home the Y axis
+    pub y: bool,
+    /// Home the Z axis
+    pub z: bool,
+}
+
+/// Which heater a temperature is for
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Heater {
+    /// A hotend (`M104` and `M109`)
+    Hotend,
+
+    /// The heated bed (`M140` and `M190`)
+    Bed,
+}
+
+/// A heater's target temperature
+#[derive(Debug, PartialEq, Clone)]
+pub struct Temperature {
+    /// The heater to set
+    pub heater: Heater,
+
+    /// Whether to wait for the heater to reach the temperature (`M109` and `M190`)
+    pub wait: bool,
+
+    /// Target temperature in degrees Celsius (`S`), only waited for when heating up
+    pub temperature: Option<Value>,
+
+    /// Target temperature in degrees Celsius (`R`), waited for when heating up or cooling down
+    pub exact_temperature: Option<Value>,
+
+    /// Hotend number (`T`), defaulting to the active one
+    pub tool: Option<UnsignedValue>,
+}
+
+/// A fan's speed
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Fan {
+    /// Fan number (`P`), defaulting to the first one
+    pub index: Option<UnsignedValue>,
+
+    /// Speed from `0` to `255` (`S`), defaulting to full speed
+    pub speed: Option<Value>,
+}
+
+impl fmt::Display for MarlinCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MarlinCode::Extrude(e) => write!(f, "E{}", e),
+            MarlinCode::Home(axes) => {
+                write!(f, "G28")?;
+
+                for (letter, home) in [('X', axes.x), ('Y', axes.y), ('Z', axes.z)].iter() {
+                    if *home {
+                        write!(f, " {}", letter)?;
+                    }
+                }
+
+                Ok(())
+            }
+            MarlinCode::LevelBed => write!(f, "G29"),
+            MarlinCode::AbsoluteExtrusion => write!(f, "M82"),
+            MarlinCode::RelativeExtrusion => write!(f, "M83"),
+            MarlinCode::Temperature(temperature) => write!(f, "{}", temperature),
+            MarlinCode::FanOn(fan) => write!(f, "M106{}", fan),
+            MarlinCode::FanOff(fan) => write!(f, "M107{}", fan),
+        }
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match (self.heater, self.wait) {
+            (Heater::Hotend, false) => "M104",
+            (Heater::Hotend, true) => "M109",
+            (Heater::Bed, false) => "M140",
+            (Heater::Bed, true) => "M190",
+        };
+
+        write!(f, "{}", code)?;
+
+        if let Some(temperature) = &self.temperature {
+            write!(f, " S{}", temperature)?;
+        }
+
+        if let Some(temperature) = &self.exact_temperature {
+            write!(f, " R{}", temperature)?;
+        }
+
+        if let Some(tool) = &self.tool {
+            write!(f, " T{}", tool)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Fan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(index) = &self.index {
+            write!(f, " P{}", index)?;
+        }
+
+        if let Some(speed) = &self.speed {
+            write!(f, " S{}", speed)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn home<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, HomeAxes, E> {
+    map(
+        preceded(
+            word("G28"),
+            many0(preceded(
+                space0,
+                terminated(one_of("xyzXYZ"), opt(decimal_value)),
+            )),
+        ),
+        |letters| HomeAxes {
+            x: letters
+                .iter()
+                .any(|letter| letter.eq_ignore_ascii_case(&'x')),
+            y: letters
+                .iter()
+                .any(|letter| letter.eq_ignore_ascii_case(&'y')),
+            z: letters
+                .iter()
+                .any(|letter| letter.eq_ignore_ascii_case(&'z')),
+        },
+    )(i)
+}
+
+fn temperature<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Temperature, E> {
+    map(
+        tuple((
+            alt((
+                map(word("M104"), |_| (Heater::Hotend, false)),
+                map(word("M109"), |_| (Heater::Hotend, true)),
+                map(word("M140"), |_| (Heater::Bed, false)),
+                map(word("M190"), |_| (Heater::Bed, true)),
+            )),
+            permutation((
+                opt(preceded(space0, preceded_decimal_value(char_no_case('S')))),
+                opt(preceded(space0, preceded_decimal_value(char_no_case('R')))),
+                opt(preceded(space0, preceded_unsigned_value(char_no_case('T')))),
+            )),
+        )),
+        |((heater, wait), (temperature, exact_temperature, tool))| Temperature {
+            heater,
+            wait,
+            temperature,
+            exact_temperature,
+            tool,
+        },
+    )(i)
+}
+
+fn fan<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Fan, E> {
+    map(
+        permutation((
+            opt(preceded(space0, preceded_unsigned_value(char_no_case('P')))),
+            opt(preceded(space0, preceded_decimal_value(char_no_case('S')))),
+        )),
+        |(index, speed)| Fan { index, speed },
+    )(i)
+}
+
+/// Parse a printer code or extruder word
+pub fn marlin_code<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, MarlinCode, E> {
+    context(
+        "Marlin code",
+        alt((
+            map(
+                preceded_decimal_value(char_no_case('E')),
+                MarlinCode::Extrude,
+            ),
+            map(home, MarlinCode::Home),
+            map(word("G29"), |_| MarlinCode::LevelBed),
+            map(word("M82"), |_| MarlinCode::AbsoluteExtrusion),
+            map(word("M83"), |_| MarlinCode::RelativeExtrusion),
+            map(temperature, MarlinCode::Temperature),
+            map(preceded(word("M106"), fan), MarlinCode::FanOn),
+            map(preceded(word("M107"), fan), MarlinCode::FanOff),
+        )),
+    )(i)
+}
+
+/// Parse a comment, reading slicer metadata from comments starting with `;`
+///
+/// Marlin doesn't act on any comments, so parenthesised comments are always plain.
+pub fn marlin_comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Comment, E> {
+    map(raw_comment, |(text, parenthesised)| Comment {
+        kind: if parenthesised {
+            CommentKind::Plain
+        } else {
+            metadata(&text)
+        },
+        text,
+    })(i)
+}
+
+/// Slicer metadata like Cura's `;LAYER:3` or PrusaSlicer's `; layer_height = 0.2`
+fn metadata(text: &str) -> CommentKind {
+    let split = match text.find(':') {
+        Some(index) if !text[..index].contains(' ') => Some((index, 1)),
+        _ => text.find(" = ").map(|index| (index, 3)),
+    };
+
+    match split {
+        Some((index, length)) if index > 0 => CommentKind::Metadata {
+            key: text[..index].trim().to_string(),
+            value: text[index + length..].trim().to_string(),
+        },
+        _ => CommentKind::Plain,
+    }
+}
+
+/// Parse a token in the Marlin dialect
+///
+/// Printer codes and words are tried before the LinuxCNC tokens, so for example `G28` means
+/// homing instead of a move to a predefined position.
+pub fn marlin_token<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Token, E> {
+    map(
+        alt((
+            map(
+                terminated(marlin_code, not(one_of("0123456789."))),
+                TokenType::Marlin,
+            ),
+            map(marlin_comment, TokenType::Comment),
+            token_type,
+        )),
+        |token| Token { token },
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_temperatures() {
+        assert_parse!(
+            parser = marlin_code;
+            input = "M104 S200", "m109 r190 t1", "M190 S60";
+            expected = MarlinCode::Temperature(Temperature {
+                heater: Heater::Hotend,
+                wait: false,
+                temperature: Some(200.0f32.into()),
+                exact_temperature: None,
+                tool: None,
+            }),
+            MarlinCode::Temperature(Temperature {
+                heater: Heater::Hotend,
+                wait: true,
+                temperature: None,
+                exact_temperature: Some(190.0f32.into()),
+                tool: Some(1.into()),
+            }),
+            MarlinCode::Temperature(Temperature {
+                heater: Heater::Bed,
+                wait: true,
+                temperature: Some(60.0f32.into()),
+                exact_temperature: None,
+                tool: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_home() {
+        assert_parse!(
+            parser = marlin_code;
+            input = "G28", "G28 X Y", "G28 X0 Z0";
+            expected = MarlinCode::Home(HomeAxes::default()),
+            MarlinCode::Home(HomeAxes { x: true, y: true, z: false }),
+            MarlinCode::Home(HomeAxes { x: true, y: false, z: true })
+        );
+    }
+
+    #[test]
+    fn parse_fans_and_extruder() {
+        assert_parse!(
+            parser = marlin_code;
+            input = "M106 S127", "M107", "M83", "E-1.5";
+            expected = MarlinCode::FanOn(Fan { index: None, speed: Some(127.0f32.into()) }),
+            MarlinCode::FanOff(Fan::default()),
+            MarlinCode::RelativeExtrusion,
+            MarlinCode::Extrude((-1.5f32).into())
+        );
+    }
+
+    #[test]
+    fn slicer_metadata() {
+        let kind = |input: &str| marlin_comment::<()>(input).unwrap().1.kind;
+
+        let metadata = |key: &str, value: &str| CommentKind::Metadata {
+            key: key.into(),
+            value: value.into(),
+        };
+
+        assert_eq!(kind(";LAYER:3\n"), metadata("LAYER", "3"));
+        assert_eq!(kind(";TYPE:WALL-OUTER\n"), metadata("TYPE", "WALL-OUTER"));
+        assert_eq!(
+            kind("; layer_height = 0.2\n"),
+            metadata("layer_height", "0.2")
+        );
+        assert_eq!(
+            kind(";Generated with Cura_SteamEngine 4.8.0\n"),
+            CommentKind::Plain
+        );
+        assert_eq!(kind("(MSG, not active)"), CommentKind::Plain);
+    }
+}
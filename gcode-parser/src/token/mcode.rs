@@ -5,6 +5,7 @@ use nom::{
     error::{context, ParseError},
     IResult,
 };
+use std::fmt;
 
 /// An M-code
 #[derive(Debug, PartialEq, Clone)]
@@ -31,6 +32,20 @@ pub enum MCode {
     OptionalPause,
 }
 
+impl fmt::Display for MCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MCode::OptionalPause => write!(f, "M1"),
+            MCode::EndProgram => write!(f, "M2"),
+            MCode::SpindleForward => write!(f, "M3"),
+            MCode::SpindleReverse => write!(f, "M4"),
+            MCode::SpindleStop => write!(f, "M5"),
+            MCode::ToolChange => write!(f, "M6"),
+            MCode::EndProgramSwapPallets => write!(f, "M30"),
+        }
+    }
+}
+
 pub fn mcode<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, MCode, E> {
     context(
         "M code",
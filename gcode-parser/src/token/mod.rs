@@ -6,26 +6,35 @@ pub(crate) mod block;
 pub(crate) mod call;
 pub(crate) mod comment;
 pub(crate) mod coord;
+pub(crate) mod fanuc;
 pub(crate) mod gcode;
+pub(crate) mod grbl;
+pub(crate) mod marlin;
 pub(crate) mod mcode;
 pub(crate) mod othercode;
 pub(crate) mod polar;
 pub(crate) mod return_stmt;
+pub(crate) mod spline;
 
 use self::arc::{center_format_arc, radius_format_arc};
 pub use self::arc::{CenterFormatArc, RadiusFormatArc};
 use self::assignment::assignment;
 pub use self::assignment::Assignment;
 use self::block::block;
-pub use self::block::{Block, Branch, BranchType, Conditional, Repeat, Subroutine, While};
+pub use self::block::{
+    Block, BlockIdent, Branch, BranchType, Conditional, DoWhile, Repeat, Subroutine, While,
+};
 use self::call::call;
-pub use self::call::Call;
+pub use self::call::{Call, CallKind};
 use self::comment::comment;
-pub use self::comment::Comment;
+pub use self::comment::{Comment, CommentKind, MessagePart};
 use self::coord::coord;
 pub use self::coord::Coord;
+pub use self::fanuc::Goto;
 use self::gcode::gcode;
-pub use self::gcode::{CutterCompensation, GCode, WorkOffset};
+pub use self::gcode::{CutterCompensation, Dwell, GCode, PathControl, PlaneSelect, WorkOffset};
+pub use self::grbl::{GrblCommand, Realtime};
+pub use self::marlin::{Fan, Heater, HomeAxes, MarlinCode, Temperature};
 use self::mcode::mcode;
 pub use self::mcode::MCode;
 use self::othercode::{feedrate, spindle_speed, tool_number};
@@ -34,9 +43,12 @@ use self::polar::polar;
 pub use self::polar::PolarCoord;
 use self::return_stmt::return_stmt;
 pub use self::return_stmt::Return;
+use self::spline::spline;
+pub use self::spline::Spline;
 use crate::token::othercode::raw_line_number;
 pub use crate::token::othercode::LineNumber;
-use crate::value::{decimal_value, Value};
+use crate::value::decimal_value;
+pub use crate::value::{UnsignedValue, Value};
 use nom::{
     branch::alt,
     character::complete::{char, one_of},
@@ -45,6 +57,7 @@ use nom::{
     sequence::tuple,
     IResult,
 };
+use std::fmt;
 
 /// Any possible token type recgonised by this parser
 #[derive(Debug, PartialEq, Clone)]
@@ -68,6 +81,10 @@ pub enum TokenType {
     /// Radius-format arc
     RadiusFormatArc(RadiusFormatArc),
 
+    /// The end point and control points of a cubic spline (`G5`), or a NURBS control point
+    /// (`G5.2`)
+    Spline(Spline),
+
     /// Feedrate
     Feedrate(Feedrate),
 
@@ -98,11 +115,23 @@ pub enum TokenType {
     /// A return statement
     Return(Return),
 
+    /// A jump to a sequence number, in the Fanuc dialect
+    Goto(Goto),
+
     /// Block delete (`/` character at beginning of line)
     BlockDelete,
 
     /// Program delimiter (`%` character literal)
     ProgramDelimiter,
+
+    /// A 3D printer code or extruder word, in the Marlin dialect
+    Marlin(MarlinCode),
+
+    /// A system command, in the GRBL dialect
+    Grbl(GrblCommand),
+
+    /// A realtime command byte, in the GRBL dialect
+    Realtime(Realtime),
 }
 
 /// An unknown token
@@ -122,6 +151,53 @@ pub struct Token {
     pub token: TokenType,
 }
 
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenType::GCode(code) => write!(f, "{}", code),
+            TokenType::MCode(code) => write!(f, "{}", code),
+            TokenType::Coord(coord) => write!(f, "{}", coord),
+            TokenType::PolarCoord(coord) => write!(f, "{}", coord),
+            TokenType::CenterFormatArc(arc) => write!(f, "{}", arc),
+            TokenType::RadiusFormatArc(arc) => write!(f, "{}", arc),
+            TokenType::Spline(spline) => write!(f, "{}", spline),
+            TokenType::Feedrate(feed) => write!(f, "{}", feed),
+            TokenType::SpindleSpeed(speed) => write!(f, "{}", speed),
+            TokenType::ToolNumber(tool) => write!(f, "{}", tool),
+            TokenType::LineNumber(number) => write!(f, "{}", number),
+            TokenType::Comment(comment) => write!(f, "{}", comment),
+            TokenType::Unknown(unknown) => write!(f, "{}", unknown),
+            TokenType::Assignment(assignment) => write!(f, "{}", assignment),
+            TokenType::Block(block) => write!(f, "{}", block),
+            TokenType::Call(call) => write!(f, "{}", call),
+            TokenType::Return(ret) => write!(f, "{}", ret),
+            TokenType::Goto(goto) => write!(f, "{}", goto),
+            TokenType::BlockDelete => write!(f, "/"),
+            TokenType::ProgramDelimiter => write!(f, "%"),
+            TokenType::Marlin(code) => write!(f, "{}", code),
+            TokenType::Grbl(command) => write!(f, "{}", command),
+            TokenType::Realtime(byte) => write!(f, "{}", byte),
+        }
+    }
+}
+
+impl fmt::Display for Unknown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.code_letter.to_ascii_uppercase(),
+            self.code_number
+        )
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token)
+    }
+}
+
 /// Parse an unknown token into its letter and numeric code parts
 pub fn unknown<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Unknown, E> {
     context(
@@ -142,6 +218,7 @@ pub fn unknown<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Unkno
 /// Parse a token into a `TokenType` enum
 pub fn token_type<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, TokenType, E> {
     alt((
+        map(spline, TokenType::Spline),
         map(center_format_arc, TokenType::CenterFormatArc),
         map(radius_format_arc, TokenType::RadiusFormatArc),
         map(coord, TokenType::Coord),
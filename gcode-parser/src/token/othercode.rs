@@ -9,6 +9,7 @@ use nom::{
     sequence::preceded,
     IResult,
 };
+use std::fmt;
 
 /// Define a feed rate in machine units per minute
 #[derive(Debug, PartialEq, Clone)]
@@ -40,6 +41,30 @@ pub struct LineNumber {
     pub line_number: u32,
 }
 
+impl fmt::Display for Feedrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "F{}", self.feedrate)
+    }
+}
+
+impl fmt::Display for SpindleSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S{}", self.rpm)
+    }
+}
+
+impl fmt::Display for ToolNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "T{}", self.tool_number)
+    }
+}
+
+impl fmt::Display for LineNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "N{}", self.line_number)
+    }
+}
+
 pub fn feedrate<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Feedrate, E> {
     context(
         "feed rate",
@@ -1,7 +1,7 @@
 //! Parse polar coordinates
 
 use crate::parsers::char_no_case;
-use crate::value::{preceded_decimal_value, Value};
+use crate::value::{preceded_decimal_value, write_words, Value};
 use nom::{
     branch::permutation,
     character::complete::space0,
@@ -10,6 +10,7 @@ use nom::{
     sequence::terminated,
     IResult,
 };
+use std::fmt;
 
 /// A polar coordinate
 #[derive(Debug, PartialEq, Clone)]
@@ -29,6 +30,12 @@ impl Default for PolarCoord {
     }
 }
 
+impl fmt::Display for PolarCoord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_words(f, &[('@', &self.distance), ('^', &self.angle)])
+    }
+}
+
 pub fn polar<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, PolarCoord, E> {
     context(
         "polar coordinate",
@@ -8,6 +8,7 @@ use nom::{
     sequence::{preceded, separated_pair},
     IResult,
 };
+use std::fmt;
 
 /// Which type of block this is
 #[derive(Debug, PartialEq, Clone)]
@@ -16,6 +17,30 @@ pub struct Return {
     value: Option<Expression<f32>>,
 }
 
+impl Return {
+    /// The subroutine to return from
+    pub fn ident(&self) -> &BlockIdent {
+        &self.ident
+    }
+
+    /// Value to return
+    pub fn value(&self) -> Option<&Expression<f32>> {
+        self.value.as_ref()
+    }
+}
+
+impl fmt::Display for Return {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "O{} return", self.ident)?;
+
+        if let Some(value) = &self.value {
+            write!(f, " {}", value)?;
+        }
+
+        Ok(())
+    }
+}
+
 pub fn return_stmt<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Return, E> {
     context(
         "return stmt",
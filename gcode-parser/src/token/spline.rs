@@ -0,0 +1,187 @@
+//! Parse the words of spline moves
+
+use crate::parsers::char_no_case;
+use crate::value::{
+    preceded_decimal_value, preceded_unsigned_value, write_words, UnsignedValue, Value,
+};
+use nom::{
+    branch::permutation,
+    character::complete::space0,
+    combinator::{map_res, opt},
+    error::{context, ParseError},
+    sequence::terminated,
+    IResult,
+};
+use std::fmt;
+
+/// The words of a cubic spline (`G5`) or a NURBS control point (`G5.2`)
+///
+/// Cubic splines give the end point, the offset of the first control point from the start (`I`
+/// and `J`) and the offset of the second control point from the end (`P` and `Q`). NURBS control
+/// points give a position, a weight (`P`) and, on the first line of the block, the order of the
+/// curve (`L`).
+///
+/// Quadratic splines (`G5.1`) are written like center format arcs, so their words are parsed as
+/// a [`CenterFormatArc`](struct.CenterFormatArc.html).
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Spline {
+    /// End position or control point, X component
+    pub x: Option<Value>,
+    /// End position or control point, Y component
+    pub y: Option<Value>,
+    /// First control point X offset from the start
+    pub i: Option<Value>,
+    /// First control point Y offset from the start
+    pub j: Option<Value>,
+    /// Second control point X offset from the end, or the weight of a NURBS control point
+    pub p: Option<Value>,
+    /// Second control point Y offset from the end
+    pub q: Option<Value>,
+    /// Order of a NURBS curve
+    pub order: Option<UnsignedValue>,
+}
+
+impl fmt::Display for Spline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_words(
+            f,
+            &[
+                ('X', &self.x),
+                ('Y', &self.y),
+                ('I', &self.i),
+                ('J', &self.j),
+                ('P', &self.p),
+                ('Q', &self.q),
+            ],
+        )?;
+
+        if let Some(order) = &self.order {
+            write!(f, " L{}", order)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the words of a spline
+///
+/// The words must include `X` or `Y`, and either `Q`, or `P` or `L` without `I` or `J`, so they
+/// can't be mistaken for a center format arc with a number of turns.
+pub fn spline<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Spline, E> {
+    context(
+        "spline",
+        map_res(
+            permutation((
+                opt(terminated(
+                    preceded_decimal_value(char_no_case('X')),
+                    space0,
+                )),
+                opt(terminated(
+                    preceded_decimal_value(char_no_case('Y')),
+                    space0,
+                )),
+                opt(terminated(
+                    preceded_decimal_value(char_no_case('I')),
+                    space0,
+                )),
+                opt(terminated(
+                    preceded_decimal_value(char_no_case('J')),
+                    space0,
+                )),
+                opt(terminated(
+                    preceded_decimal_value(char_no_case('P')),
+                    space0,
+                )),
+                opt(terminated(
+                    preceded_decimal_value(char_no_case('Q')),
+                    space0,
+                )),
+                opt(preceded_unsigned_value(char_no_case('L'))),
+            )),
+            |(x, y, i, j, p, q, order)| {
+                let spline = Spline {
+                    x,
+                    y,
+                    i,
+                    j,
+                    p,
+                    q,
+                    order,
+                };
+
+                let cubic = spline.q.is_some();
+                let nurbs = (spline.p.is_some() || spline.order.is_some())
+                    && spline.i.is_none()
+                    && spline.j.is_none();
+
+                if spline.x.is_none() && spline.y.is_none() {
+                    Err("Invalid spline: at least one of X or Y must be given")
+                } else if !cubic && !nurbs {
+                    Err("Invalid spline: not a cubic spline or NURBS control point")
+                } else {
+                    Ok(spline)
+                }
+            },
+        ),
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::error::VerboseError;
+
+    #[test]
+    fn parse_cubic() {
+        assert_parse!(
+            parser = spline;
+            input = "X1 Y2 I-1 J0.5 P0 Q-1.5", "x1y2i-1j.5p0q-1.5";
+            expected = Spline {
+                x: Some(1.0f32.into()),
+                y: Some(2.0f32.into()),
+                i: Some((-1.0f32).into()),
+                j: Some(0.5f32.into()),
+                p: Some(0.0f32.into()),
+                q: Some((-1.5f32).into()),
+                ..Spline::default()
+            },
+            Spline {
+                x: Some(1.0f32.into()),
+                y: Some(2.0f32.into()),
+                i: Some((-1.0f32).into()),
+                j: Some(0.5f32.into()),
+                p: Some(0.0f32.into()),
+                q: Some((-1.5f32).into()),
+                ..Spline::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_nurbs() {
+        assert_parse!(
+            parser = spline;
+            input = "X0 Y1 P1 L3", "Y2 P0.5";
+            expected = Spline {
+                x: Some(0.0f32.into()),
+                y: Some(1.0f32.into()),
+                p: Some(1.0f32.into()),
+                order: Some(3.into()),
+                ..Spline::default()
+            },
+            Spline {
+                y: Some(2.0f32.into()),
+                p: Some(0.5f32.into()),
+                ..Spline::default()
+            }
+        );
+    }
+
+    #[test]
+    fn not_a_spline() {
+        // Center format arcs with a number of turns, words with no end point, and plain moves
+        for input in ["X1 Y1 I2 J3 P5", "X1 Y1 I2 J3", "P1 L2", "X1 Y1"].iter() {
+            assert!(spline::<VerboseError<&str>>(input).is_err(), "{}", input);
+        }
+    }
+}
@@ -8,6 +8,7 @@ use nom::{
     sequence::separated_pair,
     IResult,
 };
+use std::fmt;
 use std::str::FromStr;
 
 // TODO: Feature for double precision/size (*32 -> *64)
@@ -36,6 +37,16 @@ impl Value {
     }
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Literal(value) => write!(f, "{}", value),
+            Value::Expression(expression) => write!(f, "{}", expression),
+            Value::Parameter(parameter) => write!(f, "{}", parameter),
+        }
+    }
+}
+
 impl FromStr for Value {
     type Err = String;
 
@@ -79,6 +90,33 @@ impl From<u32> for UnsignedValue {
     }
 }
 
+impl fmt::Display for UnsignedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnsignedValue::Literal(value) => write!(f, "{}", value),
+            UnsignedValue::Expression(expression) => write!(f, "{}", expression),
+            UnsignedValue::Parameter(parameter) => write!(f, "{}", parameter),
+        }
+    }
+}
+
+/// Write each letter and value pair that has a value as a word, separated by spaces
+pub(crate) fn write_words(f: &mut fmt::Formatter, words: &[(char, &Option<Value>)]) -> fmt::Result {
+    let mut words = words
+        .iter()
+        .filter_map(|(letter, value)| value.as_ref().map(|value| (letter, value)));
+
+    if let Some((letter, value)) = words.next() {
+        write!(f, "{}{}", letter, value)?;
+    }
+
+    for (letter, value) in words {
+        write!(f, " {}{}", letter, value)?;
+    }
+
+    Ok(())
+}
+
 pub fn decimal_value<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value, E> {
     context(
         "decimal value",
@@ -1 +1,21 @@
+/// The words of each line, so programs that group the same words into different tokens compare
+/// equal
+fn words(program: &Program) -> Vec<Vec<String>> {
+    program
+        .lines()
+        .iter()
+        .map(|line| {
+            line.iter()
+                .flat_map(|token| {
+                    token
+                        .to_string()
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        })
+        .collect()
+}
+
 include!(concat!(env!("OUT_DIR"), "/test_suites.rs"));
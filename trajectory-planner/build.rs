@@ -52,6 +52,16 @@ fn main() {
             write_test(&mut test_file, &suite_name, suite_file);
         }
     }
+
+    // Step generation is only checked against the TinyG suite
+    let destination = Path::new(&out_dir).join("step_test_suites.rs");
+    let mut test_file = File::create(&destination).unwrap();
+
+    write!(test_file, include_str!("./tests/step_suite_header"),).unwrap();
+
+    for suite_file in read_files_recursive("../test_files/tinyg") {
+        write_step_test(&mut test_file, suite_file);
+    }
 }
 
 fn write_test(
@@ -70,6 +80,16 @@ fn write_test(
     .unwrap();
 }
 
+fn write_step_test(test_file: &mut File, (source_data_path, data_file_name): (PathBuf, String)) {
+    write!(
+        test_file,
+        include_str!("./tests/step_suite_template"),
+        name = format!("tinyg_{}", data_file_name),
+        source_data_path = source_data_path.canonicalize().unwrap().display()
+    )
+    .unwrap();
+}
+
 fn write_header(test_file: &mut File) {
     write!(test_file, include_str!("./tests/file_suite_header"),).unwrap();
 }
@@ -0,0 +1,105 @@
+//! Pluggable trajectory generation backends
+
+use crate::path::{Path, PathOptions};
+use crate::trajectory::{PlanError, Trajectory, TrajectoryOptions};
+use crate::Vector9;
+
+/// A trajectory that can be queried at any point in time
+pub trait TrajectoryProfile {
+    /// Total time taken to traverse the trajectory in seconds
+    fn duration(&self) -> f64;
+
+    /// Position of all axes at time `t`
+    fn position(&self, t: f64) -> Vector9;
+
+    /// Velocity of all axes at time `t`
+    fn velocity(&self, t: f64) -> Vector9;
+
+    /// Acceleration of all axes at time `t`
+    fn acceleration(&self, t: f64) -> Vector9;
+}
+
+/// A path parameterisation algorithm that turns a list of waypoints into a timed trajectory
+pub trait Backend {
+    /// The trajectory type produced by this backend
+    type Trajectory: TrajectoryProfile;
+
+    /// Plan a trajectory through the given waypoints, starting and ending at rest
+    fn plan(
+        &self,
+        waypoints: &[Vector9],
+        path_options: PathOptions,
+        trajectory_options: TrajectoryOptions,
+    ) -> Result<Self::Trajectory, PlanError>;
+}
+
+/// Pure Rust time-optimal path parameterisation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeOptimal;
+
+impl Backend for TimeOptimal {
+    type Trajectory = Trajectory;
+
+    fn plan(
+        &self,
+        waypoints: &[Vector9],
+        path_options: PathOptions,
+        trajectory_options: TrajectoryOptions,
+    ) -> Result<Self::Trajectory, PlanError> {
+        if let Some(idx) = waypoints
+            .iter()
+            .position(|point| !point.iter().all(|x| x.is_finite()))
+        {
+            return Err(PlanError::InvalidWaypoint(idx));
+        }
+
+        let path = Path::from_waypoints(waypoints, path_options);
+
+        Trajectory::new(&path, trajectory_options)
+    }
+}
+
+impl TrajectoryProfile for Trajectory {
+    fn duration(&self) -> f64 {
+        Trajectory::duration(self)
+    }
+
+    fn position(&self, t: f64) -> Vector9 {
+        Trajectory::position(self, t)
+    }
+
+    fn velocity(&self, t: f64) -> Vector9 {
+        Trajectory::velocity(self, t)
+    }
+
+    fn acceleration(&self, t: f64) -> Vector9 {
+        Trajectory::acceleration(self, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_nan_waypoints() {
+        let mut bad = Vector9::zeros();
+        bad[2] = f64::NAN;
+
+        let result = TimeOptimal.plan(
+            &[Vector9::zeros(), bad],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+            TrajectoryOptions {
+                velocity_limit: Vector9::repeat(1.0),
+                acceleration_limit: Vector9::repeat(1.0),
+                jerk_limit: None,
+                epsilon: 0.000001,
+                timestep: 0.001,
+            },
+        );
+
+        assert_eq!(result.err(), Some(PlanError::InvalidWaypoint(1)));
+    }
+}
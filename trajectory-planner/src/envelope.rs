@@ -0,0 +1,190 @@
+//! Travel envelope checking
+//!
+//! Every move of an interpreted program is checked against the machine's soft limits before any
+//! trajectory is planned, so a program that would drive an axis past its travel is rejected up
+//! front rather than partway through a job.
+
+use crate::machine::SoftLimits;
+use crate::synced::SyncedMove;
+use gcode_interpreter::{Canonical, CanonicalKind, InterpretError};
+use std::fmt;
+
+/// A move that leaves the machine's travel
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitViolation {
+    /// The 1-indexed source line of the move
+    pub line: usize,
+
+    /// Index of the axis, `0` to `8` for `XYZUVWABC`
+    pub axis: usize,
+
+    /// How far past its limit the axis would go
+    pub overshoot: f64,
+}
+
+/// An error encountered whilst checking a program against soft limits
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvelopeError {
+    /// The program could not be interpreted
+    Interpret(InterpretError),
+
+    /// A move leaves the machine's travel
+    SoftLimit(LimitViolation),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const AXES: &[u8] = b"XYZUVWABC";
+
+        match self {
+            EnvelopeError::Interpret(e) => write!(f, "interpreter error: {}", e),
+            EnvelopeError::SoftLimit(v) => write!(
+                f,
+                "line {}: {} axis exceeds its soft limit by {}",
+                v.line, AXES[v.axis] as char, v.overshoot
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl From<InterpretError> for EnvelopeError {
+    fn from(e: InterpretError) -> Self {
+        EnvelopeError::Interpret(e)
+    }
+}
+
+/// Check that every move stays within `limits`, stopping at the first one that doesn't
+///
+/// `commands` is usually an [`Interpreter`](../gcode_interpreter/struct.Interpreter.html), so
+/// targets include work offsets and tool length offsets. Straight moves are checked at both ends
+/// and arcs over their whole extent, as an arc can bulge past a limit its end points are within.
+/// Rigid taps include how far they overshoot either end while the spindle reverses at
+/// `spindle_acceleration`, in revolutions per second squared.
+pub fn check_soft_limits<I>(
+    commands: I,
+    limits: &SoftLimits,
+    spindle_acceleration: f64,
+) -> Result<(), EnvelopeError>
+where
+    I: IntoIterator<Item = Result<Canonical, InterpretError>>,
+{
+    for canonical in commands {
+        let Canonical { line, kind } = canonical?;
+
+        let (min, max) = match kind {
+            CanonicalKind::Rapid { from, to } | CanonicalKind::Linear { from, to, .. } => {
+                (from.zip_map(&to, f64::min), from.zip_map(&to, f64::max))
+            }
+            CanonicalKind::SpindleSynchronized { from, to, .. } => {
+                match SyncedMove::from_canonical(&kind, spindle_acceleration) {
+                    Some(Ok(synced)) => synced.bounds(),
+                    // Moves that can't be followed are rejected when they're planned
+                    _ => (from.zip_map(&to, f64::min), from.zip_map(&to, f64::max)),
+                }
+            }
+            CanonicalKind::Arc { arc, .. } => arc.bounds(),
+            CanonicalKind::Spline { spline, .. } => spline.bounds(),
+            _ => continue,
+        };
+
+        if let Some((axis, overshoot)) = limits.overshoot(&min, &max) {
+            return Err(EnvelopeError::SoftLimit(LimitViolation {
+                line,
+                axis,
+                overshoot,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vector9;
+    use gcode_interpreter::{Interpreter, Tool, ToolTable};
+    use gcode_parser::Program;
+
+    fn limits() -> SoftLimits {
+        SoftLimits {
+            min: Vector9::repeat(-10.0),
+            max: Vector9::repeat(10.0),
+        }
+    }
+
+    fn check(program: &str) -> Result<(), EnvelopeError> {
+        let program = Program::from_str(program).unwrap();
+
+        check_soft_limits(Interpreter::new(&program), &limits(), f64::INFINITY)
+    }
+
+    fn violation(line: usize, axis: usize, overshoot: f64) -> Result<(), EnvelopeError> {
+        Err(EnvelopeError::SoftLimit(LimitViolation {
+            line,
+            axis,
+            overshoot,
+        }))
+    }
+
+    #[test]
+    fn within_limits() {
+        assert_eq!(check("G0 X10 Y-10\nG1 X0 Y0 F100"), Ok(()));
+    }
+
+    #[test]
+    fn first_violating_line() {
+        assert_eq!(check("G0 X5\nG1 Y12.5 F100\nG1 X-20"), violation(2, 1, 2.5));
+    }
+
+    #[test]
+    fn arc_extents() {
+        // Both ends are inside the limits, but the semicircle passes through Y = 12
+        assert_eq!(check("G0 X-6\nG2 X6 I6 J0 F100"), Ok(()));
+        assert_eq!(
+            check("G0 X-6 Y6\nG2 X6 Y6 I6 J0 F100"),
+            violation(2, 1, 2.0)
+        );
+    }
+
+    #[test]
+    fn rigid_tap_overshoot() {
+        let program = Program::from_str("S600 M3\nG0 Z5\nG33.1 Z-10 K1").unwrap();
+
+        let check = |spindle_acceleration| {
+            check_soft_limits(Interpreter::new(&program), &limits(), spindle_acceleration)
+        };
+
+        // Reversing from 10 revolutions per second at 50 carries on for another revolution past
+        // each end
+        assert_eq!(check(f64::INFINITY), Ok(()));
+        assert_eq!(check(50.0), violation(3, 2, 1.0));
+    }
+
+    #[test]
+    fn tool_length_offset() {
+        let program = Program::from_str("T1 M6\nG43\nG0 Z8").unwrap();
+
+        let mut tools = ToolTable::new();
+
+        tools.insert(
+            1,
+            Tool {
+                length: 5.0,
+                diameter: 3.0,
+                orientation: 0,
+            },
+        );
+
+        assert_eq!(
+            check_soft_limits(
+                Interpreter::new(&program).with_tools(tools),
+                &limits(),
+                f64::INFINITY
+            ),
+            violation(3, 2, 3.0)
+        );
+    }
+}
@@ -0,0 +1,686 @@
+//! Cycle time estimation for whole programs
+//!
+//! A program is run through the interpreter and its moves are grouped into runs that the machine
+//! can blend through without stopping. Runs are broken by anything that needs the machine to be at
+//! rest, like dwells, tool changes and spindle changes.
+
+use crate::machine::MachineConfig;
+use crate::path::{Corner, Path};
+use crate::synced::{SyncError, SyncedMove};
+use crate::trajectory::{PlanError, Trajectory};
+use crate::Vector9;
+use gcode_interpreter::{
+    Canonical, CanonicalKind, InterpretError, Interpreter, Parameters, State, ToolTable,
+};
+use gcode_parser::Program;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Moves shorter than this are ignored by the approximate estimator
+const MIN_MOVE_LENGTH: f64 = 1.0e-9;
+
+/// Junctions with the cosine of the angle between the moves' directions above this are treated
+/// as a complete reversal
+const REVERSAL_COSINE: f64 = 0.999999;
+
+/// How accurately to estimate a program's run time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EstimateMode {
+    /// Plan a time optimal trajectory through every run of moves
+    Exact,
+
+    /// Give every move a trapezoidal speed profile, with cornering speeds limited by junction
+    /// deviation like GRBL. This is much faster than [`Exact`](#variant.Exact) for large programs,
+    /// but doesn't merge almost collinear moves for `G64 Q`.
+    Approximate,
+}
+
+/// Estimation options
+#[derive(Debug, Clone, PartialEq)]
+pub struct EstimateOptions {
+    /// Machine limits
+    pub machine: MachineConfig,
+
+    /// Estimation mode
+    pub mode: EstimateMode,
+
+    /// The maximum distance the machine may deviate from a corner between two moves, unless the
+    /// program sets a tolerance with `G64 P`
+    pub max_deviation: f64,
+
+    /// The maximum distance the line segments used to approximate an arc or spline may deviate
+    /// from it
+    pub arc_tolerance: f64,
+
+    /// Time taken by each tool change in seconds
+    pub tool_change_time: f64,
+
+    /// Which of the `ABC` axes wrap around every 360 degrees, taking the shortest way round
+    pub wrapped_axes: [bool; 3],
+
+    /// How fast the spindle reverses when rigid tapping, in revolutions per second squared
+    pub spindle_acceleration: f64,
+
+    /// Tools available for tool length offsets and cutter compensation
+    pub tools: ToolTable,
+
+    /// Parameter values at the start of the program, for example work offsets in `#5221` onwards
+    pub parameters: Parameters,
+
+    /// Where the machine is when the program starts, in machine coordinates
+    pub position: Vector9,
+}
+
+impl EstimateOptions {
+    /// Exact estimation options with typical deviation tolerances, instant tool changes and
+    /// instant spindle reversals, starting at the machine origin with no tools or parameters set
+    pub fn new(machine: MachineConfig) -> Self {
+        Self {
+            machine,
+            mode: EstimateMode::Exact,
+            max_deviation: 0.01,
+            arc_tolerance: 0.001,
+            tool_change_time: 0.0,
+            wrapped_axes: [false; 3],
+            spindle_acceleration: f64::INFINITY,
+            tools: ToolTable::new(),
+            parameters: Parameters::new(),
+            position: Vector9::zeros(),
+        }
+    }
+}
+
+/// The estimated run time of a program, in seconds
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Estimate {
+    /// Total run time
+    pub total: f64,
+
+    /// Time spent in feed moves (`G1`, `G2`, `G3`) and spindle synchronised moves (`G33`, `G33.1`)
+    pub cutting: f64,
+
+    /// Time spent in rapid moves (`G0`)
+    pub rapid: f64,
+
+    /// Time spent dwelling (`G4`)
+    pub dwell: f64,
+
+    /// Time spent changing tools
+    pub tool_change: f64,
+
+    /// Time spent on each source line, keyed by 1-indexed line number
+    pub lines: BTreeMap<usize, f64>,
+}
+
+impl Estimate {
+    fn add(&mut self, line: usize, seconds: f64) {
+        self.total += seconds;
+
+        *self.lines.entry(line).or_insert(0.0) += seconds;
+    }
+}
+
+/// An error encountered whilst estimating a program's run time
+#[derive(Debug, Clone, PartialEq)]
+pub enum EstimateError {
+    /// The program could not be interpreted
+    Interpret(InterpretError),
+
+    /// A trajectory could not be planned through the program's moves
+    Plan(PlanError),
+
+    /// A spindle synchronised move could not be followed
+    Sync(SyncError),
+}
+
+impl fmt::Display for EstimateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EstimateError::Interpret(e) => write!(f, "interpreter error: {}", e),
+            EstimateError::Plan(e) => write!(f, "planning error: {}", e),
+            EstimateError::Sync(e) => write!(f, "spindle synchronisation error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EstimateError {}
+
+impl From<InterpretError> for EstimateError {
+    fn from(e: InterpretError) -> Self {
+        EstimateError::Interpret(e)
+    }
+}
+
+impl From<PlanError> for EstimateError {
+    fn from(e: PlanError) -> Self {
+        EstimateError::Plan(e)
+    }
+}
+
+impl From<SyncError> for EstimateError {
+    fn from(e: SyncError) -> Self {
+        EstimateError::Sync(e)
+    }
+}
+
+/// A single straight move
+#[derive(Debug, Clone)]
+struct Move {
+    line: usize,
+    to: Vector9,
+    /// Programmed speed limit in units per second, infinite for rapids
+    speed: f64,
+    rapid: bool,
+    /// How the path passes through the end of the move
+    corner: Corner,
+}
+
+/// Estimate how long a program will take to run on a machine
+pub fn estimate(program: &Program, options: &EstimateOptions) -> Result<Estimate, EstimateError> {
+    let mut estimate = Estimate::default();
+    let mut position = options.position;
+    let mut moves: Vec<Move> = Vec::new();
+    let mut corner = Corner::from_path_mode(State::default().path, options.max_deviation);
+
+    let interpreter = Interpreter::new(program)
+        .with_tools(options.tools.clone())
+        .with_parameters(options.parameters.clone())
+        .with_position(options.position)
+        .with_wrapped_axes(options.wrapped_axes);
+
+    for canonical in interpreter {
+        let Canonical { line, kind } = canonical?;
+        let speed = kind.path_speed().unwrap_or(f64::INFINITY);
+
+        match kind {
+            CanonicalKind::Rapid { to, .. } => moves.push(Move {
+                line,
+                to,
+                speed: f64::INFINITY,
+                rapid: true,
+                corner,
+            }),
+            CanonicalKind::Linear { to, .. } => moves.push(Move {
+                line,
+                to,
+                speed,
+                rapid: false,
+                corner,
+            }),
+            CanonicalKind::Arc { arc, .. } => moves.extend(
+                arc.points(options.arc_tolerance)
+                    .into_iter()
+                    .map(|to| Move {
+                        line,
+                        to,
+                        speed,
+                        rapid: false,
+                        corner,
+                    }),
+            ),
+            CanonicalKind::Spline { spline, .. } => moves.extend(
+                spline
+                    .linearise(options.arc_tolerance)
+                    .into_iter()
+                    .map(|to| Move {
+                        line,
+                        to,
+                        speed,
+                        rapid: false,
+                        corner,
+                    }),
+            ),
+            CanonicalKind::PathMode { mode } => {
+                corner = Corner::from_path_mode(mode, options.max_deviation);
+            }
+            // Coolant switches on and off without stopping the machine
+            CanonicalKind::Coolant { .. } => (),
+            other => {
+                position = run(&mut estimate, position, &moves, options)?;
+                moves.clear();
+
+                if let Some(synced) =
+                    SyncedMove::from_canonical(&other, options.spindle_acceleration)
+                {
+                    let synced = synced?;
+
+                    synced.check_limits(&options.machine)?;
+
+                    estimate.cutting += synced.duration();
+                    estimate.add(line, synced.duration());
+
+                    position = *other.target().unwrap_or(&position);
+                }
+
+                match other {
+                    CanonicalKind::Dwell { seconds } => {
+                        estimate.dwell += seconds;
+                        estimate.add(line, seconds);
+                    }
+                    CanonicalKind::ToolChange { .. } => {
+                        estimate.tool_change += options.tool_change_time;
+                        estimate.add(line, options.tool_change_time);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    run(&mut estimate, position, &moves, options)?;
+
+    Ok(estimate)
+}
+
+/// Estimate a run of moves that start and end at rest, returning the end position
+fn run(
+    estimate: &mut Estimate,
+    start: Vector9,
+    moves: &[Move],
+    options: &EstimateOptions,
+) -> Result<Vector9, PlanError> {
+    let end = match moves.last() {
+        Some(last) => last.to,
+        None => return Ok(start),
+    };
+
+    let durations = match options.mode {
+        EstimateMode::Exact => exact(start, moves, options)?,
+        EstimateMode::Approximate => approximate(start, moves, options),
+    };
+
+    for (m, duration) in moves.iter().zip(durations) {
+        if m.rapid {
+            estimate.rapid += duration;
+        } else {
+            estimate.cutting += duration;
+        }
+
+        estimate.add(m.line, duration);
+    }
+
+    Ok(end)
+}
+
+/// Time taken by each move when planning a time optimal trajectory through all of them
+fn exact(start: Vector9, moves: &[Move], options: &EstimateOptions) -> Result<Vec<f64>, PlanError> {
+    let waypoints: Vec<Vector9> = std::iter::once(start)
+        .chain(moves.iter().map(|m| m.to))
+        .collect();
+
+    let limits: Vec<f64> = std::iter::once(f64::INFINITY)
+        .chain(moves.iter().map(|m| m.speed))
+        .collect();
+
+    let corners: Vec<Corner> = std::iter::once(Corner::Stop)
+        .chain(moves.iter().map(|m| m.corner))
+        .collect();
+
+    let path = Path::with_corners(&waypoints, &corners).with_speed_limits(&limits);
+
+    let trajectory = Trajectory::new(&path, options.machine.trajectory_options())?;
+
+    let mut durations = vec![0.0; moves.len()];
+
+    // Waypoint `n` is the end of move `n - 1`
+    for (waypoint, duration) in trajectory.waypoint_durations() {
+        durations[waypoint.saturating_sub(1)] += duration;
+    }
+
+    Ok(durations)
+}
+
+/// A move's geometry and limits along its direction of travel
+struct Segment {
+    length: f64,
+    direction: Vector9,
+    /// Maximum squared speed
+    cap: f64,
+    acceleration: f64,
+}
+
+/// Time taken by each move when giving each one a trapezoidal profile
+fn approximate(start: Vector9, moves: &[Move], options: &EstimateOptions) -> Vec<f64> {
+    let vmax = &options.machine.velocity_limit;
+    let amax = &options.machine.acceleration_limit;
+
+    let mut from = start;
+
+    // Indices of the moves with non-zero length, and their geometry
+    let segments: Vec<(usize, Segment)> = moves
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, m)| {
+            let delta = m.to - from;
+            let length = delta.norm();
+
+            from = m.to;
+
+            if length < MIN_MOVE_LENGTH {
+                return None;
+            }
+
+            let direction = delta / length;
+
+            let mut speed = m.speed;
+            let mut acceleration = f64::INFINITY;
+
+            for i in 0..9 {
+                if direction[i].abs() > 0.0 {
+                    speed = speed.min(vmax[i] / direction[i].abs());
+                    acceleration = acceleration.min(amax[i] / direction[i].abs());
+                }
+            }
+
+            Some((
+                idx,
+                Segment {
+                    length,
+                    direction,
+                    cap: speed * speed,
+                    acceleration,
+                },
+            ))
+        })
+        .collect();
+
+    // Maximum squared speed at each junction
+    let mut u = vec![0.0; segments.len() + 1];
+
+    for k in 1..segments.len() {
+        let (a, b) = (&segments[k - 1].1, &segments[k].1);
+
+        let max = a.cap.min(b.cap);
+        let cos = -a.direction.dot(&b.direction);
+
+        u[k] = match moves[segments[k - 1].0].corner {
+            Corner::Stop => 0.0,
+            _ if cos > REVERSAL_COSINE => 0.0,
+            _ if cos < -REVERSAL_COSINE => max,
+            Corner::Exact => 0.0,
+            Corner::Blend { deviation, .. } => {
+                let sin_half = ((1.0 - cos) / 2.0).sqrt();
+                let acceleration = a.acceleration.min(b.acceleration);
+
+                (acceleration * deviation * sin_half / (1.0 - sin_half)).min(max)
+            }
+        };
+    }
+
+    for k in (0..segments.len()).rev() {
+        let s = &segments[k].1;
+
+        u[k] = u[k].min(u[k + 1] + 2.0 * s.acceleration * s.length);
+    }
+
+    for k in 0..segments.len() {
+        let s = &segments[k].1;
+
+        u[k + 1] = u[k + 1].min(u[k] + 2.0 * s.acceleration * s.length);
+    }
+
+    let mut durations = vec![0.0; moves.len()];
+
+    for (k, (idx, s)) in segments.iter().enumerate() {
+        let (u_start, u_end) = (u[k], u[k + 1]);
+
+        let top = s
+            .cap
+            .min((u_start + u_end) / 2.0 + s.acceleration * s.length)
+            .max(u_start)
+            .max(u_end);
+
+        let accelerate_distance = (top - u_start) / (2.0 * s.acceleration);
+        let decelerate_distance = (top - u_end) / (2.0 * s.acceleration);
+        let cruise_distance = (s.length - accelerate_distance - decelerate_distance).max(0.0);
+
+        let top = top.sqrt();
+
+        durations[*idx] = (top - u_start.sqrt()) / s.acceleration
+            + (top - u_end.sqrt()) / s.acceleration
+            + cruise_distance / top;
+    }
+
+    durations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcode_interpreter::{ErrorKind, Tool};
+    use std::fs;
+    use std::path::Path as FilePath;
+
+    fn options(mode: EstimateMode) -> EstimateOptions {
+        EstimateOptions {
+            mode,
+            tool_change_time: 5.0,
+            ..EstimateOptions::new(MachineConfig {
+                velocity_limit: Vector9::repeat(100.0),
+                acceleration_limit: Vector9::repeat(1000.0),
+                jerk_limit: None,
+            })
+        }
+    }
+
+    #[test]
+    fn breakdown() {
+        let program = Program::from_str("T1 M6\nG0 X10\nG4 P2\nG1 X20 F600\nM2").unwrap();
+
+        for mode in [EstimateMode::Exact, EstimateMode::Approximate].iter() {
+            let result = estimate(&program, &options(*mode)).unwrap();
+
+            // Rapid: accelerate to 100mm/s over 5mm then straight back down again
+            assert!((result.rapid - 0.2).abs() < 1.0e-9, "{:?}", mode);
+            // Feed at 10mm/s, taking 0.01s to accelerate and decelerate
+            assert!((result.cutting - 1.01).abs() < 1.0e-9, "{:?}", mode);
+            assert_eq!(result.dwell, 2.0);
+            assert_eq!(result.tool_change, 5.0);
+            assert!((result.total - 8.21).abs() < 1.0e-9, "{:?}", mode);
+
+            let lines: Vec<usize> = result.lines.keys().cloned().collect();
+
+            assert_eq!(lines, vec![1, 2, 3, 4]);
+            assert!((result.lines[&4] - 1.01).abs() < 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn arc() {
+        let program = Program::from_str("G0 X10\nG4 P0\nG2 X-10 I-10 F1200").unwrap();
+
+        for mode in [EstimateMode::Exact, EstimateMode::Approximate].iter() {
+            let result = estimate(&program, &options(*mode)).unwrap();
+
+            // Half a circle of radius 10 at 20mm/s
+            let expected = std::f64::consts::PI * 10.0 / 20.0;
+
+            assert!(
+                (result.cutting - expected).abs() / expected < 0.02,
+                "{:?} cutting time {} expected {}",
+                mode,
+                result.cutting,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn spline() {
+        // A quarter circle of radius 10 as a rational quadratic NURBS curve
+        let program =
+            Program::from_str("G0 X10\nG4 P0\nF1200\nG5.2 X10 Y10 P0.70710678 L3\nX0 Y10 P1\nG5.3")
+                .unwrap();
+
+        for mode in [EstimateMode::Exact, EstimateMode::Approximate].iter() {
+            let result = estimate(&program, &options(*mode)).unwrap();
+
+            // A quarter circle at 20mm/s, taking 0.02s to accelerate and decelerate
+            let expected = std::f64::consts::PI * 5.0 / 20.0 + 0.02;
+
+            assert!(
+                (result.cutting - expected).abs() / expected < 0.02,
+                "{:?} cutting time {} expected {}",
+                mode,
+                result.cutting,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn path_control() {
+        let total = |program: &str, mode: EstimateMode| {
+            estimate(&Program::from_str(program).unwrap(), &options(mode))
+                .unwrap()
+                .total
+        };
+
+        for mode in [EstimateMode::Exact, EstimateMode::Approximate].iter() {
+            let blended = total("G1 X10 F600\nG1 X20", *mode);
+            let stopped = total("G61.1\nG1 X10 F600\nG1 X20", *mode);
+
+            // Each move accelerates from and decelerates to rest at the exact stop
+            assert!((blended - 2.01).abs() < 1.0e-9, "{:?}", mode);
+            assert!((stopped - 2.02).abs() < 1.0e-9, "{:?}", mode);
+
+            let square = "G1 X10 F6000\nY10\nX0\nY0";
+
+            let exact = total(&format!("G61\n{}", square), *mode);
+            let tight = total(&format!("G64 P0.01\n{}", square), *mode);
+            let loose = total(&format!("G64 P0.5\n{}", square), *mode);
+
+            assert!(exact > tight && tight > loose, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn rotary_index() {
+        let program = fs::read_to_string(&FilePath::new(
+            "../test_files/linuxcnc/nc_files/b-index.ngc",
+        ))
+        .unwrap();
+
+        let program = Program::from_str(&program).unwrap();
+
+        for mode in [EstimateMode::Exact, EstimateMode::Approximate].iter() {
+            let result = estimate(&program, &options(*mode)).unwrap();
+
+            // One inch at 70 inches per minute, carrying on into the rapid that follows
+            let feed = 70.0 * 25.4 / 60.0;
+            let cutting = 25.4 / feed + feed / 2000.0;
+
+            assert!((result.cutting - cutting).abs() < 1.0e-6, "{:?}", mode);
+
+            // Each indexing move turns B at up to 100 degrees per second
+            for line in [8, 10, 12].iter() {
+                assert!(result.lines[line] > 0.4, "{:?} line {}", mode, line);
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_rotary_axis() {
+        let program = Program::from_str("G0 B350\nG0 B10").unwrap();
+
+        for mode in [EstimateMode::Exact, EstimateMode::Approximate].iter() {
+            let long_way = estimate(&program, &options(*mode)).unwrap();
+            let short_way = estimate(
+                &program,
+                &EstimateOptions {
+                    wrapped_axes: [false, true, false],
+                    ..options(*mode)
+                },
+            )
+            .unwrap();
+
+            // 350 then 340 degrees, cruising at 100 degrees per second
+            assert!((long_way.total - 7.1).abs() < 1.0e-6, "{:?}", mode);
+            // Back 10 then forward 20 degrees
+            assert!((short_way.total - 0.5).abs() < 1.0e-6, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn rigid_tapping() {
+        let program = Program::from_str("S600 M3\nG0 Z10\nG33.1 Z-30 K1.5\nG0 Z20").unwrap();
+
+        let options = EstimateOptions {
+            spindle_acceleration: 50.0,
+            ..options(EstimateMode::Exact)
+        };
+
+        let result = estimate(&program, &options).unwrap();
+
+        // 40mm in and out at 15mm/s, reversing the spindle twice in 0.4s each
+        assert!((result.cutting - (80.0 / 15.0 + 0.8)).abs() < 1.0e-9);
+        assert_eq!(result.lines[&3], result.cutting);
+
+        // The tap retracts to where it started, so the last rapid is only 10mm
+        assert!((result.lines[&4] - 0.2).abs() < 1.0e-9);
+
+        let too_fast = Program::from_str("S6000 M3\nG33 Z-30 K1.5").unwrap();
+
+        assert_eq!(
+            estimate(&too_fast, &options),
+            Err(EstimateError::Sync(SyncError::TooFast(2)))
+        );
+    }
+
+    #[test]
+    fn tool_length_offset() {
+        let program = Program::from_str("T1 M6\nG43 H1\nG0 Z5\nG4 P0\nG1 X10 F600\nM2").unwrap();
+
+        assert_eq!(
+            estimate(&program, &options(EstimateMode::Exact)),
+            Err(EstimateError::Interpret(InterpretError {
+                line: 2,
+                kind: ErrorKind::UnknownTool(1),
+            }))
+        );
+
+        let mut options = options(EstimateMode::Exact);
+
+        options.tools.insert(
+            1,
+            Tool {
+                length: 5.0,
+                ..Tool::default()
+            },
+        );
+
+        // Rapid 10mm up to the tool tip at Z5, reaching 100mm/s halfway
+        let result = estimate(&program, &options).unwrap();
+
+        assert!((result.rapid - 0.2).abs() < 1.0e-9);
+
+        // Starting 5mm up leaves only 5mm to go
+        options.position[2] = 5.0;
+
+        let result = estimate(&program, &options).unwrap();
+
+        assert!((result.rapid - 2.0 * (0.005f64).sqrt()).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn braid_approximate() {
+        let program =
+            fs::read_to_string(&FilePath::new("../test_files/tinyg/braid_4000mm.gcode")).unwrap();
+
+        let program = Program::from_str(&program).unwrap();
+
+        let approximate = estimate(&program, &options(EstimateMode::Approximate)).unwrap();
+        let exact = estimate(&program, &options(EstimateMode::Exact)).unwrap();
+
+        let sum: f64 = approximate.lines.values().sum();
+
+        assert!((sum - approximate.total).abs() < 1.0e-6);
+        assert!(approximate.cutting > 0.0);
+        assert!(
+            (approximate.total - exact.total).abs() / exact.total < 0.1,
+            "approximate {} exact {}",
+            approximate.total,
+            exact.total
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Machine kinematics
+//!
+//! Paths are planned in Cartesian space, but a machine's velocity and acceleration limits apply to
+//! its joints (motors). A [`Kinematics`](trait.Kinematics.html) implementation converts between
+//! the two so the planner can enforce the limits where they apply.
+//!
+//! All implementations work on `XYZUVWABC` vectors. Axes a machine doesn't transform are passed
+//! through unchanged.
+
+use crate::Vector9;
+use nalgebra::{Rotation3, Vector3};
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Step along the path used to differentiate non-linear kinematics numerically
+const DIFFERENCE_STEP: f64 = 1.0e-3;
+
+/// Conversion between Cartesian positions and machine joint positions
+pub trait Kinematics: fmt::Debug {
+    /// Joint positions for a Cartesian position, or `None` if the machine can't reach it
+    fn inverse(&self, position: &Vector9) -> Option<Vector9>;
+
+    /// Cartesian position for a set of joint positions, or `None` if the joints can't be in that
+    /// configuration
+    fn forward(&self, joints: &Vector9) -> Option<Vector9>;
+
+    /// Whether joint positions are a linear function of the Cartesian position, so straight lines
+    /// stay straight in joint space
+    fn is_linear(&self) -> bool {
+        false
+    }
+}
+
+/// First, second and third derivatives of the joint positions with respect to distance along a
+/// path, given the path's position and derivatives at a point
+///
+/// Returns `None` if the machine can't reach the point.
+pub(crate) fn joint_derivatives(
+    kinematics: &dyn Kinematics,
+    position: &Vector9,
+    d1: &Vector9,
+    d2: &Vector9,
+    d3: &Vector9,
+) -> Option<[Vector9; 3]> {
+    if kinematics.is_linear() {
+        let origin = kinematics.inverse(&Vector9::zeros())?;
+        let map = |d: &Vector9| kinematics.inverse(d).map(|joints| joints - origin);
+
+        kinematics.inverse(position)?;
+
+        return Some([map(d1)?, map(d2)?, map(d3)?]);
+    }
+
+    // Follow the path's Taylor expansion either side of the point and take central differences
+    let h = DIFFERENCE_STEP;
+
+    let joints = |k: f64| {
+        let s = k * h;
+
+        kinematics.inverse(&(position + d1 * s + d2 * (s * s / 2.0) + d3 * (s * s * s / 6.0)))
+    };
+
+    let (m2, m1, p0, p1, p2) = (
+        joints(-2.0)?,
+        joints(-1.0)?,
+        joints(0.0)?,
+        joints(1.0)?,
+        joints(2.0)?,
+    );
+
+    Some([
+        (p1 - m1) / (2.0 * h),
+        (p1 - p0 * 2.0 + m1) / (h * h),
+        (p2 - p1 * 2.0 + m1 * 2.0 - m2) / (2.0 * h * h * h),
+    ])
+}
+
+/// Every joint drives a single Cartesian axis
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Trivial;
+
+impl Kinematics for Trivial {
+    fn inverse(&self, position: &Vector9) -> Option<Vector9> {
+        Some(*position)
+    }
+
+    fn forward(&self, joints: &Vector9) -> Option<Vector9> {
+        Some(*joints)
+    }
+
+    fn is_linear(&self) -> bool {
+        true
+    }
+}
+
+/// CoreXY, where two motors share crossed belts to move the X and Y axes
+///
+/// Motor `A` (joint 0) turns by `X + Y` and motor `B` (joint 1) by `X - Y`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CoreXY;
+
+impl Kinematics for CoreXY {
+    fn inverse(&self, position: &Vector9) -> Option<Vector9> {
+        let mut joints = *position;
+
+        joints[0] = position[0] + position[1];
+        joints[1] = position[0] - position[1];
+
+        Some(joints)
+    }
+
+    fn forward(&self, joints: &Vector9) -> Option<Vector9> {
+        let mut position = *joints;
+
+        position[0] = (joints[0] + joints[1]) / 2.0;
+        position[1] = (joints[0] - joints[1]) / 2.0;
+
+        Some(position)
+    }
+
+    fn is_linear(&self) -> bool {
+        true
+    }
+}
+
+/// H-bot, where two fixed motors drive a single H shaped belt to move the X and Y axes
+///
+/// Motor `A` (joint 0) turns by `X + Y` and motor `B` (joint 1) by `Y - X`. The motion is the
+/// same as [`CoreXY`](struct.CoreXY.html) with motor `B` reversed; the difference between the two
+/// is in the forces on the frame, not the kinematics.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HBot;
+
+impl Kinematics for HBot {
+    fn inverse(&self, position: &Vector9) -> Option<Vector9> {
+        let mut joints = *position;
+
+        joints[0] = position[0] + position[1];
+        joints[1] = position[1] - position[0];
+
+        Some(joints)
+    }
+
+    fn forward(&self, joints: &Vector9) -> Option<Vector9> {
+        let mut position = *joints;
+
+        position[0] = (joints[0] - joints[1]) / 2.0;
+        position[1] = (joints[0] + joints[1]) / 2.0;
+
+        Some(position)
+    }
+
+    fn is_linear(&self) -> bool {
+        true
+    }
+}
+
+/// Linear delta, where three carriages move up and down vertical towers and push the effector
+/// around on fixed length rods
+///
+/// The towers are at 210°, 330° and 90° around the Z axis, and joints 0, 1 and 2 are the heights
+/// of their carriages above the effector's reference plane at `Z = 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearDelta {
+    /// Horizontal distance from the center of the machine to each tower, less the effector's own
+    /// offset
+    pub radius: f64,
+
+    /// Length of the rods between the carriages and the effector
+    pub rod_length: f64,
+}
+
+impl LinearDelta {
+    fn tower(&self, idx: usize) -> (f64, f64) {
+        let angle = (210.0 + 120.0 * idx as f64) * PI / 180.0;
+
+        (self.radius * angle.cos(), self.radius * angle.sin())
+    }
+}
+
+impl Kinematics for LinearDelta {
+    fn inverse(&self, position: &Vector9) -> Option<Vector9> {
+        let mut joints = *position;
+
+        for idx in 0..3 {
+            let (x, y) = self.tower(idx);
+
+            let dx = position[0] - x;
+            let dy = position[1] - y;
+            let height = self.rod_length.powi(2) - dx * dx - dy * dy;
+
+            if height < 0.0 {
+                return None;
+            }
+
+            joints[idx] = position[2] + height.sqrt();
+        }
+
+        Some(joints)
+    }
+
+    fn forward(&self, joints: &Vector9) -> Option<Vector9> {
+        let carriage = |idx: usize| {
+            let (x, y) = self.tower(idx);
+
+            Vector3::new(x, y, joints[idx])
+        };
+
+        let (p1, p2, p3) = (carriage(0), carriage(1), carriage(2));
+
+        // Intersect the three spheres of rod length around the carriages
+        let d = (p2 - p1).norm();
+        let ex = (p2 - p1) / d;
+        let i = ex.dot(&(p3 - p1));
+        let ey = (p3 - p1 - ex * i).normalize();
+        let ez = ex.cross(&ey);
+        let j = ey.dot(&(p3 - p1));
+
+        let x = d / 2.0;
+        let y = (i * i + j * j) / (2.0 * j) - i * x / j;
+        let z = self.rod_length.powi(2) - x * x - y * y;
+
+        if z < 0.0 {
+            return None;
+        }
+
+        let a = p1 + ex * x + ey * y + ez * z.sqrt();
+        let b = p1 + ex * x + ey * y - ez * z.sqrt();
+
+        // The effector hangs below the carriages
+        let effector = if a[2] < b[2] { a } else { b };
+
+        let mut position = *joints;
+
+        position[0] = effector[0];
+        position[1] = effector[1];
+        position[2] = effector[2];
+
+        Some(position)
+    }
+}
+
+/// Table-tilting 5-axis (XYZAC trunnion), where the A axis tilts the table about X and the C axis
+/// spins the table about Z on top of it
+///
+/// Cartesian positions are the tool tip in the coordinates of the workpiece on the table, with `A`
+/// and `C` in degrees giving the tool orientation. Joints are the linear axes in machine
+/// coordinates and the rotary axes themselves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableTilting {
+    /// Machine coordinates of the point where the A and C rotation axes cross
+    pub pivot: Vector3<f64>,
+}
+
+impl TableTilting {
+    fn rotation(position: &Vector9) -> Rotation3<f64> {
+        let a = position[6].to_radians();
+        let c = position[8].to_radians();
+
+        Rotation3::from_axis_angle(&Vector3::x_axis(), a)
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), c)
+    }
+
+    fn transform(input: &Vector9, rotation: Rotation3<f64>, pivot: &Vector3<f64>) -> Vector9 {
+        let point = rotation * (Vector3::new(input[0], input[1], input[2]) - pivot) + pivot;
+
+        let mut output = *input;
+
+        output[0] = point[0];
+        output[1] = point[1];
+        output[2] = point[2];
+
+        output
+    }
+}
+
+impl Kinematics for TableTilting {
+    fn inverse(&self, position: &Vector9) -> Option<Vector9> {
+        Some(Self::transform(
+            position,
+            Self::rotation(position),
+            &self.pivot,
+        ))
+    }
+
+    fn forward(&self, joints: &Vector9) -> Option<Vector9> {
+        Some(Self::transform(
+            joints,
+            Self::rotation(joints).inverse(),
+            &self.pivot,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(values: &[f64]) -> Vector9 {
+        let mut v = Vector9::zeros();
+
+        for (i, value) in values.iter().enumerate() {
+            v[i] = *value;
+        }
+
+        v
+    }
+
+    fn assert_round_trip(kinematics: &dyn Kinematics, position: Vector9) {
+        let joints = kinematics.inverse(&position).unwrap();
+        let back = kinematics.forward(&joints).unwrap();
+
+        assert!(
+            (back - position).norm() < 1.0e-9,
+            "{:?} gave {} back from {}",
+            kinematics,
+            back,
+            position
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let delta = LinearDelta {
+            radius: 100.0,
+            rod_length: 250.0,
+        };
+        let trunnion = TableTilting {
+            pivot: Vector3::new(10.0, 20.0, -50.0),
+        };
+
+        let kinematics: [&dyn Kinematics; 5] = [&Trivial, &CoreXY, &HBot, &delta, &trunnion];
+
+        for kinematics in kinematics.iter() {
+            assert_round_trip(*kinematics, point(&[1.0, 2.0, 3.0]));
+            assert_round_trip(
+                *kinematics,
+                point(&[-30.0, 40.0, -5.0, 0.0, 0.0, 0.0, 30.0, 0.0, 45.0]),
+            );
+        }
+    }
+
+    #[test]
+    fn core_xy() {
+        let joints = CoreXY.inverse(&point(&[1.0, 2.0, 3.0])).unwrap();
+
+        assert_eq!(joints, point(&[3.0, -1.0, 3.0]));
+    }
+
+    #[test]
+    fn delta_center() {
+        let delta = LinearDelta {
+            radius: 100.0,
+            rod_length: 250.0,
+        };
+
+        let joints = delta.inverse(&point(&[0.0, 0.0, 10.0])).unwrap();
+        let height = 10.0 + (250.0f64.powi(2) - 100.0f64.powi(2)).sqrt();
+
+        for i in 0..3 {
+            assert!((joints[i] - height).abs() < 1.0e-9);
+        }
+
+        assert_eq!(delta.inverse(&point(&[400.0, 0.0, 0.0])), None);
+    }
+
+    #[test]
+    fn numeric_derivatives() {
+        let delta = LinearDelta {
+            radius: 100.0,
+            rod_length: 250.0,
+        };
+
+        // Follow a circle of radius 20 around the center at unit speed
+        let s: f64 = 0.3;
+        let r: f64 = 20.0;
+        let position = point(&[r * (s / r).cos(), r * (s / r).sin(), 0.0]);
+        let d1 = point(&[-(s / r).sin(), (s / r).cos(), 0.0]);
+        let d2 = point(&[-(s / r).cos() / r, -(s / r).sin() / r, 0.0]);
+        let d3 = -d1 / (r * r);
+
+        let [j1, j2, _] = joint_derivatives(&delta, &position, &d1, &d2, &d3).unwrap();
+
+        // Compare against differencing the joints along the true circle with a smaller step
+        let joints = |s: f64| {
+            delta
+                .inverse(&point(&[r * (s / r).cos(), r * (s / r).sin(), 0.0]))
+                .unwrap()
+        };
+
+        let h = 1.0e-4;
+        let expected_j1 = (joints(s + h) - joints(s - h)) / (2.0 * h);
+        let expected_j2 = (joints(s + h) - joints(s) * 2.0 + joints(s - h)) / (h * h);
+
+        assert!((j1 - expected_j1).norm() < 1.0e-6);
+        assert!((j2 - expected_j2).norm() < 1.0e-4);
+    }
+}
@@ -1,17 +1,46 @@
+//! Plan time-optimal trajectories through GCode programs
+//!
+//! Trajectories are generated by a pure Rust [`Backend`](backend/trait.Backend.html). The
+//! `trajectories` and `trajectories-sys` (C++) reference implementations can be pulled in with the
+//! `cross-validate` feature to compare results against.
+
+mod backend;
+mod envelope;
+mod estimate;
+mod kinematics;
+mod machine;
+mod path;
+mod retime;
+mod sample;
+mod steps;
+mod stream;
+mod synced;
 mod test_helpers;
-
-use gcode_parser::{
-    token::{Coord, TokenType},
-    Program,
+mod trajectory;
+
+pub use crate::backend::{Backend, TimeOptimal, TrajectoryProfile};
+pub use crate::envelope::{check_soft_limits, EnvelopeError, LimitViolation};
+pub use crate::estimate::{estimate, Estimate, EstimateError, EstimateMode, EstimateOptions};
+pub use crate::kinematics::{CoreXY, HBot, Kinematics, LinearDelta, TableTilting, Trivial};
+pub use crate::machine::{MachineConfig, SoftLimits};
+pub use crate::path::{
+    CircularSegment, LinearSegment, Path, PathOptions, PathSegment, PlacedSegment,
+};
+pub use crate::retime::{OverrideChange, RetimeError, Retimed};
+pub use crate::sample::{Samples, Setpoint};
+pub use crate::steps::{StepConfig, StepError, StepEvent, StepGenerator, StepMethod};
+pub use crate::stream::{
+    ChunkMotion, StreamError, StreamOptions, StreamingPlanner, TrajectoryChunk,
 };
+pub use crate::synced::{SpindleProfile, SyncError, SyncedMove};
+pub use crate::trajectory::{PlanError, Trajectory, TrajectoryOptions};
+use gcode_parser::token::Coord;
 use nalgebra::{VectorN, U9};
-use std::fs;
-use std::path::Path as FilePath;
-use trajectories::{Path, Trajectory};
-use trajectories_sys::{path_create, Trajectory as CppTrajectory};
 
+/// A position or vector in all 9 `XYZUVWABC` axes
 pub type Vector9 = VectorN<f64, U9>;
 
+/// Replace the components of `current` with any axes given in `coord`
 pub fn merge_vector9_and_coord(current: &Vector9, coord: &Coord) -> Vector9 {
     let mut new = current.clone();
     let coord_c = coord.clone();
@@ -59,8 +88,10 @@ pub fn merge_vector9_and_coord(current: &Vector9, coord: &Coord) -> Vector9 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_helpers::{end_profile, start_profile};
-    use trajectories::{PathOptions, TrajectoryOptions};
+    use crate::test_helpers::{assert_within_limits, end_profile, start_profile};
+    use gcode_parser::{token::TokenType, Program};
+    use std::fs;
+    use std::path::Path as FilePath;
 
     #[test]
     fn parse_program_to_path() {
@@ -105,6 +136,7 @@ mod tests {
             TrajectoryOptions {
                 velocity_limit: Vector9::repeat(1.0),
                 acceleration_limit: Vector9::repeat(1.0),
+                jerk_limit: None,
                 epsilon: 0.000001,
                 timestep: 0.001,
             },
@@ -169,6 +201,7 @@ mod tests {
             TrajectoryOptions {
                 velocity_limit: Vector9::repeat(200.0),
                 acceleration_limit: Vector9::repeat(200.0),
+                jerk_limit: None,
                 epsilon: 0.000001,
                 timestep: 0.001,
             },
@@ -178,8 +211,62 @@ mod tests {
         end_profile();
     }
 
+    /// Waypoints for every coordinate in a corpus file
+    fn corpus_waypoints(file: &str) -> Vec<Vector9> {
+        let program = fs::read_to_string(&FilePath::new(file)).unwrap();
+
+        let parsed = Program::from_str(&program).unwrap();
+
+        parsed
+            .iter_flat()
+            .filter_map(|token| match &token.token {
+                TokenType::Coord(c) => Some(c),
+                _ => None,
+            })
+            .scan(Vector9::zeros(), |current, coord| {
+                *current = merge_vector9_and_coord(current, coord);
+
+                Some(*current)
+            })
+            .collect()
+    }
+
+    fn assert_jerk_limited(file: &str) {
+        let waypoints = corpus_waypoints(file);
+
+        let options = TrajectoryOptions {
+            velocity_limit: Vector9::repeat(2.0),
+            acceleration_limit: Vector9::repeat(10.0),
+            jerk_limit: Some(Vector9::repeat(100.0)),
+            epsilon: 0.000001,
+            timestep: 0.001,
+        };
+
+        let trajectory = TimeOptimal
+            .plan(
+                &waypoints,
+                PathOptions {
+                    max_deviation: 0.001,
+                },
+                options,
+            )
+            .unwrap();
+
+        assert_within_limits(&trajectory, &options);
+    }
+
     #[test]
-    #[ignore]
+    fn spiro_jerk_limit() {
+        assert_jerk_limited("../test_files/tinyg/spiro.gcode");
+    }
+
+    #[test]
+    fn circles2_jerk_limit() {
+        assert_jerk_limited("../test_files/tinyg/circles2.gcode");
+    }
+
+    #[test]
+    #[cfg(feature = "cross-validate")]
     fn birthday() {
         // pretty_env_logger::init();
 
@@ -200,8 +287,6 @@ mod tests {
         // Simulate the current state/position of the machine
         let current_position = Vector9::repeat(9.99);
 
-        start_profile();
-
         let waypoints: Vec<Vector9> = coords
             .iter()
             .scan(current_position, |current, coord| {
@@ -213,56 +298,55 @@ mod tests {
             })
             .collect();
 
-        // println!("{:#?}", waypoints);
+        let velocity_limit = Vector9::repeat(200.0);
+        let acceleration_limit = Vector9::repeat(5.0);
 
-        // Validate (slowly) that no waypoints contain NaNs
-        for point in waypoints.iter() {
-            for i in point.iter() {
-                assert!(!i.is_nan());
-            }
-        }
+        start_profile();
 
-        let path = Path::from_waypoints(
+        let trajectory = TimeOptimal
+            .plan(
+                &waypoints,
+                PathOptions {
+                    max_deviation: 0.001,
+                },
+                TrajectoryOptions {
+                    velocity_limit,
+                    acceleration_limit,
+                    jerk_limit: None,
+                    epsilon: 0.000001,
+                    timestep: 0.001,
+                },
+            )
+            .unwrap();
+
+        end_profile();
+
+        let reference_path = trajectories::Path::from_waypoints(
             &waypoints,
-            PathOptions {
+            trajectories::PathOptions {
                 max_deviation: 0.001,
             },
         );
 
-        let _trajectory = Trajectory::new(
-            &path,
-            TrajectoryOptions {
-                velocity_limit: Vector9::repeat(200.0),
-                acceleration_limit: Vector9::repeat(5.0),
+        let reference = trajectories::Trajectory::new(
+            &reference_path,
+            trajectories::TrajectoryOptions {
+                velocity_limit,
+                acceleration_limit,
                 epsilon: 0.000001,
                 timestep: 0.001,
             },
         )
         .unwrap();
 
-        end_profile();
+        // The reference integrates the phase plane numerically, so only expect rough agreement
+        let difference = (trajectory.duration() - reference.duration()).abs();
 
-        let cpp_path = unsafe {
-            path_create(
-                waypoints
-                    .iter()
-                    .flat_map(|point| vec![point[0], point[1], point[2]])
-                    .collect::<Vec<f64>>()
-                    .as_ptr(),
-                waypoints.len() * 3,
-                // Max deviation
-                0.001f64,
-            )
-        };
-
-        let _cpp_trajectory = unsafe {
-            CppTrajectory::new(
-                cpp_path,
-                &[200.0, 200.0, 200.0],
-                &[5.0, 5.0, 5.0],
-                // Timestep
-                0.001f64,
-            )
-        };
+        assert!(
+            difference / reference.duration() < 0.01,
+            "durations differ: {} vs reference {}",
+            trajectory.duration(),
+            reference.duration()
+        );
     }
 }
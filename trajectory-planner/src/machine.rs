@@ -0,0 +1,65 @@
+//! Physical machine limits
+
+use crate::trajectory::TrajectoryOptions;
+use crate::Vector9;
+
+/// Limits of a machine, in millimeters (or degrees for rotary axes) and seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MachineConfig {
+    /// Maximum velocity of each axis in units per second
+    pub velocity_limit: Vector9,
+
+    /// Maximum acceleration of each axis in units per second squared
+    pub acceleration_limit: Vector9,
+
+    /// Maximum jerk of each axis in units per second cubed, if speed changes should follow
+    /// S-curves
+    pub jerk_limit: Option<Vector9>,
+}
+
+impl MachineConfig {
+    /// Trajectory options that respect this machine's limits
+    pub fn trajectory_options(&self) -> TrajectoryOptions {
+        TrajectoryOptions {
+            velocity_limit: self.velocity_limit,
+            acceleration_limit: self.acceleration_limit,
+            jerk_limit: self.jerk_limit,
+            epsilon: 0.000001,
+            timestep: 0.001,
+        }
+    }
+}
+
+/// Range of travel of each axis, in machine coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoftLimits {
+    /// Lowest position each axis may move to
+    pub min: Vector9,
+
+    /// Highest position each axis may move to
+    pub max: Vector9,
+}
+
+impl SoftLimits {
+    /// Limits that allow any position on every axis
+    pub fn unlimited() -> Self {
+        Self {
+            min: Vector9::repeat(f64::NEG_INFINITY),
+            max: Vector9::repeat(f64::INFINITY),
+        }
+    }
+
+    /// The axis that strays furthest outside these limits between `min` and `max`, with how far
+    /// past its limit it goes
+    pub(crate) fn overshoot(&self, min: &Vector9, max: &Vector9) -> Option<(usize, f64)> {
+        (0..9)
+            .map(|axis| {
+                let below = self.min[axis] - min[axis];
+                let above = max[axis] - self.max[axis];
+
+                (axis, below.max(above))
+            })
+            .filter(|(_, overshoot)| *overshoot > 0.0)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+    }
+}
@@ -0,0 +1,791 @@
+//! Geometric path through a list of waypoints
+//!
+//! Straight lines between waypoints are joined by circular blends so the path tangent is
+//! continuous wherever possible. This follows the construction from
+//! [Kunz & Stilman](http://www.golems.org/papers/KunzRSS12-Trajectories.pdf), as used by the C++
+//! reference implementation.
+
+use crate::Vector9;
+use gcode_interpreter::PathMode;
+use std::f64::consts::PI;
+
+/// Waypoints closer together than this are considered to be the same point
+const MIN_SEGMENT_LENGTH: f64 = 1.0e-9;
+
+/// Tangent directions closer than this (in radians) are considered collinear
+const COLLINEAR_ANGLE: f64 = 1.0e-6;
+
+/// Rounding error in the ends of segments, which limits how well the direction of a very short
+/// line is known
+const ENDPOINT_ROUNDING: f64 = 1.0e-10;
+
+/// Path construction options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathOptions {
+    /// The maximum distance a blend may deviate from the corner it rounds off
+    pub max_deviation: f64,
+}
+
+/// A straight line between two points
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearSegment {
+    start: Vector9,
+    end: Vector9,
+    direction: Vector9,
+    length: f64,
+}
+
+impl LinearSegment {
+    fn new(start: Vector9, end: Vector9) -> Self {
+        let length = (end - start).norm();
+
+        Self {
+            start,
+            end,
+            direction: (end - start) / length,
+            length,
+        }
+    }
+}
+
+/// A circular arc joining two linear segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircularSegment {
+    center: Vector9,
+    /// Unit vector from the center to the start of the arc
+    x: Vector9,
+    /// Unit vector perpendicular to `x` in the plane of the arc (the start tangent)
+    y: Vector9,
+    radius: f64,
+    angle: f64,
+}
+
+impl CircularSegment {
+    /// Create a blend around `corner` between the midpoints of the adjoining lines
+    ///
+    /// Returns `None` if the lines are collinear, reverse direction or the blend would have no
+    /// size.
+    fn blend(
+        previous: &Vector9,
+        corner: &Vector9,
+        next: &Vector9,
+        max_deviation: f64,
+    ) -> Option<Self> {
+        let start_direction = (corner - previous).normalize();
+        let end_direction = (next - corner).normalize();
+
+        // Direction from the start of the blend towards its center. This is the difference of two
+        // almost parallel directions at shallow corners, so it is made perpendicular to the start
+        // direction a second time to make up for the precision lost
+        let cos = start_direction.dot(&end_direction);
+        let mut normal = end_direction - start_direction * cos;
+        normal -= start_direction * start_direction.dot(&normal);
+
+        // Unlike the arccosine, this stays accurate for small angles
+        let angle = normal.norm().atan2(cos);
+
+        if angle < COLLINEAR_ANGLE || PI - angle < COLLINEAR_ANGLE {
+            return None;
+        }
+
+        let half_angle = angle / 2.0;
+
+        let distance = ((corner - previous).norm() / 2.0)
+            .min((next - corner).norm() / 2.0)
+            .min(max_deviation * half_angle.sin() / (1.0 - half_angle.cos()));
+
+        if distance < MIN_SEGMENT_LENGTH {
+            return None;
+        }
+
+        let radius = distance / half_angle.tan();
+        let normal = normal.normalize();
+        let center = corner - start_direction * distance + normal * radius;
+
+        Some(Self {
+            center,
+            x: -normal,
+            y: start_direction,
+            radius,
+            angle,
+        })
+    }
+
+    fn length(&self) -> f64 {
+        self.radius * self.angle
+    }
+}
+
+/// A single section of a path
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    /// Straight line
+    Linear(LinearSegment),
+
+    /// Circular blend between two straight lines
+    Circular(CircularSegment),
+}
+
+impl PathSegment {
+    /// Length of this segment
+    pub fn length(&self) -> f64 {
+        match self {
+            PathSegment::Linear(l) => l.length,
+            PathSegment::Circular(c) => c.length(),
+        }
+    }
+
+    /// Position at distance `s` from the start of this segment
+    pub fn position(&self, s: f64) -> Vector9 {
+        match self {
+            PathSegment::Linear(l) => l.start + l.direction * s,
+            PathSegment::Circular(c) => {
+                let a = s / c.radius;
+
+                c.center + (c.x * a.cos() + c.y * a.sin()) * c.radius
+            }
+        }
+    }
+
+    /// First derivative of the position with respect to distance along the segment
+    ///
+    /// This is a unit vector as the path is parameterised by arc length.
+    pub fn tangent(&self, s: f64) -> Vector9 {
+        match self {
+            PathSegment::Linear(l) => l.direction,
+            PathSegment::Circular(c) => {
+                let a = s / c.radius;
+
+                c.y * a.cos() - c.x * a.sin()
+            }
+        }
+    }
+
+    /// Second derivative of the position with respect to distance along the segment
+    pub fn curvature(&self, s: f64) -> Vector9 {
+        match self {
+            PathSegment::Linear(_) => Vector9::zeros(),
+            PathSegment::Circular(c) => {
+                let a = s / c.radius;
+
+                -(c.x * a.cos() + c.y * a.sin()) / c.radius
+            }
+        }
+    }
+
+    /// Third derivative of the position with respect to distance along the segment
+    pub fn curvature_derivative(&self, s: f64) -> Vector9 {
+        match self {
+            PathSegment::Linear(_) => Vector9::zeros(),
+            PathSegment::Circular(c) => -self.tangent(s) / (c.radius * c.radius),
+        }
+    }
+
+    /// Angle swept by this segment, or zero for straight lines
+    pub(crate) fn sweep(&self) -> f64 {
+        match self {
+            PathSegment::Linear(_) => 0.0,
+            PathSegment::Circular(c) => c.angle,
+        }
+    }
+
+    fn start(&self) -> Vector9 {
+        self.position(0.0)
+    }
+
+    fn end(&self) -> Vector9 {
+        self.position(self.length())
+    }
+}
+
+/// A segment placed along a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedSegment {
+    /// Distance along the path at which this segment starts
+    pub start: f64,
+
+    /// Index into the original waypoint list of the waypoint this segment is heading towards
+    pub waypoint: usize,
+
+    /// Whether the machine must come to a stop at the start of this segment, either because the
+    /// path tangent is discontinuous there or an exact stop was asked for
+    pub stop: bool,
+
+    /// Maximum path speed along this segment, or infinity if only the axis limits apply
+    pub speed_limit: f64,
+
+    /// The segment geometry
+    pub segment: PathSegment,
+}
+
+/// How the path passes through a waypoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Corner {
+    /// Round the corner off with a blend
+    Blend {
+        /// The maximum distance the blend may deviate from the corner
+        deviation: f64,
+
+        /// Drop this waypoint if it lies within this distance of a straight line joining the
+        /// waypoints either side of it, or `0.0` to always keep it
+        merge: f64,
+    },
+
+    /// Follow the corner exactly, stopping if the path changes direction
+    Exact,
+
+    /// Always stop at the waypoint
+    Stop,
+}
+
+impl Corner {
+    /// The corner for a path control mode, blending with `max_deviation` if the mode doesn't
+    /// give a tolerance
+    pub(crate) fn from_path_mode(mode: PathMode, max_deviation: f64) -> Self {
+        match mode {
+            PathMode::ExactPath => Corner::Exact,
+            PathMode::ExactStop => Corner::Stop,
+            PathMode::Blend {
+                tolerance,
+                naive_tolerance,
+            } => Corner::Blend {
+                deviation: tolerance.unwrap_or(max_deviation),
+                merge: naive_tolerance.unwrap_or(0.0),
+            },
+        }
+    }
+
+    fn merge(&self) -> f64 {
+        match self {
+            Corner::Blend { merge, .. } => *merge,
+            _ => 0.0,
+        }
+    }
+}
+
+/// A waypoint being built into a path
+#[derive(Debug, Clone, Copy)]
+struct BuilderPoint {
+    idx: usize,
+    position: Vector9,
+    corner: Corner,
+}
+
+/// Builds path segments one waypoint at a time
+///
+/// The blend around a corner only depends on the waypoints either side of it, so segments can be
+/// produced as soon as the waypoint after their corner is known.
+#[derive(Debug, Clone)]
+pub(crate) struct PathBuilder {
+    /// The last (up to) two distinct waypoints. Runs of identical waypoints are collapsed,
+    /// remembering the index of the last one in each run.
+    points: Vec<BuilderPoint>,
+
+    /// Waypoints after the last of `points` that may yet be merged into a single straight line
+    run: Vec<BuilderPoint>,
+
+    /// Start of the next straight line
+    start: Vector9,
+
+    /// Whether the next segment must start from rest
+    stop: bool,
+}
+
+impl PathBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            points: Vec::with_capacity(3),
+            run: Vec::new(),
+            start: Vector9::zeros(),
+            stop: false,
+        }
+    }
+
+    /// Whether no waypoints have been added since the builder was created or last finished
+    pub(crate) fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Add a waypoint, appending any segments that can now be completed to `segments`
+    pub(crate) fn push(
+        &mut self,
+        idx: usize,
+        position: Vector9,
+        corner: Corner,
+        segments: &mut Vec<PlacedSegment>,
+    ) {
+        let point = BuilderPoint {
+            idx,
+            position,
+            corner,
+        };
+
+        if let (Some(anchor), Some(last)) = (self.points.last(), self.run.last().cloned()) {
+            let anchor = anchor.position;
+
+            let fits = self
+                .run
+                .iter()
+                .all(|p| distance_to_line(&p.position, &anchor, &position) <= p.corner.merge());
+
+            if !fits {
+                self.run.clear();
+                self.add(last, segments);
+            }
+        }
+
+        if point.corner.merge() > 0.0 && !self.points.is_empty() {
+            self.run.push(point);
+        } else {
+            self.run.clear();
+            self.add(point, segments);
+        }
+    }
+
+    /// Add a waypoint that won't be merged away
+    fn add(&mut self, point: BuilderPoint, segments: &mut Vec<PlacedSegment>) {
+        match self.points.last_mut() {
+            Some(last) if (last.position - point.position).norm() < MIN_SEGMENT_LENGTH => {
+                *last = point;
+
+                return;
+            }
+            Some(_) => self.points.push(point),
+            None => {
+                self.points.push(point);
+                self.start = point.position;
+
+                return;
+            }
+        }
+
+        if self.points.len() < 3 {
+            return;
+        }
+
+        let previous = self.points[0].position;
+        let corner = self.points[1];
+        let next = self.points[2].position;
+
+        let blend = match corner.corner {
+            Corner::Blend { deviation, .. } => {
+                CircularSegment::blend(&previous, &corner.position, &next, deviation)
+            }
+            Corner::Exact | Corner::Stop => None,
+        };
+
+        match blend {
+            Some(blend) => {
+                let blend = PathSegment::Circular(blend);
+                let blend_start = blend.start();
+
+                if (blend_start - self.start).norm() > MIN_SEGMENT_LENGTH {
+                    let line = LinearSegment::new(self.start, blend_start);
+
+                    self.emit(corner.idx, PathSegment::Linear(line), segments);
+                }
+
+                self.start = blend.end();
+
+                self.emit(corner.idx, blend, segments);
+            }
+            None => {
+                let line = LinearSegment::new(self.start, corner.position);
+
+                self.emit(corner.idx, PathSegment::Linear(line), segments);
+
+                self.start = corner.position;
+                self.stop = corner.corner == Corner::Stop;
+            }
+        }
+
+        self.points.remove(0);
+    }
+
+    fn emit(&mut self, waypoint: usize, segment: PathSegment, segments: &mut Vec<PlacedSegment>) {
+        segments.push(PlacedSegment {
+            start: 0.0,
+            waypoint,
+            stop: self.stop,
+            speed_limit: f64::INFINITY,
+            segment,
+        });
+
+        self.stop = false;
+    }
+
+    /// Append the straight line to the last waypoint, leaving the builder ready to start afresh
+    pub(crate) fn finish(&mut self, segments: &mut Vec<PlacedSegment>) {
+        if let Some(last) = self.run.pop() {
+            self.run.clear();
+            self.add(last, segments);
+        }
+
+        if self.points.len() > 1 {
+            let last = self.points[self.points.len() - 1];
+
+            if (last.position - self.start).norm() > MIN_SEGMENT_LENGTH {
+                let line = LinearSegment::new(self.start, last.position);
+
+                self.emit(last.idx, PathSegment::Linear(line), segments);
+            }
+        }
+
+        self.points.clear();
+        self.stop = false;
+    }
+}
+
+/// Distance from a point to the straight line between `start` and `end`
+fn distance_to_line(point: &Vector9, start: &Vector9, end: &Vector9) -> f64 {
+    let line = end - start;
+    let length = line.norm();
+
+    if length < MIN_SEGMENT_LENGTH {
+        return (point - start).norm();
+    }
+
+    let along = (point - start).dot(&line) / length;
+    let along = along.clamp(0.0, length);
+
+    (point - (start + line * (along / length))).norm()
+}
+
+/// A continuous path through a list of waypoints
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    segments: Vec<PlacedSegment>,
+    length: f64,
+}
+
+impl Path {
+    /// Create a path through a list of waypoints, rounding corners with circular blends
+    pub fn from_waypoints(waypoints: &[Vector9], options: PathOptions) -> Self {
+        let corner = Corner::Blend {
+            deviation: options.max_deviation,
+            merge: 0.0,
+        };
+
+        Self::with_corners(waypoints, &vec![corner; waypoints.len()])
+    }
+
+    /// Create a path through a list of waypoints, where `corners[n]` says how the path should pass
+    /// through waypoint `n`
+    pub fn with_corners(waypoints: &[Vector9], corners: &[Corner]) -> Self {
+        let mut builder = PathBuilder::new();
+        let mut segments: Vec<PlacedSegment> = Vec::new();
+
+        for (idx, (point, corner)) in waypoints.iter().zip(corners).enumerate() {
+            builder.push(idx, *point, *corner, &mut segments);
+        }
+
+        builder.finish(&mut segments);
+
+        Self::from_segments(segments, None)
+    }
+
+    /// Place segments end to end, given the tangent at the end of whatever came before them
+    pub(crate) fn from_segments(
+        segments: Vec<PlacedSegment>,
+        mut previous_tangent: Option<Vector9>,
+    ) -> Self {
+        let mut length = 0.0;
+        let mut previous_length = f64::INFINITY;
+
+        let segments = segments
+            .into_iter()
+            .map(|mut placed| {
+                let segment = &placed.segment;
+                let start_tangent = segment.tangent(0.0);
+
+                let tolerance =
+                    COLLINEAR_ANGLE.max(ENDPOINT_ROUNDING / previous_length.min(segment.length()));
+
+                // The chord between the tangents is close to the angle between them, and unlike
+                // the arccosine of their dot product it isn't swamped by rounding for tiny blends
+                let discontinuous = previous_tangent
+                    .map(|previous| (previous - start_tangent).norm() > tolerance)
+                    .unwrap_or(false);
+
+                previous_tangent = Some(segment.tangent(segment.length()));
+                previous_length = segment.length();
+
+                placed.start = length;
+                placed.stop |= discontinuous;
+
+                length += placed.segment.length();
+
+                placed
+            })
+            .collect();
+
+        Self { segments, length }
+    }
+
+    /// Limit the path speed of each segment, for example to a programmed feed rate
+    ///
+    /// `limits` holds the speed limit for the move ending at each waypoint. Blends take the
+    /// lower of the limits of the moves on either side of their corner.
+    pub fn with_speed_limits(mut self, limits: &[f64]) -> Self {
+        self.set_speed_limits(|idx| limits.get(idx).cloned().unwrap_or(f64::INFINITY));
+
+        self
+    }
+
+    /// Set segment speed limits from the limit of the move ending at each waypoint index
+    pub(crate) fn set_speed_limits(&mut self, limit: impl Fn(usize) -> f64) {
+        for placed in self.segments.iter_mut() {
+            placed.speed_limit = match placed.segment {
+                PathSegment::Linear(_) => limit(placed.waypoint),
+                PathSegment::Circular(_) => limit(placed.waypoint).min(limit(placed.waypoint + 1)),
+            };
+        }
+    }
+
+    /// Total length of the path
+    pub fn len(&self) -> f64 {
+        self.length
+    }
+
+    /// Whether this path has zero length
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// The segments that make up this path
+    pub fn segments(&self) -> &[PlacedSegment] {
+        &self.segments
+    }
+
+    /// Find the index of the segment containing the point `s` distance along the path
+    pub(crate) fn segment_index(&self, s: f64) -> usize {
+        match self
+            .segments
+            .binary_search_by(|seg| seg.start.total_cmp(&s))
+        {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        }
+    }
+
+    fn locate(&self, s: f64) -> (&PathSegment, f64) {
+        let seg = &self.segments[self.segment_index(s)];
+
+        (
+            &seg.segment,
+            (s - seg.start).max(0.0).min(seg.segment.length()),
+        )
+    }
+
+    /// Position at distance `s` along the path
+    pub fn position(&self, s: f64) -> Vector9 {
+        if self.segments.is_empty() {
+            return Vector9::zeros();
+        }
+
+        let (segment, local) = self.locate(s);
+
+        segment.position(local)
+    }
+
+    /// Unit tangent at distance `s` along the path
+    pub fn tangent(&self, s: f64) -> Vector9 {
+        if self.segments.is_empty() {
+            return Vector9::zeros();
+        }
+
+        let (segment, local) = self.locate(s);
+
+        segment.tangent(local)
+    }
+
+    /// Curvature vector (second derivative of position) at distance `s` along the path
+    pub fn curvature(&self, s: f64) -> Vector9 {
+        if self.segments.is_empty() {
+            return Vector9::zeros();
+        }
+
+        let (segment, local) = self.locate(s);
+
+        segment.curvature(local)
+    }
+
+    /// Derivative of the curvature vector (third derivative of position) at distance `s` along
+    /// the path
+    pub fn curvature_derivative(&self, s: f64) -> Vector9 {
+        if self.segments.is_empty() {
+            return Vector9::zeros();
+        }
+
+        let (segment, local) = self.locate(s);
+
+        segment.curvature_derivative(local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xy(x: f64, y: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = x;
+        v[1] = y;
+        v
+    }
+
+    #[test]
+    fn straight_line() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0), xy(3.0, 0.0)],
+            PathOptions { max_deviation: 0.1 },
+        );
+
+        assert_eq!(path.segments().len(), 2);
+        assert!((path.len() - 3.0).abs() < 1.0e-12);
+        assert!(path.segments().iter().all(|s| !s.stop));
+    }
+
+    #[test]
+    fn right_angle_blend() {
+        let max_deviation = 0.01;
+
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0), xy(1.0, 1.0)],
+            PathOptions { max_deviation },
+        );
+
+        assert_eq!(path.segments().len(), 3);
+
+        match &path.segments()[1].segment {
+            PathSegment::Circular(c) => {
+                // Deviation from the corner is the distance from the arc midpoint to the corner
+                let mid = path.segments()[1].segment.position(c.length() / 2.0);
+
+                assert!(((mid - xy(1.0, 0.0)).norm() - max_deviation).abs() < 1.0e-9);
+            }
+            _ => panic!("Expected a blend"),
+        }
+
+        // Tangent and position are continuous across every segment boundary
+        for pair in path.segments().windows(2) {
+            let a = &pair[0].segment;
+            let b = &pair[1].segment;
+
+            assert!((a.end() - b.start()).norm() < 1.0e-9);
+            assert!((a.tangent(a.length()) - b.tangent(0.0)).norm() < 1.0e-9);
+            assert!(!pair[1].stop);
+        }
+    }
+
+    #[test]
+    fn reversal_stops() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0), xy(0.0, 0.0)],
+            PathOptions { max_deviation: 0.1 },
+        );
+
+        assert_eq!(path.segments().len(), 2);
+        assert!(path.segments()[1].stop);
+    }
+
+    #[test]
+    fn no_deviation_stops_at_corners() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0), xy(1.0, 1.0)],
+            PathOptions { max_deviation: 0.0 },
+        );
+
+        assert_eq!(path.segments().len(), 2);
+        assert!(path.segments()[1].stop);
+    }
+
+    #[test]
+    fn duplicate_waypoints() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(0.0, 0.0), xy(1.0, 0.0), xy(1.0, 0.0)],
+            PathOptions { max_deviation: 0.1 },
+        );
+
+        assert_eq!(path.segments().len(), 1);
+        assert_eq!(path.segments()[0].waypoint, 3);
+    }
+
+    #[test]
+    fn corners() {
+        let blend = Corner::Blend {
+            deviation: 0.1,
+            merge: 0.0,
+        };
+
+        let waypoints = [
+            xy(0.0, 0.0),
+            xy(1.0, 0.0),
+            xy(2.0, 0.0),
+            xy(2.0, 1.0),
+            xy(2.0, 2.0),
+            xy(3.0, 2.0),
+        ];
+
+        let path = Path::with_corners(
+            &waypoints,
+            &[blend, Corner::Stop, blend, blend, Corner::Exact, blend],
+        );
+
+        let lines: Vec<(usize, bool)> = path
+            .segments()
+            .iter()
+            .map(|s| (s.waypoint, s.stop))
+            .collect();
+
+        // Stop in the middle of a straight line, blend the first corner and stop at the second
+        assert_eq!(
+            lines,
+            vec![
+                (1, false),
+                (2, true),
+                (2, false),
+                (3, false),
+                (4, false),
+                (5, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_almost_collinear() {
+        let corner = Corner::Blend {
+            deviation: 0.1,
+            merge: 0.01,
+        };
+
+        let path = Path::with_corners(
+            &[
+                xy(0.0, 0.0),
+                xy(1.0, 0.005),
+                xy(2.0, -0.005),
+                xy(3.0, 0.0),
+                xy(3.0, 1.0),
+            ],
+            &[corner; 5],
+        );
+
+        // The wobbly line is merged into one, then blended into the last move
+        assert_eq!(path.segments().len(), 3);
+        assert_eq!(path.segments()[0].waypoint, 3);
+        assert!((path.segments()[0].segment.start() - xy(0.0, 0.0)).norm() < 1.0e-12);
+    }
+
+    #[test]
+    fn speed_limits() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0), xy(1.0, 1.0)],
+            PathOptions { max_deviation: 0.1 },
+        )
+        .with_speed_limits(&[0.0, 2.0, 1.0]);
+
+        let limits: Vec<f64> = path.segments().iter().map(|s| s.speed_limit).collect();
+
+        assert_eq!(limits, vec![2.0, 1.0, 1.0]);
+    }
+}
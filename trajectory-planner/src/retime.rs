@@ -0,0 +1,535 @@
+//! Feed override and feed hold
+//!
+//! A planned trajectory is re-timed rather than replanned: the override factor sets how fast the
+//! trajectory's own clock runs, so the machine follows exactly the same path, only faster or
+//! slower. The factor can't change instantly while the machine is moving, as that would be a step
+//! in velocity, so it ramps towards each new setting as fast as the acceleration limits allow.
+
+use crate::trajectory::{Trajectory, TrajectoryOptions};
+use crate::Vector9;
+use std::fmt;
+
+/// A change made by the operator at a point in time, in seconds since the start of the re-timed
+/// trajectory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverrideChange {
+    /// Set the feed override to a factor from `0.0` to `1.0`
+    ///
+    /// The trajectory already runs as fast as the machine's limits allow, so factors above `1.0`
+    /// are treated as `1.0`. A change made during a feed hold takes effect on resume.
+    Feed {
+        /// Time of the change
+        time: f64,
+
+        /// The new override factor
+        factor: f64,
+    },
+
+    /// Bring the machine to a controlled stop along the path
+    Hold {
+        /// Time of the hold
+        time: f64,
+    },
+
+    /// Carry on after a feed hold at the current feed override
+    Resume {
+        /// Time of the resume
+        time: f64,
+    },
+}
+
+impl OverrideChange {
+    fn time(&self) -> f64 {
+        match self {
+            OverrideChange::Feed { time, .. }
+            | OverrideChange::Hold { time }
+            | OverrideChange::Resume { time } => *time,
+        }
+    }
+}
+
+/// An error in the changes given to re-time a trajectory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetimeError {
+    /// The change at the given index happens at a time that is NaN or infinite
+    InvalidTime(usize),
+}
+
+impl fmt::Display for RetimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetimeError::InvalidTime(idx) => {
+                write!(f, "override change {} is not at a finite time", idx)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RetimeError {}
+
+/// A point where the override factor starts changing at a new rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Knot {
+    /// Re-timed time
+    time: f64,
+    /// Time along the original trajectory
+    nominal: f64,
+    factor: f64,
+    /// Rate of change of the factor per second
+    rate: f64,
+}
+
+impl Knot {
+    /// Original trajectory time, factor and factor rate at `time`
+    fn state(&self, time: f64) -> (f64, f64, f64) {
+        let t = time - self.time;
+
+        (
+            self.nominal + self.factor * t + self.rate * t * t / 2.0,
+            self.factor + self.rate * t,
+            self.rate,
+        )
+    }
+}
+
+/// A trajectory re-timed by feed override changes and feed holds
+#[derive(Debug, Clone)]
+pub struct Retimed<'a> {
+    trajectory: &'a Trajectory,
+    knots: Vec<Knot>,
+    duration: f64,
+}
+
+impl<'a> Retimed<'a> {
+    /// Re-time `trajectory`, which was planned with `options`, for a sequence of operator changes
+    ///
+    /// The trajectory starts at full feed override. If it is held and never resumed, the re-timed
+    /// trajectory ends where the machine comes to a stop. Changes must be at finite times.
+    pub fn new(
+        trajectory: &'a Trajectory,
+        options: &TrajectoryOptions,
+        changes: &[OverrideChange],
+    ) -> Result<Self, RetimeError> {
+        if let Some(idx) = changes.iter().position(|c| !c.time().is_finite()) {
+            return Err(RetimeError::InvalidTime(idx));
+        }
+
+        let mut changes = changes.to_vec();
+        changes.sort_by(|a, b| a.time().total_cmp(&b.time()));
+
+        let end = trajectory.duration();
+
+        let mut knots = Vec::new();
+        let mut changes = changes.into_iter().peekable();
+
+        let (mut time, mut nominal, mut factor) = (0.0, 0.0, 1.0);
+        let (mut target, mut feed, mut held) = (1.0, 1.0, false);
+
+        while nominal < end {
+            while let Some(change) = changes.peek().filter(|c| c.time() <= time) {
+                match *change {
+                    OverrideChange::Feed { factor, .. } => feed = factor.clamp(0.0, 1.0),
+                    OverrideChange::Hold { .. } => held = true,
+                    OverrideChange::Resume { .. } => held = false,
+                }
+
+                target = if held { 0.0 } else { feed };
+
+                changes.next();
+            }
+
+            let next_change = changes.peek().map(|c| c.time()).unwrap_or(f64::INFINITY);
+
+            if factor == target {
+                knots.push(Knot {
+                    time,
+                    nominal,
+                    factor,
+                    rate: 0.0,
+                });
+
+                let finish = if factor > 0.0 {
+                    time + (end - nominal) / factor
+                } else {
+                    f64::INFINITY
+                };
+
+                if finish.is_finite() && finish <= next_change {
+                    time = finish;
+                    nominal = end;
+                } else if next_change.is_finite() {
+                    nominal += factor * (next_change - time);
+                    time = next_change;
+                } else {
+                    // Held with nothing left to resume it
+                    break;
+                }
+
+                continue;
+            }
+
+            let (slowest, fastest) = rate_range(trajectory, options, nominal, factor);
+
+            let rate = if target > factor {
+                fastest.max(0.0)
+            } else {
+                slowest.min(0.0)
+            };
+
+            // Nothing is moving, so the factor can change straight away
+            if rate.is_infinite() {
+                factor = target;
+
+                continue;
+            }
+
+            let mut step = options.timestep.min(next_change - time);
+
+            if rate != 0.0 {
+                step = step.min((target - factor) / rate);
+            }
+
+            let knot = Knot {
+                time,
+                nominal,
+                factor,
+                rate,
+            };
+
+            knots.push(knot);
+
+            let (next_nominal, next_factor, _) = knot.state(time + step);
+
+            if next_nominal >= end {
+                // Find when the end is reached part way through the step
+                let remaining = end - nominal;
+
+                step = if rate == 0.0 {
+                    remaining / factor
+                } else {
+                    (-factor + (factor * factor + 2.0 * rate * remaining).max(0.0).sqrt()) / rate
+                };
+
+                time += step;
+
+                break;
+            }
+
+            time += step;
+            nominal = next_nominal;
+
+            // Land exactly on the target to avoid creeping up on it forever
+            factor = if (next_factor - target).abs() < 1.0e-12 {
+                target
+            } else {
+                next_factor
+            };
+        }
+
+        Ok(Self {
+            trajectory,
+            knots,
+            duration: time,
+        })
+    }
+
+    /// Total time taken in seconds, including any time spent held
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// The trajectory being re-timed
+    pub fn trajectory(&self) -> &Trajectory {
+        self.trajectory
+    }
+
+    /// Time along the original trajectory, feed override factor and its rate of change at time
+    /// `t`
+    fn state(&self, t: f64) -> (f64, f64, f64) {
+        let t = t.clamp(0.0, self.duration);
+
+        let idx = match self.knots.binary_search_by(|k| k.time.total_cmp(&t)) {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        };
+
+        match self.knots.get(idx) {
+            Some(knot) => {
+                let (nominal, factor, rate) = knot.state(t);
+
+                (nominal.min(self.trajectory.duration()), factor, rate)
+            }
+            None => (0.0, 1.0, 0.0),
+        }
+    }
+
+    /// Time along the original trajectory reached at time `t`, for example to find the program
+    /// line being executed
+    pub fn nominal_time(&self, t: f64) -> f64 {
+        self.state(t).0
+    }
+
+    /// Feed override factor in effect at time `t`
+    pub fn factor(&self, t: f64) -> f64 {
+        self.state(t).1
+    }
+
+    /// Position of all axes at time `t`
+    pub fn position(&self, t: f64) -> Vector9 {
+        self.trajectory.position(self.nominal_time(t))
+    }
+
+    /// Velocity of all axes at time `t`
+    pub fn velocity(&self, t: f64) -> Vector9 {
+        let (nominal, factor, _) = self.state(t);
+
+        self.trajectory.velocity(nominal) * factor
+    }
+
+    /// Acceleration of all axes at time `t`
+    pub fn acceleration(&self, t: f64) -> Vector9 {
+        let (nominal, factor, rate) = self.state(t);
+
+        self.trajectory.acceleration(nominal) * factor * factor
+            + self.trajectory.velocity(nominal) * rate
+    }
+}
+
+/// The slowest and fastest rates the override factor can change at without any axis exceeding
+/// its acceleration limit
+///
+/// Axis acceleration is `a * factor^2 + v * rate` where `v` and `a` come from the original
+/// trajectory, so each moving axis bounds the rate. Holding the factor steady is always allowed.
+fn rate_range(
+    trajectory: &Trajectory,
+    options: &TrajectoryOptions,
+    nominal: f64,
+    factor: f64,
+) -> (f64, f64) {
+    let velocity = trajectory.velocity(nominal);
+    let acceleration = trajectory.acceleration(nominal);
+
+    let mut slowest = f64::NEG_INFINITY;
+    let mut fastest = f64::INFINITY;
+
+    for i in 0..9 {
+        if velocity[i].abs() <= options.epsilon {
+            continue;
+        }
+
+        let limit = options.acceleration_limit[i];
+        let used = acceleration[i] * factor * factor;
+
+        let a = (-limit - used) / velocity[i];
+        let b = (limit - used) / velocity[i];
+
+        slowest = slowest.max(a.min(b));
+        fastest = fastest.min(a.max(b));
+    }
+
+    (slowest.min(0.0), fastest.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::{Path, PathOptions};
+
+    fn x(value: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = value;
+        v
+    }
+
+    fn options() -> TrajectoryOptions {
+        TrajectoryOptions {
+            velocity_limit: Vector9::repeat(1.0),
+            acceleration_limit: Vector9::repeat(1.0),
+            jerk_limit: None,
+            epsilon: 0.000001,
+            timestep: 0.001,
+        }
+    }
+
+    fn trapezoid() -> Trajectory {
+        let path = Path::from_waypoints(
+            &[x(0.0), x(10.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        Trajectory::new(&path, options()).unwrap()
+    }
+
+    /// Sample every millisecond, checking the acceleration limit and returning the times the
+    /// machine is stopped at
+    fn assert_within_limits(retimed: &Retimed) -> Vec<f64> {
+        let steps = (retimed.duration() / 0.001).ceil() as usize;
+
+        let mut stopped = Vec::new();
+
+        for step in 1..steps {
+            let t = step as f64 * 0.001;
+
+            let acceleration = retimed.acceleration(t);
+
+            // The original trajectory overshoots its limits very slightly in blends
+            for i in 0..9 {
+                assert!(
+                    acceleration[i].abs() <= 1.0 + 1.0e-3,
+                    "axis {} acceleration {} at t = {}",
+                    i,
+                    acceleration[i],
+                    t
+                );
+            }
+
+            if retimed.velocity(t).norm() < 1.0e-9 {
+                stopped.push(t);
+            }
+        }
+
+        stopped
+    }
+
+    #[test]
+    fn unchanged() {
+        let trajectory = trapezoid();
+        let retimed = Retimed::new(&trajectory, &options(), &[]).unwrap();
+
+        assert!((retimed.duration() - 11.0).abs() < 1.0e-9);
+        assert!((retimed.position(5.5) - x(5.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn half_speed_from_the_start() {
+        let trajectory = trapezoid();
+        let retimed = Retimed::new(
+            &trajectory,
+            &options(),
+            &[OverrideChange::Feed {
+                time: 0.0,
+                factor: 0.5,
+            }],
+        )
+        .unwrap();
+
+        // The machine is at rest when the change is made, so it takes effect straight away
+        assert!((retimed.duration() - 22.0).abs() < 1.0e-9);
+        assert!((retimed.velocity(11.0) - x(0.5)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn override_ramps_while_moving() {
+        let trajectory = trapezoid();
+        let retimed = Retimed::new(
+            &trajectory,
+            &options(),
+            &[OverrideChange::Feed {
+                time: 3.0,
+                factor: 0.5,
+            }],
+        )
+        .unwrap();
+
+        assert_within_limits(&retimed);
+
+        // Slowing from 1 to 0.5 units/s at 1 unit/s^2 takes half a second
+        assert!((retimed.factor(3.5) - 0.5).abs() < 1.0e-6);
+        assert!((retimed.position(retimed.duration()) - x(10.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn hold_and_resume() {
+        let trajectory = trapezoid();
+        let retimed = Retimed::new(
+            &trajectory,
+            &options(),
+            &[
+                OverrideChange::Hold { time: 4.0 },
+                OverrideChange::Resume { time: 8.0 },
+            ],
+        )
+        .unwrap();
+
+        let stopped = assert_within_limits(&retimed);
+
+        // A full speed stop takes one second and covers half a unit, then an extra second and
+        // half unit getting back up to speed again
+        assert!((stopped[0] - 5.0).abs() < 0.01);
+        assert!((retimed.position(6.0) - x(4.0)).norm() < 1.0e-6);
+        assert!((retimed.duration() - (11.0 + 3.0 + 1.0)).abs() < 0.01);
+        assert!((retimed.position(retimed.duration()) - x(10.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn hold_in_corner() {
+        let mut corner = x(1.0);
+        corner[1] = 0.0;
+
+        let mut end = x(1.0);
+        end[1] = 1.0;
+
+        let path = Path::from_waypoints(&[x(0.0), corner, end], PathOptions { max_deviation: 0.1 });
+        let trajectory = Trajectory::new(&path, options()).unwrap();
+
+        // Hold part way round the blend, where both axes are moving
+        let hold = (0..)
+            .map(|step| step as f64 * 0.01)
+            .find(|t| trajectory.velocity(*t)[1] > 0.1)
+            .unwrap();
+
+        let retimed = Retimed::new(
+            &trajectory,
+            &options(),
+            &[
+                OverrideChange::Hold { time: hold },
+                OverrideChange::Resume { time: hold + 2.0 },
+            ],
+        )
+        .unwrap();
+
+        assert!(!assert_within_limits(&retimed).is_empty());
+        assert!((retimed.position(retimed.duration()) - end).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn hold_without_resume() {
+        let trajectory = trapezoid();
+        let retimed = Retimed::new(
+            &trajectory,
+            &options(),
+            &[OverrideChange::Hold { time: 4.0 }],
+        )
+        .unwrap();
+
+        assert!((retimed.duration() - 5.0).abs() < 0.01);
+        assert!((retimed.position(retimed.duration()) - x(4.0)).norm() < 1.0e-6);
+        assert!(retimed.velocity(retimed.duration()).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn not_finite() {
+        let trajectory = trapezoid();
+
+        assert_eq!(
+            Retimed::new(
+                &trajectory,
+                &options(),
+                &[
+                    OverrideChange::Hold { time: 4.0 },
+                    OverrideChange::Resume { time: f64::NAN },
+                ],
+            )
+            .unwrap_err(),
+            RetimeError::InvalidTime(1)
+        );
+
+        let retimed = Retimed::new(&trajectory, &options(), &[]).unwrap();
+
+        // A time that isn't a number has no position, but mustn't panic
+        retimed.position(f64::NAN);
+        retimed.velocity(f64::NAN);
+    }
+}
@@ -0,0 +1,247 @@
+//! Sample a trajectory at a fixed period to produce timed setpoints
+
+use crate::trajectory::Trajectory;
+use crate::Vector9;
+use std::io::{self, Write};
+
+/// The state of all axes at a single point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setpoint {
+    /// Time since the start of the trajectory in seconds
+    pub time: f64,
+
+    /// Position of all axes
+    pub position: Vector9,
+
+    /// Velocity of all axes
+    pub velocity: Vector9,
+
+    /// Acceleration of all axes
+    pub acceleration: Vector9,
+
+    /// The program line that commanded the move being executed, if source lines were provided
+    pub source_line: Option<usize>,
+}
+
+/// An iterator over setpoints spaced at a fixed period
+///
+/// Created by [`Trajectory::sample`](../struct.Trajectory.html#method.sample). The final setpoint
+/// is at or just past the end of the trajectory, where the machine is stationary.
+#[derive(Debug, Clone)]
+pub struct Samples<'a> {
+    trajectory: &'a Trajectory,
+    source_lines: Option<&'a [usize]>,
+    period: f64,
+    step: usize,
+    steps: usize,
+}
+
+impl<'a> Samples<'a> {
+    pub(crate) fn new(trajectory: &'a Trajectory, period: f64) -> Self {
+        let steps = if period > 0.0 {
+            (trajectory.duration() / period).ceil() as usize
+        } else {
+            0
+        };
+
+        Self {
+            trajectory,
+            source_lines: None,
+            period,
+            step: 0,
+            steps,
+        }
+    }
+
+    /// Attach the source line of each waypoint the trajectory was planned through
+    ///
+    /// `source_lines` must be indexed the same as the waypoints given to
+    /// [`Path::from_waypoints`](../struct.Path.html#method.from_waypoints).
+    pub fn with_source_lines(self, source_lines: &'a [usize]) -> Self {
+        Self {
+            source_lines: Some(source_lines),
+            ..self
+        }
+    }
+
+    /// Write all remaining setpoints as CSV with a header row
+    ///
+    /// Columns are time, then position, velocity and acceleration of each of the `XYZUVWABC` axes,
+    /// then the source line (empty if unknown).
+    pub fn write_csv<W: Write>(self, mut writer: W) -> io::Result<()> {
+        const AXES: &str = "xyzuvwabc";
+
+        write!(writer, "t")?;
+
+        for prefix in ["", "v", "a"].iter() {
+            for axis in AXES.chars() {
+                write!(writer, ",{}{}", prefix, axis)?;
+            }
+        }
+
+        writeln!(writer, ",line")?;
+
+        for setpoint in self {
+            write!(writer, "{}", setpoint.time)?;
+
+            for vector in [
+                &setpoint.position,
+                &setpoint.velocity,
+                &setpoint.acceleration,
+            ]
+            .iter()
+            {
+                for value in vector.iter() {
+                    write!(writer, ",{}", value)?;
+                }
+            }
+
+            match setpoint.source_line {
+                Some(line) => writeln!(writer, ",{}", line)?,
+                None => writeln!(writer, ",")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Samples<'a> {
+    type Item = Setpoint;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.step > self.steps {
+            return None;
+        }
+
+        let time = self.step as f64 * self.period;
+
+        self.step += 1;
+
+        let trajectory = self.trajectory;
+        let path = trajectory.path();
+        let (s, s_dot, s_ddot) = trajectory.path_state(time);
+
+        let source_line = self.source_lines.and_then(|lines| {
+            if path.is_empty() {
+                return None;
+            }
+
+            lines
+                .get(path.segments()[path.segment_index(s)].waypoint)
+                .cloned()
+        });
+
+        let tangent = path.tangent(s);
+
+        Some(Setpoint {
+            time,
+            position: path.position(s),
+            velocity: tangent * s_dot,
+            acceleration: tangent * s_ddot + path.curvature(s) * s_dot * s_dot,
+            source_line,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.steps + 1).saturating_sub(self.step);
+
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Samples<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::{Path, PathOptions};
+    use crate::trajectory::TrajectoryOptions;
+
+    fn x(x: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = x;
+        v
+    }
+
+    fn trajectory(waypoints: &[Vector9]) -> Trajectory {
+        let path = Path::from_waypoints(
+            waypoints,
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        Trajectory::new(
+            &path,
+            TrajectoryOptions {
+                velocity_limit: Vector9::repeat(1.0),
+                acceleration_limit: Vector9::repeat(1.0),
+                jerk_limit: None,
+                epsilon: 0.000001,
+                timestep: 0.001,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fixed_period() {
+        let trajectory = trajectory(&[x(0.0), x(10.0)]);
+
+        let samples: Vec<Setpoint> = trajectory.sample(0.001).collect();
+
+        // 11s at 1kHz, including both ends
+        assert_eq!(samples.len(), 11001);
+        assert_eq!(samples[0].time, 0.0);
+        assert!((samples[5500].position - x(5.0)).norm() < 1.0e-9);
+        assert!((samples[5500].velocity - x(1.0)).norm() < 1.0e-9);
+
+        let last = samples.last().unwrap();
+
+        assert!((last.position - x(10.0)).norm() < 1.0e-9);
+        assert!(last.velocity.norm() < 1.0e-9);
+        assert_eq!(last.acceleration, Vector9::zeros());
+        assert_eq!(last.source_line, None);
+    }
+
+    #[test]
+    fn source_lines() {
+        let trajectory = trajectory(&[x(0.0), x(1.0), x(0.0)]);
+
+        let lines: Vec<Option<usize>> = trajectory
+            .sample(0.1)
+            .with_source_lines(&[3, 4, 7])
+            .map(|setpoint| setpoint.source_line)
+            .collect();
+
+        assert_eq!(lines.first(), Some(&Some(4)));
+        assert_eq!(lines.last(), Some(&Some(7)));
+    }
+
+    #[test]
+    fn csv() {
+        let trajectory = trajectory(&[x(0.0), x(1.0)]);
+
+        let mut out = Vec::new();
+
+        trajectory
+            .sample(1.0)
+            .with_source_lines(&[1, 2])
+            .write_csv(&mut out)
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let mut rows = out.lines();
+
+        assert_eq!(
+            rows.next(),
+            Some("t,x,y,z,u,v,w,a,b,c,vx,vy,vz,vu,vv,vw,va,vb,vc,ax,ay,az,au,av,aw,aa,ab,ac,line")
+        );
+        assert_eq!(
+            rows.next(),
+            Some("0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,2")
+        );
+        assert_eq!(rows.count(), 2);
+    }
+}
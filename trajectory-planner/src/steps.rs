@@ -0,0 +1,371 @@
+//! Step and direction pulse generation
+//!
+//! Stepper motors move in whole steps, so sampled joint positions are turned into a timeline of
+//! individual step events for each axis. A joint takes a step whenever its position crosses the
+//! midpoint between two steps, which keeps the motor within half a step of the commanded
+//! position at every sample.
+
+use crate::Vector9;
+use std::fmt;
+
+/// How steps are spread out between two samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMethod {
+    /// Time each step by where the joint crosses the step boundary, interpolating linearly between
+    /// samples
+    Timed,
+
+    /// Split each sample period into equal ticks, one for each step of the fastest axis, and
+    /// distribute the other axes' steps over them with Bresenham's line algorithm like GRBL
+    Bresenham,
+}
+
+/// Step generation options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepConfig {
+    /// Steps per unit of travel for each joint
+    pub steps_per_unit: Vector9,
+
+    /// Maximum step rate for each joint in steps per second
+    pub max_step_rate: Vector9,
+
+    /// How steps are spread out between samples
+    pub method: StepMethod,
+}
+
+/// A single step of one axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepEvent {
+    /// Time of the step in seconds
+    pub time: f64,
+
+    /// Index of the axis to step, `0` to `8` for `XYZUVWABC`
+    pub axis: usize,
+
+    /// Whether the step is in the positive direction
+    pub forward: bool,
+}
+
+/// An error encountered whilst generating steps
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepError {
+    /// An axis would need to step faster than its maximum step rate
+    StepRate {
+        /// Index of the axis
+        axis: usize,
+
+        /// Time of the end of the sample period that needs the rate
+        time: f64,
+
+        /// The step rate needed in steps per second
+        rate: f64,
+    },
+
+    /// A sample is earlier than the one before it
+    TimeReversed(f64),
+
+    /// A sample's time or joint positions are NaN or infinite, given as the sample time
+    NotFinite(f64),
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepError::StepRate { axis, time, rate } => write!(
+                f,
+                "axis {} needs {} steps per second at t = {}",
+                axis, rate, time
+            ),
+            StepError::TimeReversed(time) => {
+                write!(f, "sample at t = {} is before the previous sample", time)
+            }
+            StepError::NotFinite(time) => {
+                write!(f, "sample at t = {} is not a finite position", time)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StepError {}
+
+/// Generates step events from a sequence of sampled joint positions
+#[derive(Debug, Clone)]
+pub struct StepGenerator {
+    config: StepConfig,
+
+    /// Time and joint positions of the last sample, in steps
+    last: Option<(f64, Vector9)>,
+
+    /// Position of each axis in whole steps
+    steps: [i64; 9],
+}
+
+impl StepGenerator {
+    /// Create a new step generator
+    pub fn new(config: StepConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            steps: [0; 9],
+        }
+    }
+
+    /// Position of each axis in whole steps
+    ///
+    /// This is the nearest step to the first sample until the generator has been advanced.
+    pub fn steps(&self) -> [i64; 9] {
+        self.steps
+    }
+
+    /// Move to the joint positions at time `time`, appending the steps needed to get there to
+    /// `events` in time order
+    ///
+    /// The first call sets the starting position without generating any steps.
+    pub fn advance(
+        &mut self,
+        time: f64,
+        joints: &Vector9,
+        events: &mut Vec<StepEvent>,
+    ) -> Result<(), StepError> {
+        let target = joints.component_mul(&self.config.steps_per_unit);
+
+        if !time.is_finite() || target.iter().any(|position| !position.is_finite()) {
+            return Err(StepError::NotFinite(time));
+        }
+
+        let (start_time, start) = match self.last {
+            Some(last) => last,
+            None => {
+                for (steps, position) in self.steps.iter_mut().zip(target.iter()) {
+                    *steps = position.round() as i64;
+                }
+
+                self.last = Some((time, target));
+
+                return Ok(());
+            }
+        };
+
+        let period = time - start_time;
+
+        if period < 0.0 {
+            return Err(StepError::TimeReversed(time));
+        }
+
+        for axis in 0..9 {
+            let distance = (target[axis] - start[axis]).abs();
+
+            if distance > 0.0 && distance > self.config.max_step_rate[axis] * period {
+                return Err(StepError::StepRate {
+                    axis,
+                    time,
+                    rate: distance / period,
+                });
+            }
+        }
+
+        match self.config.method {
+            StepMethod::Timed => self.timed(start_time, &start, time, &target, events),
+            StepMethod::Bresenham => self.bresenham(start_time, time, &target, events),
+        }
+
+        self.last = Some((time, target));
+
+        Ok(())
+    }
+
+    fn timed(
+        &mut self,
+        start_time: f64,
+        start: &Vector9,
+        end_time: f64,
+        end: &Vector9,
+        events: &mut Vec<StepEvent>,
+    ) {
+        let first = events.len();
+
+        for axis in 0..9 {
+            let target = end[axis].round() as i64;
+            let delta = end[axis] - start[axis];
+
+            while self.steps[axis] != target {
+                let forward = target > self.steps[axis];
+
+                // The midpoint between the current step and the next one
+                let boundary = self.steps[axis] as f64 + if forward { 0.5 } else { -0.5 };
+
+                let fraction = if delta.abs() > 0.0 {
+                    ((boundary - start[axis]) / delta).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                };
+
+                events.push(StepEvent {
+                    time: start_time + (end_time - start_time) * fraction,
+                    axis,
+                    forward,
+                });
+
+                self.steps[axis] += if forward { 1 } else { -1 };
+            }
+        }
+
+        events[first..].sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    fn bresenham(
+        &mut self,
+        start_time: f64,
+        end_time: f64,
+        end: &Vector9,
+        events: &mut Vec<StepEvent>,
+    ) {
+        let mut deltas = [0i64; 9];
+
+        for axis in 0..9 {
+            deltas[axis] = end[axis].round() as i64 - self.steps[axis];
+        }
+
+        let ticks = deltas.iter().map(|d| d.abs()).max().unwrap_or(0);
+
+        if ticks == 0 {
+            return;
+        }
+
+        let mut counters = [-(ticks / 2); 9];
+
+        for tick in 1..=ticks {
+            let time = start_time + (end_time - start_time) * tick as f64 / ticks as f64;
+
+            for axis in 0..9 {
+                counters[axis] += deltas[axis].abs();
+
+                if counters[axis] > 0 {
+                    counters[axis] -= ticks;
+
+                    let forward = deltas[axis] > 0;
+
+                    events.push(StepEvent {
+                        time,
+                        axis,
+                        forward,
+                    });
+
+                    self.steps[axis] += if forward { 1 } else { -1 };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(method: StepMethod) -> StepConfig {
+        StepConfig {
+            steps_per_unit: Vector9::repeat(10.0),
+            max_step_rate: Vector9::repeat(1000.0),
+            method,
+        }
+    }
+
+    fn x(value: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = value;
+        v
+    }
+
+    fn run(method: StepMethod, samples: &[(f64, Vector9)]) -> (StepGenerator, Vec<StepEvent>) {
+        let mut generator = StepGenerator::new(config(method));
+        let mut events = Vec::new();
+
+        for (time, joints) in samples {
+            generator.advance(*time, joints, &mut events).unwrap();
+        }
+
+        (generator, events)
+    }
+
+    #[test]
+    fn timed_steps_at_midpoints() {
+        let (generator, events) = run(StepMethod::Timed, &[(0.0, x(0.0)), (1.0, x(0.4))]);
+
+        let times: Vec<f64> = events.iter().map(|e| e.time).collect();
+
+        // Boundaries at 0.5, 1.5, 2.5 and 3.5 steps along a 4 step move
+        assert_eq!(times, vec![0.125, 0.375, 0.625, 0.875]);
+        assert!(events.iter().all(|e| e.axis == 0 && e.forward));
+        assert_eq!(generator.steps()[0], 4);
+    }
+
+    #[test]
+    fn reverse_direction() {
+        for method in [StepMethod::Timed, StepMethod::Bresenham].iter() {
+            let (generator, events) = run(*method, &[(0.0, x(0.0)), (1.0, x(0.3)), (2.0, x(-0.2))]);
+
+            let forward = events.iter().filter(|e| e.forward).count();
+            let backward = events.iter().filter(|e| !e.forward).count();
+
+            assert_eq!((forward, backward), (3, 5), "{:?}", method);
+            assert_eq!(generator.steps()[0], -2);
+        }
+    }
+
+    #[test]
+    fn bresenham_spreads_steps() {
+        let mut end = x(1.0);
+        end[1] = 0.5;
+
+        let (_, events) = run(
+            StepMethod::Bresenham,
+            &[(0.0, Vector9::zeros()), (1.0, end)],
+        );
+
+        let x_steps = events.iter().filter(|e| e.axis == 0).count();
+        let y_times: Vec<f64> = events
+            .iter()
+            .filter(|e| e.axis == 1)
+            .map(|e| e.time)
+            .collect();
+
+        assert_eq!(x_steps, 10);
+        assert_eq!(y_times, vec![0.2, 0.4, 0.6, 0.8, 1.0]);
+    }
+
+    #[test]
+    fn step_rate_limit() {
+        let mut generator = StepGenerator::new(config(StepMethod::Timed));
+        let mut events = Vec::new();
+
+        generator.advance(0.0, &x(0.0), &mut events).unwrap();
+
+        assert_eq!(
+            generator.advance(0.1, &x(20.0), &mut events),
+            Err(StepError::StepRate {
+                axis: 0,
+                time: 0.1,
+                rate: 2000.0
+            })
+        );
+    }
+
+    #[test]
+    fn not_finite() {
+        let mut generator = StepGenerator::new(config(StepMethod::Timed));
+        let mut events = Vec::new();
+
+        assert_eq!(
+            generator.advance(0.0, &x(f64::NAN), &mut events),
+            Err(StepError::NotFinite(0.0))
+        );
+
+        generator.advance(0.0, &x(0.0), &mut events).unwrap();
+
+        assert_eq!(
+            generator.advance(0.1, &x(f64::INFINITY), &mut events),
+            Err(StepError::NotFinite(0.1))
+        );
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,563 @@
+//! Incremental planning with a bounded look-ahead
+//!
+//! Planning a whole program at once needs every waypoint in memory and doesn't produce anything
+//! until the last one has been processed. The streaming planner instead consumes canonical
+//! commands one at a time and plans a window of upcoming segments, committing to the first part
+//! of the window and keeping the rest as look-ahead. The window always ends at rest, so every
+//! committed chunk is guaranteed to be able to stop before the look-ahead runs out.
+//!
+//! Each committed chunk starts at the speed the previous one ended at, so the chunks join without
+//! steps in position or velocity. Spindle synchronised moves aren't planned: the machine comes to
+//! rest, follows the spindle in a chunk of its own, then carries on from rest.
+
+use crate::kinematics::{Kinematics, Trivial};
+use crate::machine::MachineConfig;
+use crate::path::{Corner, Path, PathBuilder, PathOptions, PlacedSegment};
+use crate::synced::{SyncError, SyncedMove};
+use crate::trajectory::{PlanError, Trajectory, TrajectoryOptions};
+use crate::Vector9;
+use gcode_interpreter::{Canonical, CanonicalKind, State};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Streaming planner options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamOptions {
+    /// Path construction options
+    pub path: PathOptions,
+
+    /// Machine limits
+    pub trajectory: TrajectoryOptions,
+
+    /// The number of path segments to keep planned ahead of the committed trajectory
+    ///
+    /// Each window plans twice this many segments and commits to the first half.
+    pub lookahead: usize,
+
+    /// The maximum distance the line segments used to approximate an arc or spline may deviate
+    /// from it
+    pub arc_tolerance: f64,
+
+    /// How fast the spindle reverses when rigid tapping, in revolutions per second squared
+    pub spindle_acceleration: f64,
+}
+
+/// How the machine moves through a chunk
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkMotion {
+    /// A trajectory planned within the machine's limits
+    Planned(Trajectory),
+
+    /// A move locked to the spindle's rotation
+    Synced(SyncedMove),
+}
+
+/// A committed section of a streamed trajectory
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryChunk {
+    /// Time at the start of this chunk in seconds, measured from the start of the program
+    /// including any dwells
+    pub start_time: f64,
+
+    /// The motion through this chunk. Time `0` is the start of the chunk.
+    pub motion: ChunkMotion,
+}
+
+impl TrajectoryChunk {
+    /// Duration of this chunk in seconds
+    pub fn duration(&self) -> f64 {
+        match &self.motion {
+            ChunkMotion::Planned(trajectory) => trajectory.duration(),
+            ChunkMotion::Synced(synced) => synced.duration(),
+        }
+    }
+
+    /// Position at time `t` from the start of this chunk
+    pub fn position(&self, t: f64) -> Vector9 {
+        match &self.motion {
+            ChunkMotion::Planned(trajectory) => trajectory.position(t),
+            ChunkMotion::Synced(synced) => synced.position(t),
+        }
+    }
+
+    /// Velocity at time `t` from the start of this chunk
+    pub fn velocity(&self, t: f64) -> Vector9 {
+        match &self.motion {
+            ChunkMotion::Planned(trajectory) => trajectory.velocity(t),
+            ChunkMotion::Synced(synced) => synced.velocity(t),
+        }
+    }
+
+    /// Acceleration at time `t` from the start of this chunk
+    pub fn acceleration(&self, t: f64) -> Vector9 {
+        match &self.motion {
+            ChunkMotion::Planned(trajectory) => trajectory.acceleration(t),
+            ChunkMotion::Synced(synced) => synced.acceleration(t),
+        }
+    }
+}
+
+/// An error encountered whilst streaming a trajectory
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamError {
+    /// A trajectory could not be planned through the buffered moves
+    Plan(PlanError),
+
+    /// A spindle synchronised move could not be followed
+    Sync(SyncError),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamError::Plan(e) => write!(f, "planning error: {}", e),
+            StreamError::Sync(e) => write!(f, "spindle synchronisation error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<PlanError> for StreamError {
+    fn from(e: PlanError) -> Self {
+        StreamError::Plan(e)
+    }
+}
+
+impl From<SyncError> for StreamError {
+    fn from(e: SyncError) -> Self {
+        StreamError::Sync(e)
+    }
+}
+
+/// Plans trajectory chunks from a stream of canonical machine commands
+///
+/// Anything that needs the machine to be at rest, like dwells, tool changes and spindle changes,
+/// flushes the planner and ends the current chunk at rest. Corners are blended according to the
+/// active path control mode, using the path options' deviation if the mode doesn't give one.
+#[derive(Debug)]
+pub struct StreamingPlanner<I> {
+    commands: I,
+    options: StreamOptions,
+    kinematics: Box<dyn Kinematics>,
+    builder: PathBuilder,
+
+    /// Segments that have been built but not committed yet
+    segments: Vec<PlacedSegment>,
+
+    /// How the path passes through the waypoints of the moves being read
+    corner: Corner,
+
+    /// Speed limit of the move ending at each waypoint, starting at waypoint index `base`
+    limits: VecDeque<f64>,
+    base: usize,
+
+    /// Tangent at the end of the last committed segment
+    tangent: Option<Vector9>,
+
+    /// Path speed at the end of the last committed chunk
+    speed: f64,
+
+    /// Start time of the next chunk
+    time: f64,
+
+    /// A chunk that's ready to be returned after the one being returned now
+    pending: Option<Result<TrajectoryChunk, StreamError>>,
+
+    done: bool,
+}
+
+impl<I> StreamingPlanner<I>
+where
+    I: Iterator<Item = Canonical>,
+{
+    /// Create a new planner reading commands from an iterator
+    pub fn new(commands: I, options: StreamOptions) -> Self {
+        Self {
+            commands,
+            options,
+            kinematics: Box::new(Trivial),
+            builder: PathBuilder::new(),
+            segments: Vec::new(),
+            corner: Corner::from_path_mode(State::default().path, options.path.max_deviation),
+            limits: VecDeque::new(),
+            base: 0,
+            tangent: None,
+            speed: 0.0,
+            time: 0.0,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Apply the trajectory limits to the joints of a machine with the given kinematics
+    pub fn with_kinematics(mut self, kinematics: impl Kinematics + 'static) -> Self {
+        self.kinematics = Box::new(kinematics);
+
+        self
+    }
+
+    /// Add a waypoint reached by a move with the given speed limit
+    fn push(&mut self, point: Vector9, speed_limit: f64) {
+        let idx = self.base + self.limits.len();
+
+        self.limits.push_back(speed_limit);
+        self.builder
+            .push(idx, point, self.corner, &mut self.segments);
+    }
+
+    /// Add a straight move, starting a new path at `from` if the last one was flushed
+    fn push_move(&mut self, from: Vector9, to: Vector9, speed_limit: f64) {
+        if self.builder.is_empty() {
+            self.push(from, f64::INFINITY);
+        }
+
+        self.push(to, speed_limit);
+    }
+
+    /// Plan a trajectory through some segments, carrying on from the previous chunk
+    fn commit(&mut self, path: Path, end_speed: f64) -> Result<TrajectoryChunk, PlanError> {
+        let trajectory = Trajectory::with_boundary_speeds(
+            &path,
+            self.options.trajectory,
+            self.kinematics.as_ref(),
+            self.speed,
+            end_speed,
+        )?;
+
+        if let Some(last) = path.segments().last() {
+            self.tangent = Some(last.segment.tangent(last.segment.length()));
+        }
+
+        self.speed = trajectory.speed_at_segment(path.segments().len());
+
+        let start_time = self.time;
+
+        self.time += trajectory.duration();
+
+        Ok(TrajectoryChunk {
+            start_time,
+            motion: ChunkMotion::Planned(trajectory),
+        })
+    }
+
+    /// Follow the spindle through a synchronised move, starting and ending at rest
+    fn synchronise(
+        &mut self,
+        kind: &CanonicalKind,
+    ) -> Option<Result<TrajectoryChunk, StreamError>> {
+        let synced = SyncedMove::from_canonical(kind, self.options.spindle_acceleration)?;
+
+        let machine = MachineConfig {
+            velocity_limit: self.options.trajectory.velocity_limit,
+            acceleration_limit: self.options.trajectory.acceleration_limit,
+            jerk_limit: self.options.trajectory.jerk_limit,
+        };
+
+        let synced = match synced.and_then(|synced| synced.check_limits(&machine).map(|_| synced)) {
+            Ok(synced) => synced,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        let start_time = self.time;
+
+        self.time += synced.duration();
+
+        Some(Ok(TrajectoryChunk {
+            start_time,
+            motion: ChunkMotion::Synced(synced),
+        }))
+    }
+
+    fn path(&self, segments: Vec<PlacedSegment>) -> Path {
+        let (limits, base) = (&self.limits, self.base);
+
+        let mut path = Path::from_segments(segments, self.tangent);
+
+        path.set_speed_limits(|idx| limits[idx - base]);
+
+        path
+    }
+
+    /// Plan the whole buffer to rest and commit to everything except the look-ahead
+    fn advance(&mut self) -> Result<TrajectoryChunk, PlanError> {
+        let window = self.path(self.segments.clone());
+
+        let planned = Trajectory::with_boundary_speeds(
+            &window,
+            self.options.trajectory,
+            self.kinematics.as_ref(),
+            self.speed,
+            0.0,
+        )?;
+
+        let commit = self.segments.len() - self.options.lookahead.max(1);
+        let end_speed = planned.speed_at_segment(commit);
+
+        let committed = self.segments.drain(..commit).collect();
+        let committed = self.path(committed);
+
+        // Waypoints before the first uncommitted segment won't be referenced again
+        if let Some(first) = self.segments.first() {
+            let count = first.waypoint - self.base;
+
+            self.limits.drain(..count);
+            self.base = first.waypoint;
+        }
+
+        self.commit(committed, end_speed)
+    }
+
+    /// Plan everything remaining in the buffer to rest
+    fn flush(&mut self) -> Option<Result<TrajectoryChunk, PlanError>> {
+        self.builder.finish(&mut self.segments);
+
+        let path = if self.segments.is_empty() {
+            None
+        } else {
+            let segments = std::mem::take(&mut self.segments);
+
+            Some(self.path(segments))
+        };
+
+        self.base += self.limits.len();
+        self.limits.clear();
+
+        path.map(|path| self.commit(path, 0.0))
+    }
+}
+
+impl<I> Iterator for StreamingPlanner<I>
+where
+    I: Iterator<Item = Canonical>,
+{
+    type Item = Result<TrajectoryChunk, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(chunk) = self.pending.take() {
+            return Some(chunk);
+        }
+
+        if self.done {
+            return None;
+        }
+
+        while self.segments.len() < 2 * self.options.lookahead.max(1) {
+            let Canonical { kind, .. } = match self.commands.next() {
+                Some(command) => command,
+                None => {
+                    self.done = true;
+
+                    return self.flush().map(|chunk| chunk.map_err(StreamError::from));
+                }
+            };
+
+            let speed = kind.path_speed().unwrap_or(f64::INFINITY);
+
+            match kind {
+                CanonicalKind::Rapid { from, to } => self.push_move(from, to, f64::INFINITY),
+                CanonicalKind::Linear { from, to, .. } => self.push_move(from, to, speed),
+                CanonicalKind::Arc { arc, .. } => {
+                    let mut from = arc.from;
+
+                    for to in arc.points(self.options.arc_tolerance) {
+                        self.push_move(from, to, speed);
+
+                        from = to;
+                    }
+                }
+                CanonicalKind::Spline { spline, .. } => {
+                    let mut from = *spline.from();
+
+                    for to in spline.linearise(self.options.arc_tolerance) {
+                        self.push_move(from, to, speed);
+
+                        from = to;
+                    }
+                }
+                CanonicalKind::PathMode { mode } => {
+                    self.corner = Corner::from_path_mode(mode, self.options.path.max_deviation);
+                }
+                // Coolant switches on and off without stopping the machine
+                CanonicalKind::Coolant { .. } => (),
+                other => {
+                    let chunk = self.flush().map(|chunk| chunk.map_err(StreamError::from));
+
+                    if let CanonicalKind::Dwell { seconds } = other {
+                        self.time += seconds;
+                    }
+
+                    let synced = self.synchronise(&other);
+
+                    if let Some(Err(_)) = synced {
+                        self.done = true;
+                    }
+
+                    match (chunk, synced) {
+                        (Some(chunk), synced) => {
+                            self.pending = synced;
+
+                            return Some(chunk);
+                        }
+                        (None, Some(synced)) => return Some(synced),
+                        (None, None) => (),
+                    }
+                }
+            }
+        }
+
+        let chunk = self.advance().map_err(StreamError::from);
+
+        if chunk.is_err() {
+            self.done = true;
+        }
+
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcode_interpreter::Interpreter;
+    use gcode_parser::Program;
+    use std::fs;
+    use std::path::Path as FilePath;
+
+    fn options(lookahead: usize) -> StreamOptions {
+        StreamOptions {
+            path: PathOptions {
+                max_deviation: 0.01,
+            },
+            trajectory: TrajectoryOptions {
+                velocity_limit: Vector9::repeat(100.0),
+                acceleration_limit: Vector9::repeat(1000.0),
+                jerk_limit: None,
+                epsilon: 0.000001,
+                timestep: 0.001,
+            },
+            lookahead,
+            arc_tolerance: 0.001,
+            spindle_acceleration: 50.0,
+        }
+    }
+
+    fn stream(program: &Program, options: StreamOptions) -> Vec<TrajectoryChunk> {
+        let commands = Interpreter::new(program).map(Result::unwrap);
+
+        StreamingPlanner::new(commands, options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn dwell_between_chunks() {
+        let program = Program::from_str("G1 X10 F600\nG4 P2\nG1 X20\nM2").unwrap();
+
+        let chunks = stream(&program, options(4));
+
+        assert_eq!(chunks.len(), 2);
+
+        // 10mm at 10mm/s, taking 0.01s to accelerate and decelerate
+        assert!((chunks[0].duration() - 1.01).abs() < 1.0e-9);
+        assert!((chunks[1].start_time - 3.01).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn rigid_tap_chunk() {
+        let program = Program::from_str("S600 M3\nG0 Z5\nG33.1 Z-10 K1\nG0 X10\nM2").unwrap();
+
+        let chunks = stream(&program, options(4));
+
+        assert_eq!(chunks.len(), 3);
+        assert!(matches!(chunks[0].motion, ChunkMotion::Planned(_)));
+        assert!(matches!(chunks[1].motion, ChunkMotion::Synced(_)));
+        assert!(matches!(chunks[2].motion, ChunkMotion::Planned(_)));
+
+        // 15mm each way at 10mm/s, plus 0.4s for each reversal of the spindle
+        assert!((chunks[1].duration() - 3.8).abs() < 1.0e-9);
+
+        for pair in chunks.windows(2) {
+            let (previous, next) = (&pair[0], &pair[1]);
+            let end = previous.duration();
+
+            assert!((previous.position(end) - next.position(0.0)).norm() < 1.0e-9);
+            assert!((pair[0].start_time + end - pair[1].start_time).abs() < 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn spindle_too_fast() {
+        let program = Program::from_str("S12000 M3\nG33 Z-10 K1\nM2").unwrap();
+
+        let commands = Interpreter::new(&program).map(Result::unwrap);
+        let result = StreamingPlanner::new(commands, options(4)).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(result, Err(StreamError::Sync(SyncError::TooFast(2))));
+    }
+
+    #[test]
+    fn braid_continuity() {
+        let program =
+            fs::read_to_string(&FilePath::new("../test_files/tinyg/braid_4000mm.gcode")).unwrap();
+
+        let program = Program::from_str(&program).unwrap();
+
+        let options = options(20);
+
+        let chunks = stream(&program, options);
+
+        assert!(chunks.len() > 1);
+
+        for pair in chunks.windows(2) {
+            let (previous, next) = (&pair[0], &pair[1]);
+            let end = previous.duration();
+
+            assert!((previous.position(end) - next.position(0.0)).norm() < 1.0e-6);
+            assert!(
+                (previous.velocity(end) - next.velocity(0.0)).norm() < 1.0e-6,
+                "velocity step from {} to {}",
+                previous.velocity(end),
+                next.velocity(0.0)
+            );
+            assert!((pair[0].start_time + end - pair[1].start_time).abs() < 1.0e-9);
+        }
+
+        // Compare against planning every move in one go
+        let mut waypoints = vec![Vector9::zeros()];
+        let mut limits = vec![f64::INFINITY];
+
+        for command in Interpreter::new(&program) {
+            let kind = command.unwrap().kind;
+
+            match kind {
+                CanonicalKind::Rapid { to, .. } => {
+                    waypoints.push(to);
+                    limits.push(f64::INFINITY);
+                }
+                CanonicalKind::Linear { to, .. } => {
+                    waypoints.push(to);
+                    limits.push(kind.path_speed().unwrap());
+                }
+                _ => (),
+            }
+        }
+
+        let whole = Trajectory::new(
+            &Path::from_waypoints(&waypoints, options.path).with_speed_limits(&limits),
+            options.trajectory,
+        )
+        .unwrap();
+
+        let last = chunks.last().unwrap();
+        let total = last.start_time + last.duration();
+
+        assert!((last.position(last.duration()) - waypoints.last().unwrap()).norm() < 1.0e-9);
+        assert!(
+            total >= whole.duration() - 1.0e-6 && total < whole.duration() * 1.02,
+            "streamed {} whole {}",
+            total,
+            whole.duration()
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Spindle synchronised motion for threading (`G33`) and rigid tapping (`G33.1`)
+//!
+//! Synchronised moves don't follow a planned speed profile. Instead the axes are locked to the
+//! spindle's angle, moving one pitch along the line for every revolution, so the timing of the
+//! move comes entirely from the spindle's speed profile. Rigid tapping reverses the spindle at the
+//! bottom of the hole: the tool carries on past the programmed depth while the spindle slows down,
+//! then follows it back out, overshooting the start in the same way as the spindle turns forwards
+//! again.
+
+use crate::machine::MachineConfig;
+use crate::Vector9;
+use gcode_interpreter::CanonicalKind;
+use std::fmt;
+
+/// An error in the parameters of a spindle synchronised move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncError {
+    /// The pitch, spindle speed or spindle acceleration is zero, negative or not a number
+    InvalidSpindle,
+
+    /// Following the spindle would drive the axis at the given index faster than its velocity
+    /// limit
+    TooFast(usize),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::InvalidSpindle => write!(
+                f,
+                "pitch, spindle speed and spindle acceleration must be positive numbers"
+            ),
+            SyncError::TooFast(axis) => {
+                write!(f, "the spindle is too fast for axis {} to follow", axis)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+/// A phase of constant spindle acceleration
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SpindlePhase {
+    /// Start time of the phase
+    time: f64,
+    /// Spindle angle at the start of the phase, in revolutions
+    angle: f64,
+    /// Spindle speed at the start of the phase, in revolutions per second
+    speed: f64,
+    /// Spindle acceleration in revolutions per second squared
+    acceleration: f64,
+}
+
+/// Spindle speed over time, made of phases of constant acceleration
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpindleProfile {
+    phases: Vec<SpindlePhase>,
+    duration: f64,
+}
+
+impl SpindleProfile {
+    fn new() -> Self {
+        Self {
+            phases: Vec::new(),
+            duration: 0.0,
+        }
+    }
+
+    /// Add a phase starting at `speed`, skipping phases that take no time
+    fn push(&mut self, duration: f64, speed: f64, acceleration: f64) {
+        if duration <= 0.0 {
+            return;
+        }
+
+        self.phases.push(SpindlePhase {
+            time: self.duration,
+            angle: self.angle(self.duration),
+            speed,
+            acceleration,
+        });
+
+        self.duration += duration;
+    }
+
+    /// The phase active at time `t`, with the time since it started
+    fn phase(&self, t: f64) -> Option<(&SpindlePhase, f64)> {
+        let t = t.max(0.0).min(self.duration);
+
+        self.phases
+            .iter()
+            .rev()
+            .find(|phase| phase.time <= t)
+            .map(|phase| (phase, t - phase.time))
+    }
+
+    /// Total duration in seconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Spindle angle at time `t` in revolutions, measured from the start
+    pub fn angle(&self, t: f64) -> f64 {
+        self.phase(t).map_or(0.0, |(phase, t)| {
+            phase.angle + phase.speed * t + phase.acceleration * t * t / 2.0
+        })
+    }
+
+    /// Spindle speed at time `t` in revolutions per second
+    pub fn speed(&self, t: f64) -> f64 {
+        self.phase(t)
+            .map_or(0.0, |(phase, t)| phase.speed + phase.acceleration * t)
+    }
+
+    /// Spindle acceleration at time `t` in revolutions per second squared
+    pub fn acceleration(&self, t: f64) -> f64 {
+        self.phase(t).map_or(0.0, |(phase, _)| phase.acceleration)
+    }
+
+    /// The lowest and highest angle the spindle turns to, in revolutions from the start
+    pub fn angle_range(&self) -> (f64, f64) {
+        let mut range = (0.0f64, 0.0f64);
+
+        for (idx, phase) in self.phases.iter().enumerate() {
+            let end = self
+                .phases
+                .get(idx + 1)
+                .map_or(self.duration, |next| next.time);
+            // The spindle turns furthest either at the end of a phase or where it stops to reverse
+            let stop = phase.time - phase.speed / phase.acceleration;
+
+            for t in [end, stop].iter().filter(|&&t| t > phase.time && t <= end) {
+                let angle = self.angle(*t);
+
+                range = (range.0.min(angle), range.1.max(angle));
+            }
+        }
+
+        range
+    }
+}
+
+/// A straight move locked to the rotation of the spindle
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncedMove {
+    from: Vector9,
+    /// Unit vector along the move
+    direction: Vector9,
+    pitch: f64,
+    spindle: SpindleProfile,
+}
+
+impl SyncedMove {
+    fn with_spindle(
+        from: Vector9,
+        to: Vector9,
+        pitch: f64,
+        speed: f64,
+    ) -> Result<(Self, f64), SyncError> {
+        if !(pitch > 0.0 && pitch.is_finite() && speed > 0.0 && speed.is_finite()) {
+            return Err(SyncError::InvalidSpindle);
+        }
+
+        let length = (to - from).norm();
+        let direction = if length > 0.0 {
+            (to - from) / length
+        } else {
+            Vector9::zeros()
+        };
+
+        let synced = Self {
+            from,
+            direction,
+            pitch,
+            spindle: SpindleProfile::new(),
+        };
+
+        Ok((synced, length / (pitch * speed)))
+    }
+
+    /// Follow a spindle turning at a constant `speed` in revolutions per second from `from` to
+    /// `to`, moving `pitch` along the line every revolution (`G33`)
+    pub fn threading(
+        from: Vector9,
+        to: Vector9,
+        pitch: f64,
+        speed: f64,
+    ) -> Result<Self, SyncError> {
+        let (mut synced, cut) = Self::with_spindle(from, to, pitch, speed)?;
+
+        synced.spindle.push(cut, speed, 0.0);
+
+        Ok(synced)
+    }
+
+    /// Tap from `from` to `to`, then reverse the spindle and follow it back out to `from`
+    /// (`G33.1`)
+    ///
+    /// The spindle reverses at `acceleration` in revolutions per second squared, or instantly if
+    /// it is infinite, and ends turning forwards at `speed` again.
+    pub fn rigid_tap(
+        from: Vector9,
+        to: Vector9,
+        pitch: f64,
+        speed: f64,
+        acceleration: f64,
+    ) -> Result<Self, SyncError> {
+        if acceleration.is_nan() || acceleration <= 0.0 {
+            return Err(SyncError::InvalidSpindle);
+        }
+
+        let (mut synced, cut) = Self::with_spindle(from, to, pitch, speed)?;
+        let reverse = 2.0 * speed / acceleration;
+
+        synced.spindle.push(cut, speed, 0.0);
+        synced.spindle.push(reverse, speed, -acceleration);
+        synced.spindle.push(cut, -speed, 0.0);
+        synced.spindle.push(reverse, -speed, acceleration);
+
+        Ok(synced)
+    }
+
+    /// Create the move for a canonical spindle synchronised command, with the spindle changing
+    /// speed at `spindle_acceleration` in revolutions per second squared
+    ///
+    /// Returns `None` if the command isn't spindle synchronised.
+    pub fn from_canonical(
+        kind: &CanonicalKind,
+        spindle_acceleration: f64,
+    ) -> Option<Result<Self, SyncError>> {
+        match *kind {
+            CanonicalKind::SpindleSynchronized {
+                from,
+                to,
+                pitch,
+                speed,
+                rigid_tap,
+            } => Some(if rigid_tap {
+                Self::rigid_tap(from, to, pitch, speed / 60.0, spindle_acceleration)
+            } else {
+                Self::threading(from, to, pitch, speed / 60.0)
+            }),
+            _ => None,
+        }
+    }
+
+    /// Check that every axis can keep up with the spindle at full speed
+    ///
+    /// The axes are assumed to be able to follow the spindle as it changes speed.
+    pub fn check_limits(&self, machine: &MachineConfig) -> Result<(), SyncError> {
+        let top_speed = self
+            .spindle
+            .phases
+            .iter()
+            .map(|phase| phase.speed.abs())
+            .fold(0.0, f64::max);
+
+        for axis in 0..9 {
+            let velocity = self.direction[axis].abs() * self.pitch * top_speed;
+
+            if velocity > machine.velocity_limit[axis] * (1.0 + 1.0e-9) {
+                return Err(SyncError::TooFast(axis));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The spindle's speed profile through the move
+    pub fn spindle(&self) -> &SpindleProfile {
+        &self.spindle
+    }
+
+    /// Total duration in seconds
+    pub fn duration(&self) -> f64 {
+        self.spindle.duration()
+    }
+
+    /// Position when the spindle has turned through `angle` revolutions since the start
+    pub fn position_at_angle(&self, angle: f64) -> Vector9 {
+        self.from + self.direction * self.pitch * angle
+    }
+
+    /// The lowest and highest position of each axis through the move, including any overshoot
+    /// past either end while the spindle reverses
+    pub fn bounds(&self) -> (Vector9, Vector9) {
+        let (low, high) = self.spindle.angle_range();
+        let (a, b) = (self.position_at_angle(low), self.position_at_angle(high));
+
+        (a.zip_map(&b, f64::min), a.zip_map(&b, f64::max))
+    }
+
+    /// Position at time `t`
+    pub fn position(&self, t: f64) -> Vector9 {
+        self.position_at_angle(self.spindle.angle(t))
+    }
+
+    /// Velocity at time `t`
+    pub fn velocity(&self, t: f64) -> Vector9 {
+        self.direction * self.pitch * self.spindle.speed(t)
+    }
+
+    /// Acceleration at time `t`
+    pub fn acceleration(&self, t: f64) -> Vector9 {
+        self.direction * self.pitch * self.spindle.acceleration(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn z(z: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[2] = z;
+        v
+    }
+
+    #[test]
+    fn threading() {
+        // 1.5mm pitch at 600 RPM is 15mm/s
+        let synced = SyncedMove::threading(z(0.0), z(-30.0), 1.5, 10.0).unwrap();
+
+        assert!((synced.duration() - 2.0).abs() < 1.0e-12);
+        assert!((synced.position(1.0) - z(-15.0)).norm() < 1.0e-12);
+        assert!((synced.velocity(1.0) - z(-15.0)).norm() < 1.0e-12);
+        assert!((synced.position_at_angle(4.0) - z(-6.0)).norm() < 1.0e-12);
+        assert!((synced.position(synced.duration()) - z(-30.0)).norm() < 1.0e-12);
+    }
+
+    #[test]
+    fn rigid_tap_reversal() {
+        let (speed, acceleration) = (10.0, 50.0);
+
+        let synced = SyncedMove::rigid_tap(z(0.0), z(-30.0), 1.5, speed, acceleration).unwrap();
+
+        // Cut in, reverse, retract and reverse again, 0.4s for each reversal
+        assert!((synced.duration() - 4.8).abs() < 1.0e-12);
+
+        // Deepest point once the spindle has stopped, a revolution past the programmed depth
+        assert!((synced.position(2.2) - z(-31.5)).norm() < 1.0e-12);
+        assert!(synced.velocity(2.2).norm() < 1.0e-12);
+        assert!((synced.position(2.4) - z(-30.0)).norm() < 1.0e-12);
+        assert!((synced.velocity(2.4) - z(15.0)).norm() < 1.0e-12);
+
+        let end = synced.duration();
+
+        assert!(synced.position(end).norm() < 1.0e-12);
+        assert!((synced.spindle().speed(end) - speed).abs() < 1.0e-12);
+
+        // Overshooting the start by a revolution as the spindle turns forwards again
+        let (min, max) = synced.bounds();
+
+        assert!((min - z(-31.5)).norm() < 1.0e-12);
+        assert!((max - z(1.5)).norm() < 1.0e-12);
+
+        // Position always follows the spindle
+        let steps = 480;
+
+        for step in 0..=steps {
+            let t = end * step as f64 / steps as f64;
+            let expected = synced.position_at_angle(synced.spindle().angle(t));
+
+            assert!((synced.position(t) - expected).norm() < 1.0e-12);
+            assert!(synced.acceleration(t).norm() <= 1.5 * acceleration + 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn instant_reversal() {
+        let synced = SyncedMove::rigid_tap(z(0.0), z(-30.0), 1.5, 10.0, f64::INFINITY).unwrap();
+
+        assert!((synced.duration() - 4.0).abs() < 1.0e-12);
+        assert!((synced.position(2.0) - z(-30.0)).norm() < 1.0e-12);
+        assert!(synced.position(4.0).norm() < 1.0e-12);
+        assert_eq!(synced.bounds(), (z(-30.0), z(0.0)));
+    }
+
+    #[test]
+    fn axis_limits() {
+        let machine = MachineConfig {
+            velocity_limit: Vector9::repeat(20.0),
+            acceleration_limit: Vector9::repeat(1000.0),
+            jerk_limit: None,
+        };
+
+        let slow = SyncedMove::threading(z(0.0), z(-30.0), 1.5, 10.0).unwrap();
+        let fast = SyncedMove::threading(z(0.0), z(-30.0), 1.5, 20.0).unwrap();
+
+        assert_eq!(slow.check_limits(&machine), Ok(()));
+        assert_eq!(fast.check_limits(&machine), Err(SyncError::TooFast(2)));
+        assert_eq!(
+            SyncedMove::threading(z(0.0), z(-30.0), 0.0, 10.0),
+            Err(SyncError::InvalidSpindle)
+        );
+    }
+}
@@ -1,6 +1,9 @@
 #[cfg(feature = "profile")]
 extern crate cpuprofiler;
 
+use crate::kinematics::Kinematics;
+use crate::trajectory::{Trajectory, TrajectoryOptions};
+
 #[cfg(feature = "profile")]
 #[allow(dead_code)]
 /// Begin a profiling capture
@@ -46,3 +49,90 @@ pub fn end_profile() {
     println!("Profile noop");
     // Noop
 }
+
+#[allow(dead_code)]
+/// Sample a trajectory every timestep and check it never exceeds its limits
+///
+/// Jerk is the change in sampled acceleration from one timestep to the next, so it includes the
+/// steps in acceleration where the path curvature changes suddenly.
+pub fn assert_within_limits(trajectory: &Trajectory, options: &TrajectoryOptions) {
+    let dt = options.timestep;
+    let steps = (trajectory.duration() / dt).ceil() as usize;
+
+    let mut previous = trajectory.acceleration(0.0);
+
+    for step in 0..=steps {
+        let t = step as f64 * dt;
+
+        let velocity = trajectory.velocity(t);
+        let acceleration = trajectory.acceleration(t);
+        let jerk = (acceleration - previous) / dt;
+
+        previous = acceleration;
+
+        for i in 0..9 {
+            assert!(
+                velocity[i].abs() <= options.velocity_limit[i] * (1.0 + 1.0e-6),
+                "axis {} velocity {} exceeds limit at t = {}",
+                i,
+                velocity[i],
+                t
+            );
+            assert!(
+                acceleration[i].abs() <= options.acceleration_limit[i] * (1.0 + 1.0e-6),
+                "axis {} acceleration {} exceeds limit at t = {}",
+                i,
+                acceleration[i],
+                t
+            );
+
+            if let Some(jerk_limit) = &options.jerk_limit {
+                assert!(
+                    jerk[i].abs() <= jerk_limit[i] * (1.0 + 1.0e-6),
+                    "axis {} jerk {} exceeds limit at t = {}",
+                    i,
+                    jerk[i],
+                    t
+                );
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+/// Sample a trajectory every timestep and check the machine's joints never exceed the velocity
+/// and acceleration limits, returning the highest fraction of any limit used
+pub fn assert_within_joint_limits(
+    trajectory: &Trajectory,
+    options: &TrajectoryOptions,
+    kinematics: &dyn Kinematics,
+) -> f64 {
+    let dt = options.timestep;
+    let steps = (trajectory.duration() / dt).floor() as usize;
+
+    let joints = |t: f64| kinematics.inverse(&trajectory.position(t)).unwrap();
+
+    let mut highest: f64 = 0.0;
+
+    for step in 1..steps {
+        let t = step as f64 * dt;
+
+        let (before, now, after) = (joints(t - dt), joints(t), joints(t + dt));
+
+        let velocity = (after - before) / (2.0 * dt);
+        let acceleration = (after - now * 2.0 + before) / (dt * dt);
+
+        for i in 0..9 {
+            let v = velocity[i].abs() / options.velocity_limit[i];
+            let a = acceleration[i].abs() / options.acceleration_limit[i];
+
+            // Finite differences blur the steps in acceleration, so allow some slack
+            assert!(v <= 1.0 + 1.0e-3, "joint {} velocity {} at t = {}", i, v, t);
+            assert!(a <= 1.05, "joint {} acceleration {} at t = {}", i, a, t);
+
+            highest = highest.max(v);
+        }
+    }
+
+    highest
+}
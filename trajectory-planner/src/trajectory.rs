@@ -0,0 +1,1247 @@
+//! Time-optimal parameterisation of a path
+//!
+//! The path is split into intervals over which the velocity and acceleration constraints are
+//! (conservatively) constant. A backward then forward pass over the interval boundaries finds the
+//! fastest boundary velocities, and each interval is then traversed with an
+//! accelerate/cruise/decelerate profile. Straight lines are solved exactly; blends are split into
+//! smaller intervals so the result stays close to the time optimal solution.
+//!
+//! With a jerk limit, speed changes follow S-curves that start and end with zero acceleration.
+//! Where the curvature steps, for example where a straight line meets a blend, the acceleration
+//! changes instantly. The speed there is capped so that change, spread over one timestep, stays
+//! within part of the jerk limit, and the segments either side keep to the rest of it.
+//!
+//! Limits apply to the machine's joints, which are the Cartesian axes unless a
+//! [`Kinematics`](../kinematics/trait.Kinematics.html) implementation says otherwise. With
+//! non-linear kinematics straight lines are split into intervals too, as their joint space
+//! derivatives change along them.
+
+use crate::kinematics::{joint_derivatives, Kinematics, Trivial};
+use crate::path::{Path, PathSegment};
+use crate::sample::Samples;
+use crate::Vector9;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// The maximum number of intervals a single blend is split into
+const MAX_BLEND_INTERVALS: usize = 64;
+
+/// Number of bisection steps used when solving jerk-limited profiles
+const SEARCH_ITERATIONS: usize = 64;
+
+/// The share of each axis' jerk limit kept for steps in curvature between segments
+const CURVATURE_STEP_SHARE: f64 = 0.5;
+
+/// Trajectory generation options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryOptions {
+    /// Maximum velocity of each axis, or each joint if planning with kinematics
+    pub velocity_limit: Vector9,
+
+    /// Maximum acceleration of each axis, or each joint if planning with kinematics
+    pub acceleration_limit: Vector9,
+
+    /// Maximum jerk of each axis. Speed changes use trapezoidal profiles if this is `None`, or
+    /// jerk-limited S-curves otherwise.
+    pub jerk_limit: Option<Vector9>,
+
+    /// Tangent components smaller than this are treated as zero when evaluating constraints
+    pub epsilon: f64,
+
+    /// Integration timestep. Blends are split into intervals that take roughly this long to
+    /// traverse at full speed.
+    pub timestep: f64,
+}
+
+/// An error encountered whilst planning a trajectory
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanError {
+    /// A velocity, acceleration or jerk limit is zero, negative or not a finite number
+    InvalidLimits,
+
+    /// The waypoint at the given index has a component that is not a finite number
+    InvalidWaypoint(usize),
+
+    /// The path towards the waypoint at the given index passes outside the machine's reach
+    Unreachable(usize),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::InvalidLimits => write!(
+                f,
+                "velocity, acceleration and jerk limits must be positive finite numbers"
+            ),
+            PlanError::InvalidWaypoint(idx) => {
+                write!(f, "waypoint {} contains a non-finite component", idx)
+            }
+            PlanError::Unreachable(idx) => {
+                write!(
+                    f,
+                    "the path to waypoint {} is out of the machine's reach",
+                    idx
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// Constraints on the path speed and acceleration at a single point
+struct Limits<'a> {
+    options: &'a TrajectoryOptions,
+    kinematics: &'a dyn Kinematics,
+
+    /// The share of each axis' jerk limit available within a segment
+    jerk_share: f64,
+}
+
+impl<'a> Limits<'a> {
+    /// The jerk limit available within a segment, if there is one
+    fn jerk_limit(&self) -> Option<Vector9> {
+        self.options.jerk_limit.map(|jmax| jmax * self.jerk_share)
+    }
+
+    /// Derivatives of the joint positions with respect to distance along a segment, or `None` if
+    /// the machine can't reach the point
+    fn derivatives(&self, segment: &PathSegment, s: f64) -> Option<[Vector9; 3]> {
+        joint_derivatives(
+            self.kinematics,
+            &segment.position(s),
+            &segment.tangent(s),
+            &segment.curvature(s),
+            &segment.curvature_derivative(s),
+        )
+    }
+
+    /// Maximum squared path speed at a point with the given path derivatives
+    fn max_speed_squared(&self, d1: &Vector9, d2: &Vector9) -> f64 {
+        let eps = self.options.epsilon;
+        let vmax = &self.options.velocity_limit;
+        let amax = &self.options.acceleration_limit;
+
+        let mut u = f64::INFINITY;
+
+        for i in 0..9 {
+            if d1[i].abs() > eps {
+                u = u.min((vmax[i] / d1[i].abs()).powi(2));
+            }
+        }
+
+        for i in 0..9 {
+            // Intervals are cruised at constant path speed, so the centripetal term alone must
+            // stay within the limit
+            if d2[i].abs() > eps {
+                u = u.min(amax[i] / d2[i].abs());
+            }
+
+            if d1[i].abs() <= eps {
+                continue;
+            }
+
+            for j in (i + 1)..9 {
+                if d1[j].abs() <= eps {
+                    continue;
+                }
+
+                let a = d2[i] / d1[i] - d2[j] / d1[j];
+
+                if a.abs() > eps {
+                    u = u.min((amax[i] / d1[i].abs() + amax[j] / d1[j].abs()) / a.abs());
+                }
+            }
+        }
+
+        u
+    }
+
+    /// Maximum squared path speed at which the jerk caused by the changing direction of the
+    /// curvature alone stays within half of the jerk available to each axis
+    fn max_jerk_speed_squared(&self, d3: &Vector9) -> f64 {
+        let jmax = match self.jerk_limit() {
+            Some(jmax) => jmax,
+            None => return f64::INFINITY,
+        };
+
+        let mut u = f64::INFINITY;
+
+        for i in 0..9 {
+            if d3[i].abs() > self.options.epsilon {
+                u = u.min((jmax[i] / (2.0 * d3[i].abs())).powf(2.0 / 3.0));
+            }
+        }
+
+        u
+    }
+
+    /// Maximum path acceleration at which the jerk caused by changing speed through a curve stays
+    /// within a quarter of the jerk available to each axis
+    fn max_jerk_acceleration(&self, d2: &Vector9, speed: f64) -> f64 {
+        let jmax = match self.jerk_limit() {
+            Some(jmax) => jmax,
+            None => return f64::INFINITY,
+        };
+
+        let mut acceleration = f64::INFINITY;
+
+        for i in 0..9 {
+            if d2[i].abs() > self.options.epsilon {
+                acceleration = acceleration.min(jmax[i] / (12.0 * d2[i].abs() * speed));
+            }
+        }
+
+        acceleration
+    }
+
+    /// Maximum path jerk at a point, given the highest path speed and acceleration that will be
+    /// used there
+    ///
+    /// Axis jerk is `d1 * s''' + 3 * d2 * s' * s'' + d3 * s'^3`, so the path jerk gets whatever
+    /// is left of each axis' limit after the last two terms.
+    fn max_jerk(
+        &self,
+        d1: &Vector9,
+        d2: &Vector9,
+        d3: &Vector9,
+        speed: f64,
+        acceleration: f64,
+    ) -> f64 {
+        let jmax = match self.jerk_limit() {
+            Some(jmax) => jmax,
+            None => return f64::INFINITY,
+        };
+
+        let mut jerk = f64::INFINITY;
+
+        for i in 0..9 {
+            if d1[i].abs() <= self.options.epsilon {
+                continue;
+            }
+
+            let remaining =
+                jmax[i] - d3[i].abs() * speed.powi(3) - 3.0 * d2[i].abs() * speed * acceleration;
+
+            jerk = jerk.min(remaining / d1[i].abs());
+        }
+
+        jerk
+    }
+
+    /// Maximum squared path speed through a boundary between segments where the curvature changes
+    /// by `step`, so the change in acceleration over one timestep stays within the share of each
+    /// axis' jerk limit kept for it
+    fn max_step_speed_squared(&self, step: &Vector9) -> f64 {
+        let jmax = match &self.options.jerk_limit {
+            Some(jmax) => jmax,
+            None => return f64::INFINITY,
+        };
+
+        let mut u = f64::INFINITY;
+
+        for i in 0..9 {
+            if step[i].abs() > self.options.epsilon {
+                u = u.min(jmax[i] * CURVATURE_STEP_SHARE * self.options.timestep / step[i].abs());
+            }
+        }
+
+        u
+    }
+
+    /// The range of path accelerations achievable at a point travelling at squared speed `u`
+    fn acceleration_range(&self, d1: &Vector9, d2: &Vector9, u: f64) -> (f64, f64) {
+        let eps = self.options.epsilon;
+        let amax = &self.options.acceleration_limit;
+
+        let mut min = f64::NEG_INFINITY;
+        let mut max = f64::INFINITY;
+
+        for i in 0..9 {
+            if d1[i].abs() <= eps {
+                continue;
+            }
+
+            let c = d2[i] * u;
+            let a = (-amax[i] - c) / d1[i];
+            let b = (amax[i] - c) / d1[i];
+
+            min = min.max(a.min(b));
+            max = max.min(a.max(b));
+        }
+
+        (min, max)
+    }
+}
+
+/// A change in path speed, made with constant acceleration or with a jerk-limited S-curve
+///
+/// The acceleration is zero at both ends of a jerk-limited transition, so transitions can be
+/// joined together without a step in acceleration.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Transition {
+    start_speed: f64,
+    /// Signed peak acceleration
+    acceleration: f64,
+    /// Signed jerk used to ramp the acceleration up and back down
+    jerk: f64,
+    /// Time taken by each of the acceleration ramps
+    ramp_time: f64,
+    /// Time spent at peak acceleration
+    hold_time: f64,
+}
+
+impl Transition {
+    /// The fastest transition between two speeds given limits on the acceleration and jerk
+    ///
+    /// An infinite jerk limit gives a constant acceleration transition.
+    fn new(start_speed: f64, end_speed: f64, acceleration: f64, jerk: f64) -> Self {
+        let change = end_speed - start_speed;
+        let sign = change.signum();
+
+        if change == 0.0 || acceleration <= 0.0 {
+            return Self {
+                start_speed,
+                ..Self::default()
+            };
+        }
+
+        let change = change.abs();
+
+        let (peak, ramp_time, hold_time) = if jerk.is_infinite() {
+            (acceleration, 0.0, change / acceleration)
+        } else if change * jerk >= acceleration * acceleration {
+            (
+                acceleration,
+                acceleration / jerk,
+                change / acceleration - acceleration / jerk,
+            )
+        } else {
+            let ramp_time = (change / jerk).sqrt();
+
+            (jerk * ramp_time, ramp_time, 0.0)
+        };
+
+        Self {
+            start_speed,
+            acceleration: sign * peak,
+            jerk: if jerk.is_infinite() { 0.0 } else { sign * jerk },
+            ramp_time,
+            hold_time,
+        }
+    }
+
+    fn duration(&self) -> f64 {
+        2.0 * self.ramp_time + self.hold_time
+    }
+
+    fn end_speed(&self) -> f64 {
+        self.start_speed + self.acceleration * (self.ramp_time + self.hold_time)
+    }
+
+    /// Distance travelled during the transition. The acceleration profile is symmetric, so this
+    /// is always the mean of the start and end speeds multiplied by the duration.
+    fn distance(&self) -> f64 {
+        (self.start_speed + self.end_speed()) / 2.0 * self.duration()
+    }
+
+    /// Distance, speed, acceleration and jerk at `t` seconds into the transition
+    fn state(&self, t: f64) -> (f64, f64, f64, f64) {
+        let (v0, a, j) = (self.start_speed, self.acceleration, self.jerk);
+        let (ramp, hold) = (self.ramp_time, self.hold_time);
+
+        let t = t.max(0.0).min(self.duration());
+
+        if t < ramp {
+            return (v0 * t + j * t.powi(3) / 6.0, v0 + j * t * t / 2.0, j * t, j);
+        }
+
+        let ramp_distance = v0 * ramp + j * ramp.powi(3) / 6.0;
+        let ramp_speed = v0 + j * ramp * ramp / 2.0;
+
+        if t < ramp + hold {
+            let t = t - ramp;
+
+            return (
+                ramp_distance + ramp_speed * t + a * t * t / 2.0,
+                ramp_speed + a * t,
+                a,
+                0.0,
+            );
+        }
+
+        let hold_distance = ramp_distance + ramp_speed * hold + a * hold * hold / 2.0;
+        let hold_speed = ramp_speed + a * hold;
+
+        let t = t - ramp - hold;
+
+        if t >= ramp {
+            return (self.distance(), self.end_speed(), 0.0, 0.0);
+        }
+
+        (
+            hold_distance + hold_speed * t + a * t * t / 2.0 - j * t.powi(3) / 6.0,
+            hold_speed + a * t - j * t * t / 2.0,
+            a - j * t,
+            -j,
+        )
+    }
+}
+
+/// Distance taken to change between two speeds
+fn transition_distance(from: f64, to: f64, acceleration: f64, jerk: f64) -> f64 {
+    Transition::new(from, to, acceleration, jerk).distance()
+}
+
+/// The highest speed that can be reached from (or slowed down from to) `speed` within `distance`
+fn reachable_speed(speed: f64, distance: f64, acceleration: f64, jerk: f64) -> f64 {
+    // Constant acceleration reaches furthest, so bounds the search
+    let mut high = (speed * speed + 2.0 * acceleration * distance).sqrt();
+
+    if jerk.is_infinite() || high <= speed {
+        return high;
+    }
+
+    let mut low = speed;
+
+    for _ in 0..SEARCH_ITERATIONS {
+        let mid = (low + high) / 2.0;
+
+        if transition_distance(speed, mid, acceleration, jerk) <= distance {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    low
+}
+
+/// A section of the path with constant acceleration limits
+#[derive(Debug, Clone, PartialEq)]
+struct Interval {
+    /// Path distance at the start of this interval
+    start: f64,
+    /// Index of the waypoint the segment containing this interval is heading towards
+    waypoint: usize,
+    length: f64,
+    /// Maximum squared speed anywhere within this interval
+    cap: f64,
+    /// Maximum path acceleration (positive)
+    acceleration: f64,
+    /// Maximum path deceleration (positive)
+    deceleration: f64,
+    /// Maximum path jerk, or infinity for constant acceleration profiles
+    jerk: f64,
+
+    /// Time at the start of this interval
+    time: f64,
+    accelerate: Transition,
+    top_speed: f64,
+    cruise_time: f64,
+    decelerate: Transition,
+}
+
+impl Interval {
+    fn duration(&self) -> f64 {
+        self.accelerate.duration() + self.cruise_time + self.decelerate.duration()
+    }
+
+    /// Fill in the speed profile over this interval given the squared boundary speeds
+    fn solve(&mut self, u_start: f64, u_end: f64) {
+        let (acc, dec, length) = (self.acceleration, self.deceleration, self.length);
+        let (start_speed, end_speed) = (u_start.sqrt(), u_end.sqrt());
+
+        let top = if self.jerk.is_infinite() {
+            let peak = if acc > 0.0 && dec > 0.0 {
+                let x = (u_end - u_start + 2.0 * dec * length) / (2.0 * (acc + dec));
+
+                u_start + 2.0 * acc * x
+            } else if acc > 0.0 {
+                u_end
+            } else {
+                u_start
+            };
+
+            peak.min(self.cap).max(u_start).max(u_end).sqrt()
+        } else {
+            let ramps = |top: f64| {
+                transition_distance(start_speed, top, acc, self.jerk)
+                    + transition_distance(top, end_speed, dec, self.jerk)
+            };
+
+            let mut low = start_speed.max(end_speed);
+            let mut high = self.cap.sqrt().max(low);
+
+            if ramps(high) <= length {
+                high
+            } else {
+                for _ in 0..SEARCH_ITERATIONS {
+                    let mid = (low + high) / 2.0;
+
+                    if ramps(mid) <= length {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+
+                low
+            }
+        };
+
+        self.accelerate = Transition::new(start_speed, top, acc, self.jerk);
+        self.decelerate = Transition::new(top, end_speed, dec, self.jerk);
+        self.top_speed = top;
+
+        // Guard against rounding errors pushing the ramps past the end of the interval
+        let ramps = self.accelerate.distance() + self.decelerate.distance();
+
+        self.cruise_time = (length - ramps).max(0.0) / top.max(f64::MIN_POSITIVE);
+    }
+
+    /// Distance, speed, acceleration and jerk along the path at `t` seconds into this interval
+    fn state(&self, t: f64) -> (f64, f64, f64, f64) {
+        let t = t.max(0.0);
+
+        let accelerate_time = self.accelerate.duration();
+
+        if t < accelerate_time {
+            let (s, v, a, j) = self.accelerate.state(t);
+
+            return (self.start + s, v, a, j);
+        }
+
+        let cruise_start = self.start + self.accelerate.distance();
+
+        if t < accelerate_time + self.cruise_time {
+            return (
+                cruise_start + self.top_speed * (t - accelerate_time),
+                self.top_speed,
+                0.0,
+                0.0,
+            );
+        }
+
+        let (s, v, a, j) = self
+            .decelerate
+            .state(t - accelerate_time - self.cruise_time);
+
+        (
+            (cruise_start + self.top_speed * self.cruise_time + s).min(self.start + self.length),
+            v,
+            a,
+            j,
+        )
+    }
+}
+
+/// A time-parameterised path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    path: Path,
+    intervals: Vec<Interval>,
+    duration: f64,
+}
+
+impl Trajectory {
+    /// Find the time optimal trajectory along a path, starting and ending at rest
+    pub fn new(path: &Path, options: TrajectoryOptions) -> Result<Self, PlanError> {
+        Self::with_kinematics(path, options, &Trivial)
+    }
+
+    /// Find the time optimal trajectory along a path, starting and ending at rest, with the
+    /// velocity, acceleration and jerk limits applied to the joints of a machine
+    pub fn with_kinematics(
+        path: &Path,
+        options: TrajectoryOptions,
+        kinematics: &dyn Kinematics,
+    ) -> Result<Self, PlanError> {
+        Self::with_boundary_speeds(path, options, kinematics, 0.0, 0.0)
+    }
+
+    /// Find the time optimal trajectory along a path that starts and ends at the given path speeds
+    ///
+    /// The start speed is dropped to zero if the path has to start with a stop, and either speed
+    /// is lowered if the path is too short or too curved to allow it.
+    pub(crate) fn with_boundary_speeds(
+        path: &Path,
+        options: TrajectoryOptions,
+        kinematics: &dyn Kinematics,
+        start_speed: f64,
+        end_speed: f64,
+    ) -> Result<Self, PlanError> {
+        let valid = |v: &Vector9| v.iter().all(|x| x.is_finite() && *x > 0.0);
+
+        if !valid(&options.velocity_limit)
+            || !valid(&options.acceleration_limit)
+            || !options.jerk_limit.as_ref().map(valid).unwrap_or(true)
+            || !options.timestep.is_finite()
+            || options.timestep <= 0.0
+        {
+            return Err(PlanError::InvalidLimits);
+        }
+
+        for seg in path.segments() {
+            let start = seg.segment.position(0.0);
+
+            if !start.iter().all(|x| x.is_finite()) || !seg.segment.length().is_finite() {
+                return Err(PlanError::InvalidWaypoint(seg.waypoint));
+            }
+        }
+
+        let limits = Limits {
+            options: &options,
+            kinematics,
+            jerk_share: 1.0,
+        };
+
+        let (mut intervals, boundaries) = Self::intervals(path, &limits)?;
+
+        // Maximum squared speed at each interval boundary
+        let mut u: Vec<f64> = (0..=intervals.len())
+            .map(|idx| {
+                let before = idx.checked_sub(1).map(|i| intervals[i].cap);
+                let after = intervals.get(idx).map(|i| i.cap);
+
+                let u = match (before, after) {
+                    (Some(before), Some(after)) => before.min(after),
+                    (None, Some(after)) => after.min(start_speed * start_speed),
+                    (Some(before), None) => before.min(end_speed * end_speed),
+                    (None, None) => 0.0,
+                };
+
+                u.min(boundaries[idx])
+            })
+            .collect();
+
+        // Backward pass: make sure every boundary speed can be slowed down from in time
+        for idx in (0..intervals.len()).rev() {
+            let interval = &intervals[idx];
+
+            let reachable = reachable_speed(
+                u[idx + 1].sqrt(),
+                interval.length,
+                interval.deceleration,
+                interval.jerk,
+            );
+
+            u[idx] = u[idx].min(reachable * reachable);
+        }
+
+        // Forward pass: make sure every boundary speed can be reached in time
+        for idx in 0..intervals.len() {
+            let interval = &intervals[idx];
+
+            let reachable = reachable_speed(
+                u[idx].sqrt(),
+                interval.length,
+                interval.acceleration,
+                interval.jerk,
+            );
+
+            u[idx + 1] = u[idx + 1].min(reachable * reachable);
+        }
+
+        let mut time = 0.0;
+
+        for (idx, interval) in intervals.iter_mut().enumerate() {
+            interval.solve(u[idx], u[idx + 1]);
+            interval.time = time;
+
+            time += interval.duration();
+        }
+
+        Ok(Self {
+            path: path.clone(),
+            intervals,
+            duration: time,
+        })
+    }
+
+    /// Split a path into intervals with constant constraints, also returning the maximum squared
+    /// speed at each interval boundary, which is zero where the machine must stop
+    fn intervals(path: &Path, limits: &Limits) -> Result<(Vec<Interval>, Vec<f64>), PlanError> {
+        let segments = path.segments();
+        let eps = limits.options.epsilon;
+
+        let mut intervals = Vec::with_capacity(segments.len());
+        let mut boundaries = Vec::with_capacity(segments.len() + 1);
+
+        // The curvature at the start and end of each segment
+        let curvatures = segments
+            .iter()
+            .map(|placed| {
+                let curvature = |s: f64| {
+                    limits
+                        .derivatives(&placed.segment, s)
+                        .map(|[_, d2, _]| d2)
+                        .ok_or(PlanError::Unreachable(placed.waypoint))
+                };
+
+                Ok((curvature(0.0)?, curvature(placed.segment.length())?))
+            })
+            .collect::<Result<Vec<_>, PlanError>>()?;
+
+        // How much the curvature steps by at the start of each segment the machine doesn't stop at
+        let steps: Vec<Option<Vector9>> = (0..segments.len())
+            .map(|idx| match idx.checked_sub(1) {
+                Some(before) if !segments[idx].stop => {
+                    let step = curvatures[idx].0 - curvatures[before].1;
+
+                    Some(step).filter(|step| step.iter().any(|x| x.abs() > eps))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (idx, placed) in segments.iter().enumerate() {
+            let segment = &placed.segment;
+            let length = segment.length();
+
+            let stepped = steps[idx].is_some() || matches!(steps.get(idx + 1), Some(Some(_)));
+
+            // Segments next to a step in curvature leave some of the jerk limit for it
+            let limits = Limits {
+                jerk_share: if stepped {
+                    1.0 - CURVATURE_STEP_SHARE
+                } else {
+                    1.0
+                },
+                ..*limits
+            };
+
+            let derivatives = |s: f64| {
+                limits
+                    .derivatives(segment, s)
+                    .ok_or(PlanError::Unreachable(placed.waypoint))
+            };
+
+            // Straight lines are only straight in joint space for linear kinematics
+            let count = match segment {
+                PathSegment::Linear(_) if limits.kinematics.is_linear() => 1,
+                _ => {
+                    let [d1, d2, _] = derivatives(0.0)?;
+
+                    let speed = limits.max_speed_squared(&d1, &d2).sqrt();
+                    let by_angle = (segment.sweep() / (PI / 16.0)).ceil();
+                    let by_time = (length / (speed * limits.options.timestep)).ceil();
+
+                    (by_angle.max(by_time) as usize).clamp(1, MAX_BLEND_INTERVALS)
+                }
+            };
+
+            let step = length / count as f64;
+
+            for n in 0..count {
+                let local_start = step * n as f64;
+
+                let samples = [
+                    derivatives(local_start)?,
+                    derivatives(local_start + step / 2.0)?,
+                    derivatives(local_start + step)?,
+                ];
+
+                let cap = samples
+                    .iter()
+                    .map(|[d1, d2, d3]| {
+                        limits
+                            .max_speed_squared(d1, d2)
+                            .min(limits.max_jerk_speed_squared(d3))
+                    })
+                    .fold(placed.speed_limit.powi(2), f64::min);
+
+                let mut acceleration = f64::INFINITY;
+                let mut deceleration = f64::INFINITY;
+
+                for [d1, d2, _] in samples.iter() {
+                    for u in [0.0, cap].iter() {
+                        let (min, max) = limits.acceleration_range(d1, d2, *u);
+
+                        acceleration = acceleration.min(max);
+                        deceleration = deceleration.min(-min);
+                    }
+                }
+
+                // Changing speed through a curve adds to the jerk, so slow down the changes to
+                // leave enough jerk to make them with
+                let limit = samples
+                    .iter()
+                    .map(|[_, d2, _]| limits.max_jerk_acceleration(d2, cap.sqrt()))
+                    .fold(f64::INFINITY, f64::min);
+
+                let acceleration = acceleration.min(limit).max(0.0);
+                let deceleration = deceleration.min(limit).max(0.0);
+
+                let jerk = samples
+                    .iter()
+                    .map(|[d1, d2, d3]| {
+                        limits.max_jerk(d1, d2, d3, cap.sqrt(), acceleration.max(deceleration))
+                    })
+                    .fold(f64::INFINITY, f64::min);
+
+                boundaries.push(match &steps[idx] {
+                    _ if n > 0 => f64::INFINITY,
+                    _ if placed.stop => 0.0,
+                    Some(step) => limits.max_step_speed_squared(step),
+                    None => f64::INFINITY,
+                });
+
+                intervals.push(Interval {
+                    start: placed.start + local_start,
+                    waypoint: placed.waypoint,
+                    length: step,
+                    cap,
+                    acceleration,
+                    deceleration,
+                    jerk,
+                    time: 0.0,
+                    accelerate: Transition::default(),
+                    top_speed: 0.0,
+                    cruise_time: 0.0,
+                    decelerate: Transition::default(),
+                });
+            }
+        }
+
+        boundaries.push(f64::INFINITY);
+
+        Ok((intervals, boundaries))
+    }
+
+    /// Total time taken to traverse the trajectory in seconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Time spent travelling towards each waypoint, as `(waypoint index, seconds)` pairs in
+    /// path order
+    ///
+    /// The time spent in a blend is counted towards the waypoint at its corner.
+    pub fn waypoint_durations(&self) -> Vec<(usize, f64)> {
+        let mut durations: Vec<(usize, f64)> = Vec::new();
+
+        for interval in self.intervals.iter() {
+            match durations.last_mut() {
+                Some((waypoint, duration)) if *waypoint == interval.waypoint => {
+                    *duration += interval.duration()
+                }
+                _ => durations.push((interval.waypoint, interval.duration())),
+            }
+        }
+
+        durations
+    }
+
+    /// Path speed at the start of a segment of the path
+    pub(crate) fn speed_at_segment(&self, segment: usize) -> f64 {
+        let start = match self.path.segments().get(segment) {
+            Some(placed) => placed.start,
+            None => {
+                return self
+                    .intervals
+                    .last()
+                    .map(|i| i.decelerate.end_speed())
+                    .unwrap_or(0.0)
+            }
+        };
+
+        self.intervals
+            .iter()
+            .find(|interval| interval.start >= start)
+            .map(|interval| interval.accelerate.start_speed)
+            .unwrap_or(0.0)
+    }
+
+    /// The path this trajectory follows
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sample this trajectory every `period` seconds, starting at `t = 0`
+    pub fn sample(&self, period: f64) -> Samples<'_> {
+        Samples::new(self, period)
+    }
+
+    /// Distance along, speed along and acceleration along the path at time `t`
+    pub fn path_state(&self, t: f64) -> (f64, f64, f64) {
+        let (s, s_dot, s_ddot, _) = self.full_path_state(t);
+
+        (s, s_dot, s_ddot)
+    }
+
+    /// Distance, speed, acceleration and jerk along the path at time `t`
+    fn full_path_state(&self, t: f64) -> (f64, f64, f64, f64) {
+        if self.intervals.is_empty() {
+            return (0.0, 0.0, 0.0, 0.0);
+        }
+
+        let idx = match self.intervals.binary_search_by(|i| i.time.total_cmp(&t)) {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        };
+
+        let interval = &self.intervals[idx];
+
+        interval.state(t - interval.time)
+    }
+
+    /// Position of all axes at time `t`
+    pub fn position(&self, t: f64) -> Vector9 {
+        let (s, _, _) = self.path_state(t);
+
+        self.path.position(s)
+    }
+
+    /// Velocity of all axes at time `t`
+    pub fn velocity(&self, t: f64) -> Vector9 {
+        let (s, s_dot, _) = self.path_state(t);
+
+        self.path.tangent(s) * s_dot
+    }
+
+    /// Acceleration of all axes at time `t`
+    pub fn acceleration(&self, t: f64) -> Vector9 {
+        let (s, s_dot, s_ddot) = self.path_state(t);
+
+        self.path.tangent(s) * s_ddot + self.path.curvature(s) * s_dot * s_dot
+    }
+
+    /// Jerk of all axes at time `t`
+    ///
+    /// This doesn't include the instantaneous change in acceleration where the path curvature
+    /// changes, for example at the start and end of a blend. The speed through those points is
+    /// capped so the change, spread over one timestep, stays within the jerk limit.
+    pub fn jerk(&self, t: f64) -> Vector9 {
+        let (s, s_dot, s_ddot, s_dddot) = self.full_path_state(t);
+
+        self.path.tangent(s) * s_dddot
+            + self.path.curvature(s) * 3.0 * s_dot * s_ddot
+            + self.path.curvature_derivative(s) * s_dot.powi(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kinematics::{CoreXY, LinearDelta};
+    use crate::path::PathOptions;
+    use crate::test_helpers::{assert_within_joint_limits, assert_within_limits};
+
+    fn xy(x: f64, y: f64) -> Vector9 {
+        let mut v = Vector9::zeros();
+        v[0] = x;
+        v[1] = y;
+        v
+    }
+
+    fn options(velocity: f64, acceleration: f64) -> TrajectoryOptions {
+        TrajectoryOptions {
+            velocity_limit: Vector9::repeat(velocity),
+            acceleration_limit: Vector9::repeat(acceleration),
+            jerk_limit: None,
+            epsilon: 0.000001,
+            timestep: 0.001,
+        }
+    }
+
+    #[test]
+    fn trapezoid() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(10.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let trajectory = Trajectory::new(&path, options(1.0, 1.0)).unwrap();
+
+        // 1s to accelerate, 1s to decelerate, 9s at full speed
+        assert!((trajectory.duration() - 11.0).abs() < 1.0e-9);
+        assert!((trajectory.position(5.5) - xy(5.0, 0.0)).norm() < 1.0e-9);
+        assert!((trajectory.velocity(5.5) - xy(1.0, 0.0)).norm() < 1.0e-9);
+        assert!((trajectory.position(11.0) - xy(10.0, 0.0)).norm() < 1.0e-9);
+
+        // A time that isn't a number has no position, but mustn't panic
+        trajectory.position(f64::NAN);
+        trajectory.acceleration(f64::NAN);
+    }
+
+    #[test]
+    fn triangle() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let trajectory = Trajectory::new(&path, options(10.0, 1.0)).unwrap();
+
+        // Accelerate to 1 unit/s over 0.5 units, then decelerate again
+        assert!((trajectory.duration() - 2.0).abs() < 1.0e-9);
+        assert!((trajectory.velocity(1.0) - xy(1.0, 0.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn diagonal_uses_both_axes() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(10.0, 10.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let options = options(1.0, 1.0);
+        let trajectory = Trajectory::new(&path, options).unwrap();
+
+        assert!((trajectory.duration() - 11.0).abs() < 1.0e-9);
+        assert_within_limits(&trajectory, &options);
+    }
+
+    #[test]
+    fn corners() {
+        let path = Path::from_waypoints(
+            &[
+                xy(0.0, 0.0),
+                xy(1.0, 0.0),
+                xy(1.0, 1.0),
+                xy(2.0, 1.5),
+                xy(0.0, 0.0),
+            ],
+            PathOptions { max_deviation: 0.1 },
+        );
+
+        let options = options(1.0, 2.0);
+        let trajectory = Trajectory::new(&path, options).unwrap();
+
+        assert_within_limits(&trajectory, &options);
+        assert!((trajectory.position(trajectory.duration()) - xy(0.0, 0.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn stop_at_reversal() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(2.0, 0.0), xy(0.0, 0.0)],
+            PathOptions { max_deviation: 0.1 },
+        );
+
+        let trajectory = Trajectory::new(&path, options(10.0, 1.0)).unwrap();
+
+        // Two back to back triangle profiles
+        assert!((trajectory.duration() - 2.0 * 2.0 * 2.0f64.sqrt()).abs() < 1.0e-9);
+        assert!(trajectory.velocity(trajectory.duration() / 2.0).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn tiny_blend() {
+        // A sharp corner from Batman.tap in the TinyG suite, blended with a radius of a few
+        // tenths of a micron
+        let path = Path::from_waypoints(
+            &[
+                xy(30.15399932861328, 13.218999862670898),
+                xy(29.64699935913086, 18.051000595092773),
+                xy(28.113000869750977, 15.067000389099121),
+            ],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        assert!(path.segments().iter().skip(1).all(|placed| !placed.stop));
+
+        let trajectory = Trajectory::new(&path, options(1000.0, 100_000.0)).unwrap();
+
+        assert!(trajectory.duration() < 1.0);
+    }
+
+    #[test]
+    fn speed_limit() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(10.0, 0.0), xy(20.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        )
+        .with_speed_limits(&[0.0, 1.0, 0.5]);
+
+        let trajectory = Trajectory::new(&path, options(10.0, 1.0)).unwrap();
+
+        // Accelerate to 1, slow to 0.5 for the second move, then stop
+        let first = 1.0 + (10.0 - 0.5 - 0.375) / 1.0 + 0.5;
+        let second = (10.0 - 0.125) / 0.5 + 0.5;
+
+        let durations = trajectory.waypoint_durations();
+
+        assert_eq!(durations.len(), 2);
+        assert_eq!(durations[0].0, 1);
+        assert!((durations[0].1 - first).abs() < 1.0e-9);
+        assert!((durations[1].1 - second).abs() < 1.0e-9);
+        assert!((trajectory.duration() - first - second).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn s_curve() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(10.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let options = TrajectoryOptions {
+            jerk_limit: Some(Vector9::repeat(2.0)),
+            ..options(1.0, 1.0)
+        };
+
+        let trajectory = Trajectory::new(&path, options).unwrap();
+
+        // Each speed change takes 0.5s to ramp up to full acceleration, 0.5s at full acceleration
+        // and 0.5s to ramp back down, covering 0.75 units
+        assert!((trajectory.duration() - 11.5).abs() < 1.0e-9);
+        assert!((trajectory.jerk(0.25) - xy(2.0, 0.0)).norm() < 1.0e-9);
+        assert!((trajectory.acceleration(0.75) - xy(1.0, 0.0)).norm() < 1.0e-9);
+        assert!((trajectory.position(11.5) - xy(10.0, 0.0)).norm() < 1.0e-9);
+        assert_within_limits(&trajectory, &options);
+    }
+
+    #[test]
+    fn s_curve_short_move() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(0.1, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let options = TrajectoryOptions {
+            jerk_limit: Some(Vector9::repeat(2.0)),
+            ..options(1.0, 1.0)
+        };
+
+        let trajectory = Trajectory::new(&path, options).unwrap();
+
+        assert!((trajectory.position(trajectory.duration()) - xy(0.1, 0.0)).norm() < 1.0e-9);
+        assert!(trajectory.velocity(trajectory.duration()).norm() < 1.0e-9);
+        assert_within_limits(&trajectory, &options);
+    }
+
+    #[test]
+    fn s_curve_corners() {
+        let path = Path::from_waypoints(
+            &[
+                xy(0.0, 0.0),
+                xy(1.0, 0.0),
+                xy(1.0, 1.0),
+                xy(2.0, 1.5),
+                xy(0.0, 0.0),
+            ],
+            PathOptions { max_deviation: 0.1 },
+        );
+
+        let options = TrajectoryOptions {
+            jerk_limit: Some(Vector9::repeat(10.0)),
+            ..options(1.0, 2.0)
+        };
+
+        let trajectory = Trajectory::new(&path, options).unwrap();
+
+        assert_within_limits(&trajectory, &options);
+        assert!((trajectory.position(trajectory.duration()) - xy(0.0, 0.0)).norm() < 1.0e-9);
+    }
+
+    #[test]
+    fn core_xy() {
+        let options = options(100.0, 1000.0);
+
+        let along_x = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(100.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+        let diagonal = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(100.0, 100.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let along_x = Trajectory::with_kinematics(&along_x, options, &CoreXY).unwrap();
+        let diagonal = Trajectory::with_kinematics(&diagonal, options, &CoreXY).unwrap();
+
+        // Both motors turn at the same speed as the X axis moves
+        assert!((along_x.duration() - 1.1).abs() < 1.0e-9);
+
+        // Only motor A turns on the diagonal, covering 200mm at the same limits
+        assert!((diagonal.duration() - 2.1).abs() < 1.0e-9);
+
+        for trajectory in [along_x, diagonal].iter() {
+            let used = assert_within_joint_limits(trajectory, &options, &CoreXY);
+
+            assert!(used > 0.999);
+        }
+    }
+
+    #[test]
+    fn linear_delta() {
+        let delta = LinearDelta {
+            radius: 100.0,
+            rod_length: 250.0,
+        };
+
+        let options = options(100.0, 1000.0);
+
+        let path = Path::from_waypoints(
+            &[xy(-80.0, -40.0), xy(80.0, 20.0), xy(0.0, 60.0)],
+            PathOptions { max_deviation: 1.0 },
+        );
+
+        let trajectory = Trajectory::with_kinematics(&path, options, &delta).unwrap();
+
+        let used = assert_within_joint_limits(&trajectory, &options, &delta);
+
+        assert!(used > 0.95, "only used {} of the velocity limit", used);
+
+        let far = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(400.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        assert_eq!(
+            Trajectory::with_kinematics(&far, options, &delta),
+            Err(PlanError::Unreachable(1))
+        );
+    }
+
+    #[test]
+    fn invalid_limits() {
+        let path = Path::from_waypoints(
+            &[xy(0.0, 0.0), xy(1.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        assert_eq!(
+            Trajectory::new(&path, options(0.0, 1.0)),
+            Err(PlanError::InvalidLimits)
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let path = Path::from_waypoints(
+            &[xy(1.0, 0.0)],
+            PathOptions {
+                max_deviation: 0.001,
+            },
+        );
+
+        let trajectory = Trajectory::new(&path, options(1.0, 1.0)).unwrap();
+
+        assert_eq!(trajectory.duration(), 0.0);
+    }
+}
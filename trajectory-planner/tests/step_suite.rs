@@ -0,0 +1 @@
+include!(concat!(env!("OUT_DIR"), "/step_test_suites.rs"));